@@ -0,0 +1,141 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::{Result, ZiPatchError};
+
+/// Shared flag for cancelling a running apply from another thread
+///
+/// Clones share the same flag. The apply checks it before each chunk, between the compressed
+/// blocks of an SqpkFile AddFile and between the files of an SqpkFile RemoveAll, and stops
+/// with [`ZiPatchError::Cancelled`].
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a new token that hasn't been cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests cancellation of every apply using this token
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Checks if cancellation has been requested
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Clears a previous cancellation so the token can be reused
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+    }
+
+    /// Returns [`ZiPatchError::Cancelled`] if cancellation has been requested
+    ///
+    /// The error's position is left at zero; the chunk loop fills it in with the chunk that
+    /// was interrupted.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(ZiPatchError::Cancelled {
+                chunks_applied: 0,
+                resume_offset: 0,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::{ApplyObserver, ChunkInfo};
+    use crate::generate::PatchBuilder;
+    use crate::test_support::{open_patch, TestDir};
+    use crate::{ApplyOptions, ZiPatchConfig};
+
+    /// Cancels the token when the chunk with the given index starts
+    struct CancelAt(u64, CancellationToken);
+
+    impl ApplyObserver for CancelAt {
+        fn on_chunk_start(&mut self, info: &ChunkInfo) {
+            if info.index == self.0 {
+                self.1.cancel();
+            }
+        }
+    }
+
+    fn patch_data() -> PatchBuilder {
+        let mut builder = PatchBuilder::new("DIFF");
+        builder.add_file("first.dat", b"data").unwrap();
+        builder.add_file("second.dat", b"datadatadata").unwrap();
+        builder
+    }
+
+    #[test]
+    fn test_token_clones_share_state() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(token.check().is_ok());
+
+        clone.cancel();
+        assert!(token.is_cancelled());
+        assert!(matches!(token.check(), Err(ZiPatchError::Cancelled { .. })));
+
+        token.reset();
+        assert!(!clone.is_cancelled());
+    }
+
+    #[test]
+    fn test_cancelled_apply_reports_position_and_resumes() {
+        let dir = TestDir::new("cancel");
+        let checkpoint_path = dir.root.join("apply.checkpoint");
+        let options = ApplyOptions::new()
+            .checkpoint_path(&checkpoint_path)
+            .resume(true);
+
+        // Cancelling as the second AddFile starts stops it before its first block
+        let token = CancellationToken::new();
+        let mut config = ZiPatchConfig::builder(&dir.game)
+            .cancellation_token(token.clone())
+            .observer(CancelAt(2, token.clone()))
+            .build();
+
+        let mut patch = open_patch(&patch_data());
+        let infos: Vec<ChunkInfo> = patch
+            .chunks_with_info()
+            .unwrap()
+            .map(|result| result.unwrap().0)
+            .collect();
+
+        match patch.apply(&mut config, &options) {
+            Err(ZiPatchError::Cancelled {
+                chunks_applied,
+                resume_offset,
+            }) => {
+                assert_eq!(chunks_applied, 2);
+                assert_eq!(resume_offset, infos[2].offset);
+            }
+            other => panic!("expected cancellation, got {:?}", other),
+        }
+
+        assert_eq!(dir.read("first.dat"), b"data");
+        assert_eq!(dir.read("second.dat"), b"");
+        assert_eq!(
+            crate::ApplyCheckpoint::load(&checkpoint_path)
+                .unwrap()
+                .next_chunk_offset,
+            infos[2].offset
+        );
+
+        token.reset();
+        let mut config = ZiPatchConfig::builder(&dir.game)
+            .cancellation_token(token)
+            .build();
+        patch.apply(&mut config, &options).unwrap();
+        assert_eq!(dir.read("second.dat"), b"datadatadata");
+    }
+}
@@ -0,0 +1,372 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::{Platform, ZiPatchConfig};
+use crate::error::{Result, ZiPatchError};
+use crate::util::{BinaryReaderExt, BinaryWriterExt};
+
+/// Magic number at the start of a checkpoint file
+const CHECKPOINT_MAGIC: &[u8; 4] = b"ZPCK";
+/// Checkpoint format version
+const CHECKPOINT_VERSION: u32 = 2;
+
+/// Progress of an apply, saved after each chunk so it can be resumed later
+///
+/// Holds the offset of the next chunk to apply along with the configuration state that
+/// earlier chunks set up (the platform from SqpkTargetInfo and the APLY ignore flags), so
+/// resuming doesn't need to re-read anything before that offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApplyCheckpoint {
+    /// Offset in the patch file of the next chunk to apply
+    pub next_chunk_offset: u64,
+    /// Number of chunks applied so far
+    pub chunks_applied: u64,
+    /// Total length of the patch file, used to detect a checkpoint from a different patch
+    pub patch_length: u64,
+    /// CRC32 of the patch's FHDR chunk, telling apart different patches of the same length
+    pub header_checksum: u32,
+    /// Target platform at this point of the apply
+    pub platform: Platform,
+    /// IgnoreMissing flag at this point of the apply
+    pub ignore_missing: bool,
+    /// IgnoreOldMismatch flag at this point of the apply
+    pub ignore_old_mismatch: bool,
+}
+
+impl ApplyCheckpoint {
+    /// Creates a checkpoint from the current state of a configuration
+    pub fn new(
+        next_chunk_offset: u64,
+        chunks_applied: u64,
+        patch_length: u64,
+        header_checksum: u32,
+        config: &ZiPatchConfig,
+    ) -> Self {
+        Self {
+            next_chunk_offset,
+            chunks_applied,
+            patch_length,
+            header_checksum,
+            platform: config.platform,
+            ignore_missing: config.ignore_missing,
+            ignore_old_mismatch: config.ignore_old_mismatch,
+        }
+    }
+
+    /// Restores the saved configuration state
+    pub fn restore(&self, config: &mut ZiPatchConfig) {
+        config.platform = self.platform;
+        config.ignore_missing = self.ignore_missing;
+        config.ignore_old_mismatch = self.ignore_old_mismatch;
+    }
+
+    /// Loads a checkpoint from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut file = File::open(path)?;
+        Self::read_from(&mut file)
+    }
+
+    /// Loads a checkpoint from a file, returning `None` if the file doesn't exist
+    pub fn load_if_exists<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+        match File::open(path) {
+            Ok(mut file) => Ok(Some(Self::read_from(&mut file)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Saves the checkpoint to a file
+    ///
+    /// The checkpoint is written to a temporary file next to `path`, synced, and renamed over
+    /// it, so a crash leaves either the previous checkpoint or the new one.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let temp_path = Self::temp_path(path);
+
+        let mut file = File::create(&temp_path)?;
+        self.write_to(&mut file)?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// Reads a checkpoint from a reader
+    ///
+    /// A checkpoint that ends early, as when a crash cut it short, is reported as
+    /// [`ZiPatchError::InvalidCheckpoint`] like any other unreadable checkpoint.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        Self::read_fields(reader).map_err(|e| match e {
+            ZiPatchError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                ZiPatchError::InvalidCheckpoint("checkpoint file is truncated".to_string())
+            }
+            e => e,
+        })
+    }
+
+    fn read_fields<R: Read>(reader: &mut R) -> Result<Self> {
+        let invalid = |e: ZiPatchError| ZiPatchError::InvalidCheckpoint(e.to_string());
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        let version = reader.read_u32_le()?;
+        if &magic != CHECKPOINT_MAGIC || version != CHECKPOINT_VERSION {
+            return Err(ZiPatchError::InvalidCheckpoint(
+                "not a supported checkpoint file".to_string(),
+            ));
+        }
+
+        let next_chunk_offset = reader.read_u64_le()?;
+        let chunks_applied = reader.read_u64_le()?;
+        let patch_length = reader.read_u64_le()?;
+        let header_checksum = reader.read_u32_le()?;
+        let platform = Platform::from_u16(reader.read_u16_le()?).map_err(invalid)?;
+
+        let mut flags = [0u8; 2];
+        reader.read_exact(&mut flags)?;
+
+        Ok(Self {
+            next_chunk_offset,
+            chunks_applied,
+            patch_length,
+            header_checksum,
+            platform,
+            ignore_missing: flags[0] != 0,
+            ignore_old_mismatch: flags[1] != 0,
+        })
+    }
+
+    /// Writes the checkpoint to a writer
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        writer.write_u32_le(CHECKPOINT_VERSION)?;
+        writer.write_u64_le(self.next_chunk_offset)?;
+        writer.write_u64_le(self.chunks_applied)?;
+        writer.write_u64_le(self.patch_length)?;
+        writer.write_u32_le(self.header_checksum)?;
+        writer.write_u16_le(self.platform.as_u16())?;
+        writer.write_all(&[self.ignore_missing as u8, self.ignore_old_mismatch as u8])?;
+        Ok(())
+    }
+
+    fn temp_path(path: &Path) -> PathBuf {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".tmp");
+        path.with_file_name(name)
+    }
+}
+
+/// Options for [`ZiPatchFile::apply`](crate::ZiPatchFile::apply)
+#[derive(Debug, Clone, Default)]
+pub struct ApplyOptions {
+    /// File to save a checkpoint to after each chunk
+    pub checkpoint_path: Option<PathBuf>,
+    /// If true, resume from the checkpoint file if one exists
+    pub resume: bool,
+    /// File to write a patch undoing the apply to
+    pub undo_patch_path: Option<PathBuf>,
+}
+
+impl ApplyOptions {
+    /// Creates options for a plain apply without checkpoints
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Saves a checkpoint to the given file after each chunk
+    ///
+    /// The file is removed once the patch has been applied completely.
+    pub fn checkpoint_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.checkpoint_path = Some(path.into());
+        self
+    }
+
+    /// Resumes from the checkpoint file, if it exists, instead of starting from the first chunk
+    pub fn resume(mut self, value: bool) -> Self {
+        self.resume = value;
+        self
+    }
+
+    /// Writes a patch restoring the previous state of the game directory to the given file
+    ///
+    /// While applying, the data each chunk overwrites or deletes is saved to an
+    /// [`ApplyJournal`](super::ApplyJournal) next to the file, named after it with a
+    /// `.journal` suffix. Once the patch has been applied completely, the undo patch is
+    /// written from the journal and the journal is removed; an interrupted apply keeps it so
+    /// a resumed apply can carry on recording.
+    pub fn undo_patch_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.undo_patch_path = Some(path.into());
+        self
+    }
+
+    /// Gets the path of the journal kept while recording the undo patch
+    pub(crate) fn undo_journal_path(&self) -> Option<PathBuf> {
+        self.undo_patch_path.as_ref().map(|path| {
+            let mut name = path.file_name().unwrap_or_default().to_os_string();
+            name.push(".journal");
+            path.with_file_name(name)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{ApplyOptionChunk, ApplyOptionKind, ZiPatchChunk};
+    use crate::generate::PatchBuilder;
+    use crate::test_support::{open_patch, TestDir};
+    use std::io::Cursor;
+
+    fn resumable_patch() -> PatchBuilder {
+        resumable_patch_of_type("DIFF")
+    }
+
+    fn resumable_patch_of_type(patch_type: &str) -> PatchBuilder {
+        let mut builder = PatchBuilder::new(patch_type);
+        builder.push(ZiPatchChunk::ApplyOption(ApplyOptionChunk::new(
+            ApplyOptionKind::IgnoreOldMismatch,
+            true,
+        )));
+        builder.add_file("first.txt", b"first").unwrap();
+        builder.delete_directory("old");
+        builder.add_file("second.txt", b"second").unwrap();
+        builder
+    }
+
+    #[test]
+    fn test_apply_resumes_from_checkpoint() {
+        let dir = TestDir::new("checkpoint");
+        let builder = resumable_patch();
+        let checkpoint_path = dir.root.join("apply.checkpoint");
+        let options = ApplyOptions::new()
+            .checkpoint_path(&checkpoint_path)
+            .resume(true);
+
+        // DELD fails because the directory is missing, leaving a checkpoint after first.txt
+        let mut patch = open_patch(&builder);
+        let mut config = dir.config();
+        assert!(matches!(
+            patch.apply(&mut config, &options),
+            Err(ZiPatchError::OldFileMissing(_))
+        ));
+
+        let checkpoint = ApplyCheckpoint::load(&checkpoint_path).unwrap();
+        assert_eq!(checkpoint.chunks_applied, 3);
+        assert!(checkpoint.ignore_old_mismatch);
+        // Recorded even though the patch was opened without its length
+        assert_eq!(
+            checkpoint.patch_length,
+            builder.to_bytes().unwrap().len() as u64
+        );
+
+        // Resuming must not re-apply the chunks before the checkpoint
+        fs::remove_file(dir.path("first.txt")).unwrap();
+        dir.create_dir("old");
+
+        let mut patch = open_patch(&builder);
+        let mut config = dir.config();
+        patch.apply(&mut config, &options).unwrap();
+
+        assert!(config.ignore_old_mismatch);
+        assert!(!dir.path("first.txt").exists());
+        assert!(!dir.path("old").exists());
+        assert_eq!(dir.read("second.txt"), b"second");
+        assert!(!checkpoint_path.exists());
+    }
+
+    #[test]
+    fn test_resume_at_already_applied_delete_directory() {
+        let dir = TestDir::new("checkpoint-deld");
+        let builder = resumable_patch();
+        let checkpoint_path = dir.root.join("apply.checkpoint");
+        let options = ApplyOptions::new()
+            .checkpoint_path(&checkpoint_path)
+            .resume(true);
+
+        // Leaves a checkpoint pointing at the DELD chunk
+        let mut patch = open_patch(&builder);
+        assert!(patch.apply(&mut dir.config(), &options).is_err());
+
+        // The directory is still missing, as if the apply had removed it and then crashed
+        // before saving the next checkpoint
+
+        let mut patch = open_patch(&builder);
+        let mut config = dir.config();
+        patch.apply(&mut config, &options).unwrap();
+        assert!(!config.resuming);
+        assert_eq!(dir.read("second.txt"), b"second");
+        assert!(!checkpoint_path.exists());
+    }
+
+    #[test]
+    fn test_checkpoint_for_other_patch_is_rejected() {
+        let dir = TestDir::new("checkpoint-other");
+        let checkpoint_path = dir.root.join("apply.checkpoint");
+        ApplyCheckpoint::new(64, 2, 1, 0, &dir.config())
+            .save(&checkpoint_path)
+            .unwrap();
+
+        let mut patch = open_patch(&resumable_patch());
+        let options = ApplyOptions::new()
+            .checkpoint_path(&checkpoint_path)
+            .resume(true);
+        assert!(matches!(
+            patch.apply(&mut dir.config(), &options),
+            Err(ZiPatchError::InvalidCheckpoint(_))
+        ));
+
+        // Same length, different header
+        fs::remove_file(&checkpoint_path).unwrap();
+        let mut patch = open_patch(&resumable_patch());
+        assert!(patch.apply(&mut dir.config(), &options).is_err());
+        let checkpoint = ApplyCheckpoint::load(&checkpoint_path).unwrap();
+
+        let hist = resumable_patch_of_type("HIST");
+        assert_eq!(
+            hist.to_bytes().unwrap().len() as u64,
+            checkpoint.patch_length
+        );
+        assert!(matches!(
+            open_patch(&hist).apply(&mut dir.config(), &options),
+            Err(ZiPatchError::InvalidCheckpoint(_))
+        ));
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let checkpoint = ApplyCheckpoint {
+            next_chunk_offset: 0x0012_3456_789A,
+            chunks_applied: 42,
+            patch_length: 0x00FF_FFFF_FFFF,
+            header_checksum: 0xDEAD_BEEF,
+            platform: Platform::Ps4,
+            ignore_missing: true,
+            ignore_old_mismatch: false,
+        };
+
+        let mut buf = Vec::new();
+        checkpoint.write_to(&mut buf).unwrap();
+        let read = ApplyCheckpoint::read_from(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(read, checkpoint);
+    }
+
+    #[test]
+    fn test_truncated_checkpoint_is_rejected() {
+        let checkpoint = ApplyCheckpoint::new(64, 2, 1, 0, &ZiPatchConfig::new("game"));
+        let mut buf = Vec::new();
+        checkpoint.write_to(&mut buf).unwrap();
+
+        for len in [0, 10, buf.len() - 1] {
+            let result = ApplyCheckpoint::read_from(&mut Cursor::new(&buf[..len]));
+            assert!(matches!(result, Err(ZiPatchError::InvalidCheckpoint(_))));
+        }
+    }
+
+    #[test]
+    fn test_invalid_magic_is_rejected() {
+        let result = ApplyCheckpoint::read_from(&mut Cursor::new(vec![0u8; 40]));
+        assert!(matches!(result, Err(ZiPatchError::InvalidCheckpoint(_))));
+    }
+}
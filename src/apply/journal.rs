@@ -0,0 +1,692 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::apply::FileOperation;
+use crate::chunk::ZiPatchChunk;
+use crate::config::ZiPatchConfig;
+use crate::error::{Result, ZiPatchError};
+use crate::util::{BinaryReaderExt, BinaryWriterExt};
+
+/// Magic number at the start of a journal file
+const JOURNAL_MAGIC: &[u8; 4] = b"ZPJL";
+/// Journal format version
+const JOURNAL_VERSION: u32 = 1;
+/// Size of the journal file header (magic + version)
+const JOURNAL_HEADER_SIZE: u64 = 8;
+/// Size of the pieces saved range data is copied back in on rollback
+const ROLLBACK_PIECE_SIZE: usize = 1 << 20;
+
+/// A single entry in the apply journal
+///
+/// Paths are stored relative to the game directory, as normalized by
+/// [`ZiPatchConfig::resolve_path`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum JournalRecord {
+    /// The file did not exist before the patch touched it
+    FileCreated { path: String },
+    /// Length of the file before the patch first touched it
+    FileLength { path: String, length: u64 },
+    /// Original bytes of a range, stored in the journal file at `data_position`
+    Range {
+        path: String,
+        offset: u64,
+        length: u64,
+        data_position: u64,
+    },
+    /// The directory did not exist before the patch created it
+    DirectoryCreated { path: String },
+    /// The directory existed before the patch removed it
+    DirectoryRemoved { path: String },
+}
+
+impl JournalRecord {
+    const FILE_CREATED: u8 = 1;
+    const FILE_LENGTH: u8 = 2;
+    const RANGE: u8 = 3;
+    const DIRECTORY_CREATED: u8 = 4;
+    const DIRECTORY_REMOVED: u8 = 5;
+}
+
+/// On-disk journal of the data a patch overwrites, allowing a failed or unwanted apply
+/// to be rolled back
+///
+/// Before each chunk is applied, [`record_chunk`](Self::record_chunk) saves the original bytes
+/// of every range that SqpkAddData, SqpkDeleteData, SqpkExpandData, SqpkHeader and SqpkFile
+/// will overwrite or delete, along with the files and directories the chunk creates or
+/// removes. The journal is synced to disk before the chunk is applied, so it can also be
+/// reopened with [`open`](Self::open) to roll back after a crash.
+#[derive(Debug)]
+pub struct ApplyJournal {
+    path: PathBuf,
+    pub(super) file: File,
+    pub(super) records: Vec<JournalRecord>,
+    /// Original length of every file touched so far (None if it didn't exist)
+    touched_files: HashMap<String, Option<u64>>,
+    /// Files whose whole original contents have already been saved
+    saved_files: HashSet<String>,
+    touched_directories: HashSet<String>,
+}
+
+impl ApplyJournal {
+    /// Creates a new, empty journal at the given path, replacing any existing file
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+
+        file.write_all(JOURNAL_MAGIC)?;
+        file.write_u32_le(JOURNAL_VERSION)?;
+        file.sync_all()?;
+
+        Ok(Self {
+            path,
+            file,
+            records: Vec::new(),
+            touched_files: HashMap::new(),
+            saved_files: HashSet::new(),
+            touched_directories: HashSet::new(),
+        })
+    }
+
+    /// Opens an existing journal, e.g. to roll back an apply that was interrupted by a crash
+    ///
+    /// A partially written record at the end of the journal is discarded; it belongs to a
+    /// chunk that was never applied.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
+
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)?;
+        let version = file.read_u32_le()?;
+        if &magic != JOURNAL_MAGIC || version != JOURNAL_VERSION {
+            return Err(ZiPatchError::Custom(format!(
+                "{} is not a supported apply journal",
+                path.display()
+            )));
+        }
+
+        let file_length = file.metadata()?.len();
+        let mut reader = BufReader::new(&mut file);
+        let mut position = JOURNAL_HEADER_SIZE;
+        let mut records = Vec::new();
+
+        while let Some((record, next)) = Self::read_record(&mut reader, position, file_length)? {
+            records.push(record);
+            position = next;
+        }
+
+        // Drop any torn record at the end so new records are appended after the last good one
+        file.set_len(position)?;
+
+        let mut journal = Self {
+            path,
+            file,
+            records,
+            touched_files: HashMap::new(),
+            saved_files: HashSet::new(),
+            touched_directories: HashSet::new(),
+        };
+
+        for record in &journal.records {
+            match record {
+                JournalRecord::FileCreated { path } => {
+                    journal.touched_files.insert(path.clone(), None);
+                }
+                JournalRecord::FileLength { path, length } => {
+                    journal.touched_files.insert(path.clone(), Some(*length));
+                }
+                JournalRecord::DirectoryCreated { path }
+                | JournalRecord::DirectoryRemoved { path } => {
+                    journal.touched_directories.insert(path.clone());
+                }
+                JournalRecord::Range { .. } => {}
+            }
+        }
+
+        Ok(journal)
+    }
+
+    /// Gets the path of the journal file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Checks if the journal has no records
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Records the original state of everything the chunk is about to modify
+    ///
+    /// Must be called with the configuration the chunk will be applied with, immediately
+    /// before applying it. The journal is synced to disk before this returns.
+    pub fn record_chunk(&mut self, chunk: &ZiPatchChunk, config: &ZiPatchConfig) -> Result<()> {
+        for operation in chunk.planned_operations(config)? {
+            self.record_operation(config, &operation)?;
+        }
+
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    fn record_operation(
+        &mut self,
+        config: &ZiPatchConfig,
+        operation: &FileOperation,
+    ) -> Result<()> {
+        match operation {
+            FileOperation::CreateDirectory { path } => self.record_directory_tree(config, path),
+            FileOperation::RemoveDirectory { path } => {
+                let is_dir = config
+                    .target
+                    .metadata(path)?
+                    .is_some_and(|metadata| metadata.is_dir);
+                if is_dir {
+                    let relative = Self::relative_path(config, path)?;
+                    if self.touched_directories.insert(relative.clone()) {
+                        self.append(JournalRecord::DirectoryRemoved { path: relative }, None)?;
+                    }
+                }
+                Ok(())
+            }
+            FileOperation::TruncateFile { path, length } => {
+                self.record_range(config, path, *length, u64::MAX)
+            }
+            FileOperation::Write {
+                path,
+                offset,
+                length,
+            }
+            | FileOperation::Wipe {
+                path,
+                offset,
+                length,
+            } => self.record_range(config, path, *offset, *length),
+            FileOperation::DeleteFile { path } => self.record_range(config, path, 0, u64::MAX),
+            FileOperation::RemoveAll { files, .. } => {
+                for path in files {
+                    self.record_range(config, path, 0, u64::MAX)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Records the original bytes of a range within a file
+    ///
+    /// Only bytes within the file's original length are saved; anything past it is removed
+    /// by truncating the file back to that length on rollback.
+    fn record_range(
+        &mut self,
+        config: &ZiPatchConfig,
+        path: &Path,
+        offset: u64,
+        length: u64,
+    ) -> Result<()> {
+        let relative = Self::relative_path(config, path)?;
+
+        let original_length = match self.touched_files.get(&relative) {
+            Some(original_length) => *original_length,
+            None => {
+                let original_length = match config.target.metadata(path)? {
+                    Some(metadata) if metadata.is_file() => Some(metadata.len),
+                    Some(_) => return Err(ZiPatchError::OldFileMismatch(path.to_path_buf())),
+                    None => None,
+                };
+
+                let record = match original_length {
+                    Some(length) => JournalRecord::FileLength {
+                        path: relative.clone(),
+                        length,
+                    },
+                    None => JournalRecord::FileCreated {
+                        path: relative.clone(),
+                    },
+                };
+                self.append(record, None)?;
+                self.touched_files.insert(relative.clone(), original_length);
+                original_length
+            }
+        };
+
+        // Nothing to save for files created by this patch or already saved in full
+        let Some(original_length) = original_length else {
+            return Ok(());
+        };
+        if self.saved_files.contains(&relative) {
+            return Ok(());
+        }
+
+        // An earlier chunk may have shortened or removed the file; that chunk's own record
+        // restores whatever is missing here
+        let current_length = config
+            .target
+            .metadata(path)?
+            .map_or(0, |metadata| metadata.len);
+        let end = offset
+            .saturating_add(length)
+            .min(original_length)
+            .min(current_length);
+        if offset >= end {
+            return Ok(());
+        }
+
+        if offset == 0 && end == original_length {
+            self.saved_files.insert(relative.clone());
+        }
+
+        let mut reader = config.target.open(path)?;
+        reader.seek(SeekFrom::Start(offset))?;
+        let record = JournalRecord::Range {
+            path: relative,
+            offset,
+            length: end - offset,
+            data_position: 0,
+        };
+        self.append(record, Some(&mut reader))
+    }
+
+    /// Records every missing directory from the game directory down to `path`
+    fn record_directory_tree(&mut self, config: &ZiPatchConfig, path: &Path) -> Result<()> {
+        let mut missing = Vec::new();
+        let mut current = Some(path);
+
+        while let Some(dir) = current {
+            if dir == config.game_path() || config.target.metadata(dir)?.is_some() {
+                break;
+            }
+            missing.push(dir.to_path_buf());
+            current = dir.parent();
+        }
+
+        // Outermost first, so rollback removes the innermost directories first
+        for dir in missing.into_iter().rev() {
+            let relative = Self::relative_path(config, &dir)?;
+            if self.touched_directories.insert(relative.clone()) {
+                self.append(JournalRecord::DirectoryCreated { path: relative }, None)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Restores everything recorded in the journal through the configuration's target,
+    /// newest record first, then clears it
+    ///
+    /// Index commands the configuration holds but hasn't written out yet are dropped, so a
+    /// later apply doesn't write them over the restored index files.
+    pub fn rollback(&mut self, config: &mut ZiPatchConfig) -> Result<()> {
+        config.pending_indexes.clear();
+
+        for record in self.records.clone().iter().rev() {
+            match record {
+                JournalRecord::Range {
+                    path,
+                    offset,
+                    length,
+                    data_position,
+                } => {
+                    let full_path = config.resolve_path(path)?;
+                    if let Some(parent) = full_path.parent() {
+                        config.target.create_dir_all(parent)?;
+                    }
+
+                    let mut piece = vec![0u8; (*length).min(ROLLBACK_PIECE_SIZE as u64) as usize];
+                    self.file.seek(SeekFrom::Start(*data_position))?;
+                    let mut done = 0;
+                    while done < *length {
+                        let size = (*length - done).min(piece.len() as u64) as usize;
+                        self.file.read_exact(&mut piece[..size])?;
+                        config
+                            .target
+                            .write_at(&full_path, offset + done, &piece[..size])?;
+                        done += size as u64;
+                    }
+                }
+                JournalRecord::FileLength { path, length } => {
+                    let full_path = config.resolve_path(path)?;
+                    config.target.set_len(&full_path, *length)?;
+                }
+                JournalRecord::FileCreated { path } => {
+                    let full_path = config.resolve_path(path)?;
+                    if config.target.metadata(&full_path)?.is_some() {
+                        config.target.remove_file(&full_path)?;
+                    }
+                }
+                JournalRecord::DirectoryCreated { path } => {
+                    // Leave the directory if something outside the patch put files in it
+                    let _ = config.target.remove_dir(&config.resolve_path(path)?);
+                }
+                JournalRecord::DirectoryRemoved { path } => {
+                    config.target.create_dir_all(&config.resolve_path(path)?)?;
+                }
+            }
+        }
+
+        self.clear()
+    }
+
+    /// Discards the journal after a successful apply, deleting the journal file
+    pub fn commit(self) -> Result<()> {
+        let Self { path, file, .. } = self;
+        drop(file);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    /// Removes all records, keeping the journal file open for reuse
+    fn clear(&mut self) -> Result<()> {
+        self.file.set_len(JOURNAL_HEADER_SIZE)?;
+        self.file.sync_all()?;
+        self.records.clear();
+        self.touched_files.clear();
+        self.saved_files.clear();
+        self.touched_directories.clear();
+        Ok(())
+    }
+
+    /// Appends a record to the journal file, copying a range's data from `data`
+    fn append(&mut self, mut record: JournalRecord, data: Option<&mut dyn Read>) -> Result<()> {
+        let mut buf = Vec::new();
+
+        let (tag, path) = match &record {
+            JournalRecord::FileCreated { path } => (JournalRecord::FILE_CREATED, path),
+            JournalRecord::FileLength { path, .. } => (JournalRecord::FILE_LENGTH, path),
+            JournalRecord::Range { path, .. } => (JournalRecord::RANGE, path),
+            JournalRecord::DirectoryCreated { path } => (JournalRecord::DIRECTORY_CREATED, path),
+            JournalRecord::DirectoryRemoved { path } => (JournalRecord::DIRECTORY_REMOVED, path),
+        };
+        buf.write_all(&[tag])?;
+        buf.write_u32_le(path.len() as u32)?;
+        buf.write_all(path.as_bytes())?;
+
+        let position = self.file.seek(SeekFrom::End(0))?;
+        match &mut record {
+            JournalRecord::FileLength { length, .. } => buf.write_u64_le(*length)?,
+            JournalRecord::Range {
+                offset,
+                length,
+                data_position,
+                ..
+            } => {
+                buf.write_u64_le(*offset)?;
+                buf.write_u64_le(*length)?;
+                *data_position = position + buf.len() as u64;
+            }
+            _ => {}
+        }
+
+        self.file.write_all(&buf)?;
+        if let (JournalRecord::Range { length, .. }, Some(data)) = (&record, data) {
+            let copied = io::copy(&mut data.take(*length), &mut self.file)?;
+            if copied != *length {
+                self.file.set_len(position)?;
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+        self.records.push(record);
+        Ok(())
+    }
+
+    /// Reads the record starting at `position`, returning it and the position of the next one
+    ///
+    /// Returns `None` at the end of the journal or if the record was only partially written.
+    fn read_record<R: Read>(
+        reader: &mut R,
+        position: u64,
+        file_length: u64,
+    ) -> Result<Option<(JournalRecord, u64)>> {
+        let mut tag = [0u8; 1];
+        match reader.read_exact(&mut tag) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        let parsed = (|| -> Result<(JournalRecord, u64)> {
+            let path_len = reader.read_u32_le()? as u64;
+            let path = String::from_utf8(reader.read_bytes_required(path_len as usize)?)?;
+            let mut next = position + 1 + 4 + path_len;
+
+            let record = match tag[0] {
+                JournalRecord::FILE_CREATED => JournalRecord::FileCreated { path },
+                JournalRecord::FILE_LENGTH => {
+                    next += 8;
+                    JournalRecord::FileLength {
+                        path,
+                        length: reader.read_u64_le()?,
+                    }
+                }
+                JournalRecord::RANGE => {
+                    let offset = reader.read_u64_le()?;
+                    let length = reader.read_u64_le()?;
+                    let data_position = next + 16;
+                    next = data_position + length;
+                    if next > file_length {
+                        return Err(ZiPatchError::UnexpectedEof(data_position));
+                    }
+                    io::copy(&mut reader.take(length), &mut io::sink())?;
+
+                    JournalRecord::Range {
+                        path,
+                        offset,
+                        length,
+                        data_position,
+                    }
+                }
+                JournalRecord::DIRECTORY_CREATED => JournalRecord::DirectoryCreated { path },
+                JournalRecord::DIRECTORY_REMOVED => JournalRecord::DirectoryRemoved { path },
+                other => {
+                    return Err(ZiPatchError::InvalidChunkData {
+                        offset: position,
+                        reason: format!("unknown journal record type {}", other),
+                    })
+                }
+            };
+
+            Ok((record, next))
+        })();
+
+        match parsed {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(ZiPatchError::UnexpectedEof(_)) => Ok(None),
+            Err(ZiPatchError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Converts a resolved path back to a normalized path relative to the game directory
+    fn relative_path(config: &ZiPatchConfig, path: &Path) -> Result<String> {
+        let relative =
+            path.strip_prefix(config.game_path())
+                .map_err(|_| ZiPatchError::UnsafePath {
+                    path: path.display().to_string(),
+                    reason: "path is outside the game directory".to_string(),
+                })?;
+
+        let components: Option<Vec<&str>> = relative
+            .components()
+            .map(|c| c.as_os_str().to_str())
+            .collect();
+        components
+            .map(|c| c.join("/"))
+            .ok_or_else(|| ZiPatchError::UnsafePath {
+                path: path.display().to_string(),
+                reason: "path is not valid UTF-8".to_string(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::sqpk::{IndexCommandKind, SqpkCommand, SqpkIndex};
+    use crate::chunk::DeleteDirectoryChunk;
+    use crate::generate::PatchBuilder;
+    use crate::sqpack::{empty_index, IndexKind};
+    use crate::test_support::{add_data, dat_file, open_patch, TestDir};
+    use crate::util::SqpackIndexFile;
+    use crate::ZiPatchFile;
+    use std::io::Cursor;
+
+    fn test_dir(name: &str) -> TestDir {
+        let dir = TestDir::new(&format!("journal-{}", name));
+        dir.write("sqpack/ffxiv/000000.win32.dat0", &[0x11; 0x200]);
+        dir.write("boot/ffxivboot.exe", b"original boot");
+        dir.create_dir("movie/old");
+        dir
+    }
+
+    fn assert_original(dir: &TestDir) {
+        assert_eq!(dir.read("sqpack/ffxiv/000000.win32.dat0"), [0x11; 0x200]);
+        assert_eq!(dir.read("boot/ffxivboot.exe"), b"original boot");
+        assert!(dir.path("movie/old").is_dir());
+        assert!(!dir.path("movie/ex1").exists());
+        assert!(!dir.path("boot/sub").exists());
+    }
+
+    fn patch(extra: Vec<ZiPatchChunk>) -> ZiPatchFile<Cursor<Vec<u8>>> {
+        let mut builder = PatchBuilder::new("DIFF");
+        builder.push(add_data(dat_file(0, 0, 0), 0x180, vec![0x22; 0x80], 0x80));
+        builder.add_file("boot/ffxivboot.exe", b"patched").unwrap();
+        builder.add_file("boot/sub/new.exe", b"new file").unwrap();
+        builder.add_directory("movie/ex1");
+        builder.delete_directory("movie/old");
+        for chunk in extra {
+            builder.push(chunk);
+        }
+        open_patch(&builder)
+    }
+
+    #[test]
+    fn test_failed_apply_is_rolled_back() {
+        let dir = test_dir("failed");
+        let mut config = dir.config();
+        let mut journal = ApplyJournal::create(dir.root.join("apply.journal")).unwrap();
+
+        // Deleting a directory that doesn't exist fails without IgnoreMissing
+        let mut patch = patch(vec![ZiPatchChunk::DeleteDirectory(
+            DeleteDirectoryChunk::new("movie/missing"),
+        )]);
+
+        let result = patch.apply_journaled(&mut config, &mut journal);
+        assert!(matches!(result, Err(ZiPatchError::OldFileMissing(_))));
+        assert_original(&dir);
+        assert!(journal.is_empty());
+    }
+
+    fn index_add(main_id: u16, file_hash: u64) -> ZiPatchChunk {
+        ZiPatchChunk::Sqpk(SqpkCommand::Index(SqpkIndex {
+            index_command: IndexCommandKind::Add,
+            synonym: 0,
+            alignment: 0,
+            target_file: SqpackIndexFile {
+                sqpack: dat_file(main_id, 0, 0).sqpack,
+            },
+            file_hash,
+            block_offset: 0x20,
+            block_number: 1,
+        }))
+    }
+
+    #[test]
+    fn test_failed_apply_drops_pending_index_commands() {
+        let dir = test_dir("pending-indexes");
+        let index = empty_index(IndexKind::Index1);
+        dir.write("sqpack/ffxiv/0a0000.win32.index", &index);
+        let mut config = dir.config();
+        config.apply_index_commands = true;
+        let mut journal = ApplyJournal::create(dir.root.join("apply.journal")).unwrap();
+
+        // The second command's index doesn't exist, so it fails with the first one pending
+        let mut builder = PatchBuilder::new("DIFF");
+        builder.push(index_add(0x0A, 0x1_0000_0002));
+        builder.push(index_add(0x0B, 0x1_0000_0002));
+        let result = open_patch(&builder).apply_journaled(&mut config, &mut journal);
+        assert!(matches!(result, Err(ZiPatchError::OldFileMissing(_))));
+        assert!(config.pending_indexes.is_empty());
+
+        patch(vec![])
+            .apply_journaled(&mut config, &mut journal)
+            .unwrap();
+        assert_eq!(dir.read("sqpack/ffxiv/0a0000.win32.index"), index);
+    }
+
+    #[test]
+    fn test_successful_apply_can_be_rolled_back() {
+        let dir = test_dir("success");
+        let mut config = dir.config();
+        let mut journal = ApplyJournal::create(dir.root.join("apply.journal")).unwrap();
+
+        patch(vec![])
+            .apply_journaled(&mut config, &mut journal)
+            .unwrap();
+
+        let dat = dir.read("sqpack/ffxiv/000000.win32.dat0");
+        assert_eq!(&dat[0x180..0x200], &[0x22; 0x80]);
+        assert_eq!(dir.read("boot/sub/new.exe"), b"new file");
+        assert!(!dir.path("movie/old").exists());
+
+        journal.rollback(&mut config).unwrap();
+        assert_original(&dir);
+
+        let journal_path = journal.path().to_path_buf();
+        journal.commit().unwrap();
+        assert!(!journal_path.exists());
+    }
+
+    #[test]
+    fn test_file_larger_than_a_piece_is_rolled_back() {
+        let dir = test_dir("large");
+        let data: Vec<u8> = (0..ROLLBACK_PIECE_SIZE * 2 + 300)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        dir.write("sqpack/ffxiv/000000.win32.dat1", &data);
+        let mut config = dir.config();
+        let mut journal = ApplyJournal::create(dir.root.join("apply.journal")).unwrap();
+
+        let mut builder = PatchBuilder::new("DIFF");
+        builder.delete_file("sqpack/ffxiv/000000.win32.dat1");
+        open_patch(&builder)
+            .apply_journaled(&mut config, &mut journal)
+            .unwrap();
+        assert!(!dir.path("sqpack/ffxiv/000000.win32.dat1").exists());
+        assert!(journal.records.iter().any(|record| matches!(
+            record,
+            JournalRecord::Range { length, .. } if *length == data.len() as u64
+        )));
+
+        journal.rollback(&mut config).unwrap();
+        assert_eq!(dir.read("sqpack/ffxiv/000000.win32.dat1"), data);
+    }
+
+    #[test]
+    fn test_reopened_journal_ignores_torn_record() {
+        let dir = test_dir("reopen");
+        let mut config = dir.config();
+        let journal_path = dir.root.join("apply.journal");
+        let mut journal = ApplyJournal::create(&journal_path).unwrap();
+
+        patch(vec![])
+            .apply_journaled(&mut config, &mut journal)
+            .unwrap();
+        drop(journal);
+
+        // Simulate a crash while a record was being written
+        let mut file = OpenOptions::new().append(true).open(&journal_path).unwrap();
+        file.write_all(&[JournalRecord::RANGE, 0xFF, 0x00]).unwrap();
+        drop(file);
+
+        let mut journal = ApplyJournal::open(&journal_path).unwrap();
+        assert!(!journal.is_empty());
+        journal.rollback(&mut config).unwrap();
+        assert_original(&dir);
+    }
+}
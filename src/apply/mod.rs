@@ -0,0 +1,12 @@
+mod cancel;
+mod checkpoint;
+mod journal;
+mod observer;
+mod plan;
+mod undo;
+
+pub use cancel::CancellationToken;
+pub use checkpoint::{ApplyCheckpoint, ApplyOptions};
+pub use journal::ApplyJournal;
+pub use observer::{ApplyObserver, ChunkInfo};
+pub use plan::{ApplyPlan, FileOperation, PlannedOperation};
@@ -0,0 +1,199 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::chunk::ZiPatchChunk;
+
+/// Position and progress details for a chunk being applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkInfo {
+    /// Index of the chunk within the patch, starting at 0 for the first chunk after the magic
+    pub index: u64,
+    /// Offset of the chunk in the patch file
+    pub offset: u64,
+    /// Size of the chunk in the patch file, including its size, type and checksum fields
+    pub size: u64,
+    /// Chunk type (e.g. "SQPK", "ADIR")
+    pub chunk_type: &'static str,
+    /// SQPK command character, for SQPK chunks
+    pub sqpk_command: Option<char>,
+    /// Bytes of the patch file processed so far
+    ///
+    /// Equal to `offset` when a chunk starts and `offset + size` when it ends.
+    pub bytes_processed: u64,
+    /// Total length of the patch file, if known
+    pub total_bytes: Option<u64>,
+}
+
+impl ChunkInfo {
+    /// Creates the info for a chunk that is about to be applied
+    ///
+    /// # Arguments
+    /// * `chunk` - The chunk
+    /// * `index` - Index of the chunk within the patch
+    /// * `offset` - Offset of the chunk in the patch file
+    /// * `size` - Size of the chunk in the patch file
+    /// * `total_bytes` - Total length of the patch file, if known
+    pub fn new(
+        chunk: &ZiPatchChunk,
+        index: u64,
+        offset: u64,
+        size: u64,
+        total_bytes: Option<u64>,
+    ) -> Self {
+        Self {
+            index,
+            offset,
+            size,
+            chunk_type: chunk.chunk_type(),
+            sqpk_command: chunk.sqpk_command(),
+            bytes_processed: offset,
+            total_bytes,
+        }
+    }
+
+    /// Gets the info reported once the chunk has been applied
+    pub fn completed(&self) -> Self {
+        Self {
+            bytes_processed: self.offset + self.size,
+            ..*self
+        }
+    }
+}
+
+/// Callbacks for following the progress of a patch being applied
+///
+/// Set on the configuration with [`ZiPatchConfigBuilder::observer`](crate::ZiPatchConfigBuilder::observer)
+/// and called by [`ZiPatchFile::apply`](crate::ZiPatchFile::apply) and
+/// [`ZiPatchChunk::apply_observed`](crate::ZiPatchChunk::apply_observed). All methods do
+/// nothing by default.
+pub trait ApplyObserver {
+    /// Called before a chunk is applied
+    fn on_chunk_start(&mut self, _info: &ChunkInfo) {}
+
+    /// Called after a chunk has been applied successfully
+    fn on_chunk_end(&mut self, _info: &ChunkInfo) {}
+
+    /// Called after data has been written to a file
+    ///
+    /// # Arguments
+    /// * `path` - Resolved path of the file
+    /// * `offset` - Offset the data was written at
+    /// * `length` - Number of bytes written
+    fn on_file_write(&mut self, _path: &Path, _offset: u64, _length: u64) {}
+}
+
+impl fmt::Debug for dyn ApplyObserver + Send {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ApplyObserver")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::PatchBuilder;
+    use crate::test_support::TestDir;
+    use crate::{ApplyOptions, ZiPatchConfig, ZiPatchFile};
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Event {
+        Start(ChunkInfo),
+        End(ChunkInfo),
+        Write(PathBuf, u64, u64),
+    }
+
+    #[derive(Default, Clone)]
+    struct Recorder(Arc<Mutex<Vec<Event>>>);
+
+    impl ApplyObserver for Recorder {
+        fn on_chunk_start(&mut self, info: &ChunkInfo) {
+            self.0.lock().unwrap().push(Event::Start(*info));
+        }
+
+        fn on_chunk_end(&mut self, info: &ChunkInfo) {
+            self.0.lock().unwrap().push(Event::End(*info));
+        }
+
+        fn on_file_write(&mut self, path: &Path, offset: u64, length: u64) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(Event::Write(path.to_path_buf(), offset, length));
+        }
+    }
+
+    fn patch_data() -> Vec<u8> {
+        let mut builder = PatchBuilder::new("DIFF");
+        builder.add_directory("boot");
+        builder.add_file("boot/game.ver", b"2024.").unwrap();
+        builder.to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_apply_reports_chunks_and_writes() {
+        let dir = TestDir::new("observer");
+        let data = patch_data();
+        let total = data.len() as u64;
+        let recorder = Recorder::default();
+        let mut config = ZiPatchConfig::builder(&dir.game)
+            .observer(recorder.clone())
+            .build();
+
+        let mut patch = ZiPatchFile::new(Cursor::new(data)).unwrap();
+        patch.apply(&mut config, &ApplyOptions::new()).unwrap();
+
+        let events = recorder.0.lock().unwrap().clone();
+        let starts: Vec<&ChunkInfo> = events
+            .iter()
+            .filter_map(|e| match e {
+                Event::Start(info) => Some(info),
+                _ => None,
+            })
+            .collect();
+
+        let types: Vec<&str> = starts.iter().map(|info| info.chunk_type).collect();
+        assert_eq!(types, ["FHDR", "ADIR", "SQPK", "EOF_"]);
+        assert_eq!(starts[0].offset, 12);
+        assert_eq!(starts[2].sqpk_command, Some('F'));
+        assert!(starts
+            .iter()
+            .enumerate()
+            .all(|(i, info)| info.index == i as u64));
+
+        // Chunks are contiguous and the last one ends at the end of the patch
+        for pair in starts.windows(2) {
+            assert_eq!(pair[0].offset + pair[0].size, pair[1].offset);
+        }
+        match events.last().unwrap() {
+            Event::End(info) => assert_eq!(info.bytes_processed, total),
+            other => panic!("unexpected last event {:?}", other),
+        }
+
+        let written = Event::Write(dir.game.join("boot").join("game.ver"), 0, 5);
+        let write_index = events.iter().position(|e| *e == written).unwrap();
+        assert!(
+            matches!(&events[write_index - 1], Event::Start(info) if info.chunk_type == "SQPK")
+        );
+        assert!(matches!(&events[write_index + 1], Event::End(info) if info.chunk_type == "SQPK"));
+    }
+
+    #[test]
+    fn test_chunks_with_info_matches_apply() {
+        let data = patch_data();
+        let mut patch = ZiPatchFile::new(Cursor::new(data.clone())).unwrap();
+
+        let infos: Vec<ChunkInfo> = patch
+            .chunks_with_info()
+            .unwrap()
+            .map(|result| result.unwrap().0)
+            .collect();
+
+        assert_eq!(infos.len(), 4);
+        assert_eq!(infos[1].chunk_type, "ADIR");
+        assert_eq!(infos[3].offset + infos[3].size, data.len() as u64);
+        assert_eq!(infos[3].completed().bytes_processed, data.len() as u64);
+    }
+}
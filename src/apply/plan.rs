@@ -0,0 +1,319 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A single filesystem operation performed while applying a chunk
+///
+/// Paths are fully resolved against the game directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileOperation {
+    /// Create a directory and any missing parents
+    CreateDirectory { path: PathBuf },
+    /// Remove an empty directory
+    RemoveDirectory { path: PathBuf },
+    /// Create the file if needed and set its length
+    TruncateFile { path: PathBuf, length: u64 },
+    /// Write `length` bytes of patch data at `offset`
+    Write {
+        path: PathBuf,
+        offset: u64,
+        length: u64,
+    },
+    /// Overwrite `length` bytes at `offset` with zeros
+    Wipe {
+        path: PathBuf,
+        offset: u64,
+        length: u64,
+    },
+    /// Delete a file
+    DeleteFile { path: PathBuf },
+    /// Delete every file of an expansion, as found when the plan was made
+    RemoveAll {
+        expansion_id: u16,
+        files: Vec<PathBuf>,
+    },
+}
+
+impl FileOperation {
+    /// Gets the path the operation acts on, or `None` for [`FileOperation::RemoveAll`]
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            FileOperation::CreateDirectory { path }
+            | FileOperation::RemoveDirectory { path }
+            | FileOperation::TruncateFile { path, .. }
+            | FileOperation::Write { path, .. }
+            | FileOperation::Wipe { path, .. }
+            | FileOperation::DeleteFile { path } => Some(path),
+            FileOperation::RemoveAll { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for FileOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileOperation::CreateDirectory { path } => {
+                write!(f, "create directory {}", path.display())
+            }
+            FileOperation::RemoveDirectory { path } => {
+                write!(f, "remove directory {}", path.display())
+            }
+            FileOperation::TruncateFile { path, length } => {
+                write!(f, "truncate {} to {} bytes", path.display(), length)
+            }
+            FileOperation::Write {
+                path,
+                offset,
+                length,
+            } => write!(
+                f,
+                "write {} bytes at offset {} to {}",
+                length,
+                offset,
+                path.display()
+            ),
+            FileOperation::Wipe {
+                path,
+                offset,
+                length,
+            } => write!(
+                f,
+                "wipe {} bytes at offset {} in {}",
+                length,
+                offset,
+                path.display()
+            ),
+            FileOperation::DeleteFile { path } => write!(f, "delete {}", path.display()),
+            FileOperation::RemoveAll {
+                expansion_id,
+                files,
+            } => write!(
+                f,
+                "remove all files of expansion {} ({} files)",
+                expansion_id,
+                files.len()
+            ),
+        }
+    }
+}
+
+/// A file operation along with the chunk it comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedOperation {
+    /// Index of the chunk within the patch
+    pub chunk_index: u64,
+    /// Offset of the chunk in the patch file
+    pub chunk_offset: u64,
+    /// The operation
+    pub operation: FileOperation,
+}
+
+/// Ordered list of every filesystem operation a patch performs when applied
+///
+/// Produced by [`ZiPatchFile::plan`](crate::ZiPatchFile::plan) without touching the game
+/// directory. Operations that depend on the directory's contents (such as the files matched by
+/// an SqpkFile RemoveAll) reflect its state when the plan was made.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApplyPlan {
+    /// Operations in the order they are performed
+    pub operations: Vec<PlannedOperation>,
+}
+
+impl ApplyPlan {
+    /// Gets the number of operations
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    /// Checks if the plan has no operations
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Iterates over the operations without their chunk positions
+    pub fn file_operations(&self) -> impl Iterator<Item = &FileOperation> {
+        self.operations.iter().map(|planned| &planned.operation)
+    }
+
+    /// Gets the total number of bytes written or wiped
+    pub fn bytes_written(&self) -> u64 {
+        self.file_operations()
+            .map(|operation| match operation {
+                FileOperation::Write { length, .. } | FileOperation::Wipe { length, .. } => *length,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+impl fmt::Display for ApplyPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for planned in &self.operations {
+            writeln!(
+                f,
+                "[{}@{:#x}] {}",
+                planned.chunk_index, planned.chunk_offset, planned.operation
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::sqpk::{IndexCommandKind, RegionId, SqpkIndex, SqpkTargetInfo};
+    use crate::chunk::{ApplyOptionChunk, ApplyOptionKind, SqpkCommand, ZiPatchChunk};
+    use crate::config::Platform;
+    use crate::generate::PatchBuilder;
+    use crate::sqpack::{empty_index, IndexKind};
+    use crate::test_support::{add_data, dat_file, open_patch, remove_all, TestDir};
+    use crate::util::SqpackIndexFile;
+
+    fn patch_data() -> PatchBuilder {
+        let mut builder = PatchBuilder::new("DIFF");
+        builder.push(ZiPatchChunk::Sqpk(SqpkCommand::TargetInfo(
+            SqpkTargetInfo {
+                alignment: [0; 3],
+                platform: Platform::Ps3,
+                region: RegionId::Global,
+                debug: 0,
+                version: 0,
+                deleted_data_size: 0,
+                seek_count: 0,
+                reserved: Vec::new(),
+            },
+        )));
+        builder.push(add_data(
+            dat_file(0x0A, 0x0100, 2),
+            0x100,
+            vec![0; 0x80],
+            0x100,
+        ));
+        builder.add_directory("movie/ex2");
+        builder.add_file("boot/ffxivboot.exe", b"boot").unwrap();
+        builder.delete_file("boot/old.dll");
+        builder.push(remove_all(1));
+        builder
+    }
+
+    #[test]
+    fn test_plan_lists_operations_without_side_effects() {
+        let dir = TestDir::new("plan");
+        dir.write("sqpack/ex1/020100.win32.dat0", b"");
+        dir.write("movie/ex1/00000.bk2", b"");
+        dir.write("movie/ex1/00004.bk2", b"");
+
+        let game = &dir.game;
+        let mut config = dir.config();
+        let mut patch = open_patch(&patch_data());
+        let mut plan = patch.plan(&mut config).unwrap();
+
+        // The directory walk order isn't stable, so sort RemoveAll's matches before comparing
+        if let FileOperation::RemoveAll { files, .. } =
+            &mut plan.operations.last_mut().unwrap().operation
+        {
+            files.sort();
+        }
+
+        let dat = game.join("sqpack/ex1/0a0100.ps3.dat2");
+        let boot = game.join("boot/ffxivboot.exe");
+        let expected = vec![
+            FileOperation::Write {
+                path: dat.clone(),
+                offset: 0x100,
+                length: 0x80,
+            },
+            FileOperation::Wipe {
+                path: dat,
+                offset: 0x180,
+                length: 0x100,
+            },
+            FileOperation::CreateDirectory {
+                path: game.join("movie/ex2"),
+            },
+            FileOperation::CreateDirectory {
+                path: game.join("boot"),
+            },
+            FileOperation::TruncateFile {
+                path: boot.clone(),
+                length: 0,
+            },
+            FileOperation::Write {
+                path: boot,
+                offset: 0,
+                length: 4,
+            },
+            FileOperation::DeleteFile {
+                path: game.join("boot/old.dll"),
+            },
+            FileOperation::RemoveAll {
+                expansion_id: 1,
+                files: vec![
+                    game.join("movie/ex1/00004.bk2"),
+                    game.join("sqpack/ex1/020100.win32.dat0"),
+                ],
+            },
+        ];
+
+        assert_eq!(
+            plan.file_operations().cloned().collect::<Vec<_>>(),
+            expected
+        );
+        assert_eq!(plan.operations[0].chunk_index, 2);
+        assert_eq!(plan.bytes_written(), 0x184);
+
+        // Nothing was created and the caller's platform is unchanged
+        assert!(!game.join("boot").exists());
+        assert!(!game.join("movie/ex2").exists());
+        assert_eq!(config.platform, Platform::Win32);
+    }
+
+    fn index_add(main_id: u16, file_hash: u64) -> ZiPatchChunk {
+        ZiPatchChunk::Sqpk(SqpkCommand::Index(SqpkIndex {
+            index_command: IndexCommandKind::Add,
+            synonym: 0,
+            alignment: 0,
+            target_file: SqpackIndexFile {
+                sqpack: dat_file(main_id, 0, 0).sqpack,
+            },
+            file_hash,
+            block_offset: 0x20,
+            block_number: 0,
+        }))
+    }
+
+    #[test]
+    fn test_plan_follows_apply_options_and_index_commands() {
+        let dir = TestDir::new("plan-index");
+        let index = empty_index(IndexKind::Index1);
+        dir.write("sqpack/ffxiv/0a0000.win32.index", &index);
+
+        let mut builder = PatchBuilder::new("DIFF");
+        builder.push(index_add(0x0A, 0x1_0000_0002));
+        builder.push(index_add(0x0A, 0x1_0000_0003));
+        builder.push(ZiPatchChunk::ApplyOption(ApplyOptionChunk::new(
+            ApplyOptionKind::IgnoreMissing,
+            true,
+        )));
+        // The index doesn't exist, which the APLY chunk allows
+        builder.push(index_add(0x0B, 0x1_0000_0002));
+
+        let mut config = dir.config();
+        config.apply_index_commands = true;
+        let plan = open_patch(&builder).plan(&mut config).unwrap();
+
+        // Each command's length includes the entries added before it
+        let lengths: Vec<u64> = plan
+            .file_operations()
+            .filter_map(|operation| match operation {
+                FileOperation::TruncateFile { length, .. } => Some(*length),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(lengths, [0x800 + 16 + 16, 0x800 + 2 * 16 + 16]);
+
+        assert!(!config.ignore_missing);
+        assert!(config.pending_indexes.is_empty());
+        assert_eq!(dir.read("sqpack/ffxiv/0a0000.win32.index"), index);
+    }
+}
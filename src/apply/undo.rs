@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use super::journal::JournalRecord;
+use super::ApplyJournal;
+use crate::chunk::sqpk::OperationKind;
+use crate::chunk::{AddDirectoryChunk, DeleteDirectoryChunk, ZiPatchChunk};
+use crate::config::ZiPatchConfig;
+use crate::error::Result;
+use crate::generate::{add_file_command, file_command, file_header, ADD_FILE_COMMAND_SIZE};
+use crate::inspection::ZiPatchCommandCounts;
+use crate::writer::ZiPatchWriter;
+
+/// A command of the undo patch, planned up front so the FHDR counts are known before any
+/// data is read
+#[derive(Debug)]
+enum UndoStep {
+    AddDirectory(String),
+    DeleteFile(String),
+    /// Writes back `length` original bytes at `offset` of a file `file_size` bytes long
+    Restore {
+        path: String,
+        offset: u64,
+        length: u64,
+        file_size: u64,
+    },
+    DeleteDirectory(String),
+}
+
+impl ApplyJournal {
+    /// Writes a patch that restores everything recorded in the journal, and returns the
+    /// inner writer
+    ///
+    /// Call this after the apply, with the same configuration: the original bytes come from
+    /// the journal, and the rest of each file from its current contents. Removed directories
+    /// are recreated with ADIR chunks, then files the apply created are deleted and the
+    /// others written back with SqpkFile AddFile. Files whose length changed are rewritten
+    /// whole, as AddFile can only shorten a file by truncating it; other files only get
+    /// the ranges that were overwritten. Directories the apply created are removed last
+    /// with DELD chunks.
+    ///
+    /// Unlike the journal, the patch doesn't depend on this library's file format and can be
+    /// applied later like any other patch. It only restores the state the journal recorded,
+    /// so the game directory must not be changed by anything else in the meantime.
+    pub fn write_undo_patch<W: Write>(&mut self, config: &ZiPatchConfig, writer: W) -> Result<W> {
+        let steps = self.plan_undo(config)?;
+
+        let mut counts = ZiPatchCommandCounts {
+            total_commands: steps.len() as u32 + 1,
+            ..ZiPatchCommandCounts::default()
+        };
+        for step in &steps {
+            match step {
+                UndoStep::AddDirectory(_) => counts.add_directories += 1,
+                UndoStep::DeleteDirectory(_) => counts.delete_directories += 1,
+                UndoStep::DeleteFile(_) | UndoStep::Restore { .. } => {
+                    counts.sqpk_file_commands += 1
+                }
+            }
+        }
+
+        let mut writer = ZiPatchWriter::new(writer)?;
+        writer.write_chunk(&ZiPatchChunk::FileHeader(file_header("DIFF", counts, 0)))?;
+
+        for step in steps {
+            let chunk = match step {
+                UndoStep::AddDirectory(dir_name) => {
+                    ZiPatchChunk::AddDirectory(AddDirectoryChunk::new(dir_name))
+                }
+                UndoStep::DeleteFile(path) => {
+                    file_command(OperationKind::DeleteFile, &path, 0, 0, Vec::new())
+                }
+                UndoStep::Restore {
+                    path,
+                    offset,
+                    length,
+                    file_size,
+                } => {
+                    let data = self.original_data(config, &path, offset, length)?;
+                    add_file_command(&path, offset, file_size, &data)?
+                }
+                UndoStep::DeleteDirectory(dir_name) => {
+                    ZiPatchChunk::DeleteDirectory(DeleteDirectoryChunk::new(dir_name))
+                }
+            };
+            writer.write_chunk(&chunk)?;
+        }
+
+        writer.finish()
+    }
+
+    fn plan_undo(&self, config: &ZiPatchConfig) -> Result<Vec<UndoStep>> {
+        let mut files = Vec::new();
+        let mut ranges: HashMap<&str, Vec<(u64, u64)>> = HashMap::new();
+        let mut created_directories = Vec::new();
+        let mut removed_directories = Vec::new();
+
+        for record in &self.records {
+            match record {
+                JournalRecord::FileCreated { path } => files.push((path, None)),
+                JournalRecord::FileLength { path, length } => files.push((path, Some(*length))),
+                JournalRecord::Range {
+                    path,
+                    offset,
+                    length,
+                    ..
+                } => ranges
+                    .entry(path)
+                    .or_default()
+                    .push((*offset, offset + length)),
+                JournalRecord::DirectoryCreated { path } => created_directories.push(path),
+                JournalRecord::DirectoryRemoved { path } => removed_directories.push(path),
+            }
+        }
+
+        let mut steps = Vec::new();
+
+        // Directories were removed deepest first, so reversing recreates parents first
+        for path in removed_directories.into_iter().rev() {
+            if config
+                .target
+                .metadata(&config.resolve_path(path)?)?
+                .is_none()
+            {
+                steps.push(UndoStep::AddDirectory(path.clone()));
+            }
+        }
+
+        for (path, original_length) in files {
+            let current_length = config
+                .target
+                .metadata(&config.resolve_path(path)?)?
+                .map(|metadata| metadata.len);
+
+            let Some(original_length) = original_length else {
+                if current_length.is_some() {
+                    steps.push(UndoStep::DeleteFile(path.clone()));
+                }
+                continue;
+            };
+
+            let mut regions = merge_ranges(ranges.remove(path.as_str()).unwrap_or_default());
+            // AddFile truncates when writing at offset 0, so that also needs the whole file
+            if current_length != Some(original_length)
+                || regions.first().is_some_and(|&(start, _)| start == 0)
+            {
+                regions = vec![(0, original_length)];
+            }
+
+            for (start, end) in regions {
+                let mut offset = start;
+                loop {
+                    let length = (end - offset).min(ADD_FILE_COMMAND_SIZE as u64);
+                    steps.push(UndoStep::Restore {
+                        path: path.clone(),
+                        offset,
+                        length,
+                        file_size: original_length,
+                    });
+
+                    offset += length;
+                    if offset >= end {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Created directories were recorded outermost first
+        for path in created_directories.into_iter().rev() {
+            if config
+                .target
+                .metadata(&config.resolve_path(path)?)?
+                .is_some()
+            {
+                steps.push(UndoStep::DeleteDirectory(path.clone()));
+            }
+        }
+
+        Ok(steps)
+    }
+
+    /// Reads the original contents of a range: the file's current bytes, overlaid with the
+    /// data saved in the journal
+    fn original_data(
+        &mut self,
+        config: &ZiPatchConfig,
+        path: &str,
+        offset: u64,
+        length: u64,
+    ) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; length as usize];
+
+        let full_path = config.resolve_path(path)?;
+        if config
+            .target
+            .metadata(&full_path)?
+            .is_some_and(|metadata| metadata.is_file())
+        {
+            let mut current = Vec::new();
+            let mut reader = config.target.open(&full_path)?;
+            reader.seek(SeekFrom::Start(offset))?;
+            reader.take(length).read_to_end(&mut current)?;
+            data[..current.len()].copy_from_slice(&current);
+        }
+
+        // A range can be saved again after an earlier chunk changed it, so the oldest copy
+        // is written last
+        let end = offset + length;
+        for record in self.records.iter().rev() {
+            let JournalRecord::Range {
+                path: range_path,
+                offset: range_offset,
+                length: range_length,
+                data_position,
+            } = record
+            else {
+                continue;
+            };
+
+            let start = offset.max(*range_offset);
+            let range_end = end.min(range_offset + range_length);
+            if range_path != path || start >= range_end {
+                continue;
+            }
+
+            self.file
+                .seek(SeekFrom::Start(data_position + (start - range_offset)))?;
+            self.file
+                .read_exact(&mut data[(start - offset) as usize..(range_end - offset) as usize])?;
+        }
+
+        Ok(data)
+    }
+}
+
+/// Sorts ranges and merges the ones that overlap or touch
+fn merge_ranges(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort_unstable();
+
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::ApplyOptions;
+    use crate::chunk::SqpkCommand;
+    use crate::generate::PatchBuilder;
+    use crate::test_support::{add_data, dat_file, open_patch, TestDir};
+    use crate::ZiPatchFile;
+
+    fn patch() -> PatchBuilder {
+        let mut builder = PatchBuilder::new("DIFF");
+        builder.push(add_data(dat_file(0, 0, 0), 0x180, vec![0x22; 0x80], 0));
+        builder.add_file("boot/ffxivboot.exe", b"patched").unwrap();
+        builder
+            .add_file_range("boot/ffxivboot.cfg", 4, 7, b"XYZ")
+            .unwrap();
+        builder.add_file("boot/sub/new.exe", b"new file").unwrap();
+        builder.add_directory("movie/ex1");
+        builder.delete_directory("movie/old");
+        builder
+    }
+
+    #[test]
+    fn test_undo_patch_restores_previous_state() {
+        let dir = TestDir::new("undo");
+        dir.write("sqpack/ffxiv/000000.win32.dat0", &[0x11; 0x200]);
+        dir.write("boot/ffxivboot.exe", b"original boot");
+        dir.write("boot/ffxivboot.cfg", b"abcdefghij");
+        dir.create_dir("movie/old");
+
+        let undo_path = dir.root.join("undo.patch");
+        let options = ApplyOptions::new().undo_patch_path(&undo_path);
+        let mut config = dir.config();
+        open_patch(&patch()).apply(&mut config, &options).unwrap();
+
+        assert_eq!(dir.read("boot/ffxivboot.cfg"), b"abcdXYZhij");
+        assert!(dir.path("movie/ex1").is_dir());
+        assert!(!options.undo_journal_path().unwrap().exists());
+
+        let mut undo = ZiPatchFile::from_path(&undo_path).unwrap();
+        let actual_counts = undo.calculate_actual_counts().unwrap();
+        assert_eq!(undo.header().command_counts, Some(actual_counts));
+
+        // Only the overwritten part of a file that kept its length is written back
+        let dat_offsets: Vec<i64> = undo
+            .chunks()
+            .filter_map(|chunk| match chunk.unwrap() {
+                ZiPatchChunk::Sqpk(SqpkCommand::File(file))
+                    if file.target_file.relative_path.ends_with(".dat0") =>
+                {
+                    Some(file.file_offset)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(dat_offsets, [0x180]);
+
+        undo.apply(&mut config, &ApplyOptions::default()).unwrap();
+
+        assert_eq!(dir.read("sqpack/ffxiv/000000.win32.dat0"), [0x11; 0x200]);
+        assert_eq!(dir.read("boot/ffxivboot.exe"), b"original boot");
+        assert_eq!(dir.read("boot/ffxivboot.cfg"), b"abcdefghij");
+        assert!(!dir.path("boot/sub").exists());
+        assert!(!dir.path("movie/ex1").exists());
+        assert!(dir.path("movie/old").is_dir());
+    }
+
+    #[test]
+    fn test_merge_ranges() {
+        assert_eq!(
+            merge_ranges(vec![(10, 20), (0, 4), (15, 30), (30, 32), (40, 41)]),
+            [(0, 4), (10, 32), (40, 41)]
+        );
+    }
+}
@@ -1,8 +1,8 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
 use crate::config::ZiPatchConfig;
 use crate::error::Result;
-use crate::util::BinaryReaderExt;
+use crate::util::{BinaryReaderExt, BinaryWriterExt};
 
 /// Apply Free Space chunk (APFS)
 ///
@@ -30,6 +30,13 @@ impl ApplyFreeSpaceChunk {
         })
     }
 
+    /// Writes the chunk body (everything after the chunk type) to a writer
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_i64_be(self.unknown_field_a)?;
+        writer.write_i64_be(self.unknown_field_b)?;
+        Ok(())
+    }
+
     /// Applies the chunk (NOP - does nothing)
     pub fn apply(&self, _config: &mut ZiPatchConfig) -> Result<()> {
         // NOP on modern patchers
@@ -1,8 +1,8 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
 use crate::config::ZiPatchConfig;
 use crate::error::Result;
-use crate::util::BinaryReaderExt;
+use crate::util::{BinaryReaderExt, BinaryWriterExt};
 
 /// Apply Option chunk (APLY)
 ///
@@ -13,18 +13,19 @@ pub struct ApplyOptionChunk {
     pub option_kind: ApplyOptionKind,
     /// The value for the option
     pub option_value: bool,
+    /// Padding between the option kind and value, 4 in every observed patch
+    pub padding: u32,
 }
 
 /// Kind of apply option
-#[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ApplyOptionKind {
     /// Ignore missing files
-    IgnoreMissing = 1,
+    IgnoreMissing,
     /// Ignore old file mismatches
-    IgnoreOldMismatch = 2,
-    /// Unknown option kind
-    Unknown = 0,
+    IgnoreOldMismatch,
+    /// Unknown option kind, with its raw value
+    Unknown(u32),
 }
 
 impl ApplyOptionKind {
@@ -33,7 +34,16 @@ impl ApplyOptionKind {
         match value {
             1 => ApplyOptionKind::IgnoreMissing,
             2 => ApplyOptionKind::IgnoreOldMismatch,
-            _ => ApplyOptionKind::Unknown,
+            _ => ApplyOptionKind::Unknown(value),
+        }
+    }
+
+    /// Gets the u32 value of the option kind
+    pub fn as_u32(self) -> u32 {
+        match self {
+            ApplyOptionKind::IgnoreMissing => 1,
+            ApplyOptionKind::IgnoreOldMismatch => 2,
+            ApplyOptionKind::Unknown(value) => value,
         }
     }
 }
@@ -41,29 +51,41 @@ impl ApplyOptionKind {
 impl ApplyOptionChunk {
     pub const CHUNK_TYPE: &'static str = "APLY";
 
+    /// Creates an ApplyOptionChunk setting an option, with the usual padding
+    pub fn new(option_kind: ApplyOptionKind, option_value: bool) -> Self {
+        Self {
+            option_kind,
+            option_value,
+            padding: 4,
+        }
+    }
+
     /// Reads an ApplyOptionChunk from a reader
     pub fn read<R: Read>(reader: &mut R, _size: u32) -> Result<Self> {
         let option_kind_value = reader.read_u32_be()?;
         let option_kind = ApplyOptionKind::from_u32(option_kind_value);
 
-        // Read and discard padding (always 0x0000_0004 as far as observed)
-        let _padding = reader.read_bytes_required(4)?;
-
-        let value_raw = reader.read_u32_be()?;
-        let value = value_raw != 0;
+        // Padding, always 0x0000_0004 as far as observed
+        let padding = reader.read_u32_be()?;
 
-        // Only set the value if the option kind is valid
-        let option_value = match option_kind {
-            ApplyOptionKind::IgnoreMissing | ApplyOptionKind::IgnoreOldMismatch => value,
-            ApplyOptionKind::Unknown => false,
-        };
+        // Unknown options are kept as read, but never applied
+        let option_value = reader.read_u32_be()? != 0;
 
         Ok(Self {
             option_kind,
             option_value,
+            padding,
         })
     }
 
+    /// Writes the chunk body (everything after the chunk type) to a writer
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u32_be(self.option_kind.as_u32())?;
+        writer.write_u32_be(self.padding)?;
+        writer.write_u32_be(self.option_value as u32)?;
+        Ok(())
+    }
+
     /// Applies the chunk by setting the configuration option
     pub fn apply(&self, config: &mut ZiPatchConfig) -> Result<()> {
         match self.option_kind {
@@ -73,7 +95,7 @@ impl ApplyOptionChunk {
             ApplyOptionKind::IgnoreOldMismatch => {
                 config.ignore_old_mismatch = self.option_value;
             }
-            ApplyOptionKind::Unknown => {
+            ApplyOptionKind::Unknown(_) => {
                 // Do nothing for unknown options
             }
         }
@@ -87,7 +109,7 @@ impl std::fmt::Display for ApplyOptionChunk {
         let kind_str = match self.option_kind {
             ApplyOptionKind::IgnoreMissing => "IgnoreMissing",
             ApplyOptionKind::IgnoreOldMismatch => "IgnoreOldMismatch",
-            ApplyOptionKind::Unknown => "Unknown",
+            ApplyOptionKind::Unknown(_) => "Unknown",
         };
 
         write!(f, "{}:{}:{}", Self::CHUNK_TYPE, kind_str, self.option_value)
@@ -1,9 +1,9 @@
-use std::fs;
-use std::io::Read;
+use std::io::{Read, Write};
 
+use crate::apply::FileOperation;
 use crate::config::ZiPatchConfig;
-use crate::error::{Result, ZiPatchError};
-use crate::util::BinaryReaderExt;
+use crate::error::Result;
+use crate::util::{BinaryReaderExt, BinaryWriterExt};
 
 /// Delete Directory chunk (DELD)
 ///
@@ -12,29 +12,64 @@ use crate::util::BinaryReaderExt;
 pub struct DeleteDirectoryChunk {
     /// Name/path of the directory to delete
     pub dir_name: String,
+    /// Bytes after the name within its declared length, kept for re-serialization
+    ///
+    /// Usually the single null terminator. The declared length is the name's length plus
+    /// these.
+    pub dir_name_padding: Vec<u8>,
 }
 
 impl DeleteDirectoryChunk {
     pub const CHUNK_TYPE: &'static str = "DELD";
 
+    /// Creates a DeleteDirectoryChunk for a directory, with the usual null terminator
+    pub fn new<S: Into<String>>(dir_name: S) -> Self {
+        Self {
+            dir_name: dir_name.into(),
+            dir_name_padding: vec![0],
+        }
+    }
+
     /// Reads a DeleteDirectoryChunk from a reader
     pub fn read<R: Read>(reader: &mut R, _size: u32) -> Result<Self> {
         let dir_name_len = reader.read_u32_be()?;
-        let dir_name = reader.read_fixed_string(dir_name_len as usize)?;
+        let (dir_name, dir_name_padding) = reader.read_padded_string(dir_name_len as usize)?;
 
-        Ok(Self { dir_name })
+        Ok(Self {
+            dir_name,
+            dir_name_padding,
+        })
+    }
+
+    /// Writes the chunk body (everything after the chunk type) to a writer
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let dir_name_len = self.dir_name.len() + self.dir_name_padding.len();
+        writer.write_u32_be(dir_name_len as u32)?;
+        writer.write_all(self.dir_name.as_bytes())?;
+        writer.write_all(&self.dir_name_padding)?;
+        Ok(())
+    }
+
+    /// Lists the filesystem operations applying the chunk performs, without performing them
+    pub fn planned_operations(&self, config: &ZiPatchConfig) -> Result<Vec<FileOperation>> {
+        Ok(vec![FileOperation::RemoveDirectory {
+            path: config.resolve_path(&self.dir_name)?,
+        }])
     }
 
     /// Applies the chunk by deleting the directory
     pub fn apply(&self, config: &mut ZiPatchConfig) -> Result<()> {
-        let full_path = config.game_path().join(&self.dir_name);
+        let full_path = config.resolve_path(&self.dir_name)?;
+
+        config.verify_old_directory(&full_path)?;
 
         // Only delete if the directory exists
-        if full_path.exists() && full_path.is_dir() {
-            fs::remove_dir(&full_path).map_err(|e| ZiPatchError::FileOperationFailed {
-                path: full_path,
-                source: e,
-            })?;
+        if config
+            .target
+            .metadata(&full_path)?
+            .is_some_and(|metadata| metadata.is_dir)
+        {
+            config.target.remove_dir(&full_path)?;
         }
 
         Ok(())
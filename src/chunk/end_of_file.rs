@@ -1,21 +1,32 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
 use crate::config::ZiPatchConfig;
 use crate::error::Result;
+use crate::util::BinaryReaderExt;
 
 /// End of File chunk (EOF_)
 ///
-/// Marks the end of a patch file. No data is contained in this chunk.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct EndOfFileChunk;
+/// Marks the end of a patch file. No data has been observed in this chunk.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EndOfFileChunk {
+    /// Chunk body, empty as far as observed, kept for re-serialization
+    pub data: Vec<u8>,
+}
 
 impl EndOfFileChunk {
     pub const CHUNK_TYPE: &'static str = "EOF_";
 
     /// Reads an EndOfFileChunk from a reader
-    pub fn read<R: Read>(_reader: &mut R, _size: u32) -> Result<Self> {
-        // EOF chunk contains no data
-        Ok(Self)
+    pub fn read<R: Read>(reader: &mut R, size: u32) -> Result<Self> {
+        // EOF chunk contains no data as far as observed, but keep anything that is there
+        let data = reader.read_bytes_required(size as usize)?;
+        Ok(Self { data })
+    }
+
+    /// Writes the chunk body (everything after the chunk type) to a writer
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.data)?;
+        Ok(())
     }
 
     /// Applies the chunk (no-op for EOF)
@@ -1,9 +1,9 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
 use crate::config::ZiPatchConfig;
 use crate::error::{Result, ZiPatchError};
 use crate::inspection::ZiPatchCommandCounts;
-use crate::util::BinaryReaderExt;
+use crate::util::{BinaryReaderExt, BinaryWriterExt};
 
 /// File Header chunk (FHDR)
 ///
@@ -12,6 +12,11 @@ use crate::util::BinaryReaderExt;
 pub struct FileHeaderChunk {
     /// Version of the patch file format (2 or 3)
     pub version: u8,
+    /// The other bytes of the version field, of unknown meaning, kept for re-serialization
+    ///
+    /// The field is read as a little-endian u32 with `version` in its third byte, which is
+    /// always clear here.
+    pub version_reserved: u32,
     /// Patch type identifier (4-character string)
     pub patch_type: String,
     /// Number of entry files
@@ -28,16 +33,24 @@ pub struct FileHeaderChunk {
     pub minor_version: u32,
     /// Repository name (V3 only)
     pub repository_name: u32,
+    /// Unknown trailing data (0xB8 bytes for V3, 0x08 bytes for V2), kept for re-serialization
+    pub reserved: Vec<u8>,
 }
 
 impl FileHeaderChunk {
     pub const CHUNK_TYPE: &'static str = "FHDR";
 
+    /// Size of the fields preceding the reserved data in a V2 header
+    const V2_FIELDS_SIZE: u32 = 12;
+    /// Size of the fields preceding the reserved data in a V3 header
+    const V3_FIELDS_SIZE: u32 = 60;
+
     /// Reads a FileHeaderChunk from a reader
-    pub fn read<R: Read>(reader: &mut R, _size: u32) -> Result<Self> {
+    pub fn read<R: Read>(reader: &mut R, size: u32) -> Result<Self> {
         // Read version from upper 16 bits of a u32 (little-endian)
         let version_field = reader.read_u32_le()?;
         let version = (version_field >> 16) as u8;
+        let version_reserved = version_field & !0x00FF_0000;
 
         // Validate version
         if version != 2 && version != 3 {
@@ -90,11 +103,18 @@ impl FileHeaderChunk {
             (None, 0, 0, 0, 0, 0)
         };
 
-        // nb: 0xB8 bytes of unknown data for V3 and 0x08 bytes for V2, but we don't need to read it
-        // as we use the advance guard pattern to skip remaining bytes
+        // nb: 0xB8 bytes of unknown data for V3 and 0x08 bytes for V2; we don't need it for applying,
+        // but keep it around so the header can be written back unchanged
+        let fields_size = if version == 3 {
+            Self::V3_FIELDS_SIZE
+        } else {
+            Self::V2_FIELDS_SIZE
+        };
+        let reserved = reader.read_bytes_required(size.saturating_sub(fields_size) as usize)?;
 
         Ok(Self {
             version,
+            version_reserved,
             patch_type,
             entry_files,
             command_counts,
@@ -103,9 +123,37 @@ impl FileHeaderChunk {
             delete_data_size,
             minor_version,
             repository_name,
+            reserved,
         })
     }
 
+    /// Writes the chunk body (everything after the chunk type) to a writer
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u32_le(self.version_reserved & !0x00FF_0000 | (self.version as u32) << 16)?;
+        writer.write_fixed_string(&self.patch_type, 4)?;
+        writer.write_u32_be(self.entry_files)?;
+
+        if self.version == 3 {
+            writer.write_u32_be(self.add_directories)?;
+            writer.write_u32_be(self.delete_directories)?;
+            writer.write_u32_be(self.delete_data_size as u32)?;
+            writer.write_u32_be((self.delete_data_size >> 32) as u32)?;
+            writer.write_u32_be(self.minor_version)?;
+            writer.write_u32_be(self.repository_name)?;
+
+            let counts = self.command_counts.clone().unwrap_or_default();
+            writer.write_u32_be(counts.total_commands)?;
+            writer.write_u32_be(counts.sqpk_add_commands)?;
+            writer.write_u32_be(counts.sqpk_delete_commands)?;
+            writer.write_u32_be(counts.sqpk_expand_commands)?;
+            writer.write_u32_be(counts.sqpk_header_commands)?;
+            writer.write_u32_be(counts.sqpk_file_commands)?;
+        }
+
+        writer.write_all(&self.reserved)?;
+        Ok(())
+    }
+
     /// Applies the chunk (no-op for file header)
     pub fn apply(&self, _config: &mut ZiPatchConfig) -> Result<()> {
         // File header doesn't modify anything
@@ -16,11 +16,24 @@ pub use file_header::FileHeaderChunk;
 pub use sqpk::SqpkCommand;
 pub use xxxx::XXXXChunk;
 
-use std::io::{Read, Seek};
+use std::io::{Read, Seek, Write};
 
+use crate::apply::{ChunkInfo, FileOperation};
 use crate::config::ZiPatchConfig;
 use crate::error::{Result, ZiPatchError};
-use crate::util::{AdvanceGuard, BinaryReaderExt, ChecksumReader};
+use crate::util::{AdvanceGuard, BinaryReaderExt, BinaryWriterExt, ChecksumReader, Crc32};
+
+/// Every chunk type [`ZiPatchChunk::read`] understands
+pub(crate) const CHUNK_TYPES: [&str; 8] = [
+    FileHeaderChunk::CHUNK_TYPE,
+    ApplyOptionChunk::CHUNK_TYPE,
+    ApplyFreeSpaceChunk::CHUNK_TYPE,
+    AddDirectoryChunk::CHUNK_TYPE,
+    DeleteDirectoryChunk::CHUNK_TYPE,
+    "SQPK",
+    EndOfFileChunk::CHUNK_TYPE,
+    XXXXChunk::CHUNK_TYPE,
+];
 
 /// ZiPatch chunk variants
 #[derive(Debug, Clone)]
@@ -90,8 +103,52 @@ impl ZiPatchChunk {
         Ok(chunk)
     }
 
+    /// Writes the chunk to a writer, framed exactly as [`ZiPatchChunk::read`] expects
+    ///
+    /// The chunk is written as its big-endian body size, the chunk type, the body and a
+    /// big-endian CRC32 over the chunk type and body.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut body = Vec::new();
+        match self {
+            ZiPatchChunk::FileHeader(chunk) => chunk.write(&mut body)?,
+            ZiPatchChunk::ApplyOption(chunk) => chunk.write(&mut body)?,
+            ZiPatchChunk::ApplyFreeSpace(chunk) => chunk.write(&mut body)?,
+            ZiPatchChunk::AddDirectory(chunk) => chunk.write(&mut body)?,
+            ZiPatchChunk::DeleteDirectory(chunk) => chunk.write(&mut body)?,
+            ZiPatchChunk::Sqpk(chunk) => chunk.write(&mut body)?,
+            ZiPatchChunk::EndOfFile(chunk) => chunk.write(&mut body)?,
+            ZiPatchChunk::XXXX(chunk) => chunk.write(&mut body)?,
+        }
+
+        let size = u32::try_from(body.len()).map_err(|_| {
+            ZiPatchError::Custom(format!(
+                "{} chunk body of {} bytes is too large",
+                self.chunk_type(),
+                body.len()
+            ))
+        })?;
+
+        let mut crc32 = Crc32::new();
+        crc32.update(self.chunk_type().as_bytes());
+        crc32.update(&body);
+
+        writer.write_u32_be(size)?;
+        writer.write_chunk_type(self.chunk_type())?;
+        writer.write_all(&body)?;
+        writer.write_u32_be(crc32.finalize())?;
+
+        Ok(())
+    }
+
     /// Applies the chunk to the configuration
+    ///
+    /// Any chunk but an SqpkIndex command first writes out the index files earlier index
+    /// commands updated (see [`ZiPatchConfig::flush_indexes`]).
     pub fn apply(&mut self, config: &mut ZiPatchConfig) -> Result<()> {
+        if !matches!(self, ZiPatchChunk::Sqpk(SqpkCommand::Index(_))) {
+            config.flush_indexes()?;
+        }
+
         match self {
             ZiPatchChunk::FileHeader(chunk) => chunk.apply(config),
             ZiPatchChunk::ApplyOption(chunk) => chunk.apply(config),
@@ -104,11 +161,63 @@ impl ZiPatchChunk {
         }
     }
 
+    /// Lists the filesystem operations applying the chunk performs, without performing them
+    ///
+    /// Chunks that only change the configuration (APLY, SqpkTargetInfo) have no operations.
+    pub fn planned_operations(&self, config: &ZiPatchConfig) -> Result<Vec<FileOperation>> {
+        match self {
+            ZiPatchChunk::AddDirectory(chunk) => chunk.planned_operations(config),
+            ZiPatchChunk::DeleteDirectory(chunk) => chunk.planned_operations(config),
+            ZiPatchChunk::Sqpk(chunk) => chunk.planned_operations(config),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Applies the chunk, notifying the configuration's observer before and after
+    ///
+    /// Checks the configuration's cancellation token first. A [`ZiPatchError::Cancelled`]
+    /// error reports the chunk from `info` as the one to resume at.
+    pub fn apply_observed(&mut self, config: &mut ZiPatchConfig, info: &ChunkInfo) -> Result<()> {
+        let cancelled = |_: ZiPatchError| ZiPatchError::Cancelled {
+            chunks_applied: info.index,
+            resume_offset: info.offset,
+        };
+
+        if let Err(e) = config.check_cancelled() {
+            // Index commands before this chunk count as applied, so they must be written
+            config.flush_indexes()?;
+            return Err(cancelled(e));
+        }
+
+        if let Some(ref mut observer) = config.observer {
+            observer.on_chunk_start(info);
+        }
+
+        match self.apply(config) {
+            Err(e @ ZiPatchError::Cancelled { .. }) => return Err(cancelled(e)),
+            result => result?,
+        }
+
+        if let Some(ref mut observer) = config.observer {
+            observer.on_chunk_end(&info.completed());
+        }
+
+        Ok(())
+    }
+
     /// Checks if this is an EOF chunk
     pub fn is_eof(&self) -> bool {
         matches!(self, ZiPatchChunk::EndOfFile(_))
     }
 
+    /// Gets the SQPK command character, for SQPK chunks
+    pub fn sqpk_command(&self) -> Option<char> {
+        match self {
+            ZiPatchChunk::Sqpk(command) => Some(command.command_char()),
+            _ => None,
+        }
+    }
+
     /// Gets the chunk type string
     pub fn chunk_type(&self) -> &'static str {
         match self {
@@ -1,14 +1,18 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
+use crate::apply::FileOperation;
 use crate::config::ZiPatchConfig;
 use crate::error::Result;
-use crate::util::{BinaryReaderExt, SqpackDatFile};
+use crate::util::{BinaryReaderExt, BinaryWriterExt, SqpackDatFile};
 
 /// SQPK Add Data command ('A')
 ///
 /// Adds data blocks to .dat files
 #[derive(Debug, Clone)]
 pub struct SqpkAddData {
+    /// Alignment bytes before the target file, zero as far as observed, kept for
+    /// re-serialization
+    pub alignment: [u8; 3],
     /// Target .dat file
     pub target_file: SqpackDatFile,
     /// Block offset (shifted left by 7)
@@ -26,8 +30,8 @@ impl SqpkAddData {
 
     /// Reads an SqpkAddData from a reader
     pub fn read<R: Read>(reader: &mut R) -> Result<Self> {
-        // Read and discard 3 bytes of alignment
-        let _alignment = reader.read_bytes_required(3)?;
+        let mut alignment = [0u8; 3];
+        reader.read_exact(&mut alignment)?;
 
         let target_file = SqpackDatFile::read_from(reader)?;
 
@@ -39,6 +43,7 @@ impl SqpkAddData {
         let block_data = reader.read_bytes_required(block_number as usize)?;
 
         Ok(Self {
+            alignment,
             target_file,
             block_offset,
             block_number,
@@ -47,27 +52,64 @@ impl SqpkAddData {
         })
     }
 
+    /// Writes the command body (everything after the command character) to a writer
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.alignment)?;
+        self.target_file.write_to(writer)?;
+
+        writer.write_u32_be((self.block_offset >> 7) as u32)?;
+        writer.write_u32_be((self.block_number >> 7) as u32)?;
+        writer.write_u32_be((self.block_delete_number >> 7) as u32)?;
+
+        writer.write_all(&self.block_data)?;
+        Ok(())
+    }
+
+    /// Lists the filesystem operations applying the command performs, without performing them
+    pub fn planned_operations(&self, config: &ZiPatchConfig) -> Result<Vec<FileOperation>> {
+        let path = config.resolve_path(&self.target_file.get_file_name(config.platform))?;
+
+        let mut operations = vec![FileOperation::Write {
+            path: path.clone(),
+            offset: self.block_offset as u64,
+            length: self.block_number as u64,
+        }];
+
+        if self.block_delete_number > 0 {
+            operations.push(FileOperation::Wipe {
+                path,
+                offset: (self.block_offset + self.block_number) as u64,
+                length: self.block_delete_number as u64,
+            });
+        }
+
+        Ok(operations)
+    }
+
     /// Applies the command by writing block data and wiping deleted data
     pub fn apply(&mut self, config: &mut ZiPatchConfig) -> Result<()> {
         self.target_file.resolve_path(config.platform);
 
-        let game_path = config.game_path().to_path_buf();
-
-        if let Some(ref mut store) = config.store {
-            let file = self
-                .target_file
-                .sqex_file_mut()
-                .open_stream_with_store(store, &game_path, true, 5, 1)?;
-            file.write_from_offset(&self.block_data, self.block_offset)?;
-            file.wipe(self.block_delete_number as u64)?;
-        } else {
-            let mut file = self
-                .target_file
-                .sqex_file()
-                .open_stream(&game_path, true, 5, 1)?;
-            file.write_from_offset(&self.block_data, self.block_offset)?;
-            file.wipe(self.block_delete_number as u64)?;
-        }
+        let full_path = config.resolve_path(&self.target_file.sqex_file().relative_path)?;
+
+        // The target .dat must already exist
+        config.verify_old_file(&full_path)?;
+
+        let offset = self.block_offset as u64;
+        config
+            .target
+            .write_at(&full_path, offset, &self.block_data)?;
+        config.target.wipe_at(
+            &full_path,
+            offset + self.block_number as u64,
+            self.block_delete_number as u64,
+        )?;
+
+        config.report_write(
+            &full_path,
+            offset,
+            (self.block_number + self.block_delete_number) as u64,
+        );
 
         Ok(())
     }
@@ -1,20 +1,26 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
+use crate::apply::FileOperation;
 use crate::config::ZiPatchConfig;
 use crate::error::Result;
-use crate::util::{BinaryReaderExt, SqpackDatFile};
+use crate::util::{BinaryReaderExt, BinaryWriterExt, SqpackDatFile};
 
 /// SQPK Delete Data command ('D')
 ///
 /// Deletes data blocks from .dat files
 #[derive(Debug, Clone)]
 pub struct SqpkDeleteData {
+    /// Alignment bytes before the target file, zero as far as observed, kept for
+    /// re-serialization
+    pub alignment: [u8; 3],
     /// Target .dat file
     pub target_file: SqpackDatFile,
     /// Block offset (shifted left by 7)
     pub block_offset: i64,
     /// Block number
     pub block_number: u32,
+    /// Reserved field at the end of the command, kept for re-serialization
+    pub reserved: u32,
 }
 
 impl SqpkDeleteData {
@@ -22,51 +28,76 @@ impl SqpkDeleteData {
 
     /// Reads an SqpkDeleteData from a reader
     pub fn read<R: Read>(reader: &mut R) -> Result<Self> {
-        // Read and discard 3 bytes of alignment
-        let _alignment = reader.read_bytes_required(3)?;
+        let mut alignment = [0u8; 3];
+        reader.read_exact(&mut alignment)?;
 
         let target_file = SqpackDatFile::read_from(reader)?;
 
         let block_offset = (reader.read_u32_be()? as i64) << 7;
         let block_number = reader.read_u32_be()?;
 
-        // Read and discard reserved field
-        let _reserved = reader.read_u32_be()?;
+        let reserved = reader.read_u32_be()?;
 
         Ok(Self {
+            alignment,
             target_file,
             block_offset,
             block_number,
+            reserved,
         })
     }
 
+    /// Writes the command body (everything after the command character) to a writer
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.alignment)?;
+        self.target_file.write_to(writer)?;
+
+        writer.write_u32_be((self.block_offset >> 7) as u32)?;
+        writer.write_u32_be(self.block_number)?;
+        writer.write_u32_be(self.reserved)?;
+        Ok(())
+    }
+
+    /// Lists the filesystem operations applying the command performs, without performing them
+    pub fn planned_operations(&self, config: &ZiPatchConfig) -> Result<Vec<FileOperation>> {
+        let path = config.resolve_path(&self.target_file.get_file_name(config.platform))?;
+        let offset = self.block_offset as u64;
+
+        Ok(vec![
+            FileOperation::Wipe {
+                path: path.clone(),
+                offset,
+                length: (self.block_number as u64) << 7,
+            },
+            FileOperation::Write {
+                path,
+                offset,
+                length: SqpackDatFile::EMPTY_BLOCK_HEADER_SIZE,
+            },
+        ])
+    }
+
     /// Applies the command by writing an empty file block
     pub fn apply(&mut self, config: &mut ZiPatchConfig) -> Result<()> {
         self.target_file.resolve_path(config.platform);
 
-        let game_path = config.game_path().to_path_buf();
-
-        if let Some(ref mut store) = config.store {
-            let file = self
-                .target_file
-                .sqex_file_mut()
-                .open_stream_with_store(store, &game_path, true, 5, 1)?;
-            SqpackDatFile::write_empty_file_block_at(
-                file,
-                self.block_offset,
-                self.block_number as i64,
-            )?;
-        } else {
-            let mut file = self
-                .target_file
-                .sqex_file()
-                .open_stream(&game_path, true, 5, 1)?;
-            SqpackDatFile::write_empty_file_block_at(
-                &mut file,
-                self.block_offset,
-                self.block_number as i64,
-            )?;
-        }
+        let full_path = config.resolve_path(&self.target_file.sqex_file().relative_path)?;
+
+        // The target .dat must already exist
+        config.verify_old_file(&full_path)?;
+
+        SqpackDatFile::write_empty_file_block_to(
+            config.target.as_mut(),
+            &full_path,
+            self.block_offset,
+            self.block_number as i64,
+        )?;
+
+        config.report_write(
+            &full_path,
+            self.block_offset as u64,
+            (self.block_number as u64) << 7,
+        );
 
         Ok(())
     }
@@ -1,9 +1,9 @@
-use std::fs;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Read, Write};
 
+use crate::apply::FileOperation;
 use crate::config::ZiPatchConfig;
 use crate::error::Result;
-use crate::util::{BinaryReaderExt, SqexFile, SqpkCompressedBlock};
+use crate::util::{BinaryReaderExt, BinaryWriterExt, SqexFile, SqpkCompressedBlock};
 
 /// SQPK File command ('F')
 ///
@@ -12,41 +12,66 @@ use crate::util::{BinaryReaderExt, SqexFile, SqpkCompressedBlock};
 pub struct SqpkFile {
     /// Operation to perform
     pub operation: OperationKind,
+    /// Alignment bytes after the operation, zero as far as observed, kept for
+    /// re-serialization
+    pub alignment: [u8; 2],
     /// File offset
     pub file_offset: i64,
     /// File size
     pub file_size: i64,
     /// Expansion ID
     pub expansion_id: u16,
+    /// Padding bytes after the expansion ID, zero as far as observed, kept for
+    /// re-serialization
+    pub padding: [u8; 2],
     /// Target file
     pub target_file: SqexFile,
+    /// Bytes after the path within its declared length, kept for re-serialization
+    ///
+    /// Usually the single null terminator. The declared length is the path's length plus
+    /// these.
+    pub path_padding: Vec<u8>,
     /// Compressed data blocks (only for AddFile operation)
     pub compressed_data: Vec<SqpkCompressedBlock>,
+    /// Data after the path of an unknown operation, kept for re-serialization
+    pub unknown_data: Vec<u8>,
 }
 
 /// Kind of file operation
-#[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OperationKind {
     /// Add a file
-    AddFile = b'A',
+    AddFile,
     /// Remove all files in an expansion
-    RemoveAll = b'R',
+    RemoveAll,
     /// Delete a specific file (rarely seen)
-    DeleteFile = b'D',
+    DeleteFile,
     /// Make directory tree (rarely seen)
-    MakeDirTree = b'M',
+    MakeDirTree,
+    /// Unknown operation, with its raw value. Never applied.
+    Unknown(u8),
 }
 
 impl OperationKind {
     /// Creates an OperationKind from a u8 value
-    pub fn from_u8(value: u8) -> Option<Self> {
+    pub fn from_u8(value: u8) -> Self {
         match value {
-            b'A' => Some(OperationKind::AddFile),
-            b'R' => Some(OperationKind::RemoveAll),
-            b'D' => Some(OperationKind::DeleteFile),
-            b'M' => Some(OperationKind::MakeDirTree),
-            _ => None,
+            b'A' => OperationKind::AddFile,
+            b'R' => OperationKind::RemoveAll,
+            b'D' => OperationKind::DeleteFile,
+            b'M' => OperationKind::MakeDirTree,
+            _ => OperationKind::Unknown(value),
+        }
+    }
+
+    /// Gets the u8 value of the operation
+    pub fn as_u8(self) -> u8 {
+        match self {
+            OperationKind::AddFile => b'A',
+            OperationKind::RemoveAll => b'R',
+            OperationKind::DeleteFile => b'D',
+            OperationKind::MakeDirTree => b'M',
+            OperationKind::Unknown(value) => value,
         }
     }
 }
@@ -62,10 +87,10 @@ impl SqpkFile {
             buf[0]
         };
 
-        let operation = OperationKind::from_u8(operation_byte).unwrap_or(OperationKind::AddFile);
+        let operation = OperationKind::from_u8(operation_byte);
 
-        // Read and discard 2 bytes of alignment
-        let _alignment = reader.read_bytes_required(2)?;
+        let mut alignment = [0u8; 2];
+        reader.read_exact(&mut alignment)?;
 
         let file_offset = reader.read_i64_be()?;
         let file_size = reader.read_i64_be()?;
@@ -73,12 +98,14 @@ impl SqpkFile {
         let path_len = reader.read_u32_be()?;
         let expansion_id = reader.read_u16_be()?;
 
-        // Read and discard 2 bytes of padding
-        let _padding = reader.read_bytes_required(2)?;
+        let mut padding = [0u8; 2];
+        reader.read_exact(&mut padding)?;
 
-        let target_file = SqexFile::new(reader.read_fixed_string(path_len as usize)?);
+        let (path, path_padding) = reader.read_padded_string(path_len as usize)?;
+        let target_file = SqexFile::new(path);
 
         let mut compressed_data = Vec::new();
+        let mut unknown_data = Vec::new();
 
         // Calculate bytes consumed so far
         let header_bytes = 1 + 2 + 8 + 8 + 4 + 2 + 2 + path_len;
@@ -93,96 +120,184 @@ impl SqpkFile {
                 bytes_remaining = bytes_remaining.saturating_sub(block_bytes);
                 compressed_data.push(block);
             }
+        } else if let OperationKind::Unknown(_) = operation {
+            reader
+                .take(bytes_remaining)
+                .read_to_end(&mut unknown_data)?;
         }
 
         Ok(Self {
             operation,
+            alignment,
             file_offset,
             file_size,
             expansion_id,
+            padding,
             target_file,
+            path_padding,
             compressed_data,
+            unknown_data,
         })
     }
 
+    /// Writes the command body (everything after the command character) to a writer
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&[self.operation.as_u8()])?;
+        writer.write_all(&self.alignment)?;
+
+        writer.write_i64_be(self.file_offset)?;
+        writer.write_i64_be(self.file_size)?;
+
+        let path_len = self.target_file.relative_path.len() + self.path_padding.len();
+        writer.write_u32_be(path_len as u32)?;
+        writer.write_u16_be(self.expansion_id)?;
+        writer.write_all(&self.padding)?;
+        writer.write_all(self.target_file.relative_path.as_bytes())?;
+        writer.write_all(&self.path_padding)?;
+
+        for block in &self.compressed_data {
+            block.write_to(writer)?;
+        }
+        writer.write_all(&self.unknown_data)?;
+
+        Ok(())
+    }
+
+    /// Gets the total number of bytes the compressed blocks decompress to
+    pub fn decompressed_length(&self) -> u64 {
+        self.compressed_data
+            .iter()
+            .map(|block| block.decompressed_size as u64)
+            .sum()
+    }
+
     /// Filter for RemoveAll operation - excludes .var files and specific .bk2 files
-    fn remove_all_filter(file_path: &str) -> bool {
+    pub(crate) fn remove_all_filter(file_path: &str) -> bool {
         let exclusions = [".var", "00000.bk2", "00001.bk2", "00002.bk2", "00003.bk2"];
         !exclusions.iter().any(|ext| file_path.ends_with(ext))
     }
 
-    /// Applies the command by performing the file operation
-    pub fn apply(&mut self, config: &mut ZiPatchConfig) -> Result<()> {
-        match self.operation {
+    /// Lists the filesystem operations applying the command performs, without performing them
+    pub fn planned_operations(&self, config: &ZiPatchConfig) -> Result<Vec<FileOperation>> {
+        let operations = match self.operation {
             OperationKind::AddFile => {
-                let game_path = config.game_path().to_path_buf();
+                let path = config.resolve_path(&self.target_file.relative_path)?;
+                let mut operations = Vec::new();
 
-                // Create directory tree
-                self.target_file.create_directory_tree(&game_path)?;
+                if let Some(parent) = path.parent() {
+                    operations.push(FileOperation::CreateDirectory {
+                        path: parent.to_path_buf(),
+                    });
+                }
+                if self.file_offset == 0 {
+                    operations.push(FileOperation::TruncateFile {
+                        path: path.clone(),
+                        length: 0,
+                    });
+                }
+                operations.push(FileOperation::Write {
+                    path,
+                    offset: self.file_offset as u64,
+                    length: self.decompressed_length(),
+                });
 
-                if let Some(ref mut store) = config.store {
-                    // Use store
-                    let file_stream = self
-                        .target_file
-                        .open_stream_with_store(store, &game_path, true, 5, 1)?;
+                operations
+            }
 
-                    // If starting at offset 0, truncate the file
-                    if self.file_offset == 0 {
-                        file_stream.get_mut().set_len(0)?;
-                    }
+            OperationKind::RemoveAll => {
+                let files = config
+                    .expansion_files(self.expansion_id)?
+                    .into_iter()
+                    .filter(|path| path.to_str().is_some_and(Self::remove_all_filter))
+                    .collect();
+
+                vec![FileOperation::RemoveAll {
+                    expansion_id: self.expansion_id,
+                    files,
+                }]
+            }
 
-                    // Seek to the file offset
-                    file_stream.seek(SeekFrom::Start(self.file_offset as u64))?;
+            OperationKind::DeleteFile => vec![FileOperation::DeleteFile {
+                path: config.resolve_path(&self.target_file.relative_path)?,
+            }],
 
-                    // Decompress all blocks into the file
-                    for block in &self.compressed_data {
-                        block.decompress_into(file_stream)?;
-                    }
-                } else {
-                    // Open directly
-                    let mut file_stream = self.target_file.open_stream(&game_path, true, 5, 1)?;
+            OperationKind::MakeDirTree => vec![FileOperation::CreateDirectory {
+                path: config.resolve_path(&self.target_file.relative_path)?,
+            }],
 
-                    // If starting at offset 0, truncate the file
-                    if self.file_offset == 0 {
-                        file_stream.get_mut().set_len(0)?;
-                    }
+            OperationKind::Unknown(_) => Vec::new(),
+        };
 
-                    // Seek to the file offset
-                    file_stream.seek(SeekFrom::Start(self.file_offset as u64))?;
+        Ok(operations)
+    }
 
-                    // Decompress all blocks into the file
-                    for block in &self.compressed_data {
-                        block.decompress_into(&mut file_stream)?;
-                    }
+    /// Applies the command by performing the file operation
+    pub fn apply(&mut self, config: &mut ZiPatchConfig) -> Result<()> {
+        match self.operation {
+            OperationKind::AddFile => {
+                let full_path = config.resolve_path(&self.target_file.relative_path)?;
+
+                // Continuing a file written by an earlier command, so it must already be there
+                if self.file_offset != 0 {
+                    config.verify_old_file(&full_path)?;
                 }
+
+                // Create directory tree
+                if let Some(parent) = full_path.parent() {
+                    config.target.create_dir_all(parent)?;
+                }
+
+                // If starting at offset 0, truncate the file
+                if self.file_offset == 0 {
+                    config.target.set_len(&full_path, 0)?;
+                }
+
+                // Decompress all blocks into the file
+                let mut offset = self.file_offset as u64;
+                let mut data = Vec::new();
+                for block in &self.compressed_data {
+                    config.check_cancelled()?;
+
+                    data.clear();
+                    block.decompress_into(&mut data)?;
+                    config.target.write_at(&full_path, offset, &data)?;
+                    offset += data.len() as u64;
+                }
+
+                config.report_write(
+                    &full_path,
+                    self.file_offset as u64,
+                    offset - self.file_offset as u64,
+                );
             }
 
             OperationKind::RemoveAll => {
-                // Get all files for the expansion
-                let files =
-                    SqexFile::get_all_expansion_files(config.game_path(), self.expansion_id)?;
+                // Delete all files of the expansion that pass the filter
+                for file_path in config.expansion_files(self.expansion_id)? {
+                    config.check_cancelled()?;
 
-                // Delete all files that pass the filter
-                for file_path in files {
                     if let Some(path_str) = file_path.to_str() {
                         if Self::remove_all_filter(path_str) {
-                            let _ = fs::remove_file(&file_path); // Ignore errors
+                            let _ = config.target.remove_file(&file_path); // Ignore errors
                         }
                     }
                 }
             }
 
             OperationKind::DeleteFile => {
-                let full_path = config.game_path().join(&self.target_file.relative_path);
-                if full_path.exists() {
-                    fs::remove_file(&full_path)?;
+                let full_path = config.resolve_path(&self.target_file.relative_path)?;
+                if config.target.metadata(&full_path)?.is_some() {
+                    config.target.remove_file(&full_path)?;
                 }
             }
 
             OperationKind::MakeDirTree => {
-                let full_path = config.game_path().join(&self.target_file.relative_path);
-                fs::create_dir_all(&full_path)?;
+                let full_path = config.resolve_path(&self.target_file.relative_path)?;
+                config.target.create_dir_all(&full_path)?;
             }
+
+            // Not known to do anything, so left alone
+            OperationKind::Unknown(_) => {}
         }
 
         Ok(())
@@ -1,8 +1,10 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
+use crate::apply::FileOperation;
 use crate::config::ZiPatchConfig;
-use crate::error::Result;
-use crate::util::{BinaryReaderExt, SqpackDatFile, SqpackIndexFile};
+use crate::error::{Result, ZiPatchError};
+use crate::sqpack::{SqpackDataHeader, SqpackHeader, SqpackIndexHeader, SqpackVersionHeader};
+use crate::util::{BinaryReaderExt, BinaryWriterExt, SqpackDatFile, SqpackIndexFile};
 
 /// SQPK Header command ('H')
 ///
@@ -13,6 +15,8 @@ pub struct SqpkHeader {
     pub file_kind: TargetFileKind,
     /// Header kind (Version, Index, or Data)
     pub header_kind: TargetHeaderKind,
+    /// Alignment byte after the kinds, zero as far as observed, kept for re-serialization
+    pub alignment: u8,
     /// Target file (either SqpackDatFile or SqpackIndexFile)
     pub target_file: TargetFile,
     /// Header data (1024 bytes)
@@ -20,46 +24,68 @@ pub struct SqpkHeader {
 }
 
 /// Kind of target file
-#[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TargetFileKind {
     /// .dat file
-    Dat = b'D',
+    Dat,
     /// .index file
-    Index = b'I',
+    Index,
+    /// Unknown file kind, with its raw value. The target is read as a dat file.
+    Unknown(u8),
 }
 
 impl TargetFileKind {
     /// Creates a TargetFileKind from a u8 value
-    pub fn from_u8(value: u8) -> Option<Self> {
+    pub fn from_u8(value: u8) -> Self {
         match value {
-            b'D' => Some(TargetFileKind::Dat),
-            b'I' => Some(TargetFileKind::Index),
-            _ => None,
+            b'D' => TargetFileKind::Dat,
+            b'I' => TargetFileKind::Index,
+            _ => TargetFileKind::Unknown(value),
+        }
+    }
+
+    /// Gets the u8 value of the file kind
+    pub fn as_u8(self) -> u8 {
+        match self {
+            TargetFileKind::Dat => b'D',
+            TargetFileKind::Index => b'I',
+            TargetFileKind::Unknown(value) => value,
         }
     }
 }
 
 /// Kind of header to update
-#[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TargetHeaderKind {
     /// Version header
-    Version = b'V',
+    Version,
     /// Index header
-    Index = b'I',
+    Index,
     /// Data header
-    Data = b'D',
+    Data,
+    /// Unknown header kind, with its raw value. Written after the version header, like the
+    /// index and data headers.
+    Unknown(u8),
 }
 
 impl TargetHeaderKind {
     /// Creates a TargetHeaderKind from a u8 value
-    pub fn from_u8(value: u8) -> Option<Self> {
+    pub fn from_u8(value: u8) -> Self {
         match value {
-            b'V' => Some(TargetHeaderKind::Version),
-            b'I' => Some(TargetHeaderKind::Index),
-            b'D' => Some(TargetHeaderKind::Data),
-            _ => None,
+            b'V' => TargetHeaderKind::Version,
+            b'I' => TargetHeaderKind::Index,
+            b'D' => TargetHeaderKind::Data,
+            _ => TargetHeaderKind::Unknown(value),
+        }
+    }
+
+    /// Gets the u8 value of the header kind
+    pub fn as_u8(self) -> u8 {
+        match self {
+            TargetHeaderKind::Version => b'V',
+            TargetHeaderKind::Index => b'I',
+            TargetHeaderKind::Data => b'D',
+            TargetHeaderKind::Unknown(value) => value,
         }
     }
 }
@@ -89,19 +115,18 @@ impl SqpkHeader {
             buf[0]
         };
 
-        // Read and discard alignment byte
-        {
+        let alignment = {
             let mut buf = [0u8; 1];
             reader.read_exact(&mut buf)?;
+            buf[0]
         };
 
-        let file_kind = TargetFileKind::from_u8(file_kind_byte).unwrap_or(TargetFileKind::Dat);
-        let header_kind =
-            TargetHeaderKind::from_u8(header_kind_byte).unwrap_or(TargetHeaderKind::Version);
+        let file_kind = TargetFileKind::from_u8(file_kind_byte);
+        let header_kind = TargetHeaderKind::from_u8(header_kind_byte);
 
         let target_file = match file_kind {
-            TargetFileKind::Dat => TargetFile::Dat(SqpackDatFile::read_from(reader)?),
             TargetFileKind::Index => TargetFile::Index(SqpackIndexFile::read_from(reader)?),
+            _ => TargetFile::Dat(SqpackDatFile::read_from(reader)?),
         };
 
         let header_data = reader.read_bytes_required(Self::HEADER_SIZE)?;
@@ -109,48 +134,94 @@ impl SqpkHeader {
         Ok(Self {
             file_kind,
             header_kind,
+            alignment,
             target_file,
             header_data,
         })
     }
 
+    /// Writes the command body (everything after the command character) to a writer
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&[
+            self.file_kind.as_u8(),
+            self.header_kind.as_u8(),
+            self.alignment,
+        ])?;
+
+        match &self.target_file {
+            TargetFile::Dat(dat) => dat.write_to(writer)?,
+            TargetFile::Index(index) => index.write_to(writer)?,
+        }
+
+        // The header is always exactly HEADER_SIZE bytes
+        let used = self.header_data.len().min(Self::HEADER_SIZE);
+        writer.write_all(&self.header_data[..used])?;
+        writer.write_zeros(Self::HEADER_SIZE - used)?;
+        Ok(())
+    }
+
+    /// Parses the header data according to the header kind
+    pub fn parsed(&self) -> Result<SqpackHeader> {
+        Ok(match self.header_kind {
+            TargetHeaderKind::Version => {
+                SqpackHeader::Version(SqpackVersionHeader::from_bytes(&self.header_data)?)
+            }
+            TargetHeaderKind::Index => {
+                SqpackHeader::Index(SqpackIndexHeader::from_bytes(&self.header_data)?)
+            }
+            TargetHeaderKind::Data => {
+                SqpackHeader::Data(SqpackDataHeader::from_bytes(&self.header_data)?)
+            }
+            TargetHeaderKind::Unknown(value) => {
+                return Err(ZiPatchError::InvalidSqpackFile(format!(
+                    "unknown header kind {:#04X}",
+                    value
+                )))
+            }
+        })
+    }
+
+    /// Lists the filesystem operations applying the command performs, without performing them
+    pub fn planned_operations(&self, config: &ZiPatchConfig) -> Result<Vec<FileOperation>> {
+        let file_name = match &self.target_file {
+            TargetFile::Dat(dat_file) => dat_file.get_file_name(config.platform),
+            TargetFile::Index(index_file) => index_file.get_file_name(config.platform),
+        };
+        let offset = match self.header_kind {
+            TargetHeaderKind::Version => 0,
+            _ => Self::HEADER_SIZE as u64,
+        };
+
+        Ok(vec![FileOperation::Write {
+            path: config.resolve_path(&file_name)?,
+            offset,
+            length: self.header_data.len() as u64,
+        }])
+    }
+
     /// Applies the command by writing the header data
     pub fn apply(&mut self, config: &mut ZiPatchConfig) -> Result<()> {
         let offset = match self.header_kind {
             TargetHeaderKind::Version => 0,
-            _ => Self::HEADER_SIZE as i64,
+            _ => Self::HEADER_SIZE as u64,
         };
 
-        let game_path = config.game_path().to_path_buf();
-
-        match &mut self.target_file {
+        let relative_path = match &mut self.target_file {
             TargetFile::Dat(dat_file) => {
                 dat_file.resolve_path(config.platform);
-
-                if let Some(ref mut store) = config.store {
-                    let file = dat_file
-                        .sqex_file_mut()
-                        .open_stream_with_store(store, &game_path, true, 5, 1)?;
-                    file.write_from_offset(&self.header_data, offset)?;
-                } else {
-                    let mut file = dat_file.sqex_file().open_stream(&game_path, true, 5, 1)?;
-                    file.write_from_offset(&self.header_data, offset)?;
-                }
+                &dat_file.sqex_file().relative_path
             }
             TargetFile::Index(index_file) => {
                 index_file.resolve_path(config.platform);
-
-                if let Some(ref mut store) = config.store {
-                    let file = index_file
-                        .sqex_file_mut()
-                        .open_stream_with_store(store, &game_path, true, 5, 1)?;
-                    file.write_from_offset(&self.header_data, offset)?;
-                } else {
-                    let mut file = index_file.sqex_file().open_stream(&game_path, true, 5, 1)?;
-                    file.write_from_offset(&self.header_data, offset)?;
-                }
+                &index_file.sqex_file().relative_path
             }
-        }
+        };
+        let full_path = config.resolve_path(relative_path)?;
+
+        config
+            .target
+            .write_at(&full_path, offset, &self.header_data)?;
+        config.report_write(full_path, offset, self.header_data.len() as u64);
 
         Ok(())
     }
@@ -173,3 +244,58 @@ impl std::fmt::Display for SqpkHeader {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Platform;
+    use crate::sqpack::{SqpackFileType, SQPACK_HEADER_SIZE};
+    use crate::util::{SqexFile, SqpackFile};
+
+    fn header(header_kind: TargetHeaderKind, header_data: Vec<u8>) -> SqpkHeader {
+        SqpkHeader {
+            file_kind: TargetFileKind::Dat,
+            header_kind,
+            alignment: 0,
+            target_file: TargetFile::Dat(SqpackDatFile {
+                sqpack: SqpackFile {
+                    main_id: 0,
+                    sub_id: 0,
+                    file_id: 0,
+                    sqex_file: SqexFile::default(),
+                },
+            }),
+            header_data,
+        }
+    }
+
+    #[test]
+    fn test_parsed_follows_header_kind() {
+        let version = SqpackVersionHeader {
+            platform: Platform::Win32,
+            size: 0x400,
+            version: 1,
+            file_type: SqpackFileType::Data,
+            sha1: [0; 20],
+            raw: [0; SQPACK_HEADER_SIZE],
+        };
+        let parsed = header(TargetHeaderKind::Version, version.to_bytes().to_vec())
+            .parsed()
+            .unwrap();
+        assert_eq!(parsed, SqpackHeader::Version(version));
+
+        let mut data = vec![0u8; SqpkHeader::HEADER_SIZE];
+        data[0x10] = 3;
+        match header(TargetHeaderKind::Data, data).parsed().unwrap() {
+            SqpackHeader::Data(data) => assert_eq!(data.spanned_dat, 3),
+            other => panic!("unexpected header {:?}", other),
+        }
+
+        assert!(header(TargetHeaderKind::Index, vec![0; 16])
+            .parsed()
+            .is_err());
+        assert!(header(TargetHeaderKind::Unknown(b'X'), vec![0; 1024])
+            .parsed()
+            .is_err());
+    }
+}
@@ -1,45 +1,64 @@
-use std::io::Read;
+use std::collections::btree_map::Entry;
+use std::io::{Read, Write};
+use std::path::PathBuf;
 
+use crate::apply::FileOperation;
 use crate::config::ZiPatchConfig;
 use crate::error::Result;
-use crate::util::{BinaryReaderExt, SqpackIndexFile};
+use crate::sqpack::{IndexEntry, IndexKind, PathResolver, SqpackIndex};
+use crate::util::{BinaryReaderExt, BinaryWriterExt, SqpackIndexFile};
 
 /// SQPK Index command ('I')
 ///
-/// This is a NOP (no operation) on modern patchers.
+/// Adds or removes a hash table entry of an index file. The official patcher ignores these
+/// commands; they are only applied when
+/// [`ZiPatchConfig::apply_index_commands`](crate::ZiPatchConfig::apply_index_commands) is set.
 #[derive(Debug, Clone)]
 pub struct SqpkIndex {
     /// Index command kind (Add or Delete)
     pub index_command: IndexCommandKind,
-    /// Whether this is a synonym
-    pub is_synonym: bool,
+    /// Synonym flag, non-zero for synonyms, kept raw for re-serialization
+    pub synonym: u8,
+    /// Alignment byte after the synonym flag, zero as far as observed, kept for
+    /// re-serialization
+    pub alignment: u8,
     /// Target index file
     pub target_file: SqpackIndexFile,
     /// File hash
     pub file_hash: u64,
-    /// Block offset
+    /// Offset of the file in its dat file, in 128-byte blocks
     pub block_offset: u32,
-    /// Block number (purpose unknown)
+    /// Number of the dat file holding the file
     pub block_number: u32,
 }
 
 /// Kind of index command
-#[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IndexCommandKind {
     /// Add to index
-    Add = b'A',
+    Add,
     /// Delete from index
-    Delete = b'D',
+    Delete,
+    /// Unknown command kind, with its raw value. Never applied.
+    Unknown(u8),
 }
 
 impl IndexCommandKind {
     /// Creates an IndexCommandKind from a u8 value
-    pub fn from_u8(value: u8) -> Option<Self> {
+    pub fn from_u8(value: u8) -> Self {
         match value {
-            b'A' => Some(IndexCommandKind::Add),
-            b'D' => Some(IndexCommandKind::Delete),
-            _ => None,
+            b'A' => IndexCommandKind::Add,
+            b'D' => IndexCommandKind::Delete,
+            _ => IndexCommandKind::Unknown(value),
+        }
+    }
+
+    /// Gets the u8 value of the command kind
+    pub fn as_u8(self) -> u8 {
+        match self {
+            IndexCommandKind::Add => b'A',
+            IndexCommandKind::Delete => b'D',
+            IndexCommandKind::Unknown(value) => value,
         }
     }
 }
@@ -55,19 +74,18 @@ impl SqpkIndex {
             buf[0]
         };
 
-        let index_command =
-            IndexCommandKind::from_u8(index_command_byte).unwrap_or(IndexCommandKind::Add);
+        let index_command = IndexCommandKind::from_u8(index_command_byte);
 
-        let is_synonym = {
+        let synonym = {
             let mut buf = [0u8; 1];
             reader.read_exact(&mut buf)?;
-            buf[0] != 0
+            buf[0]
         };
 
-        // Read and discard alignment byte
-        {
+        let alignment = {
             let mut buf = [0u8; 1];
             reader.read_exact(&mut buf)?;
+            buf[0]
         };
 
         let target_file = SqpackIndexFile::read_from(reader)?;
@@ -77,7 +95,8 @@ impl SqpkIndex {
 
         Ok(Self {
             index_command,
-            is_synonym,
+            synonym,
+            alignment,
             target_file,
             file_hash,
             block_offset,
@@ -85,11 +104,149 @@ impl SqpkIndex {
         })
     }
 
-    /// Applies the command (NOP - does nothing)
-    pub fn apply(&self, _config: &mut ZiPatchConfig) -> Result<()> {
-        // NOP on modern patchers
+    /// Writes the command body (everything after the command character) to a writer
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&[self.index_command.as_u8(), self.synonym, self.alignment])?;
+        self.target_file.write_to(writer)?;
+        writer.write_u64_be(self.file_hash)?;
+        writer.write_u32_be(self.block_offset)?;
+        writer.write_u32_be(self.block_number)?;
+        Ok(())
+    }
+
+    /// Whether the command's entry is a synonym
+    pub fn is_synonym(&self) -> bool {
+        self.synonym != 0
+    }
+
+    /// Whether the command is of an unknown kind, which is never applied
+    fn is_unknown(&self) -> bool {
+        matches!(self.index_command, IndexCommandKind::Unknown(_))
+    }
+
+    /// Gets the kind of index the command targets
+    pub fn index_kind(&self) -> IndexKind {
+        IndexKind::from_file_id(self.target_file.sqpack.file_id)
+    }
+
+    /// Resolves the command's file hash to a game path, if the resolver knows it
+    pub fn resolve_path<'a>(&self, resolver: &'a PathResolver) -> Option<&'a str> {
+        resolver.resolve(self.file_hash, self.index_kind())
+    }
+
+    /// Lists the filesystem operations applying the command performs, without performing them
+    ///
+    /// Rewriting an index changes its length, so the whole file is listed as rewritten. The
+    /// length follows the index commands already applied to the configuration but not yet
+    /// written out.
+    pub fn planned_operations(&self, config: &ZiPatchConfig) -> Result<Vec<FileOperation>> {
+        if !config.apply_index_commands || self.is_unknown() {
+            return Ok(Vec::new());
+        }
+
+        let Some(path) = self.index_path(config)? else {
+            return Ok(Vec::new());
+        };
+
+        let mut index = match config.pending_indexes.get(&path) {
+            Some(index) => index.clone(),
+            None => SqpackIndex::read_from(&mut config.target.open(&path)?, self.index_kind())?,
+        };
+        self.update(&mut index);
+        let length = index.serialized_len() as u64;
+
+        Ok(vec![
+            FileOperation::TruncateFile {
+                path: path.clone(),
+                length,
+            },
+            FileOperation::Write {
+                path,
+                offset: 0,
+                length,
+            },
+        ])
+    }
+
+    /// Applies the command to the index file's hash and synonym tables
+    ///
+    /// Does nothing unless the configuration's `apply_index_commands` flag is set. The index
+    /// is read once and kept in the configuration, so consecutive commands for the same file
+    /// don't rewrite it each time; it's written out by
+    /// [`ZiPatchConfig::flush_indexes`](crate::ZiPatchConfig::flush_indexes), which applying
+    /// the next chunk of another kind does.
+    pub fn apply(&self, config: &mut ZiPatchConfig) -> Result<()> {
+        if !config.apply_index_commands || self.is_unknown() {
+            return Ok(());
+        }
+
+        let Some(path) = self.index_path(config)? else {
+            return Ok(());
+        };
+
+        let index = match config.pending_indexes.entry(path) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let index = SqpackIndex::read_from(
+                    &mut config.target.open(entry.key())?,
+                    self.index_kind(),
+                )?;
+                entry.insert(index)
+            }
+        };
+        self.update(index);
+
         Ok(())
     }
+
+    /// Resolves the path of the target index
+    ///
+    /// Returns `None` if the index doesn't exist and missing files are ignored.
+    fn index_path(&self, config: &ZiPatchConfig) -> Result<Option<PathBuf>> {
+        let path = config.resolve_path(&self.target_file.get_file_name(config.platform))?;
+        if !config.pending_indexes.contains_key(&path) && config.target.metadata(&path)?.is_none() {
+            config.verify_old_file(&path)?;
+            return Ok(None);
+        }
+
+        Ok(Some(path))
+    }
+
+    /// Adds or removes the command's entry in a parsed index
+    ///
+    /// Index commands carry no paths, so the synonym table only loses entries: deleting a
+    /// synonym removes the synonym entry at the command's location, and the hash table entry
+    /// once no synonyms for its hash are left.
+    fn update(&self, index: &mut SqpackIndex) {
+        let entry = IndexEntry::new(
+            self.file_hash,
+            self.block_number as u8,
+            (self.block_offset as u64) << 7,
+            self.is_synonym(),
+        );
+
+        match self.index_command {
+            IndexCommandKind::Add => {
+                index.insert(entry);
+            }
+            IndexCommandKind::Delete => {
+                if self.is_synonym() {
+                    index.remove_synonym(entry.hash, entry.data);
+                    if index
+                        .synonyms()
+                        .iter()
+                        .any(|synonym| synonym.hash == entry.hash)
+                    {
+                        return;
+                    }
+                }
+                index.remove(entry.hash);
+            }
+            IndexCommandKind::Unknown(_) => {
+                // Do nothing for unknown commands
+            }
+        }
+    }
 }
 
 impl std::fmt::Display for SqpkIndex {
@@ -99,7 +256,7 @@ impl std::fmt::Display for SqpkIndex {
             "SQPK:{}:{:?}:{}:{}:{:X}:{}:{}",
             Self::COMMAND,
             self.index_command,
-            self.is_synonym,
+            self.is_synonym(),
             self.target_file,
             self.file_hash,
             self.block_offset,
@@ -107,3 +264,165 @@ impl std::fmt::Display for SqpkIndex {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::ZiPatchChunk;
+    use crate::config::Platform;
+    use crate::sqpack::IndexKind;
+    use crate::target::MemoryTarget;
+    use crate::util::{SqexFile, SqpackFile};
+
+    fn command(index_command: IndexCommandKind, file_id: u32, file_hash: u64) -> SqpkIndex {
+        SqpkIndex {
+            index_command,
+            synonym: 0,
+            alignment: 0,
+            target_file: SqpackIndexFile {
+                sqpack: SqpackFile {
+                    main_id: 0x0A,
+                    sub_id: 0,
+                    file_id,
+                    sqex_file: SqexFile::default(),
+                },
+            },
+            file_hash,
+            block_offset: 0x20,
+            block_number: 1,
+        }
+    }
+
+    #[test]
+    fn test_apply_adds_and_deletes_entries() {
+        let target = MemoryTarget::new();
+        let index_path = PathBuf::from("/game/sqpack/ffxiv/0a0000.win32.index");
+        let index2_path = PathBuf::from("/game/sqpack/ffxiv/0a0000.win32.index2");
+        target.insert_file(&index_path, crate::sqpack::empty_index(IndexKind::Index1));
+        target.insert_file(&index2_path, crate::sqpack::empty_index(IndexKind::Index2));
+
+        let mut config = ZiPatchConfig::builder("/game")
+            .platform(Platform::Win32)
+            .target(target.clone())
+            .build();
+
+        // Ignored unless enabled
+        command(IndexCommandKind::Add, 0, 0x1_0000_0002)
+            .apply(&mut config)
+            .unwrap();
+        assert!(target.changed_files().is_empty());
+
+        config.apply_index_commands = true;
+        command(IndexCommandKind::Add, 0, 0x1_0000_0002)
+            .apply(&mut config)
+            .unwrap();
+        command(IndexCommandKind::Add, 2, 0x1234)
+            .apply(&mut config)
+            .unwrap();
+
+        // Batched until flushed
+        assert!(target.changed_files().is_empty());
+        config.flush_indexes().unwrap();
+
+        let read = |path: &PathBuf, kind| {
+            SqpackIndex::from_bytes(&target.file(path).unwrap(), kind).unwrap()
+        };
+
+        let index = read(&index_path, IndexKind::Index1);
+        let entry = index.get(0x1_0000_0002).unwrap();
+        assert_eq!(entry.offset(), 0x1000);
+        assert_eq!(entry.data_file_id(), 1);
+        assert_eq!(read(&index2_path, IndexKind::Index2).entries().len(), 1);
+
+        command(IndexCommandKind::Delete, 0, 0x1_0000_0002)
+            .apply(&mut config)
+            .unwrap();
+        config.flush_indexes().unwrap();
+        assert!(read(&index_path, IndexKind::Index1).entries().is_empty());
+    }
+
+    #[test]
+    fn test_synonym_commands_update_synonym_table() {
+        let target = MemoryTarget::new();
+        let index_path = PathBuf::from("/game/sqpack/ffxiv/0a0000.win32.index");
+        let hash = 0x1_0000_0002;
+        let first = IndexEntry::new(hash, 1, 0, true);
+        let second = IndexEntry::new(hash, 1, 0x40 << 7, true);
+
+        let mut index = SqpackIndex::from_bytes(
+            &crate::sqpack::empty_index(IndexKind::Index1),
+            IndexKind::Index1,
+        )
+        .unwrap();
+        index.insert_synonym(first, "a".to_string());
+        index.insert_synonym(second, "b".to_string());
+        target.insert_file(&index_path, index.to_bytes());
+
+        let mut config = ZiPatchConfig::builder("/game")
+            .platform(Platform::Win32)
+            .target(target.clone())
+            .apply_index_commands(true)
+            .build();
+        let read_index = || {
+            SqpackIndex::from_bytes(&target.file(&index_path).unwrap(), IndexKind::Index1).unwrap()
+        };
+
+        // Adding doesn't touch the synonym table, which needs paths
+        let mut add = command(IndexCommandKind::Add, 0, hash);
+        add.synonym = 1;
+        add.apply(&mut config).unwrap();
+
+        // The planned length follows the commands not written out yet
+        let mut delete = command(IndexCommandKind::Delete, 0, hash);
+        delete.synonym = 1;
+        delete.block_offset = 0x40;
+        let planned = delete.planned_operations(&config).unwrap();
+
+        // Applying any other chunk writes the index
+        ZiPatchChunk::XXXX(Default::default())
+            .apply(&mut config)
+            .unwrap();
+        let index = read_index();
+        assert!(index.get(hash).unwrap().is_synonym());
+        assert_eq!(index.synonyms().len(), 2);
+
+        // Deleting one synonym keeps the other and the hash table entry
+        delete.apply(&mut config).unwrap();
+        config.flush_indexes().unwrap();
+        assert!(matches!(
+            planned[0],
+            FileOperation::TruncateFile { length, .. }
+                if length == target.file(&index_path).unwrap().len() as u64
+        ));
+        let index = read_index();
+        assert!(index.get(hash).is_some());
+        assert_eq!(index.synonyms().len(), 1);
+        assert_eq!(index.synonyms()[0].path, "a");
+        assert_eq!(index.synonyms()[0].conflict_index, 0);
+
+        delete.block_offset = 0;
+        delete.apply(&mut config).unwrap();
+        config.flush_indexes().unwrap();
+        let index = read_index();
+        assert!(index.get(hash).is_none());
+        assert!(index.synonyms().is_empty());
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let path = "exd/root.exl";
+        let resolver = PathResolver::from_paths([path]);
+
+        let index1 = command(IndexCommandKind::Add, 0, crate::sqpack::index_hash(path));
+        assert_eq!(index1.resolve_path(&resolver), Some(path));
+
+        let index2 = command(
+            IndexCommandKind::Add,
+            2,
+            crate::sqpack::index2_hash(path) as u64,
+        );
+        assert_eq!(index2.index_kind(), IndexKind::Index2);
+        assert_eq!(index2.resolve_path(&resolver), Some(path));
+        assert_eq!(index2.resolve_path(&PathResolver::new()), None);
+    }
+}
@@ -16,11 +16,12 @@ pub use index::{IndexCommandKind, SqpkIndex};
 pub use patch_info::SqpkPatchInfo;
 pub use target_info::{RegionId, SqpkTargetInfo};
 
-use std::io::Read;
+use std::io::{Read, Write};
 
+use crate::apply::FileOperation;
 use crate::config::ZiPatchConfig;
 use crate::error::{Result, ZiPatchError};
-use crate::util::BinaryReaderExt;
+use crate::util::{BinaryReaderExt, BinaryWriterExt};
 
 /// SQPK command variants
 #[derive(Debug, Clone)]
@@ -82,6 +83,40 @@ impl SqpkCommand {
         Ok(command)
     }
 
+    /// Writes the SQPK chunk body (inner size, command character and command data) to a writer
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut body = Vec::new();
+        match self {
+            SqpkCommand::AddData(cmd) => cmd.write(&mut body)?,
+            SqpkCommand::DeleteData(cmd) => cmd.write(&mut body)?,
+            SqpkCommand::ExpandData(cmd) => cmd.write(&mut body)?,
+            SqpkCommand::File(cmd) => cmd.write(&mut body)?,
+            SqpkCommand::Header(cmd) => cmd.write(&mut body)?,
+            SqpkCommand::Index(cmd) => cmd.write(&mut body)?,
+            SqpkCommand::PatchInfo(cmd) => cmd.write(&mut body)?,
+            SqpkCommand::TargetInfo(cmd) => cmd.write(&mut body)?,
+        }
+
+        // The inner size covers itself and the command character, and matches the outer size
+        writer.write_i32_be(body.len() as i32 + 5)?;
+        writer.write_all(&[self.command_char() as u8])?;
+        writer.write_all(&body)?;
+        Ok(())
+    }
+
+    /// Lists the filesystem operations applying the command performs, without performing them
+    pub fn planned_operations(&self, config: &ZiPatchConfig) -> Result<Vec<FileOperation>> {
+        match self {
+            SqpkCommand::AddData(cmd) => cmd.planned_operations(config),
+            SqpkCommand::DeleteData(cmd) => cmd.planned_operations(config),
+            SqpkCommand::ExpandData(cmd) => cmd.planned_operations(config),
+            SqpkCommand::File(cmd) => cmd.planned_operations(config),
+            SqpkCommand::Header(cmd) => cmd.planned_operations(config),
+            SqpkCommand::Index(cmd) => cmd.planned_operations(config),
+            SqpkCommand::PatchInfo(_) | SqpkCommand::TargetInfo(_) => Ok(Vec::new()),
+        }
+    }
+
     /// Applies the SQPK command
     pub fn apply(&mut self, config: &mut ZiPatchConfig) -> Result<()> {
         match self {
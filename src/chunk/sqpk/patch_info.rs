@@ -1,8 +1,8 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
 use crate::config::ZiPatchConfig;
 use crate::error::Result;
-use crate::util::BinaryReaderExt;
+use crate::util::{BinaryReaderExt, BinaryWriterExt};
 
 /// SQPK Patch Info command ('X')
 ///
@@ -14,6 +14,8 @@ pub struct SqpkPatchInfo {
     pub status: u8,
     /// Version byte
     pub version: u8,
+    /// Alignment byte after the version, zero as far as observed, kept for re-serialization
+    pub alignment: u8,
     /// Install size
     pub install_size: u64,
 }
@@ -35,8 +37,7 @@ impl SqpkPatchInfo {
             buf[0]
         };
 
-        // Read and discard alignment byte
-        let _alignment = {
+        let alignment = {
             let mut buf = [0u8; 1];
             reader.read_exact(&mut buf)?;
             buf[0]
@@ -47,10 +48,18 @@ impl SqpkPatchInfo {
         Ok(Self {
             status,
             version,
+            alignment,
             install_size,
         })
     }
 
+    /// Writes the command body (everything after the command character) to a writer
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&[self.status, self.version, self.alignment])?;
+        writer.write_u64_be(self.install_size)?;
+        Ok(())
+    }
+
     /// Applies the command (NOP - does nothing)
     pub fn apply(&self, _config: &mut ZiPatchConfig) -> Result<()> {
         // NOP on modern patchers
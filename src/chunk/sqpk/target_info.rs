@@ -1,34 +1,42 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
 use crate::config::{Platform, ZiPatchConfig};
 use crate::error::Result;
-use crate::util::BinaryReaderExt;
+use crate::util::{BinaryReaderExt, BinaryWriterExt};
 
 /// SQPK Target Info command ('T')
 ///
 /// Sets platform and region information. Only Platform is used on recent patcher versions.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SqpkTargetInfo {
+    /// Reserved bytes at the start of the command, zero as far as observed, kept for
+    /// re-serialization
+    pub alignment: [u8; 3],
     /// Target platform
     pub platform: Platform,
     /// Region ID
     pub region: RegionId,
-    /// Debug flag
-    pub is_debug: bool,
+    /// Debug flag, non-zero for debug builds, kept raw for re-serialization
+    pub debug: i16,
     /// Version
     pub version: u16,
     /// Deleted data size
     pub deleted_data_size: u64,
     /// Seek count
     pub seek_count: u64,
+    /// Empty data at the end of the command, kept for re-serialization
+    ///
+    /// Written padded with zeros to the 96 bytes the command always ends with.
+    pub reserved: Vec<u8>,
 }
 
 /// Region identifier
-#[repr(i16)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RegionId {
     /// Global region (US/EU/JP/ZH)
-    Global = -1,
+    Global,
+    /// Unknown region, with its raw value
+    Unknown(i16),
 }
 
 impl RegionId {
@@ -36,22 +44,32 @@ impl RegionId {
     pub fn from_i16(value: i16) -> Self {
         match value {
             -1 => RegionId::Global,
-            _ => RegionId::Global, // Default to Global for unknown values
+            _ => RegionId::Unknown(value),
+        }
+    }
+
+    /// Gets the i16 value of the region
+    pub fn as_i16(self) -> i16 {
+        match self {
+            RegionId::Global => -1,
+            RegionId::Unknown(value) => value,
         }
     }
 }
 
 impl SqpkTargetInfo {
     pub const COMMAND: char = 'T';
+    /// Size of the empty data at the end of the command
+    const RESERVED_TAIL_SIZE: usize = 32 + 64;
 
     /// Reads an SqpkTargetInfo from a reader
     pub fn read<R: Read>(reader: &mut R) -> Result<Self> {
-        // Read and discard 3 bytes of reserved data
-        let _reserved = reader.read_bytes_required(3)?;
+        let mut alignment = [0u8; 3];
+        reader.read_exact(&mut alignment)?;
 
         let platform = Platform::from_u16(reader.read_u16_be()?)?;
         let region = RegionId::from_i16(reader.read_i16_be()?);
-        let is_debug = reader.read_i16_be()? != 0;
+        let debug = reader.read_i16_be()?;
         let version = reader.read_u16_be()?;
 
         // Note: These are little-endian (not BE)
@@ -68,17 +86,43 @@ impl SqpkTargetInfo {
         };
 
         // Note: There are 32 + 64 bytes of empty data at the end
+        let reserved = reader.read_bytes_required(Self::RESERVED_TAIL_SIZE)?;
 
         Ok(Self {
+            alignment,
             platform,
             region,
-            is_debug,
+            debug,
             version,
             deleted_data_size,
             seek_count,
+            reserved,
         })
     }
 
+    /// Writes the command body (everything after the command character) to a writer
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.alignment)?;
+        writer.write_u16_be(self.platform.as_u16())?;
+        writer.write_i16_be(self.region.as_i16())?;
+        writer.write_i16_be(self.debug)?;
+        writer.write_u16_be(self.version)?;
+
+        // Note: These are little-endian (not BE)
+        writer.write_u64_le(self.deleted_data_size)?;
+        writer.write_u64_le(self.seek_count)?;
+
+        let reserved = &self.reserved[..self.reserved.len().min(Self::RESERVED_TAIL_SIZE)];
+        writer.write_all(reserved)?;
+        writer.write_zeros(Self::RESERVED_TAIL_SIZE - reserved.len())?;
+        Ok(())
+    }
+
+    /// Whether the patch targets a debug build
+    pub fn is_debug(&self) -> bool {
+        self.debug != 0
+    }
+
     /// Applies the command by setting the platform in the config
     pub fn apply(&self, config: &mut ZiPatchConfig) -> Result<()> {
         config.platform = self.platform;
@@ -94,7 +138,7 @@ impl std::fmt::Display for SqpkTargetInfo {
             Self::COMMAND,
             self.platform,
             self.region,
-            self.is_debug,
+            self.is_debug(),
             self.version,
             self.deleted_data_size,
             self.seek_count
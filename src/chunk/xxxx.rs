@@ -1,22 +1,33 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
 use crate::config::ZiPatchConfig;
 use crate::error::Result;
+use crate::util::BinaryReaderExt;
 
 /// XXXX chunk
 ///
 /// This chunk type has never been observed in practice.
 /// It's included for completeness but is essentially a placeholder.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct XXXXChunk;
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XXXXChunk {
+    /// Chunk body, empty as far as observed, kept for re-serialization
+    pub data: Vec<u8>,
+}
 
 impl XXXXChunk {
     pub const CHUNK_TYPE: &'static str = "XXXX";
 
     /// Reads an XXXXChunk from a reader
-    pub fn read<R: Read>(_reader: &mut R, _size: u32) -> Result<Self> {
-        // XXXX chunk contains no data
-        Ok(Self)
+    pub fn read<R: Read>(reader: &mut R, size: u32) -> Result<Self> {
+        // XXXX chunk contains no data as far as observed, but keep anything that is there
+        let data = reader.read_bytes_required(size as usize)?;
+        Ok(Self { data })
+    }
+
+    /// Writes the chunk body (everything after the chunk type) to a writer
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.data)?;
+        Ok(())
     }
 
     /// Applies the chunk (no-op)
@@ -1,7 +1,11 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
+use crate::apply::{ApplyObserver, CancellationToken};
 use crate::error::{Result, ZiPatchError};
-use crate::util::SqexFileStreamStore;
+use crate::sqpack::SqpackIndex;
+use crate::target::{FileSystemTarget, PatchTarget};
+use crate::util::{resolve_game_path_in, SqexFile, SqexFileStreamStore};
 
 /// Platform identifier for FFXIV installation
 #[repr(u16)]
@@ -62,8 +66,27 @@ pub struct ZiPatchConfig {
     /// If true, ignore mismatches in old file content
     pub ignore_old_mismatch: bool,
 
-    /// Optional file stream cache for performance
-    pub store: Option<SqexFileStreamStore>,
+    /// If true, apply SqpkIndex commands to the `.index`/`.index2` files
+    ///
+    /// Off by default, as the official patcher ignores these commands and relies on
+    /// SqpkHeader to rewrite the index headers.
+    pub apply_index_commands: bool,
+
+    /// Filesystem the patch is applied to
+    pub target: Box<dyn PatchTarget + Send>,
+
+    /// Optional observer notified of chunk progress and file writes
+    pub observer: Option<Box<dyn ApplyObserver + Send>>,
+
+    /// Optional token for cancelling the apply
+    pub cancellation: Option<CancellationToken>,
+
+    /// Index files updated by SqpkIndex commands and not yet written to the target
+    pub(crate) pending_indexes: BTreeMap<PathBuf, SqpackIndex>,
+
+    /// Set while re-applying the chunk a resumed apply starts from, which may already have
+    /// been partly applied before the apply was interrupted
+    pub(crate) resuming: bool,
 }
 
 impl ZiPatchConfig {
@@ -74,7 +97,12 @@ impl ZiPatchConfig {
             platform: Platform::default(),
             ignore_missing: false,
             ignore_old_mismatch: false,
-            store: None,
+            apply_index_commands: false,
+            target: Box::new(FileSystemTarget::new()),
+            observer: None,
+            cancellation: None,
+            pending_indexes: BTreeMap::new(),
+            resuming: false,
         }
     }
 
@@ -83,6 +111,112 @@ impl ZiPatchConfig {
         &self.game_path
     }
 
+    /// Resolves a patch-supplied relative path to a full path inside the game directory
+    ///
+    /// See [`resolve_game_path`](crate::util::resolve_game_path) for the normalization and
+    /// sandboxing rules. Symlinks are looked up through the configuration's target.
+    pub fn resolve_path(&self, relative_path: &str) -> Result<PathBuf> {
+        resolve_game_path_in(self.target.as_ref(), &self.game_path, relative_path)
+    }
+
+    /// Checks that a file the patch expects to already exist is present
+    ///
+    /// Returns [`ZiPatchError::OldFileMissing`] if the file doesn't exist, and
+    /// [`ZiPatchError::OldFileMismatch`] if it isn't a regular file, unless the
+    /// corresponding ignore flag is set.
+    pub fn verify_old_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+
+        match self.target.metadata(path)? {
+            Some(metadata) => {
+                if !metadata.is_file() && !self.ignore_old_mismatch {
+                    return Err(ZiPatchError::OldFileMismatch(path.to_path_buf()));
+                }
+            }
+            None => {
+                if !self.ignore_missing {
+                    return Err(ZiPatchError::OldFileMissing(path.to_path_buf()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that a directory the patch expects to already exist is present
+    ///
+    /// Returns [`ZiPatchError::OldFileMissing`] if the directory doesn't exist, and
+    /// [`ZiPatchError::OldFileMismatch`] if the path isn't a directory, unless the
+    /// corresponding ignore flag is set. A missing directory is also accepted for the first
+    /// chunk of a resumed apply, which may have removed it before being interrupted.
+    pub fn verify_old_directory<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+
+        match self.target.metadata(path)? {
+            Some(metadata) => {
+                if !metadata.is_dir && !self.ignore_old_mismatch {
+                    return Err(ZiPatchError::OldFileMismatch(path.to_path_buf()));
+                }
+            }
+            None => {
+                if !self.ignore_missing && !self.resuming {
+                    return Err(ZiPatchError::OldFileMissing(path.to_path_buf()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists the files of an expansion's sqpack and movie directories in the target
+    ///
+    /// These are the candidates for an SqpkFile RemoveAll.
+    pub fn expansion_files(&self, expansion_id: u16) -> Result<Vec<PathBuf>> {
+        let folder = SqexFile::get_expansion_folder(expansion_id as u8);
+
+        let mut files = self
+            .target
+            .list_files(&self.resolve_path(&format!("sqpack/{}", folder))?)?;
+        files.extend(
+            self.target
+                .list_files(&self.resolve_path(&format!("movie/{}", folder))?)?,
+        );
+
+        Ok(files)
+    }
+
+    /// Notifies the observer, if any, of data written to a file
+    pub fn report_write<P: AsRef<Path>>(&mut self, path: P, offset: u64, length: u64) {
+        if let Some(ref mut observer) = self.observer {
+            observer.on_file_write(path.as_ref(), offset, length);
+        }
+    }
+
+    /// Writes the index files updated by SqpkIndex commands since the last flush to the target
+    ///
+    /// Index commands are batched per file: each index is read once, updated in memory, and
+    /// rewritten when the next chunk of another kind is applied. Applying a whole patch
+    /// flushes on its own; call this after applying chunks by hand if the last one may have
+    /// been an index command.
+    pub fn flush_indexes(&mut self) -> Result<()> {
+        for (path, index) in std::mem::take(&mut self.pending_indexes) {
+            let data = index.to_bytes();
+            self.target.set_len(&path, data.len() as u64)?;
+            self.target.write_at(&path, 0, &data)?;
+            self.report_write(&path, 0, data.len() as u64);
+        }
+
+        Ok(())
+    }
+
+    /// Returns [`ZiPatchError::Cancelled`] if the cancellation token has been cancelled
+    pub fn check_cancelled(&self) -> Result<()> {
+        match self.cancellation {
+            Some(ref token) => token.check(),
+            None => Ok(()),
+        }
+    }
+
     /// Creates a builder for ZiPatchConfig
     pub fn builder<P: Into<PathBuf>>(game_path: P) -> ZiPatchConfigBuilder {
         ZiPatchConfigBuilder::new(game_path)
@@ -96,7 +230,10 @@ pub struct ZiPatchConfigBuilder {
     platform: Platform,
     ignore_missing: bool,
     ignore_old_mismatch: bool,
-    store: Option<SqexFileStreamStore>,
+    apply_index_commands: bool,
+    target: Option<Box<dyn PatchTarget + Send>>,
+    observer: Option<Box<dyn ApplyObserver + Send>>,
+    cancellation: Option<CancellationToken>,
 }
 
 impl ZiPatchConfigBuilder {
@@ -107,7 +244,10 @@ impl ZiPatchConfigBuilder {
             platform: Platform::default(),
             ignore_missing: false,
             ignore_old_mismatch: false,
-            store: None,
+            apply_index_commands: false,
+            target: None,
+            observer: None,
+            cancellation: None,
         }
     }
 
@@ -129,9 +269,33 @@ impl ZiPatchConfigBuilder {
         self
     }
 
-    /// Sets the file stream store
+    /// Sets whether to apply SqpkIndex commands to the index files
+    pub fn apply_index_commands(mut self, apply: bool) -> Self {
+        self.apply_index_commands = apply;
+        self
+    }
+
+    /// Applies to the local filesystem, keeping file streams open in the given store
     pub fn store(mut self, store: SqexFileStreamStore) -> Self {
-        self.store = Some(store);
+        self.target = Some(Box::new(FileSystemTarget::with_store(store)));
+        self
+    }
+
+    /// Sets the filesystem the patch is applied to
+    pub fn target<T: PatchTarget + Send + 'static>(mut self, target: T) -> Self {
+        self.target = Some(Box::new(target));
+        self
+    }
+
+    /// Sets the observer notified of chunk progress and file writes
+    pub fn observer<O: ApplyObserver + Send + 'static>(mut self, observer: O) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Sets the token for cancelling the apply
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
         self
     }
 
@@ -142,7 +306,152 @@ impl ZiPatchConfigBuilder {
             platform: self.platform,
             ignore_missing: self.ignore_missing,
             ignore_old_mismatch: self.ignore_old_mismatch,
-            store: self.store,
+            apply_index_commands: self.apply_index_commands,
+            target: self
+                .target
+                .unwrap_or_else(|| Box::new(FileSystemTarget::new())),
+            observer: self.observer,
+            cancellation: self.cancellation,
+            pending_indexes: BTreeMap::new(),
+            resuming: false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::sqpk::{SqpkDeleteData, SqpkExpandData};
+    use crate::chunk::{SqpkCommand, ZiPatchChunk};
+    use crate::generate::add_file_command;
+    use crate::test_support::{add_data, dat_file, TestDir};
+    use std::fs;
+
+    #[test]
+    fn test_verify_old_file_missing() {
+        let dir = TestDir::new("config-missing");
+        let path = dir.path("000000.win32.dat0");
+
+        let mut config = ZiPatchConfig::new(&dir.game);
+        assert!(matches!(
+            config.verify_old_file(&path),
+            Err(ZiPatchError::OldFileMissing(_))
+        ));
+
+        config.ignore_missing = true;
+        assert!(config.verify_old_file(&path).is_ok());
+    }
+
+    #[test]
+    fn test_verify_old_file_mismatch() {
+        let dir = TestDir::new("config-mismatch");
+        dir.write("000000.win32.dat0", &[0u8; 16]);
+        dir.create_dir("sqpack");
+        let path = dir.path("000000.win32.dat0");
+        let sub_dir = dir.path("sqpack");
+
+        let mut config = ZiPatchConfig::new(&dir.game);
+        assert!(config.verify_old_file(&path).is_ok());
+        assert!(config.verify_old_directory(&sub_dir).is_ok());
+        assert!(matches!(
+            config.verify_old_file(&sub_dir),
+            Err(ZiPatchError::OldFileMismatch(_))
+        ));
+        assert!(matches!(
+            config.verify_old_directory(&path),
+            Err(ZiPatchError::OldFileMismatch(_))
+        ));
+
+        config.ignore_old_mismatch = true;
+        assert!(config.verify_old_file(&sub_dir).is_ok());
+        assert!(config.verify_old_directory(&path).is_ok());
+    }
+
+    /// Applies a chunk that changes an existing file, first with the file missing and then
+    /// with a directory in its place, checking the errors and the flags that ignore them
+    fn assert_old_file_checked(name: &str, chunk: ZiPatchChunk, path: &str) {
+        let dir = TestDir::new(name);
+        dir.create_dir(path.rsplit_once('/').unwrap().0);
+
+        let mut config = dir.config();
+        assert!(matches!(
+            chunk.clone().apply(&mut config),
+            Err(ZiPatchError::OldFileMissing(_))
+        ));
+        assert!(!dir.path(path).exists());
+
+        config.ignore_missing = true;
+        chunk.clone().apply(&mut config).unwrap();
+        assert!(dir.path(path).is_file());
+
+        // Close the file before replacing it
+        drop(config);
+        fs::remove_file(dir.path(path)).unwrap();
+        dir.create_dir(path);
+
+        let mut config = dir.config();
+        assert!(matches!(
+            chunk.clone().apply(&mut config),
+            Err(ZiPatchError::OldFileMismatch(_))
+        ));
+
+        // The check passes, though a directory still can't be written to
+        config.ignore_old_mismatch = true;
+        assert!(!matches!(
+            chunk.clone().apply(&mut config),
+            Err(ZiPatchError::OldFileMismatch(_))
+        ));
+    }
+
+    const DAT_PATH: &str = "sqpack/ffxiv/000000.win32.dat0";
+
+    #[test]
+    fn test_add_data_checks_old_file() {
+        assert_old_file_checked(
+            "config-add-data",
+            add_data(dat_file(0, 0, 0), 0x80, vec![0x11; 0x80], 0x80),
+            DAT_PATH,
+        );
+    }
+
+    #[test]
+    fn test_delete_data_checks_old_file() {
+        assert_old_file_checked(
+            "config-delete-data",
+            ZiPatchChunk::Sqpk(SqpkCommand::DeleteData(SqpkDeleteData {
+                alignment: [0; 3],
+                target_file: dat_file(0, 0, 0),
+                block_offset: 0x80,
+                block_number: 2,
+                reserved: 0,
+            })),
+            DAT_PATH,
+        );
+    }
+
+    #[test]
+    fn test_expand_data_checks_old_file() {
+        assert_old_file_checked(
+            "config-expand-data",
+            ZiPatchChunk::Sqpk(SqpkCommand::ExpandData(SqpkExpandData {
+                alignment: [0; 3],
+                target_file: dat_file(0, 0, 0),
+                block_offset: 0x80,
+                block_number: 2,
+                reserved: 0,
+            })),
+            DAT_PATH,
+        );
+    }
+
+    #[test]
+    fn test_add_file_continuation_checks_old_file() {
+        // Only a command continuing a file needs the file to be there
+        let path = "boot/ffxivboot.exe";
+        assert_old_file_checked(
+            "config-add-file",
+            add_file_command(path, 0x10, 0x20, &[0x22; 0x10]).unwrap(),
+            path,
+        );
+    }
+}
@@ -3,7 +3,10 @@ use std::path::PathBuf;
 use thiserror::Error;
 
 /// Error type for ZiPatch operations
+///
+/// New variants may be added in minor releases, so matches on it need a wildcard arm.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum ZiPatchError {
     /// I/O error occurred
     #[error("I/O error: {0}")]
@@ -53,6 +56,10 @@ pub enum ZiPatchError {
     #[error("File operation failed on {path}: {source}")]
     FileOperationFailed { path: PathBuf, source: io::Error },
 
+    /// Patch path would resolve outside the game directory
+    #[error("Unsafe path {path:?}: {reason}")]
+    UnsafePath { path: String, reason: String },
+
     /// Missing old file (when IgnoreMissing is false)
     #[error("Old file missing: {0}")]
     OldFileMissing(PathBuf),
@@ -85,6 +92,35 @@ pub enum ZiPatchError {
     #[error("Invalid file header version: {0}")]
     InvalidFileHeaderVersion(u8),
 
+    /// Apply was cancelled through a [`CancellationToken`](crate::CancellationToken)
+    ///
+    /// The chunk at `resume_offset` may be partially applied; applying it again from the
+    /// start (e.g. by resuming from a checkpoint) completes it.
+    #[error("Apply cancelled after {chunks_applied} chunks, resume at offset {resume_offset}")]
+    Cancelled {
+        chunks_applied: u64,
+        resume_offset: u64,
+    },
+
+    /// Checkpoint file is malformed or doesn't belong to the patch being applied
+    #[error("Invalid checkpoint: {0}")]
+    InvalidCheckpoint(String),
+
+    /// Chunk index file is malformed or doesn't belong to the patch it's used with
+    #[error("Invalid chunk index: {0}")]
+    InvalidChunkIndex(String),
+
+    /// Applying a chunk failed, and so did rolling back the changes already made
+    #[error("{error} (rollback also failed: {rollback_error})")]
+    RollbackFailed {
+        error: Box<ZiPatchError>,
+        rollback_error: Box<ZiPatchError>,
+    },
+
+    /// SqPack index or dat file is malformed
+    #[error("Invalid SqPack file: {0}")]
+    InvalidSqpackFile(String),
+
     /// Generic error with custom message
     #[error("{0}")]
     Custom(String),
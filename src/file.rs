@@ -1,16 +1,23 @@
 use std::collections::HashSet;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::path::Path;
 
-use crate::chunk::{FileHeaderChunk, ZiPatchChunk};
+use crate::apply::{
+    ApplyCheckpoint, ApplyJournal, ApplyOptions, ApplyPlan, ChunkInfo, PlannedOperation,
+};
+use crate::chunk::sqpk::IndexCommandKind;
+use crate::chunk::{FileHeaderChunk, SqpkCommand, ZiPatchChunk, CHUNK_TYPES};
 use crate::config::ZiPatchConfig;
 use crate::error::{Result, ZiPatchError};
-use crate::inspection::{ZiPatchChangeSet, ZiPatchCommandCounts};
+use crate::inspection::{
+    ChunkIndex, ChunkIndexEntry, ZiPatchChangeSet, ZiPatchCommandCounts, ZiPatchScan,
+};
+use crate::sqpack::PathResolver;
 use crate::util::{BinaryReaderExt, ChecksumReader, SqexFile};
 
 /// Magic number for ZiPatch files (3 x u32 big-endian)
-const ZIPATCH_MAGIC: [u32; 3] = [0x50495A91, 0x48435441, 0x0A1A0A0D];
+pub(crate) const ZIPATCH_MAGIC: [u32; 3] = [0x50495A91, 0x48435441, 0x0A1A0A0D];
 
 /// Main ZiPatch file reader
 ///
@@ -19,18 +26,25 @@ pub struct ZiPatchFile<R: Read + Seek> {
     reader: ChecksumReader<R>,
     head_position: u64,
     header: FileHeaderChunk,
+    header_checksum: u32,
+    patch_length: Option<u64>,
 }
 
 impl ZiPatchFile<File> {
     /// Opens a ZiPatch file from a file path
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
         let file = File::open(path)?;
-        Self::new(file)
+        let patch_length = file.metadata()?.len();
+        Ok(Self::new(file)?.with_patch_length(patch_length))
     }
 }
 
 impl<R: Read + Seek> ZiPatchFile<R> {
     /// Creates a new ZiPatchFile from a reader
+    ///
+    /// The length of the patch isn't looked up until something needs it (see
+    /// [`patch_length`](Self::patch_length)), so a reader over a file that is still being
+    /// downloaded can be applied without waiting for the download to finish.
     pub fn new(mut reader: R) -> Result<Self> {
         // Read and verify magic number
         let mut magic = [0u32; 3];
@@ -54,7 +68,10 @@ impl<R: Read + Seek> ZiPatchFile<R> {
             let chunk = ZiPatchChunk::read(&mut checksum_reader)?;
 
             if let ZiPatchChunk::FileHeader(fhdr) = chunk {
-                header = Some(fhdr);
+                // The checksum the chunk ends with identifies the patch in chunk indexes
+                let reader = checksum_reader.get_mut();
+                reader.seek(SeekFrom::Current(-4))?;
+                header = Some((fhdr, reader.read_u32_be()?));
                 break;
             }
 
@@ -64,7 +81,7 @@ impl<R: Read + Seek> ZiPatchFile<R> {
             }
         }
 
-        let header =
+        let (header, header_checksum) =
             header.ok_or_else(|| ZiPatchError::Custom("Could not find FHDR chunk".to_string()))?;
 
         // Rewind back to head position
@@ -76,9 +93,39 @@ impl<R: Read + Seek> ZiPatchFile<R> {
             reader: checksum_reader,
             head_position,
             header,
+            header_checksum,
+            patch_length: None,
         })
     }
 
+    /// Sets the length of the patch file, if it's known without reading to its end
+    ///
+    /// The length is reported to observers in [`ChunkInfo::total_bytes`] and recorded in
+    /// checkpoints, for example from the Content-Length of a patch being downloaded.
+    pub fn with_patch_length(mut self, patch_length: u64) -> Self {
+        self.patch_length = Some(patch_length);
+        self
+    }
+
+    /// Gets the length of the patch file
+    ///
+    /// Unless it was set with [`with_patch_length`](Self::with_patch_length) or looked up
+    /// before, this seeks to the end of the reader, which waits for a file being downloaded
+    /// to finish. Applying a patch only does so when saving or resuming from a checkpoint.
+    pub fn patch_length(&mut self) -> Result<u64> {
+        if let Some(patch_length) = self.patch_length {
+            return Ok(patch_length);
+        }
+
+        let reader = self.reader.get_mut();
+        let position = reader.stream_position()?;
+        let patch_length = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(position))?;
+
+        self.patch_length = Some(patch_length);
+        Ok(patch_length)
+    }
+
     /// Gets a reference to the file header
     pub fn header(&self) -> &FileHeaderChunk {
         &self.header
@@ -102,6 +149,252 @@ impl<R: Read + Seek> ZiPatchFile<R> {
         ChunkIterator::new(&mut self.reader, current_pos)
     }
 
+    /// Applies every chunk in the patch
+    ///
+    /// The configuration's [`ApplyObserver`](crate::apply::ApplyObserver), if any, is notified
+    /// before and after each chunk. If the configuration's cancellation token is cancelled, the
+    /// apply stops with [`ZiPatchError::Cancelled`], leaving the last checkpoint in place.
+    ///
+    /// With a checkpoint path set in `options`, an [`ApplyCheckpoint`] is saved after each
+    /// chunk, once the configuration's target has synced the chunk's writes to disk. With
+    /// [`ApplyOptions::resume`] set and a checkpoint present, the configuration state is
+    /// restored from it and the apply seeks straight to the next chunk. The checkpoint file
+    /// is removed once the EOF_ chunk has been applied.
+    ///
+    /// Checkpoints record the [patch length](Self::patch_length) and the FHDR chunk's
+    /// checksum, so that one saved for a different patch isn't resumed from. For a patch still being downloaded, set the length
+    /// with [`with_patch_length`](Self::with_patch_length), or saving the first checkpoint
+    /// waits for the download to finish.
+    ///
+    /// With an undo patch path set in `options`, the data each chunk overwrites or deletes
+    /// is journaled before the chunk is applied, and a patch restoring it is written once
+    /// the EOF_ chunk has been applied (see [`ApplyJournal::write_undo_patch`]).
+    pub fn apply(&mut self, config: &mut ZiPatchConfig, options: &ApplyOptions) -> Result<()> {
+        let start_pos = self.reader.get_mut().stream_position()?;
+
+        let mut offset = self.head_position;
+        let mut chunks_applied = 0u64;
+        let mut resumed = false;
+
+        if let (Some(path), true) = (&options.checkpoint_path, options.resume) {
+            if let Some(checkpoint) = ApplyCheckpoint::load_if_exists(path)? {
+                let patch_length = self.patch_length()?;
+                if checkpoint.patch_length != patch_length {
+                    return Err(ZiPatchError::InvalidCheckpoint(format!(
+                        "checkpoint is for a patch of {} bytes, this one is {} bytes",
+                        checkpoint.patch_length, patch_length
+                    )));
+                }
+                if checkpoint.header_checksum != self.header_checksum {
+                    return Err(ZiPatchError::InvalidCheckpoint(format!(
+                        "checkpoint is for a patch with FHDR checksum {:08X}, this one has {:08X}",
+                        checkpoint.header_checksum, self.header_checksum
+                    )));
+                }
+                if checkpoint.next_chunk_offset < self.head_position
+                    || checkpoint.next_chunk_offset >= patch_length
+                {
+                    return Err(ZiPatchError::InvalidCheckpoint(format!(
+                        "chunk offset {} is outside the patch",
+                        checkpoint.next_chunk_offset
+                    )));
+                }
+
+                checkpoint.restore(config);
+                offset = checkpoint.next_chunk_offset;
+                chunks_applied = checkpoint.chunks_applied;
+                resumed = true;
+            }
+        }
+
+        // A resumed apply keeps recording into the journal of the interrupted one
+        let mut undo_journal = match options.undo_journal_path() {
+            Some(path) if resumed && path.exists() => Some(ApplyJournal::open(path)?),
+            Some(path) => Some(ApplyJournal::create(path)?),
+            None => None,
+        };
+
+        self.reader.get_mut().seek(SeekFrom::Start(offset))?;
+
+        let mut first_after_resume = resumed;
+        loop {
+            let mut chunk = ZiPatchChunk::read(&mut self.reader)?;
+            let next_offset = self.reader.get_mut().stream_position()?;
+
+            let info = ChunkInfo::new(
+                &chunk,
+                chunks_applied,
+                offset,
+                next_offset - offset,
+                self.patch_length,
+            );
+            if let Some(journal) = &mut undo_journal {
+                journal.record_chunk(&chunk, config)?;
+            }
+
+            // An interrupted apply may have got through this chunk before saving its checkpoint
+            config.resuming = std::mem::take(&mut first_after_resume);
+            let result = chunk.apply_observed(config, &info);
+            config.resuming = false;
+            result?;
+            chunks_applied += 1;
+            offset = next_offset;
+
+            if chunk.is_eof() {
+                break;
+            }
+
+            if let Some(ref path) = options.checkpoint_path {
+                // Everything up to this chunk must be on disk before the checkpoint says so
+                config.flush_indexes()?;
+                config.target.sync()?;
+
+                let patch_length = self.patch_length()?;
+                ApplyCheckpoint::new(
+                    offset,
+                    chunks_applied,
+                    patch_length,
+                    self.header_checksum,
+                    config,
+                )
+                .save(path)?;
+            }
+        }
+
+        if let (Some(mut journal), Some(path)) = (undo_journal, &options.undo_patch_path) {
+            journal
+                .write_undo_patch(config, File::create(path)?)?
+                .sync_all()?;
+            journal.commit()?;
+        }
+
+        if let Some(ref path) = options.checkpoint_path {
+            match std::fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+
+        self.reader.get_mut().seek(SeekFrom::Start(start_pos))?;
+        Ok(())
+    }
+
+    /// Applies every chunk in the patch, journaling the original data first
+    ///
+    /// If any chunk fails to apply or the apply is cancelled, everything the patch already
+    /// changed is rolled back and the original error is returned. On success the journal is
+    /// left intact, so the apply can still be undone with [`ApplyJournal::rollback`]; call
+    /// [`ApplyJournal::commit`] to discard it. The configuration's observer is notified as
+    /// with [`apply`](Self::apply).
+    pub fn apply_journaled(
+        &mut self,
+        config: &mut ZiPatchConfig,
+        journal: &mut ApplyJournal,
+    ) -> Result<()> {
+        let result = self.apply_journaled_chunks(config, journal);
+
+        if let Err(error) = result {
+            // The failed apply's batched index commands must never be written out
+            config.pending_indexes.clear();
+            if let Err(rollback_error) = journal.rollback(config) {
+                return Err(ZiPatchError::RollbackFailed {
+                    error: Box::new(error),
+                    rollback_error: Box::new(rollback_error),
+                });
+            }
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    fn apply_journaled_chunks(
+        &mut self,
+        config: &mut ZiPatchConfig,
+        journal: &mut ApplyJournal,
+    ) -> Result<()> {
+        for result in self.chunks_with_info()? {
+            let (info, mut chunk) = result?;
+            journal.record_chunk(&chunk, config)?;
+            chunk.apply_observed(config, &info)?;
+        }
+
+        Ok(())
+    }
+
+    /// Creates an iterator over all chunks in the file, along with their position and size
+    ///
+    /// Pass the [`ChunkInfo`] to [`ZiPatchChunk::apply_observed`] to notify the configuration's
+    /// observer while applying chunks by hand. [`ChunkInfo::total_bytes`] is only set if the
+    /// patch length is already known.
+    pub fn chunks_with_info(&mut self) -> Result<ChunkInfoIterator<'_, R>> {
+        let current_pos = self.reader.get_mut().stream_position()?;
+        let total_bytes = self.patch_length;
+        self.reader
+            .get_mut()
+            .seek(SeekFrom::Start(self.head_position))?;
+
+        Ok(ChunkInfoIterator {
+            inner: ChunkIterator::new(&mut self.reader, current_pos),
+            index: 0,
+            offset: self.head_position,
+            total_bytes,
+        })
+    }
+
+    /// Lists every filesystem operation applying the patch would perform, without applying it
+    ///
+    /// Starts from the platform and ignore flags of `config`, and follows the changes the
+    /// patch's APLY and SqpkTargetInfo chunks make to them; they are restored before this
+    /// returns. SqpkIndex commands are applied to in-memory copies of their index files, so
+    /// each one is planned after the ones before it. Nothing in the configuration's target
+    /// is modified.
+    pub fn plan(&mut self, config: &mut ZiPatchConfig) -> Result<ApplyPlan> {
+        let saved = (
+            config.platform,
+            config.ignore_missing,
+            config.ignore_old_mismatch,
+        );
+        let pending_indexes = std::mem::take(&mut config.pending_indexes);
+
+        let result = self.plan_operations(config);
+
+        (
+            config.platform,
+            config.ignore_missing,
+            config.ignore_old_mismatch,
+        ) = saved;
+        config.pending_indexes = pending_indexes;
+
+        result
+    }
+
+    fn plan_operations(&mut self, config: &mut ZiPatchConfig) -> Result<ApplyPlan> {
+        let mut plan = ApplyPlan::default();
+
+        for result in self.chunks_with_info()? {
+            let (info, chunk) = result?;
+
+            for operation in chunk.planned_operations(config)? {
+                plan.operations.push(PlannedOperation {
+                    chunk_index: info.index,
+                    chunk_offset: info.offset,
+                    operation,
+                });
+            }
+
+            match chunk {
+                ZiPatchChunk::ApplyOption(ref option) => option.apply(config)?,
+                ZiPatchChunk::Sqpk(SqpkCommand::TargetInfo(ref target)) => target.apply(config)?,
+                // Only updates the configuration's copy of the index; nothing writes it out
+                ZiPatchChunk::Sqpk(SqpkCommand::Index(ref index)) => index.apply(config)?,
+                _ => {}
+            }
+        }
+
+        Ok(plan)
+    }
+
     /// Calculates which files were changed by this patch
     pub fn calculate_changed_files(&mut self, config: &ZiPatchConfig) -> Result<ZiPatchChangeSet> {
         let start_pos = self.reader.get_mut().stream_position()?;
@@ -158,6 +451,7 @@ impl<R: Read + Seek> ZiPatchFile<R> {
                             OperationKind::MakeDirTree => {
                                 added.insert(f.target_file.relative_path.clone());
                             }
+                            OperationKind::Unknown(_) => {}
                         }
                     }
                     crate::chunk::SqpkCommand::AddData(ref a) => {
@@ -187,6 +481,61 @@ impl<R: Read + Seek> ZiPatchFile<R> {
         ))
     }
 
+    /// Calculates which game files were changed by this patch, from its SqpkIndex commands
+    ///
+    /// Index entries that are both removed and added are reported as modified. Hashes the
+    /// resolver doesn't know are reported as `<index file>:<hash>`, with the hash in hex.
+    pub fn calculate_changed_game_files(
+        &mut self,
+        config: &ZiPatchConfig,
+        resolver: &PathResolver,
+    ) -> Result<ZiPatchChangeSet> {
+        let start_pos = self.reader.get_mut().stream_position()?;
+        self.reader
+            .get_mut()
+            .seek(SeekFrom::Start(self.head_position))?;
+
+        let mut added = HashSet::new();
+        let mut deleted = HashSet::new();
+
+        loop {
+            let chunk = ZiPatchChunk::read(&mut self.reader)?;
+
+            if chunk.is_eof() {
+                break;
+            }
+
+            if let ZiPatchChunk::Sqpk(SqpkCommand::Index(ref index)) = chunk {
+                let path = match index.resolve_path(resolver) {
+                    Some(path) => path.to_string(),
+                    None => format!(
+                        "{}:{:x}",
+                        index.target_file.get_file_name(config.platform),
+                        index.file_hash
+                    ),
+                };
+
+                match index.index_command {
+                    IndexCommandKind::Add => added.insert(path),
+                    IndexCommandKind::Delete => deleted.insert(path),
+                    IndexCommandKind::Unknown(_) => false,
+                };
+            }
+        }
+
+        let modified: HashSet<String> = added.intersection(&deleted).cloned().collect();
+        added.retain(|item| !modified.contains(item));
+        deleted.retain(|item| !modified.contains(item));
+
+        self.reader.get_mut().seek(SeekFrom::Start(start_pos))?;
+
+        Ok(ZiPatchChangeSet::with_changes(
+            added.into_iter().collect(),
+            deleted.into_iter().collect(),
+            modified.into_iter().collect(),
+        ))
+    }
+
     /// Calculates actual command counts in the patch file
     pub fn calculate_actual_counts(&mut self) -> Result<ZiPatchCommandCounts> {
         let start_pos = self.reader.get_mut().stream_position()?;
@@ -233,6 +582,158 @@ impl<R: Read + Seek> ZiPatchFile<R> {
             adir, deld, total, sqpk_a, sqpk_d, sqpk_e, sqpk_h, sqpk_f,
         ))
     }
+
+    /// Scans the chunk structure of the patch, without reading chunk bodies or checking CRCs
+    ///
+    /// Walks the chunks by the sizes in their headers, starting after the magic number, until
+    /// it reaches an EOF_ chunk, the end of the file or a chunk of unknown type. This only
+    /// reads eight bytes per chunk, so it's far cheaper than [`chunks`](Self::chunks) for
+    /// telling whether a download is complete.
+    ///
+    /// The [patch length](Self::patch_length) is only used if it's already known, so a
+    /// reader over a file being downloaded isn't waited on to finish. Otherwise the scan
+    /// stops at the end of the data the reader returns, and the file length and missing
+    /// bytes are left unknown.
+    pub fn scan_structure(&mut self) -> Result<ZiPatchScan> {
+        let start_pos = self.reader.get_mut().stream_position()?;
+        let file_length = self.patch_length;
+        let reader = self.reader.get_mut();
+
+        let mut scan = ZiPatchScan {
+            valid_length: self.head_position,
+            file_length,
+            ..ZiPatchScan::default()
+        };
+        let mut offset = self.head_position;
+
+        // Size, type and checksum fields around each chunk's body
+        const FRAMING_SIZE: u64 = 12;
+
+        loop {
+            reader.seek(SeekFrom::Start(offset))?;
+            let mut header = Vec::with_capacity(8);
+            (&mut *reader).take(8).read_to_end(&mut header)?;
+            if header.len() < 8 {
+                break;
+            }
+
+            let mut header = Cursor::new(header);
+            let size = header.read_u32_be()? as u64;
+            let chunk_type = header.read_chunk_type()?;
+
+            if !CHUNK_TYPES.contains(&chunk_type.as_str()) {
+                scan.invalid_chunk_offset = Some(offset);
+                break;
+            }
+
+            let chunk_end = offset + FRAMING_SIZE + size;
+            match file_length {
+                Some(file_length) if chunk_end > file_length => {
+                    scan.missing_bytes = Some(chunk_end - file_length);
+                    break;
+                }
+                Some(_) => {}
+                None => {
+                    // The chunk is complete if its last byte can be read
+                    reader.seek(SeekFrom::Start(chunk_end - 1))?;
+                    if (&mut *reader).take(1).read_to_end(&mut Vec::new())? == 0 {
+                        break;
+                    }
+                }
+            }
+
+            scan.chunk_count += 1;
+            scan.last_complete_chunk_offset = Some(offset);
+            scan.valid_length = chunk_end;
+            offset = chunk_end;
+
+            if chunk_type == "EOF_" {
+                scan.complete = true;
+                scan.missing_bytes = Some(0);
+                break;
+            }
+        }
+
+        self.reader.get_mut().seek(SeekFrom::Start(start_pos))?;
+        Ok(scan)
+    }
+
+    /// Builds an index of every chunk in the patch, for random access with [`chunk`](Self::chunk)
+    ///
+    /// SqPack target paths are resolved starting from the platform of `config`, following the
+    /// patch's SqpkTargetInfo chunks.
+    pub fn build_chunk_index(&mut self, config: &ZiPatchConfig) -> Result<ChunkIndex> {
+        let mut platform = config.platform;
+        let mut index = ChunkIndex::new(self.patch_length()?, self.header_checksum);
+
+        for result in self.chunks_with_info()? {
+            let (info, chunk) = result?;
+            if let ZiPatchChunk::Sqpk(SqpkCommand::TargetInfo(ref target)) = chunk {
+                platform = target.platform;
+            }
+
+            index.push(ChunkIndexEntry::new(&info, &chunk, platform));
+        }
+
+        Ok(index)
+    }
+
+    /// Reads the chunk starting at the given offset in the patch file
+    ///
+    /// The offset must be the start of a chunk, such as [`ChunkIndexEntry::offset`]; the
+    /// reader's position is left unchanged.
+    pub fn read_chunk_at(&mut self, offset: u64) -> Result<ZiPatchChunk> {
+        if offset < self.head_position {
+            return Err(ZiPatchError::Custom(format!(
+                "Chunk offset {} is before the first chunk",
+                offset
+            )));
+        }
+
+        let start_pos = self.reader.get_mut().stream_position()?;
+        self.reader.get_mut().seek(SeekFrom::Start(offset))?;
+        let result = ZiPatchChunk::read(&mut self.reader);
+        self.reader.get_mut().seek(SeekFrom::Start(start_pos))?;
+
+        result
+    }
+
+    /// Checks that a chunk index was built from this patch
+    ///
+    /// Fails with [`ZiPatchError::InvalidChunkIndex`] if the index records a different patch
+    /// length or FHDR chunk checksum. Call this after [`ChunkIndex::load`] to catch an index
+    /// saved for another patch before using it.
+    pub fn verify_chunk_index(&mut self, index: &ChunkIndex) -> Result<()> {
+        let patch_length = self.patch_length()?;
+        if index.patch_length != patch_length {
+            return Err(ZiPatchError::InvalidChunkIndex(format!(
+                "index is for a patch of {} bytes, this one is {} bytes",
+                index.patch_length, patch_length
+            )));
+        }
+        if index.header_checksum != self.header_checksum {
+            return Err(ZiPatchError::InvalidChunkIndex(format!(
+                "index is for a patch with FHDR checksum {:08X}, this one has {:08X}",
+                index.header_checksum, self.header_checksum
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Reads the n-th chunk of the patch, counting from 0, using an index built for it
+    ///
+    /// Returns `None` if the patch has no n-th chunk, and fails with
+    /// [`ZiPatchError::InvalidChunkIndex`] if the index was built from a different patch
+    /// (see [`verify_chunk_index`](Self::verify_chunk_index)).
+    pub fn chunk(&mut self, index: &ChunkIndex, n: usize) -> Result<Option<ZiPatchChunk>> {
+        self.verify_chunk_index(index)?;
+
+        index
+            .get(n)
+            .map(|entry| self.read_chunk_at(entry.offset))
+            .transpose()
+    }
 }
 
 /// Iterator over chunks in a ZiPatch file
@@ -279,6 +780,45 @@ impl<'a, R: Read + Seek> Iterator for ChunkIterator<'a, R> {
     }
 }
 
+/// Iterator over chunks in a ZiPatch file, along with their position and size
+pub struct ChunkInfoIterator<'a, R: Read + Seek> {
+    inner: ChunkIterator<'a, R>,
+    index: u64,
+    offset: u64,
+    total_bytes: Option<u64>,
+}
+
+impl<'a, R: Read + Seek> Iterator for ChunkInfoIterator<'a, R> {
+    type Item = Result<(ChunkInfo, ZiPatchChunk)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunk = match self.inner.next()? {
+            Ok(chunk) => chunk,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let next_offset = match self.inner.reader.get_mut().stream_position() {
+            Ok(offset) => offset,
+            Err(e) => {
+                self.inner.done = true;
+                return Some(Err(e.into()));
+            }
+        };
+
+        let info = ChunkInfo::new(
+            &chunk,
+            self.index,
+            self.offset,
+            next_offset - self.offset,
+            self.total_bytes,
+        );
+        self.index += 1;
+        self.offset = next_offset;
+
+        Some(Ok((info, chunk)))
+    }
+}
+
 impl<'a, R: Read + Seek> Drop for ChunkIterator<'a, R> {
     fn drop(&mut self) {
         // Restore original position
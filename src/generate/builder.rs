@@ -0,0 +1,310 @@
+use std::io::Write;
+
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use crate::chunk::sqpk::{OperationKind, SqpkFile};
+use crate::chunk::{
+    AddDirectoryChunk, DeleteDirectoryChunk, FileHeaderChunk, SqpkCommand, ZiPatchChunk,
+};
+use crate::error::Result;
+use crate::inspection::ZiPatchCommandCounts;
+use crate::util::{SqexFile, SqpkCompressedBlock};
+use crate::writer::ZiPatchWriter;
+
+/// Largest amount of file data stored in a single compressed block
+pub const MAX_BLOCK_SIZE: usize = 16000;
+
+/// Largest number of blocks stored in a single SqpkFile AddFile command
+pub const MAX_BLOCKS_PER_COMMAND: usize = 64;
+
+/// Largest amount of file data written by a single SqpkFile AddFile command
+pub(crate) const ADD_FILE_COMMAND_SIZE: usize = MAX_BLOCK_SIZE * MAX_BLOCKS_PER_COMMAND;
+
+/// Size of the unknown data trailing a V3 FHDR chunk
+const FILE_HEADER_RESERVED_SIZE: usize = 0xB8;
+
+/// Collects the chunks of a patch being generated and writes them behind a matching FHDR
+///
+/// The FHDR chunk is built from the collected chunks by [`header`](Self::header), so its
+/// command counts always match what [`ZiPatchFile::calculate_actual_counts`] reports for the
+/// written patch.
+///
+/// [`ZiPatchFile::calculate_actual_counts`]: crate::ZiPatchFile::calculate_actual_counts
+#[derive(Debug, Clone)]
+pub struct PatchBuilder {
+    patch_type: String,
+    chunks: Vec<ZiPatchChunk>,
+}
+
+impl PatchBuilder {
+    /// Creates a new builder for a patch of the given type (e.g. "DIFF" or "HIST")
+    pub fn new<S: Into<String>>(patch_type: S) -> Self {
+        Self {
+            patch_type: patch_type.into(),
+            chunks: Vec::new(),
+        }
+    }
+
+    /// Adds a chunk
+    pub fn push(&mut self, chunk: ZiPatchChunk) {
+        self.chunks.push(chunk);
+    }
+
+    /// Adds an ADIR chunk creating a directory
+    pub fn add_directory<S: Into<String>>(&mut self, dir_name: S) {
+        self.push(ZiPatchChunk::AddDirectory(AddDirectoryChunk::new(dir_name)));
+    }
+
+    /// Adds a DELD chunk deleting an empty directory
+    pub fn delete_directory<S: Into<String>>(&mut self, dir_name: S) {
+        self.push(ZiPatchChunk::DeleteDirectory(DeleteDirectoryChunk::new(
+            dir_name,
+        )));
+    }
+
+    /// Adds an SqpkFile MakeDirTree command creating a directory and its parents
+    pub fn make_dir_tree(&mut self, path: &str) {
+        self.push(file_command(
+            OperationKind::MakeDirTree,
+            path,
+            0,
+            0,
+            Vec::new(),
+        ));
+    }
+
+    /// Adds an SqpkFile DeleteFile command
+    pub fn delete_file(&mut self, path: &str) {
+        self.push(file_command(
+            OperationKind::DeleteFile,
+            path,
+            0,
+            0,
+            Vec::new(),
+        ));
+    }
+
+    /// Adds SqpkFile AddFile commands writing a whole file
+    ///
+    /// The data is split into deflate-compressed blocks of up to [`MAX_BLOCK_SIZE`] bytes,
+    /// with up to [`MAX_BLOCKS_PER_COMMAND`] blocks per command. Empty files get a single
+    /// command without blocks, which creates the file.
+    pub fn add_file(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            self.push(file_command(OperationKind::AddFile, path, 0, 0, Vec::new()));
+            return Ok(());
+        }
+
+        self.add_file_range(path, 0, data.len() as u64, data)
+    }
+
+    /// Adds SqpkFile AddFile commands writing part of a file
+    ///
+    /// Unless `offset` is 0, which truncates the file first, the file must already exist and
+    /// reach `offset` when the patch is applied. Data is split as in [`add_file`](Self::add_file).
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file relative to the game directory
+    /// * `offset` - Offset in the file to write the data at
+    /// * `file_size` - Size of the whole file, as recorded in the commands
+    /// * `data` - Data to write
+    pub fn add_file_range(
+        &mut self,
+        path: &str,
+        offset: u64,
+        file_size: u64,
+        data: &[u8],
+    ) -> Result<()> {
+        for (index, command_data) in data.chunks(ADD_FILE_COMMAND_SIZE).enumerate() {
+            let command_offset = offset + (index * ADD_FILE_COMMAND_SIZE) as u64;
+            self.push(add_file_command(
+                path,
+                command_offset,
+                file_size,
+                command_data,
+            )?);
+        }
+
+        Ok(())
+    }
+
+    /// Gets the chunks collected so far, not including the FHDR chunk
+    pub fn chunks(&self) -> &[ZiPatchChunk] {
+        &self.chunks
+    }
+
+    /// Counts the commands the written patch will contain, including its FHDR chunk
+    pub fn command_counts(&self) -> ZiPatchCommandCounts {
+        let mut counts = ZiPatchCommandCounts {
+            total_commands: 1,
+            ..ZiPatchCommandCounts::default()
+        };
+        for chunk in &self.chunks {
+            count_chunk(&mut counts, chunk);
+        }
+        counts
+    }
+
+    /// Builds the V3 FHDR chunk describing the collected chunks
+    ///
+    /// The deleted data size is the number of bytes SqpkAddData and SqpkDeleteData commands
+    /// wipe from dat files.
+    pub fn header(&self) -> FileHeaderChunk {
+        let mut counts = ZiPatchCommandCounts {
+            total_commands: 1,
+            ..ZiPatchCommandCounts::default()
+        };
+        let delete_data_size = self
+            .chunks
+            .iter()
+            .map(|chunk| count_chunk(&mut counts, chunk))
+            .sum();
+
+        file_header(&self.patch_type, counts, delete_data_size)
+    }
+
+    /// Writes the patch, FHDR chunk first, and returns the inner writer
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<W> {
+        let mut writer = ZiPatchWriter::new(writer)?;
+        writer.write_chunk(&ZiPatchChunk::FileHeader(self.header()))?;
+        writer.write_chunks(&self.chunks)?;
+        writer.finish()
+    }
+
+    /// Writes the patch to a byte vector
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.write_to(Vec::new())
+    }
+}
+
+/// Adds a chunk to command counts, returning the number of bytes it deletes from dat files
+pub(crate) fn count_chunk(counts: &mut ZiPatchCommandCounts, chunk: &ZiPatchChunk) -> i64 {
+    counts.total_commands += 1;
+
+    match chunk {
+        ZiPatchChunk::AddDirectory(_) => counts.add_directories += 1,
+        ZiPatchChunk::DeleteDirectory(_) => counts.delete_directories += 1,
+        ZiPatchChunk::Sqpk(SqpkCommand::Header(_)) => counts.sqpk_header_commands += 1,
+        ZiPatchChunk::Sqpk(SqpkCommand::File(_)) => counts.sqpk_file_commands += 1,
+        ZiPatchChunk::Sqpk(SqpkCommand::AddData(add)) => {
+            counts.sqpk_add_commands += 1;
+            return add.block_delete_number;
+        }
+        ZiPatchChunk::Sqpk(SqpkCommand::DeleteData(delete)) => {
+            counts.sqpk_delete_commands += 1;
+            return (delete.block_number as i64) << 7;
+        }
+        ZiPatchChunk::Sqpk(SqpkCommand::ExpandData(_)) => counts.sqpk_expand_commands += 1,
+        _ => {}
+    }
+
+    0
+}
+
+/// Builds a V3 FHDR chunk with the given command counts
+pub(crate) fn file_header(
+    patch_type: &str,
+    counts: ZiPatchCommandCounts,
+    delete_data_size: i64,
+) -> FileHeaderChunk {
+    FileHeaderChunk {
+        version: 3,
+        version_reserved: 0,
+        patch_type: patch_type.to_string(),
+        entry_files: 0,
+        add_directories: counts.add_directories,
+        delete_directories: counts.delete_directories,
+        command_counts: Some(counts),
+        delete_data_size,
+        minor_version: 0,
+        repository_name: 0,
+        reserved: vec![0; FILE_HEADER_RESERVED_SIZE],
+    }
+}
+
+pub(crate) fn file_command(
+    operation: OperationKind,
+    path: &str,
+    file_offset: i64,
+    file_size: i64,
+    compressed_data: Vec<SqpkCompressedBlock>,
+) -> ZiPatchChunk {
+    ZiPatchChunk::Sqpk(SqpkCommand::File(SqpkFile {
+        operation,
+        alignment: [0; 2],
+        file_offset,
+        file_size,
+        expansion_id: 0,
+        padding: [0; 2],
+        target_file: SqexFile::new(path),
+        path_padding: vec![0],
+        compressed_data,
+        unknown_data: Vec::new(),
+    }))
+}
+
+/// Builds an SqpkFile AddFile command writing up to [`ADD_FILE_COMMAND_SIZE`] bytes
+pub(crate) fn add_file_command(
+    path: &str,
+    offset: u64,
+    file_size: u64,
+    data: &[u8],
+) -> Result<ZiPatchChunk> {
+    let blocks = data
+        .chunks(MAX_BLOCK_SIZE)
+        .map(compress_block)
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(file_command(
+        OperationKind::AddFile,
+        path,
+        offset as i64,
+        file_size as i64,
+        blocks,
+    ))
+}
+
+/// Deflate-compresses data into a block
+pub(crate) fn compress_block(data: &[u8]) -> Result<SqpkCompressedBlock> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+
+    Ok(SqpkCompressedBlock {
+        header_size: 16,
+        reserved: 0,
+        compressed_size: compressed.len() as i32,
+        decompressed_size: data.len() as i32,
+        compressed_block: compressed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ZiPatchFile;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_header_counts_match_written_patch() {
+        let mut builder = PatchBuilder::new("DIFF");
+        builder.add_directory("boot");
+        builder.make_dir_tree("boot/data");
+        builder
+            .add_file(
+                "boot/large.bin",
+                &vec![7u8; MAX_BLOCK_SIZE * MAX_BLOCKS_PER_COMMAND + 1],
+            )
+            .unwrap();
+        builder.add_file("boot/empty.txt", b"").unwrap();
+        builder.delete_file("boot/old.dll");
+        builder.delete_directory("boot/old");
+
+        let mut patch = ZiPatchFile::new(Cursor::new(builder.to_bytes().unwrap())).unwrap();
+        assert_eq!(patch.header().patch_type, "DIFF");
+        let actual_counts = patch.calculate_actual_counts().unwrap();
+        assert_eq!(patch.header().command_counts, Some(actual_counts));
+        assert_eq!(builder.command_counts().sqpk_file_commands, 5);
+    }
+}
@@ -0,0 +1,239 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use super::builder::{add_file_command, file_command, file_header, ADD_FILE_COMMAND_SIZE};
+use super::FileTree;
+use crate::chunk::sqpk::OperationKind;
+use crate::chunk::{AddDirectoryChunk, ZiPatchChunk};
+use crate::error::Result;
+use crate::inspection::ZiPatchCommandCounts;
+use crate::writer::ZiPatchWriter;
+
+/// Packs a whole game installation into one patch that recreates it from an empty directory
+///
+/// Every directory gets an ADIR chunk, parents first, and every leaf directory an SqpkFile
+/// MakeDirTree command; every file is then written with SqpkFile AddFile commands, split into
+/// deflate-compressed blocks as by [`PatchBuilder::add_file`].
+///
+/// Installs are far too large to hold in memory, so unlike the other generators the patch is
+/// streamed straight to a writer: the FHDR counts are worked out from the directory walk,
+/// and each file is read and compressed as it's written.
+///
+/// [`PatchBuilder::add_file`]: super::PatchBuilder::add_file
+///
+/// # Example
+///
+/// ```no_run
+/// use zipatch::generate::InstallPacker;
+///
+/// let file = std::fs::File::create("install.patch")?;
+/// InstallPacker::new("C:/Games/FINAL FANTASY XIV - A Realm Reborn/game").write_to(file)?;
+/// # Ok::<(), zipatch::ZiPatchError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct InstallPacker {
+    root: PathBuf,
+    patch_type: String,
+}
+
+impl InstallPacker {
+    /// Creates a packer for the installation at the given directory
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: root.into(),
+            patch_type: "HIST".to_string(),
+        }
+    }
+
+    /// Sets the patch type written to the FHDR chunk (defaults to "HIST")
+    pub fn patch_type<S: Into<String>>(mut self, patch_type: S) -> Self {
+        self.patch_type = patch_type.into();
+        self
+    }
+
+    /// Writes the patch and returns the inner writer
+    ///
+    /// Files must not change size while they are packed.
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<W> {
+        let tree = FileTree::scan(&self.root)?;
+        let leaf_directories = FileTree::leaf_directories(&tree.directories);
+
+        let mut files = Vec::with_capacity(tree.files.len());
+        for file in &tree.files {
+            files.push((file, fs::metadata(self.root.join(file))?.len()));
+        }
+
+        let file_commands = leaf_directories.len() as u32
+            + files
+                .iter()
+                .map(|&(_, len)| add_file_command_count(len))
+                .sum::<u32>();
+        let counts = ZiPatchCommandCounts {
+            add_directories: tree.directories.len() as u32,
+            total_commands: 1 + tree.directories.len() as u32 + file_commands,
+            sqpk_file_commands: file_commands,
+            ..ZiPatchCommandCounts::default()
+        };
+
+        let mut writer = ZiPatchWriter::new(writer)?;
+        writer.write_chunk(&ZiPatchChunk::FileHeader(file_header(
+            &self.patch_type,
+            counts,
+            0,
+        )))?;
+
+        for dir in &tree.directories {
+            writer.write_chunk(&ZiPatchChunk::AddDirectory(AddDirectoryChunk::new(
+                dir.clone(),
+            )))?;
+        }
+        for dir in leaf_directories {
+            writer.write_chunk(&file_command(
+                OperationKind::MakeDirTree,
+                dir,
+                0,
+                0,
+                Vec::new(),
+            ))?;
+        }
+
+        let mut buffer = vec![0u8; ADD_FILE_COMMAND_SIZE];
+        for (file, len) in files {
+            let mut reader = File::open(self.root.join(file))?;
+            let mut offset = 0;
+
+            loop {
+                let size = (len - offset).min(ADD_FILE_COMMAND_SIZE as u64) as usize;
+                reader.read_exact(&mut buffer[..size])?;
+                writer.write_chunk(&add_file_command(file, offset, len, &buffer[..size])?)?;
+
+                offset += size as u64;
+                if offset >= len {
+                    break;
+                }
+            }
+        }
+
+        writer.finish()
+    }
+}
+
+/// Number of SqpkFile AddFile commands writing a file of the given length
+fn add_file_command_count(len: u64) -> u32 {
+    len.div_ceil(ADD_FILE_COMMAND_SIZE as u64).max(1) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::ApplyOptions;
+    use crate::chunk::SqpkCommand;
+    use crate::generate::{MAX_BLOCKS_PER_COMMAND, MAX_BLOCK_SIZE};
+    use crate::test_support::TestDir;
+    use crate::{ZiPatchConfig, ZiPatchFile};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_packed_install_recreates_tree() {
+        let dir = TestDir::new("install");
+        let install = dir.root.join("install");
+        let restored = &dir.game;
+
+        fs::create_dir_all(install.join("boot")).unwrap();
+        fs::create_dir_all(install.join("game/sqpack/ffxiv")).unwrap();
+        fs::create_dir_all(install.join("game/movie/ffxiv")).unwrap();
+        fs::write(install.join("boot/ffxivboot.ver"), b"2024.01.01.0000.0000").unwrap();
+        fs::write(install.join("game/ffxivgame.ver"), b"").unwrap();
+        let dat: Vec<u8> = (0..ADD_FILE_COMMAND_SIZE * 2 + 300)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        fs::write(install.join("game/sqpack/ffxiv/000000.win32.dat0"), &dat).unwrap();
+
+        let data = InstallPacker::new(&install).write_to(Vec::new()).unwrap();
+        let mut patch = ZiPatchFile::new(Cursor::new(data)).unwrap();
+        assert_eq!(patch.header().patch_type, "HIST");
+
+        let actual_counts = patch.calculate_actual_counts().unwrap();
+        assert_eq!(patch.header().command_counts, Some(actual_counts));
+        // Six directories, three of them leaves, and 1 + 1 + 3 AddFile commands
+        assert_eq!(patch.header().add_directories, 6);
+        assert_eq!(
+            patch
+                .header()
+                .command_counts
+                .as_ref()
+                .unwrap()
+                .sqpk_file_commands,
+            3 + 5
+        );
+
+        let mut config = ZiPatchConfig::new(restored);
+        patch.apply(&mut config, &ApplyOptions::default()).unwrap();
+
+        let tree = FileTree::scan(restored).unwrap();
+        assert_eq!(tree, FileTree::scan(&install).unwrap());
+        for file in &tree.files {
+            assert_eq!(
+                fs::read(restored.join(file)).unwrap(),
+                fs::read(install.join(file)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_packed_install_splits_large_files_and_keeps_empty_ones() {
+        let dir = TestDir::new("install-split");
+        dir.write("exact.bin", &vec![1; ADD_FILE_COMMAND_SIZE]);
+        dir.write("large.bin", &vec![2; ADD_FILE_COMMAND_SIZE + 1]);
+        dir.write("empty.bin", b"");
+
+        let data = InstallPacker::new(&dir.game).write_to(Vec::new()).unwrap();
+        let mut patch = ZiPatchFile::new(Cursor::new(data)).unwrap();
+        let commands: Vec<(String, i64, i64, Vec<i32>)> = patch
+            .chunks()
+            .filter_map(|chunk| match chunk.unwrap() {
+                ZiPatchChunk::Sqpk(SqpkCommand::File(file)) => Some((
+                    file.target_file.relative_path,
+                    file.file_offset,
+                    file.file_size,
+                    file.compressed_data
+                        .iter()
+                        .map(|block| block.decompressed_size)
+                        .collect(),
+                )),
+                _ => None,
+            })
+            .collect();
+
+        let size = ADD_FILE_COMMAND_SIZE as i64;
+        let full_blocks = vec![MAX_BLOCK_SIZE as i32; MAX_BLOCKS_PER_COMMAND];
+        assert_eq!(
+            commands,
+            [
+                ("empty.bin".to_string(), 0, 0, vec![]),
+                ("exact.bin".to_string(), 0, size, full_blocks.clone()),
+                ("large.bin".to_string(), 0, size + 1, full_blocks),
+                ("large.bin".to_string(), size, size + 1, vec![1]),
+            ]
+        );
+        assert_eq!(
+            patch
+                .header()
+                .command_counts
+                .as_ref()
+                .unwrap()
+                .sqpk_file_commands,
+            4
+        );
+
+        let restored = dir.root.join("restored");
+        let mut config = ZiPatchConfig::new(&restored);
+        patch.apply(&mut config, &ApplyOptions::default()).unwrap();
+        assert!(fs::read(restored.join("empty.bin")).unwrap().is_empty());
+        assert_eq!(
+            fs::read(restored.join("large.bin")).unwrap(),
+            dir.read("large.bin")
+        );
+    }
+}
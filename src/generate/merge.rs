@@ -0,0 +1,805 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Read, Seek, Write};
+
+use super::{file_command, file_header};
+use crate::chunk::sqpk::{OperationKind, SqpkFile, SqpkHeader, TargetFile, TargetHeaderKind};
+use crate::chunk::{SqpkCommand, ZiPatchChunk};
+use crate::config::Platform;
+use crate::error::{Result, ZiPatchError};
+use crate::file::ZiPatchFile;
+use crate::inspection::ZiPatchCommandCounts;
+use crate::util::{normalize_path, SqexFile};
+use crate::writer::ZiPatchWriter;
+
+/// Merges a chain of patches for the same repository into one equivalent patch
+///
+/// Chunks are kept in their original order; the merged patch only leaves out work that
+/// doesn't survive to the end of the chain:
+///
+/// - Everything written to a file that a later SqpkFile command deletes, truncates or
+///   removes with RemoveAll. A deletion is itself dropped when the file was already known
+///   to be gone, so an add and delete pair collapses to nothing.
+/// - Writes that later writes to the same file fully overwrite, which includes every
+///   SqpkHeader but the last for each target and header kind. A write is kept anyway if a
+///   later command needs the file to reach a length only that write gave it, and writes
+///   before an SqpkIndex command are kept, as the command reads the index file.
+///
+/// When a dropped SqpkFile AddFile was the only thing creating its directory, an SqpkFile
+/// MakeDirTree takes its place. Patch headers and EOF_ chunks are replaced by a single new
+/// FHDR and EOF_.
+///
+/// The patches are read twice: once to decide what to keep, then again to write it, so the
+/// merged patch is streamed without holding any file data in memory.
+///
+/// # Example
+///
+/// ```no_run
+/// use zipatch::generate::PatchMerger;
+/// use zipatch::ZiPatchFile;
+///
+/// let mut patches = vec![
+///     ZiPatchFile::from_path("D2024.01.01.0000.0000.patch")?,
+///     ZiPatchFile::from_path("D2024.02.01.0000.0000.patch")?,
+/// ];
+/// let file = std::fs::File::create("merged.patch")?;
+/// PatchMerger::new().write_to(&mut patches, file)?;
+/// # Ok::<(), zipatch::ZiPatchError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct PatchMerger {
+    platform: Platform,
+    patch_type: String,
+}
+
+impl Default for PatchMerger {
+    fn default() -> Self {
+        Self {
+            platform: Platform::Win32,
+            patch_type: "DIFF".to_string(),
+        }
+    }
+}
+
+impl PatchMerger {
+    /// Creates a merger
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the platform SqPack files are named for until the patches set one with
+    /// SqpkTargetInfo (defaults to Win32)
+    pub fn platform(mut self, platform: Platform) -> Self {
+        self.platform = platform;
+        self
+    }
+
+    /// Sets the patch type written to the FHDR chunk (defaults to "DIFF")
+    pub fn patch_type<S: Into<String>>(mut self, patch_type: S) -> Self {
+        self.patch_type = patch_type.into();
+        self
+    }
+
+    /// Merges the patches, given in the order they apply, and returns the inner writer
+    ///
+    /// Fails if the patches' headers name different repositories.
+    pub fn write_to<R: Read + Seek, W: Write>(
+        &self,
+        patches: &mut [ZiPatchFile<R>],
+        writer: W,
+    ) -> Result<W> {
+        if let Some(first) = patches.first() {
+            let repository = first.header().repository_name;
+            if patches
+                .iter()
+                .any(|patch| patch.header().repository_name != repository)
+            {
+                return Err(ZiPatchError::Custom(
+                    "Cannot merge patches for different repositories".to_string(),
+                ));
+            }
+        }
+
+        let mut analysis = MergeAnalysis::new(self.platform);
+        for patch in patches.iter_mut() {
+            for chunk in patch.chunks() {
+                analysis.add_chunk(&chunk?);
+            }
+        }
+        let plan = analysis.finish();
+
+        let mut writer = ZiPatchWriter::new(writer)?;
+        writer.write_chunk(&ZiPatchChunk::FileHeader(file_header(
+            &self.patch_type,
+            plan.counts,
+            plan.delete_data_size,
+        )))?;
+
+        let mut actions = plan.actions.into_iter();
+        for patch in patches.iter_mut() {
+            for chunk in patch.chunks() {
+                let chunk = chunk?;
+                match actions.next() {
+                    Some(MergeAction::Keep) => writer.write_chunk(&chunk)?,
+                    Some(MergeAction::MakeDirTree(path)) => writer.write_chunk(&file_command(
+                        OperationKind::MakeDirTree,
+                        &path,
+                        0,
+                        0,
+                        Vec::new(),
+                    ))?,
+                    Some(MergeAction::Drop) => {}
+                    None => {
+                        return Err(ZiPatchError::Custom(
+                            "Patch changed while it was being merged".to_string(),
+                        ))
+                    }
+                }
+            }
+        }
+
+        writer.finish()
+    }
+}
+
+/// What the merged patch does with a chunk of the input patches
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MergeAction {
+    Keep,
+    Drop,
+    /// Replaces a dropped SqpkFile AddFile, creating its directory
+    MakeDirTree(String),
+}
+
+/// Which command count a chunk adds to, and the data it deletes from dat files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChunkKind {
+    AddDirectory,
+    DeleteDirectory,
+    Header,
+    File,
+    AddData(i64),
+    DeleteData(i64),
+    ExpandData,
+    Other,
+}
+
+/// A byte range a chunk writes to a file
+#[derive(Debug, Clone, Copy)]
+struct FileWrite {
+    chunk: usize,
+    start: u64,
+    end: u64,
+    /// Kept even if later writes cover it
+    pinned: bool,
+}
+
+/// Everything that happened to a file since it was last created, truncated or removed
+#[derive(Debug, Default)]
+struct Generation {
+    /// Every chunk touching the file, all dropped if the file is removed
+    chunks: Vec<usize>,
+    writes: Vec<FileWrite>,
+    /// Minimum file lengths commands check for, with the number of writes before each check
+    checks: Vec<(usize, u64)>,
+    /// The file was known not to exist when the generation started
+    started_absent: bool,
+}
+
+/// Result of the first pass over the patches
+struct MergePlan {
+    actions: Vec<MergeAction>,
+    counts: ZiPatchCommandCounts,
+    delete_data_size: i64,
+}
+
+/// Follows the chunks of the patches, deciding which of them the merged patch needs
+struct MergeAnalysis {
+    platform: Platform,
+    actions: Vec<MergeAction>,
+    kinds: Vec<ChunkKind>,
+    files: HashMap<String, Generation>,
+    /// Files known not to exist at this point of the chain
+    absent_files: HashSet<String>,
+    /// Directories the chunks create, need or remove
+    directories: HashMap<usize, DirectoryUse>,
+}
+
+impl MergeAnalysis {
+    fn new(platform: Platform) -> Self {
+        Self {
+            platform,
+            actions: Vec::new(),
+            kinds: Vec::new(),
+            files: HashMap::new(),
+            absent_files: HashSet::new(),
+            directories: HashMap::new(),
+        }
+    }
+
+    fn add_chunk(&mut self, chunk: &ZiPatchChunk) {
+        let index = self.actions.len();
+        let (action, kind) = match chunk {
+            ZiPatchChunk::FileHeader(_) | ZiPatchChunk::EndOfFile(_) => {
+                (MergeAction::Drop, ChunkKind::Other)
+            }
+            ZiPatchChunk::AddDirectory(_) => (MergeAction::Keep, ChunkKind::AddDirectory),
+            ZiPatchChunk::DeleteDirectory(_) => (MergeAction::Keep, ChunkKind::DeleteDirectory),
+            ZiPatchChunk::Sqpk(SqpkCommand::Header(_)) => (MergeAction::Keep, ChunkKind::Header),
+            ZiPatchChunk::Sqpk(SqpkCommand::File(_)) => (MergeAction::Keep, ChunkKind::File),
+            ZiPatchChunk::Sqpk(SqpkCommand::AddData(add)) => (
+                MergeAction::Keep,
+                ChunkKind::AddData(add.block_delete_number),
+            ),
+            ZiPatchChunk::Sqpk(SqpkCommand::DeleteData(delete)) => (
+                MergeAction::Keep,
+                ChunkKind::DeleteData((delete.block_number as i64) << 7),
+            ),
+            ZiPatchChunk::Sqpk(SqpkCommand::ExpandData(_)) => {
+                (MergeAction::Keep, ChunkKind::ExpandData)
+            }
+            _ => (MergeAction::Keep, ChunkKind::Other),
+        };
+        self.actions.push(action);
+        self.kinds.push(kind);
+
+        match chunk {
+            ZiPatchChunk::AddDirectory(adir) => {
+                let dir = normalize_path(&adir.dir_name);
+                self.directories.insert(index, DirectoryUse::Create(dir));
+            }
+            ZiPatchChunk::DeleteDirectory(deld) => {
+                let dir = normalize_path(&deld.dir_name);
+                self.directories.insert(index, DirectoryUse::Delete(dir));
+            }
+            _ => {}
+        }
+
+        let ZiPatchChunk::Sqpk(command) = chunk else {
+            return;
+        };
+
+        match command {
+            SqpkCommand::TargetInfo(target) => self.platform = target.platform,
+
+            SqpkCommand::Header(header) => {
+                let path = match &header.target_file {
+                    TargetFile::Dat(dat) => dat.get_file_name(self.platform),
+                    TargetFile::Index(index) => index.get_file_name(self.platform),
+                };
+                let start = match header.header_kind {
+                    TargetHeaderKind::Version => 0,
+                    _ => SqpkHeader::HEADER_SIZE as u64,
+                };
+                let end = start + header.header_data.len() as u64;
+                self.write(&path, index, start, end, None, false);
+
+                let dir = parent_directory(&normalize_path(&path)).to_string();
+                self.directories.insert(index, DirectoryUse::Require(dir));
+            }
+
+            SqpkCommand::AddData(add) => {
+                let path = add.target_file.get_file_name(self.platform);
+                let start = add.block_offset as u64;
+                let end = start + add.block_data.len() as u64 + add.block_delete_number as u64;
+                self.write(&path, index, start, end, Some(start), false);
+            }
+
+            SqpkCommand::DeleteData(delete) => {
+                let path = delete.target_file.get_file_name(self.platform);
+                let start = delete.block_offset as u64;
+                let end = start + ((delete.block_number as u64) << 7);
+                self.write(&path, index, start, end, Some(start), false);
+            }
+
+            SqpkCommand::ExpandData(expand) => {
+                let path = expand.target_file.get_file_name(self.platform);
+                let start = expand.block_offset as u64;
+                let end = start + ((expand.block_number as u64) << 7);
+                self.write(&path, index, start, end, Some(start), false);
+            }
+
+            SqpkCommand::Index(command) => {
+                // The command reads the index, so nothing written to it before can go
+                let path = normalize_path(&command.target_file.get_file_name(self.platform));
+                let generation = self.generation(&path);
+                for write in &mut generation.writes {
+                    write.pinned = true;
+                }
+                generation.chunks.push(index);
+            }
+
+            SqpkCommand::File(file) => self.add_file_command(file, index),
+
+            SqpkCommand::PatchInfo(_) => {}
+        }
+    }
+
+    fn add_file_command(&mut self, file: &SqpkFile, index: usize) {
+        let path = normalize_path(&file.target_file.relative_path);
+
+        match file.operation {
+            OperationKind::AddFile => {
+                let parent = parent_directory(&path).to_string();
+                self.directories.insert(index, DirectoryUse::Create(parent));
+
+                let start = file.file_offset as u64;
+                let end = start + file.decompressed_length();
+                if start == 0 {
+                    // Truncating the file undoes everything written to it before
+                    self.remove_file(&path);
+                    self.write(&path, index, start, end, None, true);
+                } else {
+                    self.write(&path, index, start, end, Some(start), false);
+                }
+            }
+
+            OperationKind::DeleteFile => {
+                let started_absent = match self.files.get(&path) {
+                    Some(generation) => generation.started_absent,
+                    None => self.absent_files.contains(&path),
+                };
+                self.remove_file(&path);
+                self.absent_files.insert(path);
+
+                if started_absent {
+                    self.actions[index] = MergeAction::Drop;
+                }
+            }
+
+            OperationKind::RemoveAll => {
+                let folder = SqexFile::get_expansion_folder(file.expansion_id as u8);
+                let prefixes = [format!("sqpack/{}/", folder), format!("movie/{}/", folder)];
+
+                let removed: Vec<String> = self
+                    .files
+                    .keys()
+                    .filter(|path| {
+                        prefixes.iter().any(|prefix| path.starts_with(prefix))
+                            && SqpkFile::remove_all_filter(path)
+                    })
+                    .cloned()
+                    .collect();
+                for path in removed {
+                    self.remove_file(&path);
+                    self.absent_files.insert(path);
+                }
+            }
+
+            OperationKind::MakeDirTree => {
+                self.directories.insert(index, DirectoryUse::Create(path));
+            }
+
+            // Kept as it is, since nothing is known about what it touches
+            OperationKind::Unknown(_) => {}
+        }
+    }
+
+    /// Gets the current generation of a file, starting one if there is none
+    fn generation(&mut self, path: &str) -> &mut Generation {
+        let started_absent = self.absent_files.remove(path);
+        self.files
+            .entry(path.to_string())
+            .or_insert_with(|| Generation {
+                started_absent,
+                ..Generation::default()
+            })
+    }
+
+    /// Records a chunk writing `start..end` of a file, after checking it reaches `min_len`
+    fn write(
+        &mut self,
+        path: &str,
+        chunk: usize,
+        start: u64,
+        end: u64,
+        min_len: Option<u64>,
+        pinned: bool,
+    ) {
+        let generation = self.generation(&normalize_path(path));
+        if let Some(min_len) = min_len {
+            generation.checks.push((generation.writes.len(), min_len));
+        }
+        generation.writes.push(FileWrite {
+            chunk,
+            start,
+            end,
+            pinned,
+        });
+        generation.chunks.push(chunk);
+    }
+
+    /// Drops every chunk of a file's current generation, as the file is being removed
+    fn remove_file(&mut self, path: &str) {
+        if let Some(generation) = self.files.remove(path) {
+            for chunk in generation.chunks {
+                self.actions[chunk] = MergeAction::Drop;
+            }
+        }
+    }
+
+    fn finish(mut self) -> MergePlan {
+        for (_, generation) in std::mem::take(&mut self.files) {
+            for chunk in overwritten_chunks(&generation) {
+                self.actions[chunk] = MergeAction::Drop;
+            }
+        }
+
+        self.replace_directory_creation();
+
+        let mut counts = ZiPatchCommandCounts {
+            total_commands: 1,
+            ..ZiPatchCommandCounts::default()
+        };
+        let mut delete_data_size = 0;
+
+        for (action, kind) in self.actions.iter().zip(&self.kinds) {
+            let kind = match action {
+                MergeAction::Keep => *kind,
+                MergeAction::MakeDirTree(_) => ChunkKind::File,
+                MergeAction::Drop => continue,
+            };
+
+            counts.total_commands += 1;
+            match kind {
+                ChunkKind::AddDirectory => counts.add_directories += 1,
+                ChunkKind::DeleteDirectory => counts.delete_directories += 1,
+                ChunkKind::Header => counts.sqpk_header_commands += 1,
+                ChunkKind::File => counts.sqpk_file_commands += 1,
+                ChunkKind::AddData(deleted) => {
+                    counts.sqpk_add_commands += 1;
+                    delete_data_size += deleted;
+                }
+                ChunkKind::DeleteData(deleted) => {
+                    counts.sqpk_delete_commands += 1;
+                    delete_data_size += deleted;
+                }
+                ChunkKind::ExpandData => counts.sqpk_expand_commands += 1,
+                ChunkKind::Other => {}
+            }
+        }
+
+        MergePlan {
+            actions: self.actions,
+            counts,
+            delete_data_size,
+        }
+    }
+
+    /// Replaces dropped SqpkFile AddFile commands with MakeDirTree where no kept chunk
+    /// creates their directory before a later chunk needs it
+    fn replace_directory_creation(&mut self) {
+        // Directories kept chunks later in the chain create before anything needs them
+        let mut created_later: HashSet<&str> = HashSet::new();
+
+        for index in (0..self.actions.len()).rev() {
+            let Some(directory) = self.directories.get(&index) else {
+                continue;
+            };
+
+            match directory {
+                DirectoryUse::Create(dir) => {
+                    if dir.is_empty() {
+                        continue;
+                    }
+                    if self.actions[index] == MergeAction::Drop
+                        && !created_later.contains(dir.as_str())
+                    {
+                        self.actions[index] = MergeAction::MakeDirTree(dir.clone());
+                    }
+                    if self.actions[index] != MergeAction::Drop {
+                        created_later.extend(ancestors(dir));
+                    }
+                }
+                DirectoryUse::Require(dir) => {
+                    if self.actions[index] != MergeAction::Drop {
+                        for ancestor in ancestors(dir) {
+                            created_later.remove(ancestor);
+                        }
+                    }
+                }
+                DirectoryUse::Delete(dir) => {
+                    let prefix = format!("{}/", dir);
+                    created_later.retain(|created| created != dir && !created.starts_with(&prefix));
+                }
+            }
+        }
+    }
+}
+
+/// How a chunk depends on a directory
+#[derive(Debug, Clone)]
+enum DirectoryUse {
+    /// The chunk creates the directory and its parents
+    Create(String),
+    /// The chunk creates a file in the directory, which must already exist
+    Require(String),
+    /// The chunk removes the directory, which must exist
+    Delete(String),
+}
+
+/// Gets a directory and every parent of it
+fn ancestors(dir: &str) -> impl Iterator<Item = &str> {
+    dir.match_indices('/')
+        .map(move |(separator, _)| &dir[..separator])
+        .chain(std::iter::once(dir))
+}
+
+/// Gets the directory of a normalized path
+fn parent_directory(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+/// Finds the writes of a generation that later writes fully overwrite and can be dropped
+///
+/// A write that later commands rely on to make the file long enough is kept.
+fn overwritten_chunks(generation: &Generation) -> Vec<usize> {
+    let writes = &generation.writes;
+    let mut dropped = vec![false; writes.len()];
+    let mut covered = RangeSet::default();
+
+    for (i, write) in writes.iter().enumerate().rev() {
+        if !write.pinned && covered.contains(write.start, write.end) {
+            dropped[i] = true;
+        }
+        covered.insert(write.start, write.end);
+    }
+
+    for &(writes_before, min_len) in &generation.checks {
+        // The file must also exist, so at least one byte has to be written
+        let min_len = min_len.max(1);
+        let reach = (0..writes_before)
+            .filter(|&i| !dropped[i])
+            .map(|i| writes[i].end)
+            .max()
+            .unwrap_or(0);
+
+        if reach < min_len {
+            let longest = (0..writes_before)
+                .filter(|&i| dropped[i])
+                .max_by_key(|&i| writes[i].end);
+            if let Some(i) = longest.filter(|&i| writes[i].end >= min_len) {
+                dropped[i] = false;
+            }
+        }
+    }
+
+    writes
+        .iter()
+        .zip(dropped)
+        .filter(|(_, dropped)| *dropped)
+        .map(|(write, _)| write.chunk)
+        .collect()
+}
+
+/// Set of byte ranges, merged as they are inserted
+#[derive(Debug, Default)]
+struct RangeSet {
+    /// Start of every range, mapped to its end
+    ranges: BTreeMap<u64, u64>,
+}
+
+impl RangeSet {
+    fn contains(&self, start: u64, end: u64) -> bool {
+        start >= end
+            || self
+                .ranges
+                .range(..=start)
+                .next_back()
+                .is_some_and(|(_, &range_end)| range_end >= end)
+    }
+
+    fn insert(&mut self, mut start: u64, mut end: u64) {
+        if start >= end {
+            return;
+        }
+
+        if let Some((&prev_start, &prev_end)) = self.ranges.range(..=start).next_back() {
+            if prev_end >= start {
+                start = prev_start;
+                end = end.max(prev_end);
+            }
+        }
+
+        let overlapping: Vec<u64> = self.ranges.range(start..=end).map(|(&s, _)| s).collect();
+        for range_start in overlapping {
+            end = end.max(self.ranges.remove(&range_start).unwrap_or(0));
+        }
+
+        self.ranges.insert(start, end);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::ApplyOptions;
+    use crate::chunk::sqpk::{SqpkAddData, TargetFileKind};
+    use crate::generate::{FileTree, PatchBuilder};
+    use crate::test_support::TestDir;
+    use crate::util::{SqpackDatFile, SqpackFile};
+    use crate::ZiPatchConfig;
+    use std::fs;
+    use std::io::Cursor;
+
+    fn dat_file() -> SqpackDatFile {
+        SqpackDatFile {
+            sqpack: SqpackFile {
+                main_id: 0,
+                sub_id: 0,
+                file_id: 0,
+                sqex_file: SqexFile::default(),
+            },
+        }
+    }
+
+    fn header(header_kind: TargetHeaderKind, value: u8) -> ZiPatchChunk {
+        ZiPatchChunk::Sqpk(SqpkCommand::Header(SqpkHeader {
+            file_kind: TargetFileKind::Dat,
+            header_kind,
+            alignment: 0,
+            target_file: TargetFile::Dat(dat_file()),
+            header_data: vec![value; SqpkHeader::HEADER_SIZE],
+        }))
+    }
+
+    fn add_data(value: u8) -> ZiPatchChunk {
+        ZiPatchChunk::Sqpk(SqpkCommand::AddData(SqpkAddData {
+            alignment: [0; 3],
+            target_file: dat_file(),
+            block_offset: 0x800,
+            block_number: 0x100,
+            block_delete_number: 0x80,
+            block_data: vec![value; 0x100],
+        }))
+    }
+
+    fn patch(build: impl FnOnce(&mut PatchBuilder)) -> ZiPatchFile<Cursor<Vec<u8>>> {
+        let mut builder = PatchBuilder::new("DIFF");
+        build(&mut builder);
+        ZiPatchFile::new(Cursor::new(builder.to_bytes().unwrap())).unwrap()
+    }
+
+    fn patches() -> Vec<ZiPatchFile<Cursor<Vec<u8>>>> {
+        vec![
+            patch(|builder| {
+                builder.push(header(TargetHeaderKind::Version, 0xAA));
+                builder.push(header(TargetHeaderKind::Data, 0xBB));
+                builder.push(add_data(0x01));
+                builder.add_file("boot/tmp.txt", b"temporary").unwrap();
+                builder.add_file("movie/ffxiv/a.bk2", b"first").unwrap();
+            }),
+            patch(|builder| {
+                builder.push(header(TargetHeaderKind::Version, 0xCC));
+                builder.push(add_data(0x02));
+                builder.delete_file("boot/tmp.txt");
+                builder.add_file("boot/tmp.txt", b"again").unwrap();
+                builder.delete_file("boot/tmp.txt");
+                builder.add_file("movie/ffxiv/a.bk2", b"second").unwrap();
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_merged_patch_matches_chain() {
+        let dir = TestDir::new("merge");
+        let chained = dir.root.join("chained");
+        let merged = dir.root.join("merged");
+        for dir in [&chained, &merged] {
+            fs::create_dir_all(dir.join("sqpack/ffxiv")).unwrap();
+        }
+
+        let mut config = ZiPatchConfig::new(&chained);
+        for mut patch in patches() {
+            patch.apply(&mut config, &ApplyOptions::default()).unwrap();
+        }
+
+        let data = PatchMerger::new()
+            .write_to(&mut patches(), Vec::new())
+            .unwrap();
+        let mut patch = ZiPatchFile::new(Cursor::new(data)).unwrap();
+        let actual_counts = patch.calculate_actual_counts().unwrap();
+        assert_eq!(patch.header().command_counts, Some(actual_counts));
+
+        // The first version header and AddData are overwritten, and the temporary file's
+        // writes are replaced by a single MakeDirTree keeping its directory
+        let counts = patch.header().command_counts.clone().unwrap();
+        assert_eq!(counts.sqpk_header_commands, 2);
+        assert_eq!(counts.sqpk_add_commands, 1);
+        assert_eq!(counts.sqpk_file_commands, 3);
+        assert_eq!(patch.header().delete_data_size, 0x80);
+
+        let mut config = ZiPatchConfig::new(&merged);
+        patch.apply(&mut config, &ApplyOptions::default()).unwrap();
+
+        let tree = FileTree::scan(&merged).unwrap();
+        assert_eq!(tree, FileTree::scan(&chained).unwrap());
+        assert!(tree.directories.contains("boot"));
+        for file in &tree.files {
+            assert_eq!(
+                fs::read(merged.join(file)).unwrap(),
+                fs::read(chained.join(file)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_delete_and_re_add_keep_their_order_across_chain() {
+        let chain = || {
+            vec![
+                patch(|builder| {
+                    builder.delete_file("boot/installed.txt");
+                    builder.add_file("boot/new.txt", b"one").unwrap();
+                }),
+                patch(|builder| {
+                    builder.add_file("boot/installed.txt", b"re-added").unwrap();
+                    builder.delete_file("boot/new.txt");
+                }),
+                patch(|builder| {
+                    builder.add_file("boot/new.txt", b"three").unwrap();
+                }),
+            ]
+        };
+
+        let data = PatchMerger::new()
+            .write_to(&mut chain(), Vec::new())
+            .unwrap();
+        let mut patch = ZiPatchFile::new(Cursor::new(data)).unwrap();
+        let commands: Vec<(OperationKind, String)> = patch
+            .chunks()
+            .filter_map(|chunk| match chunk.unwrap() {
+                ZiPatchChunk::Sqpk(SqpkCommand::File(file)) => {
+                    Some((file.operation, file.target_file.relative_path))
+                }
+                _ => None,
+            })
+            .collect();
+        // The first write of new.txt is dropped, but both deletes still have to run before
+        // the files are added again
+        assert_eq!(
+            commands,
+            [
+                (OperationKind::DeleteFile, "boot/installed.txt".to_string()),
+                (OperationKind::AddFile, "boot/installed.txt".to_string()),
+                (OperationKind::DeleteFile, "boot/new.txt".to_string()),
+                (OperationKind::AddFile, "boot/new.txt".to_string()),
+            ]
+        );
+
+        let dir = TestDir::new("merge-re-add");
+        let chained = dir.root.join("chained");
+        let merged = dir.root.join("merged");
+        for dir in [&chained, &merged] {
+            fs::create_dir_all(dir.join("boot")).unwrap();
+            fs::write(dir.join("boot/installed.txt"), b"installed").unwrap();
+        }
+
+        let mut config = ZiPatchConfig::new(&chained);
+        for mut patch in chain() {
+            patch.apply(&mut config, &ApplyOptions::default()).unwrap();
+        }
+        let mut config = ZiPatchConfig::new(&merged);
+        patch.apply(&mut config, &ApplyOptions::default()).unwrap();
+
+        for file in ["boot/installed.txt", "boot/new.txt"] {
+            assert_eq!(
+                fs::read(merged.join(file)).unwrap(),
+                fs::read(chained.join(file)).unwrap()
+            );
+        }
+        assert_eq!(fs::read(merged.join("boot/new.txt")).unwrap(), b"three");
+    }
+
+    #[test]
+    fn test_range_set() {
+        let mut ranges = RangeSet::default();
+        ranges.insert(10, 20);
+        ranges.insert(30, 40);
+        assert!(ranges.contains(12, 20));
+        assert!(!ranges.contains(15, 35));
+
+        ranges.insert(18, 32);
+        assert!(ranges.contains(10, 40));
+        assert!(!ranges.contains(5, 15));
+        assert_eq!(ranges.ranges.len(), 1);
+    }
+}
@@ -0,0 +1,14 @@
+mod builder;
+mod install;
+mod merge;
+mod rewrite;
+mod sqpack_diff;
+mod tree_diff;
+
+pub(crate) use builder::{add_file_command, file_command, file_header, ADD_FILE_COMMAND_SIZE};
+pub use builder::{PatchBuilder, MAX_BLOCKS_PER_COMMAND, MAX_BLOCK_SIZE};
+pub use install::InstallPacker;
+pub use merge::PatchMerger;
+pub use rewrite::PatchRewriter;
+pub use sqpack_diff::SqpackDiff;
+pub use tree_diff::{FileTree, TreeDiff};
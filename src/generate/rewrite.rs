@@ -0,0 +1,282 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use super::builder::count_chunk;
+use crate::chunk::ZiPatchChunk;
+use crate::error::{Result, ZiPatchError};
+use crate::file::ZiPatchFile;
+use crate::inspection::ZiPatchCommandCounts;
+use crate::writer::ZiPatchWriter;
+
+type Transform<'a> = Box<dyn FnMut(ZiPatchChunk) -> Option<ZiPatchChunk> + 'a>;
+
+/// Rewrites a patch chunk by chunk, dropping or changing chunks on the way
+///
+/// Each chunk between the FHDR and EOF_ chunks is passed through the rewriter's filters and
+/// transforms, in the order they were added, and written out unless one of them drops it.
+/// Every chunk is serialized anew, so CRCs always match the rewritten contents. The FHDR is
+/// copied from the original patch. Only V3 headers are updated: their command counts,
+/// directory counts and deleted data size are recomputed for the chunks that were kept. V2
+/// headers hold none of these fields and are copied unchanged.
+///
+/// The patch is streamed; the writer must be seekable, as the FHDR is only known once every
+/// chunk has been written and is filled in afterwards.
+///
+/// # Example
+///
+/// ```no_run
+/// use zipatch::chunk::{SqpkCommand, ZiPatchChunk};
+/// use zipatch::generate::PatchRewriter;
+/// use zipatch::ZiPatchFile;
+///
+/// let mut patch = ZiPatchFile::from_path("D2024.01.01.0000.0000.patch")?;
+/// let file = std::fs::File::create("D2024.01.01.0000.0000.slim.patch")?;
+///
+/// PatchRewriter::new()
+///     .filter(|chunk| {
+///         !matches!(chunk, ZiPatchChunk::Sqpk(SqpkCommand::File(file))
+///             if file.target_file.relative_path.starts_with("movie/"))
+///     })
+///     .write_to(&mut patch, file)?;
+/// # Ok::<(), zipatch::ZiPatchError>(())
+/// ```
+#[derive(Default)]
+pub struct PatchRewriter<'a> {
+    transforms: Vec<Transform<'a>>,
+}
+
+impl<'a> PatchRewriter<'a> {
+    /// Creates a rewriter that copies every chunk unchanged
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops chunks the predicate returns false for
+    pub fn filter<F>(mut self, mut predicate: F) -> Self
+    where
+        F: FnMut(&ZiPatchChunk) -> bool + 'a,
+    {
+        self.transforms
+            .push(Box::new(move |chunk| predicate(&chunk).then_some(chunk)));
+        self
+    }
+
+    /// Replaces every chunk with the one the transform returns
+    pub fn map<F>(mut self, mut transform: F) -> Self
+    where
+        F: FnMut(ZiPatchChunk) -> ZiPatchChunk + 'a,
+    {
+        self.transforms
+            .push(Box::new(move |chunk| Some(transform(chunk))));
+        self
+    }
+
+    /// Replaces every chunk with the one the transform returns, dropping it on `None`
+    pub fn filter_map<F>(mut self, transform: F) -> Self
+    where
+        F: FnMut(ZiPatchChunk) -> Option<ZiPatchChunk> + 'a,
+    {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Rewrites the patch and returns the inner writer
+    ///
+    /// An FHDR or EOF_ chunk returned by a transform is rejected, as the rewriter writes
+    /// those itself.
+    pub fn write_to<R: Read + Seek, W: Write + Seek>(
+        &mut self,
+        patch: &mut ZiPatchFile<R>,
+        writer: W,
+    ) -> Result<W> {
+        let mut header = patch.header().clone();
+        let mut counts = ZiPatchCommandCounts {
+            total_commands: 1,
+            ..ZiPatchCommandCounts::default()
+        };
+        let mut delete_data_size = 0;
+
+        let mut writer = ZiPatchWriter::new(writer)?;
+        let header_position = writer.get_mut().stream_position()?;
+        // Placeholder of the same size, filled in once the chunks have been counted
+        writer.write_chunk(&ZiPatchChunk::FileHeader(header.clone()))?;
+
+        for chunk in patch.chunks() {
+            let chunk = chunk?;
+            if matches!(
+                chunk,
+                ZiPatchChunk::FileHeader(_) | ZiPatchChunk::EndOfFile(_)
+            ) {
+                continue;
+            }
+
+            let Some(chunk) = self.transform(chunk) else {
+                continue;
+            };
+            if matches!(
+                chunk,
+                ZiPatchChunk::FileHeader(_) | ZiPatchChunk::EndOfFile(_)
+            ) {
+                return Err(ZiPatchError::Custom(format!(
+                    "Rewritten patches can't contain a {} chunk",
+                    chunk.chunk_type()
+                )));
+            }
+
+            delete_data_size += count_chunk(&mut counts, &chunk);
+            writer.write_chunk(&chunk)?;
+        }
+
+        // V2 headers have no counts to update
+        if header.version == 3 {
+            header.add_directories = counts.add_directories;
+            header.delete_directories = counts.delete_directories;
+            header.delete_data_size = delete_data_size;
+            header.command_counts = Some(counts);
+        }
+
+        let end_position = writer.get_mut().stream_position()?;
+        writer.get_mut().seek(SeekFrom::Start(header_position))?;
+        ZiPatchChunk::FileHeader(header).write(writer.get_mut())?;
+        writer.get_mut().seek(SeekFrom::Start(end_position))?;
+
+        writer.finish()
+    }
+
+    fn transform(&mut self, chunk: ZiPatchChunk) -> Option<ZiPatchChunk> {
+        self.transforms
+            .iter_mut()
+            .try_fold(chunk, |chunk, transform| transform(chunk))
+    }
+}
+
+impl std::fmt::Debug for PatchRewriter<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PatchRewriter")
+            .field("transforms", &self.transforms.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::sqpk::{RegionId, SqpkDeleteData, SqpkTargetInfo};
+    use crate::chunk::SqpkCommand;
+    use crate::config::Platform;
+    use crate::generate::PatchBuilder;
+    use crate::test_support::{add_data, dat_file, open_patch};
+    use std::io::Cursor;
+
+    fn target_info(is_debug: bool) -> ZiPatchChunk {
+        ZiPatchChunk::Sqpk(SqpkCommand::TargetInfo(SqpkTargetInfo {
+            alignment: [0; 3],
+            platform: Platform::Win32,
+            region: RegionId::Global,
+            debug: is_debug as i16,
+            version: 0,
+            deleted_data_size: 0,
+            seek_count: 0,
+            reserved: Vec::new(),
+        }))
+    }
+
+    #[test]
+    fn test_rewrite_drops_chunks_and_recounts() {
+        let mut builder = PatchBuilder::new("DIFF");
+        builder.push(target_info(true));
+        builder.push(target_info(false));
+        builder.add_directory("movie/ffxiv");
+        builder.add_file("movie/ffxiv/00000.bk2", b"movie").unwrap();
+        builder.add_file("boot/ffxivboot.exe", b"boot").unwrap();
+        builder.delete_file("movie/ffxiv/old.bk2");
+        let mut patch = ZiPatchFile::new(Cursor::new(builder.to_bytes().unwrap())).unwrap();
+
+        let data = PatchRewriter::new()
+            .filter(|chunk| {
+                !matches!(chunk, ZiPatchChunk::Sqpk(SqpkCommand::File(file))
+                    if file.target_file.relative_path.starts_with("movie/"))
+            })
+            .filter(|chunk| {
+                !matches!(chunk, ZiPatchChunk::Sqpk(SqpkCommand::TargetInfo(info)) if info.is_debug())
+            })
+            .map(|chunk| match chunk {
+                ZiPatchChunk::AddDirectory(mut adir) => {
+                    adir.dir_name = adir.dir_name.replace("movie", "sqpack");
+                    ZiPatchChunk::AddDirectory(adir)
+                }
+                chunk => chunk,
+            })
+            .write_to(&mut patch, Cursor::new(Vec::new()))
+            .unwrap()
+            .into_inner();
+
+        let mut rewritten = ZiPatchFile::new(Cursor::new(data)).unwrap();
+        let actual_counts = rewritten.calculate_actual_counts().unwrap();
+        assert_eq!(rewritten.header().command_counts, Some(actual_counts));
+        assert_eq!(rewritten.header().patch_type, "DIFF");
+
+        let chunks: Vec<ZiPatchChunk> = rewritten.chunks().map(Result::unwrap).collect();
+        assert_eq!(chunks.len(), 5);
+        assert!(matches!(
+            &chunks[1],
+            ZiPatchChunk::Sqpk(SqpkCommand::TargetInfo(info)) if !info.is_debug()
+        ));
+        assert!(matches!(
+            &chunks[2],
+            ZiPatchChunk::AddDirectory(adir) if adir.dir_name == "sqpack/ffxiv"
+        ));
+        assert!(matches!(
+            &chunks[3],
+            ZiPatchChunk::Sqpk(SqpkCommand::File(file))
+                if file.target_file.relative_path == "boot/ffxivboot.exe"
+        ));
+    }
+
+    #[test]
+    fn test_rewrite_recounts_header_for_kept_chunks() {
+        let mut builder = PatchBuilder::new("D");
+        builder.add_directory("sqpack/ffxiv");
+        builder.add_directory("sqpack/ex1");
+        builder.delete_directory("sqpack/old");
+        builder.push(add_data(dat_file(0, 0, 0), 0, vec![1; 0x80], 0x100));
+        builder.push(add_data(dat_file(0, 0x100, 0), 0, vec![2; 0x80], 0x200));
+        builder.push(ZiPatchChunk::Sqpk(SqpkCommand::DeleteData(
+            SqpkDeleteData {
+                alignment: [0; 3],
+                target_file: dat_file(0, 0, 0),
+                block_offset: 0x80,
+                block_number: 3,
+                reserved: 0,
+            },
+        )));
+        let mut patch = open_patch(&builder);
+        assert_eq!(patch.header().add_directories, 2);
+        assert_eq!(patch.header().delete_directories, 1);
+        assert_eq!(patch.header().delete_data_size, 0x100 + 0x200 + (3 << 7));
+
+        // Keep only what targets the base game
+        let data = PatchRewriter::new()
+            .filter(|chunk| match chunk {
+                ZiPatchChunk::AddDirectory(adir) => adir.dir_name == "sqpack/ffxiv",
+                ZiPatchChunk::DeleteDirectory(_) => false,
+                ZiPatchChunk::Sqpk(SqpkCommand::AddData(add)) => {
+                    add.target_file.sqpack.sub_id >> 8 == 0
+                }
+                _ => true,
+            })
+            .write_to(&mut patch, Cursor::new(Vec::new()))
+            .unwrap()
+            .into_inner();
+
+        let rewritten = ZiPatchFile::new(Cursor::new(data)).unwrap();
+        let header = rewritten.header();
+        assert_eq!(header.patch_type, "D");
+        assert_eq!(header.add_directories, 1);
+        assert_eq!(header.delete_directories, 0);
+        assert_eq!(header.delete_data_size, 0x100 + (3 << 7));
+        assert_eq!(
+            header.command_counts,
+            Some(ZiPatchCommandCounts::with_counts(1, 0, 4, 1, 1, 0, 0, 0))
+        );
+    }
+}
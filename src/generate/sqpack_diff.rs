@@ -0,0 +1,640 @@
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use super::PatchBuilder;
+use crate::chunk::sqpk::{
+    SqpkAddData, SqpkDeleteData, SqpkExpandData, SqpkHeader, TargetFile, TargetFileKind,
+    TargetHeaderKind,
+};
+use crate::chunk::{SqpkCommand, ZiPatchChunk};
+use crate::config::Platform;
+use crate::error::{Result, ZiPatchError};
+use crate::sqpack::SQPACK_HEADER_SIZE;
+use crate::util::{SqexFile, SqpackDatFile, SqpackFile, SqpackIndexFile};
+
+/// Granularity of SqPack data, in bytes
+const BLOCK_SIZE: usize = 128;
+
+/// Offset of the data following the version header and the index or data header
+const BODY_OFFSET: u64 = 2 * SQPACK_HEADER_SIZE as u64;
+
+/// Largest amount of data written by a single SqpkAddData command
+const MAX_ADD_DATA_SIZE: usize = 0x4_0000;
+
+/// Generates a patch turning one version of a `sqpack/<expansion>` directory into another
+///
+/// Files are matched by name, and only `.datN`, `.index` and `.indexN` files for the
+/// configured platform are considered. Changed headers are written with SqpkHeader.
+/// Dat bodies are compared in 128-byte blocks: empty file blocks become SqpkDeleteData, or
+/// SqpkExpandData where they grow the file, and any other changed blocks become SqpkAddData,
+/// with runs of zeroed blocks folded into the command's delete count.
+///
+/// Index bodies can't be targeted by SqpkAddData, so their changed blocks are written with
+/// SqpkFile AddFile at the changed offsets. An index that shrank is rewritten whole, as
+/// nothing else can truncate it; dat files can't shrink. Files that were removed get an
+/// SqpkFile DeleteFile.
+///
+/// # Example
+///
+/// ```no_run
+/// use zipatch::generate::SqpackDiff;
+/// use zipatch::Platform;
+///
+/// let patch = SqpackDiff::new("old/sqpack/ffxiv", "new/sqpack/ffxiv")
+///     .platform(Platform::Win32)
+///     .generate()?;
+/// patch.write_to(std::fs::File::create("D2024.01.01.0000.0000.patch")?)?;
+/// # Ok::<(), zipatch::ZiPatchError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct SqpackDiff {
+    old_dir: PathBuf,
+    new_dir: PathBuf,
+    platform: Platform,
+}
+
+impl SqpackDiff {
+    /// Creates a diff between two versions of an expansion's sqpack directory
+    ///
+    /// The old directory may be missing, meaning every file is new.
+    pub fn new<P: Into<PathBuf>, Q: Into<PathBuf>>(old_dir: P, new_dir: Q) -> Self {
+        Self {
+            old_dir: old_dir.into(),
+            new_dir: new_dir.into(),
+            platform: Platform::Win32,
+        }
+    }
+
+    /// Sets the platform whose files are compared (defaults to Win32)
+    pub fn platform(mut self, platform: Platform) -> Self {
+        self.platform = platform;
+        self
+    }
+
+    /// Compares the directories and collects the patch chunks
+    pub fn generate(&self) -> Result<PatchBuilder> {
+        let mut names = list_file_names(&self.old_dir)?;
+        names.extend(list_file_names(&self.new_dir)?);
+
+        let mut builder = PatchBuilder::new("DIFF");
+        for name in names {
+            let Some(target) = self.parse_file_name(&name) else {
+                continue;
+            };
+
+            let old_path = Some(self.old_dir.join(&name)).filter(|path| path.is_file());
+            let new_path = self.new_dir.join(&name);
+
+            if !new_path.is_file() {
+                builder.delete_file(&self.relative_path(&target));
+                continue;
+            }
+
+            match target {
+                TargetFile::Dat(ref dat) => {
+                    diff_dat(&mut builder, dat, old_path.as_deref(), &new_path)?
+                }
+                TargetFile::Index(_) => {
+                    let path = self.relative_path(&target);
+                    diff_index(&mut builder, &target, &path, old_path.as_deref(), &new_path)?
+                }
+            }
+        }
+
+        Ok(builder)
+    }
+
+    /// Parses a `{main_id:02x}{sub_id:04x}.{platform}.dat{N}` or `.index{N}` file name
+    fn parse_file_name(&self, name: &str) -> Option<TargetFile> {
+        let (id, rest) = name.split_once('.')?;
+        let (_, extension) = rest.split_once('.')?;
+        if id.len() != 6 || !id.is_ascii() {
+            return None;
+        }
+
+        let sqpack = SqpackFile {
+            main_id: u16::from_str_radix(&id[..2], 16).ok()?,
+            sub_id: u16::from_str_radix(&id[2..], 16).ok()?,
+            file_id: 0,
+            sqex_file: SqexFile::default(),
+        };
+
+        let target = if let Some(number) = extension.strip_prefix("dat") {
+            TargetFile::Dat(SqpackDatFile {
+                sqpack: SqpackFile {
+                    file_id: number.parse().ok()?,
+                    ..sqpack
+                },
+            })
+        } else if let Some(number) = extension.strip_prefix("index") {
+            let file_id = if number.is_empty() {
+                0
+            } else {
+                number.parse().ok()?
+            };
+            TargetFile::Index(SqpackIndexFile {
+                sqpack: SqpackFile { file_id, ..sqpack },
+            })
+        } else {
+            return None;
+        };
+
+        // Rebuilding the name checks the platform and rejects non-canonical spellings
+        let expected = self.relative_path(&target);
+        expected
+            .strip_suffix(name)
+            .is_some_and(|prefix| prefix.ends_with('/'))
+            .then_some(target)
+    }
+
+    fn relative_path(&self, target: &TargetFile) -> String {
+        match target {
+            TargetFile::Dat(dat) => dat.get_file_name(self.platform),
+            TargetFile::Index(index) => index.get_file_name(self.platform),
+        }
+    }
+}
+
+/// Lists the names of the files directly inside a directory, which may be missing
+fn list_file_names(dir: &Path) -> Result<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    if !dir.is_dir() {
+        return Ok(names);
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.path().is_file() {
+            if let Ok(name) = entry.file_name().into_string() {
+                names.insert(name);
+            }
+        }
+    }
+
+    Ok(names)
+}
+
+/// Adds SqpkHeader commands for the headers that differ between two files
+fn diff_headers(builder: &mut PatchBuilder, target: &TargetFile, old: &[u8], new: &[u8]) {
+    let (file_kind, second_header) = match target {
+        TargetFile::Dat(_) => (TargetFileKind::Dat, TargetHeaderKind::Data),
+        TargetFile::Index(_) => (TargetFileKind::Index, TargetHeaderKind::Index),
+    };
+
+    for (index, header_kind) in [TargetHeaderKind::Version, second_header]
+        .into_iter()
+        .enumerate()
+    {
+        let range = index * SQPACK_HEADER_SIZE..(index + 1) * SQPACK_HEADER_SIZE;
+        if old.get(range.clone()) != Some(&new[range.clone()]) {
+            builder.push(ZiPatchChunk::Sqpk(SqpkCommand::Header(SqpkHeader {
+                file_kind,
+                header_kind,
+                alignment: 0,
+                target_file: target.clone(),
+                header_data: new[range].to_vec(),
+            })));
+        }
+    }
+}
+
+fn invalid_file(path: &Path, reason: &str) -> ZiPatchError {
+    ZiPatchError::InvalidSqpackFile(format!("{}: {}", path.display(), reason))
+}
+
+fn diff_index(
+    builder: &mut PatchBuilder,
+    target: &TargetFile,
+    relative_path: &str,
+    old_path: Option<&Path>,
+    new_path: &Path,
+) -> Result<()> {
+    let new = fs::read(new_path)?;
+    let old = match old_path {
+        Some(path) => fs::read(path)?,
+        None => Vec::new(),
+    };
+
+    if new.len() < BODY_OFFSET as usize {
+        return Err(invalid_file(new_path, "file is shorter than its headers"));
+    }
+    if new.len() < old.len() {
+        return builder.add_file(relative_path, &new);
+    }
+
+    diff_headers(builder, target, &old, &new);
+
+    // The end of the file closes the last run of changed blocks
+    let mut changed: Option<usize> = None;
+    let block_offsets = (BODY_OFFSET as usize..new.len()).step_by(BLOCK_SIZE);
+    for offset in block_offsets.chain([new.len()]) {
+        let end = (offset + BLOCK_SIZE).min(new.len());
+        let is_changed = offset < new.len() && old.get(offset..end) != Some(&new[offset..end]);
+
+        match (changed, is_changed) {
+            (None, true) => changed = Some(offset),
+            (Some(start), false) => {
+                builder.add_file_range(
+                    relative_path,
+                    start as u64,
+                    new.len() as u64,
+                    &new[start..offset],
+                )?;
+                changed = None;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Sequential reader of 128-byte blocks, seeking only when asked for a different block
+struct BlockReader {
+    reader: BufReader<File>,
+    position: u64,
+    len: u64,
+}
+
+impl BlockReader {
+    fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+
+        Ok(Self {
+            reader: BufReader::new(file),
+            position: 0,
+            len,
+        })
+    }
+
+    /// Reads the block at the given offset, returning false if it's past the end of the file
+    fn read_block(&mut self, offset: u64, block: &mut [u8; BLOCK_SIZE]) -> Result<bool> {
+        if offset + BLOCK_SIZE as u64 > self.len {
+            return Ok(false);
+        }
+
+        if offset != self.position {
+            self.reader
+                .seek_relative(offset as i64 - self.position as i64)?;
+        }
+        self.reader.read_exact(block)?;
+        self.position = offset + BLOCK_SIZE as u64;
+
+        Ok(true)
+    }
+
+    /// Reads the first `size` bytes of the file
+    fn read_start(&mut self, size: usize) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut block = [0u8; BLOCK_SIZE];
+        let mut offset = 0;
+
+        while offset < size as u64 && self.read_block(offset, &mut block)? {
+            data.extend_from_slice(&block);
+            offset += BLOCK_SIZE as u64;
+        }
+
+        Ok(data)
+    }
+}
+
+/// Consecutive changed dat blocks collected into one SqpkAddData command
+struct AddDataRun {
+    offset: u64,
+    data: Vec<u8>,
+}
+
+fn diff_dat(
+    builder: &mut PatchBuilder,
+    dat: &SqpackDatFile,
+    old_path: Option<&Path>,
+    new_path: &Path,
+) -> Result<()> {
+    let mut new = BlockReader::open(new_path)?;
+    let mut old = old_path.map(BlockReader::open).transpose()?;
+    let old_len = old.as_ref().map_or(0, |old| old.len);
+
+    if new.len < BODY_OFFSET || new.len % BLOCK_SIZE as u64 != 0 {
+        return Err(invalid_file(
+            new_path,
+            "dat files must be made of whole 128-byte blocks",
+        ));
+    }
+    if new.len < old_len {
+        return Err(invalid_file(new_path, "dat files can only grow"));
+    }
+
+    let target = TargetFile::Dat(dat.clone());
+    let old_headers = match old {
+        Some(ref mut old) => old.read_start(BODY_OFFSET as usize)?,
+        None => Vec::new(),
+    };
+    diff_headers(
+        builder,
+        &target,
+        &old_headers,
+        &new.read_start(BODY_OFFSET as usize)?,
+    );
+
+    let mut run: Option<AddDataRun> = None;
+    let mut new_block = [0u8; BLOCK_SIZE];
+    let mut old_block = [0u8; BLOCK_SIZE];
+    let mut offset = BODY_OFFSET;
+
+    while offset < new.len {
+        new.read_block(offset, &mut new_block)?;
+        let old_present = match old {
+            Some(ref mut old) => old.read_block(offset, &mut old_block)?,
+            None => false,
+        };
+
+        if old_present && old_block == new_block {
+            flush_add_data(builder, dat, run.take());
+            offset += BLOCK_SIZE as u64;
+            continue;
+        }
+
+        if let Some(block_count) = empty_file_block_at(&mut new, offset, &new_block)? {
+            flush_add_data(builder, dat, run.take());
+
+            let length = block_count << 7;
+            let command = if offset + length <= old_len {
+                SqpkCommand::DeleteData(SqpkDeleteData {
+                    alignment: [0; 3],
+                    target_file: dat.clone(),
+                    block_offset: offset as i64,
+                    block_number: block_count as u32,
+                    reserved: 0,
+                })
+            } else {
+                SqpkCommand::ExpandData(SqpkExpandData {
+                    alignment: [0; 3],
+                    target_file: dat.clone(),
+                    block_offset: offset as i64,
+                    block_number: block_count as i64,
+                    reserved: 0,
+                })
+            };
+            builder.push(ZiPatchChunk::Sqpk(command));
+
+            offset += length;
+            continue;
+        }
+
+        let current = run.get_or_insert_with(|| AddDataRun {
+            offset,
+            data: Vec::new(),
+        });
+        current.data.extend_from_slice(&new_block);
+        if current.data.len() >= MAX_ADD_DATA_SIZE {
+            flush_add_data(builder, dat, run.take());
+        }
+
+        offset += BLOCK_SIZE as u64;
+    }
+
+    flush_add_data(builder, dat, run.take());
+    Ok(())
+}
+
+/// Checks if an empty file block, as written by SqpkDeleteData and SqpkExpandData, starts
+/// at the given offset, and returns its length in blocks
+fn empty_file_block_at(
+    reader: &mut BlockReader,
+    offset: u64,
+    first_block: &[u8; BLOCK_SIZE],
+) -> Result<Option<u64>> {
+    let header_size = SqpackDatFile::EMPTY_BLOCK_HEADER_SIZE as usize;
+    let block_count = i32::from_le_bytes(first_block[12..16].try_into().unwrap()) as i64 + 1;
+
+    if block_count < 1
+        || offset + ((block_count as u64) << 7) > reader.len
+        || first_block[..header_size] != SqpackDatFile::empty_file_block_header(block_count)
+        || first_block[header_size..].iter().any(|&b| b != 0)
+    {
+        return Ok(None);
+    }
+
+    let mut block = [0u8; BLOCK_SIZE];
+    for index in 1..block_count as u64 {
+        reader.read_block(offset + (index << 7), &mut block)?;
+        if block.iter().any(|&b| b != 0) {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(block_count as u64))
+}
+
+/// Adds the SqpkAddData command for a run of changed blocks, wiping its trailing zeroed
+/// blocks instead of storing them
+fn flush_add_data(builder: &mut PatchBuilder, dat: &SqpackDatFile, run: Option<AddDataRun>) {
+    let Some(mut run) = run else {
+        return;
+    };
+
+    let zero_blocks = run
+        .data
+        .rchunks(BLOCK_SIZE)
+        .take_while(|block| block.iter().all(|&b| b == 0))
+        .count();
+    let delete_length = zero_blocks * BLOCK_SIZE;
+    run.data.truncate(run.data.len() - delete_length);
+
+    builder.push(ZiPatchChunk::Sqpk(SqpkCommand::AddData(SqpkAddData {
+        alignment: [0; 3],
+        target_file: dat.clone(),
+        block_offset: run.offset as i64,
+        block_number: run.data.len() as i64,
+        block_delete_number: delete_length as i64,
+        block_data: run.data,
+    })));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::ApplyOptions;
+    use crate::test_support::TestDir;
+    use crate::{ZiPatchConfig, ZiPatchFile};
+    use std::io::Cursor;
+
+    fn sqpack_file(version: u8, body: &[&[u8]]) -> Vec<u8> {
+        let mut data = vec![version; BODY_OFFSET as usize];
+        for part in body {
+            data.extend_from_slice(part);
+        }
+        data
+    }
+
+    fn empty_file_block(block_count: i64) -> Vec<u8> {
+        let mut data = SqpackDatFile::empty_file_block_header(block_count).to_vec();
+        data.resize((block_count as usize) << 7, 0);
+        data
+    }
+
+    #[test]
+    fn test_parse_file_name() {
+        let diff = SqpackDiff::new("old", "new");
+        assert!(matches!(
+            diff.parse_file_name("0a0100.win32.dat2"),
+            Some(TargetFile::Dat(dat)) if dat.sqpack.main_id == 0x0A
+                && dat.sqpack.sub_id == 0x0100
+                && dat.sqpack.file_id == 2
+        ));
+        assert!(matches!(
+            diff.parse_file_name("000000.win32.index2"),
+            Some(TargetFile::Index(index)) if index.sqpack.file_id == 2
+        ));
+        assert!(diff.parse_file_name("000000.ps3.index").is_none());
+        assert!(diff.parse_file_name("000000.win32.index0").is_none());
+        assert!(diff.parse_file_name("ffxivgame.ver").is_none());
+    }
+
+    #[test]
+    fn test_diff_applies_to_old_sqpack() {
+        let dir = TestDir::new("sqpack-diff");
+        let old = dir.root.join("old");
+        let new = dir.root.join("new");
+        let game = &dir.game;
+        let sqpack = Path::new("sqpack/ffxiv");
+        for root in [&old, &new, game] {
+            fs::create_dir_all(root.join(sqpack)).unwrap();
+        }
+
+        let old_dat = sqpack_file(1, &[&[0x11; 0x400], &[0x22; 0x200], &[0x33; 0x80]]);
+        let new_dat = sqpack_file(
+            2,
+            &[
+                &[0x11; 0x100],
+                &[0x44; 0x80],
+                &[0; 0x80],
+                &[0x11; 0x200],
+                &empty_file_block(4),
+                &[0x33; 0x80],
+                &[0x55; 0x100],
+                &[0; 0x100],
+                &empty_file_block(2),
+            ],
+        );
+        let old_index = sqpack_file(1, &[&[0x66; 0x300]]);
+        let new_index = sqpack_file(1, &[&[0x66; 0x100], &[0x77; 0x80], &[0x66; 0x180]]);
+        let new_dat1 = sqpack_file(3, &[&[0x88; 0x80]]);
+
+        for root in [&old, game] {
+            let dir = root.join(sqpack);
+            fs::write(dir.join("000000.win32.dat0"), &old_dat).unwrap();
+            fs::write(dir.join("000000.win32.index"), &old_index).unwrap();
+            fs::write(dir.join("000000.win32.index2"), &old_index).unwrap();
+            fs::write(dir.join("000000.ps3.dat0"), b"other platform").unwrap();
+        }
+        let dir = new.join(sqpack);
+        fs::write(dir.join("000000.win32.dat0"), &new_dat).unwrap();
+        fs::write(dir.join("000000.win32.dat1"), &new_dat1).unwrap();
+        fs::write(dir.join("000000.win32.index"), &new_index).unwrap();
+
+        let builder = SqpackDiff::new(old.join(sqpack), new.join(sqpack))
+            .generate()
+            .unwrap();
+
+        let counts = builder.command_counts();
+        // Version and data headers of both dat files
+        assert_eq!(counts.sqpk_header_commands, 4);
+        assert_eq!(counts.sqpk_delete_commands, 1);
+        assert_eq!(counts.sqpk_expand_commands, 1);
+        assert_eq!(counts.sqpk_add_commands, 3);
+        // index block and index2 removal
+        assert_eq!(counts.sqpk_file_commands, 2);
+
+        let mut patch = ZiPatchFile::new(Cursor::new(builder.to_bytes().unwrap())).unwrap();
+        let mut config = ZiPatchConfig::builder(game)
+            .platform(Platform::Win32)
+            .build();
+        patch.apply(&mut config, &ApplyOptions::default()).unwrap();
+
+        let dir = game.join(sqpack);
+        assert_eq!(fs::read(dir.join("000000.win32.dat0")).unwrap(), new_dat);
+        assert_eq!(fs::read(dir.join("000000.win32.dat1")).unwrap(), new_dat1);
+        assert_eq!(fs::read(dir.join("000000.win32.index")).unwrap(), new_index);
+        assert!(!dir.join("000000.win32.index2").exists());
+        assert!(dir.join("000000.ps3.dat0").exists());
+    }
+
+    #[test]
+    fn test_diff_rejects_invalid_dat_files() {
+        let dir = TestDir::new("sqpack-diff-invalid");
+        let old = dir.root.join("old");
+        let new = dir.root.join("new");
+        fs::create_dir_all(&old).unwrap();
+        fs::create_dir_all(&new).unwrap();
+        let dat = "000000.win32.dat0";
+
+        fs::write(old.join(dat), sqpack_file(1, &[&[1; 0x100]])).unwrap();
+        fs::write(new.join(dat), sqpack_file(1, &[&[1; 0x80]])).unwrap();
+        assert!(matches!(
+            SqpackDiff::new(&old, &new).generate(),
+            Err(ZiPatchError::InvalidSqpackFile(_))
+        ));
+
+        fs::write(new.join(dat), sqpack_file(1, &[&[1; 0x100], &[2; 0x10]])).unwrap();
+        assert!(matches!(
+            SqpackDiff::new(&old, &new).generate(),
+            Err(ZiPatchError::InvalidSqpackFile(_))
+        ));
+    }
+
+    #[test]
+    fn test_diff_splits_long_runs_and_rewrites_shrunk_index() {
+        let dir = TestDir::new("sqpack-diff-runs");
+        let old = dir.root.join("old");
+        let new = dir.root.join("new");
+        fs::create_dir_all(&old).unwrap();
+        fs::create_dir_all(&new).unwrap();
+
+        let unchanged = sqpack_file(1, &[&[9; 0x80]]);
+        fs::write(old.join("000000.win32.dat1"), &unchanged).unwrap();
+        fs::write(new.join("000000.win32.dat1"), &unchanged).unwrap();
+        fs::write(old.join("000000.win32.dat0"), sqpack_file(1, &[])).unwrap();
+        fs::write(
+            new.join("000000.win32.dat0"),
+            sqpack_file(1, &[&vec![1; MAX_ADD_DATA_SIZE + 0x80]]),
+        )
+        .unwrap();
+        fs::write(
+            old.join("000000.win32.index"),
+            sqpack_file(1, &[&[6; 0x200]]),
+        )
+        .unwrap();
+        let new_index = sqpack_file(1, &[&[6; 0x80]]);
+        fs::write(new.join("000000.win32.index"), &new_index).unwrap();
+
+        let builder = SqpackDiff::new(&old, &new).generate().unwrap();
+        let commands: Vec<(u64, usize)> = builder
+            .chunks()
+            .iter()
+            .map(|chunk| match chunk {
+                ZiPatchChunk::Sqpk(SqpkCommand::AddData(add)) => {
+                    (add.block_offset as u64, add.block_data.len())
+                }
+                ZiPatchChunk::Sqpk(SqpkCommand::File(file)) => {
+                    assert_eq!(file.file_offset, 0);
+                    (0, file.decompressed_length() as usize)
+                }
+                chunk => panic!("unexpected chunk {}", chunk.chunk_type()),
+            })
+            .collect();
+
+        // The unchanged dat file gets no commands, and the shrunk index is written whole
+        assert_eq!(
+            commands,
+            [
+                (BODY_OFFSET, MAX_ADD_DATA_SIZE),
+                (BODY_OFFSET + MAX_ADD_DATA_SIZE as u64, 0x80),
+                (0, new_index.len()),
+            ]
+        );
+    }
+}
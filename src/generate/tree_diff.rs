@@ -0,0 +1,292 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::PatchBuilder;
+use crate::error::{Result, ZiPatchError};
+
+/// Directories and files of a loose-file tree, as `/`-separated paths relative to its root
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileTree {
+    /// Every directory below the root
+    pub directories: BTreeSet<String>,
+    /// Every file below the root
+    pub files: BTreeSet<String>,
+}
+
+impl FileTree {
+    /// Walks a directory on the local filesystem
+    ///
+    /// A missing root is treated as an empty tree. Symlinks are followed, except those
+    /// leading back to a directory the walk is already in, which are skipped so a symlink
+    /// loop can't recurse forever.
+    pub fn scan<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let root = root.as_ref();
+        let mut tree = Self::default();
+        if root.is_dir() {
+            tree.scan_dir(root, "", &mut vec![fs::canonicalize(root)?])?;
+        }
+        Ok(tree)
+    }
+
+    /// Walks a directory, given the canonical paths of it and the directories it's in
+    fn scan_dir(&mut self, dir: &Path, prefix: &str, ancestors: &mut Vec<PathBuf>) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().into_string().map_err(|name| {
+                ZiPatchError::Custom(format!(
+                    "Path is not valid UTF-8: {}",
+                    dir.join(name).display()
+                ))
+            })?;
+            let path = format!("{}{}", prefix, name);
+
+            if entry.path().is_dir() {
+                let canonical = fs::canonicalize(entry.path())?;
+                if ancestors.contains(&canonical) {
+                    continue;
+                }
+
+                ancestors.push(canonical);
+                self.scan_dir(&entry.path(), &format!("{}/", path), ancestors)?;
+                ancestors.pop();
+                self.directories.insert(path);
+            } else {
+                self.files.insert(path);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gets the directories that have no subdirectories in the given set
+    pub(crate) fn leaf_directories(directories: &BTreeSet<String>) -> Vec<&str> {
+        directories
+            .iter()
+            .filter(|dir| {
+                let prefix = format!("{}/", dir);
+                !directories
+                    .range(prefix.clone()..)
+                    .next()
+                    .is_some_and(|next| next.starts_with(&prefix))
+            })
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// Generates a patch turning one loose-file tree into another
+///
+/// New directories get an ADIR chunk each, parents first. New and changed files are written
+/// whole with SqpkFile AddFile; removed files get SqpkFile DeleteFile, and removed directories
+/// a DELD chunk each, deepest first. Removals come last, except for paths that change between
+/// a file and a directory and everything below them, which are removed before anything is
+/// added so the path is free for the other type.
+///
+/// # Example
+///
+/// ```no_run
+/// use zipatch::generate::TreeDiff;
+///
+/// let patch = TreeDiff::new("mods/v1", "mods/v2").generate()?;
+/// patch.write_to(std::fs::File::create("mods-v2.patch")?)?;
+/// # Ok::<(), zipatch::ZiPatchError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct TreeDiff {
+    old_root: PathBuf,
+    new_root: PathBuf,
+    patch_type: String,
+}
+
+impl TreeDiff {
+    /// Creates a diff between two directories; either may be missing, meaning an empty tree
+    pub fn new<P: Into<PathBuf>, Q: Into<PathBuf>>(old_root: P, new_root: Q) -> Self {
+        Self {
+            old_root: old_root.into(),
+            new_root: new_root.into(),
+            patch_type: "DIFF".to_string(),
+        }
+    }
+
+    /// Sets the patch type written to the FHDR chunk (defaults to "DIFF")
+    pub fn patch_type<S: Into<String>>(mut self, patch_type: S) -> Self {
+        self.patch_type = patch_type.into();
+        self
+    }
+
+    /// Compares the trees and collects the patch chunks
+    pub fn generate(&self) -> Result<PatchBuilder> {
+        let old = FileTree::scan(&self.old_root)?;
+        let new = FileTree::scan(&self.new_root)?;
+        let mut builder = PatchBuilder::new(self.patch_type.clone());
+
+        // A path turning into a directory, or into a file in place of a directory, must be
+        // cleared before it's added again
+        let replaced = |path: &str| {
+            new.directories.contains(path)
+                || path_and_parents(path).any(|path| new.files.contains(path))
+        };
+        let (replaced_files, removed_files): (Vec<&String>, Vec<&String>) = old
+            .files
+            .difference(&new.files)
+            .partition(|file| replaced(file));
+        let (replaced_directories, removed_directories): (Vec<&String>, Vec<&String>) = old
+            .directories
+            .difference(&new.directories)
+            .partition(|dir| replaced(dir));
+        Self::delete(&mut builder, &replaced_files, &replaced_directories);
+
+        // Sorted order puts parents before their children
+        let added_directories: BTreeSet<String> = new
+            .directories
+            .difference(&old.directories)
+            .cloned()
+            .collect();
+        for dir in &added_directories {
+            builder.add_directory(dir.clone());
+        }
+
+        for file in &new.files {
+            let data = fs::read(self.new_root.join(file))?;
+            if old.files.contains(file) && fs::read(self.old_root.join(file))? == data {
+                continue;
+            }
+            builder.add_file(file, &data)?;
+        }
+
+        Self::delete(&mut builder, &removed_files, &removed_directories);
+
+        Ok(builder)
+    }
+
+    /// Adds the chunks deleting files, then directories deepest first
+    fn delete(builder: &mut PatchBuilder, files: &[&String], directories: &[&String]) {
+        for file in files {
+            builder.delete_file(file);
+        }
+
+        // Sorted order puts parents before their children, so reverse it
+        for dir in directories.iter().rev() {
+            builder.delete_directory(dir.as_str());
+        }
+    }
+}
+
+/// Iterates over a `/`-separated path and each of its parents, innermost first
+fn path_and_parents(path: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(path), |path| {
+        path.rsplit_once('/').map(|(parent, _)| parent)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::ApplyOptions;
+    use crate::test_support::TestDir;
+    use crate::{ZiPatchConfig, ZiPatchFile};
+    use std::io::Cursor;
+
+    fn write(root: &Path, path: &str, data: &[u8]) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn test_leaf_directories() {
+        let directories: BTreeSet<String> = ["a", "a/b", "a/b/c", "a/d", "ab"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            FileTree::leaf_directories(&directories),
+            ["a/b/c", "a/d", "ab"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_skips_symlink_loops() {
+        let dir = TestDir::new("tree-scan-symlinks");
+        dir.write("a/file.txt", b"file");
+        std::os::unix::fs::symlink(&dir.game, dir.path("a/loop")).unwrap();
+        std::os::unix::fs::symlink(dir.path("a"), dir.path("b")).unwrap();
+        std::os::unix::fs::symlink(dir.path("a/file.txt"), dir.path("link.txt")).unwrap();
+
+        let tree = FileTree::scan(&dir.game).unwrap();
+        assert_eq!(tree.directories.into_iter().collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(
+            tree.files.into_iter().collect::<Vec<_>>(),
+            ["a/file.txt", "b/file.txt", "link.txt"]
+        );
+    }
+
+    #[test]
+    fn test_diff_applies_to_old_tree() {
+        let dir = TestDir::new("tree-diff");
+        let old = dir.root.join("old");
+        let new = dir.root.join("new");
+        let game = &dir.game;
+
+        for root in [&old, game] {
+            write(root, "boot/unchanged.dll", b"same");
+            write(root, "boot/changed.exe", b"old version");
+            write(root, "boot/removed.txt", b"gone soon");
+            write(root, "old/nested/file.dat", b"gone too");
+        }
+        write(&new, "boot/unchanged.dll", b"same");
+        write(&new, "boot/changed.exe", &vec![0x5A; 40_000]);
+        write(&new, "mods/textures/new.tex", b"new file");
+        write(&new, "mods/empty.txt", b"");
+        fs::create_dir_all(new.join("mods/empty")).unwrap();
+
+        let builder = TreeDiff::new(&old, &new).generate().unwrap();
+        let mut patch = ZiPatchFile::new(Cursor::new(builder.to_bytes().unwrap())).unwrap();
+        let mut config = ZiPatchConfig::new(game);
+        patch.apply(&mut config, &ApplyOptions::default()).unwrap();
+
+        let applied = FileTree::scan(game).unwrap();
+        assert_eq!(applied, FileTree::scan(&new).unwrap());
+        for file in &applied.files {
+            assert_eq!(
+                fs::read(game.join(file)).unwrap(),
+                fs::read(new.join(file)).unwrap()
+            );
+        }
+
+        let counts = builder.command_counts();
+        assert_eq!(counts.add_directories, 3);
+        assert_eq!(counts.delete_directories, 2);
+    }
+
+    #[test]
+    fn test_diff_replaces_files_and_directories() {
+        let dir = TestDir::new("tree-diff-replace");
+        let old = dir.root.join("old");
+        let new = dir.root.join("new");
+        let game = &dir.game;
+
+        // A file becoming a directory, and a directory with contents becoming a file
+        for root in [&old, game] {
+            write(root, "mods/config", b"a file for now");
+            write(root, "mods/textures/a.tex", b"texture");
+            write(root, "mods/textures/hd/b.tex", b"hd texture");
+        }
+        write(&new, "mods/config/settings.ini", b"now a directory");
+        write(&new, "mods/textures", b"now a file");
+
+        let builder = TreeDiff::new(&old, &new).generate().unwrap();
+        let mut patch = ZiPatchFile::new(Cursor::new(builder.to_bytes().unwrap())).unwrap();
+        let mut config = ZiPatchConfig::new(game);
+        patch.apply(&mut config, &ApplyOptions::default()).unwrap();
+
+        assert_eq!(FileTree::scan(game).unwrap(), FileTree::scan(&new).unwrap());
+        assert_eq!(
+            fs::read(game.join("mods/config/settings.ini")).unwrap(),
+            b"now a directory"
+        );
+        assert_eq!(fs::read(game.join("mods/textures")).unwrap(), b"now a file");
+    }
+}
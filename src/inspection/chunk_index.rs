@@ -0,0 +1,378 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::apply::ChunkInfo;
+use crate::chunk::sqpk::TargetFile;
+use crate::chunk::{SqpkCommand, ZiPatchChunk, CHUNK_TYPES};
+use crate::config::Platform;
+use crate::error::{Result, ZiPatchError};
+use crate::util::{normalize_path, BinaryReaderExt, BinaryWriterExt};
+
+/// Magic number at the start of a chunk index file
+const CHUNK_INDEX_MAGIC: &[u8; 4] = b"ZPCI";
+
+/// Chunk index format version
+const CHUNK_INDEX_VERSION: u32 = 2;
+
+/// Longest target path accepted when reading an index
+const MAX_TARGET_PATH_LEN: u32 = 4096;
+
+/// Where a chunk sits in a patch file, and what it touches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkIndexEntry {
+    /// Offset of the chunk in the patch file
+    pub offset: u64,
+    /// Size of the chunk in the patch file, including its size, type and checksum fields
+    pub size: u64,
+    /// Chunk type (e.g. "SQPK", "ADIR")
+    pub chunk_type: &'static str,
+    /// SQPK command character, for SQPK chunks
+    pub sqpk_command: Option<char>,
+    /// Normalized path of the file or directory the chunk targets, if any
+    ///
+    /// SqPack paths are resolved for the platform in effect at the chunk. SqpkFile RemoveAll
+    /// commands, which delete a whole expansion's files, have no target.
+    pub target_file: Option<String>,
+}
+
+impl ChunkIndexEntry {
+    /// Creates the entry for a chunk, resolving SqPack paths for the given platform
+    pub(crate) fn new(info: &ChunkInfo, chunk: &ZiPatchChunk, platform: Platform) -> Self {
+        let target_file = match chunk {
+            ZiPatchChunk::AddDirectory(adir) => Some(adir.dir_name.clone()),
+            ZiPatchChunk::DeleteDirectory(deld) => Some(deld.dir_name.clone()),
+            ZiPatchChunk::Sqpk(command) => match command {
+                SqpkCommand::Header(header) => Some(match &header.target_file {
+                    TargetFile::Dat(dat) => dat.get_file_name(platform),
+                    TargetFile::Index(index) => index.get_file_name(platform),
+                }),
+                SqpkCommand::AddData(add) => Some(add.target_file.get_file_name(platform)),
+                SqpkCommand::DeleteData(delete) => Some(delete.target_file.get_file_name(platform)),
+                SqpkCommand::ExpandData(expand) => Some(expand.target_file.get_file_name(platform)),
+                SqpkCommand::Index(index) => Some(index.target_file.get_file_name(platform)),
+                SqpkCommand::File(file) => Some(file.target_file.relative_path.clone()),
+                SqpkCommand::TargetInfo(_) | SqpkCommand::PatchInfo(_) => None,
+            },
+            _ => None,
+        };
+
+        Self {
+            offset: info.offset,
+            size: info.size,
+            chunk_type: info.chunk_type,
+            sqpk_command: info.sqpk_command,
+            target_file: target_file
+                .map(|path| normalize_path(&path))
+                .filter(|path| !path.is_empty()),
+        }
+    }
+}
+
+/// Index of every chunk in a patch, for random access without walking the whole file
+///
+/// Built by [`ZiPatchFile::build_chunk_index`] and used with
+/// [`ZiPatchFile::chunk`]. The index can be saved next to the patch and loaded again later;
+/// it records the length and FHDR chunk checksum of the patch it was built from, so an index
+/// for a different patch is rejected.
+///
+/// [`ZiPatchFile::build_chunk_index`]: crate::ZiPatchFile::build_chunk_index
+/// [`ZiPatchFile::chunk`]: crate::ZiPatchFile::chunk
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChunkIndex {
+    /// Total length of the patch file the index was built from
+    pub patch_length: u64,
+    /// Checksum of the FHDR chunk of the patch file the index was built from
+    pub header_checksum: u32,
+    entries: Vec<ChunkIndexEntry>,
+}
+
+impl ChunkIndex {
+    /// Creates an empty index for a patch of the given length and FHDR chunk checksum
+    pub(crate) fn new(patch_length: u64, header_checksum: u32) -> Self {
+        Self {
+            patch_length,
+            header_checksum,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Adds the entry for the next chunk
+    pub(crate) fn push(&mut self, entry: ChunkIndexEntry) {
+        self.entries.push(entry);
+    }
+
+    /// Gets every entry, in patch order
+    pub fn entries(&self) -> &[ChunkIndexEntry] {
+        &self.entries
+    }
+
+    /// Gets the entry of the n-th chunk, counting from 0 for the first chunk after the magic
+    pub fn get(&self, n: usize) -> Option<&ChunkIndexEntry> {
+        self.entries.get(n)
+    }
+
+    /// Gets the number of chunks in the index
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Checks if the index has no chunks
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Finds the chunks targeting a file or directory, along with their chunk numbers
+    ///
+    /// The path is normalized first, so either separator and a leading one are accepted.
+    pub fn chunks_for_file<'a>(
+        &'a self,
+        path: &str,
+    ) -> impl Iterator<Item = (usize, &'a ChunkIndexEntry)> + 'a {
+        let path = normalize_path(path);
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(move |(_, entry)| entry.target_file.as_deref() == Some(path.as_str()))
+    }
+
+    /// Loads an index from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        Self::read_from(&mut reader)
+    }
+
+    /// Saves the index to a file
+    ///
+    /// The index is written to a temporary file next to `path`, synced, and renamed over it,
+    /// so a crash never leaves a partially written index behind.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".tmp");
+        let temp_path = path.with_file_name(name);
+
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        self.write_to(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// Reads an index from a reader
+    ///
+    /// An index that ends early, as when a crash cut it short, is reported as
+    /// [`ZiPatchError::InvalidChunkIndex`] like any other unreadable index.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        Self::read_entries(reader).map_err(|e| match e {
+            ZiPatchError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                ZiPatchError::InvalidChunkIndex("chunk index file is truncated".to_string())
+            }
+            e => e,
+        })
+    }
+
+    fn read_entries<R: Read>(reader: &mut R) -> Result<Self> {
+        let invalid = |reason: &str| ZiPatchError::InvalidChunkIndex(reason.to_string());
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        let version = reader.read_u32_le()?;
+        if &magic != CHUNK_INDEX_MAGIC || version != CHUNK_INDEX_VERSION {
+            return Err(invalid("not a supported chunk index file"));
+        }
+
+        let patch_length = reader.read_u64_le()?;
+        let header_checksum = reader.read_u32_le()?;
+        let count = reader.read_u64_le()?;
+
+        let mut index = Self::new(patch_length, header_checksum);
+        for _ in 0..count {
+            let offset = reader.read_u64_le()?;
+            let size = reader.read_u64_le()?;
+            let chunk_type = reader.read_chunk_type()?;
+            let chunk_type = CHUNK_TYPES
+                .into_iter()
+                .find(|known| *known == chunk_type)
+                .ok_or_else(|| invalid(&format!("unknown chunk type '{}'", chunk_type)))?;
+
+            let mut command = [0u8; 1];
+            reader.read_exact(&mut command)?;
+            let sqpk_command = (command[0] != 0).then(|| char::from(command[0]));
+
+            let target_len = reader.read_u32_le()?;
+            let target_file = match target_len {
+                u32::MAX => None,
+                len if len > MAX_TARGET_PATH_LEN => {
+                    return Err(invalid(&format!("target path of {} bytes", len)))
+                }
+                len => Some(String::from_utf8(
+                    reader.read_bytes_required(len as usize)?,
+                )?),
+            };
+
+            index.push(ChunkIndexEntry {
+                offset,
+                size,
+                chunk_type,
+                sqpk_command,
+                target_file,
+            });
+        }
+
+        Ok(index)
+    }
+
+    /// Writes the index to a writer
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(CHUNK_INDEX_MAGIC)?;
+        writer.write_u32_le(CHUNK_INDEX_VERSION)?;
+        writer.write_u64_le(self.patch_length)?;
+        writer.write_u32_le(self.header_checksum)?;
+        writer.write_u64_le(self.entries.len() as u64)?;
+
+        for entry in &self.entries {
+            writer.write_u64_le(entry.offset)?;
+            writer.write_u64_le(entry.size)?;
+            writer.write_chunk_type(entry.chunk_type)?;
+            writer.write_all(&[entry.sqpk_command.map_or(0, |command| command as u8)])?;
+
+            match &entry.target_file {
+                Some(path) => {
+                    writer.write_u32_le(path.len() as u32)?;
+                    writer.write_all(path.as_bytes())?;
+                }
+                None => writer.write_u32_le(u32::MAX)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::PatchBuilder;
+    use crate::test_support::TestDir;
+    use crate::{ZiPatchConfig, ZiPatchFile};
+    use std::io::Cursor;
+
+    fn indexed_patch() -> (ZiPatchFile<Cursor<Vec<u8>>>, ChunkIndex) {
+        indexed_patch_of_type("DIFF")
+    }
+
+    fn indexed_patch_of_type(patch_type: &str) -> (ZiPatchFile<Cursor<Vec<u8>>>, ChunkIndex) {
+        let mut builder = PatchBuilder::new(patch_type);
+        builder.add_directory("sqpack/ffxiv");
+        builder
+            .add_file("boot/ffxivboot.ver", b"2024.01.01.0000.0000")
+            .unwrap();
+        builder
+            .add_file("game/ffxivgame.ver", b"2024.01.01.0000.0000")
+            .unwrap();
+        builder.delete_file("boot/ffxivboot.ver");
+
+        let mut patch = ZiPatchFile::new(Cursor::new(builder.to_bytes().unwrap())).unwrap();
+        let index = patch
+            .build_chunk_index(&ZiPatchConfig::new("/game"))
+            .unwrap();
+        (patch, index)
+    }
+
+    #[test]
+    fn test_chunk_index_random_access() {
+        let (mut patch, index) = indexed_patch();
+        let chunks: Vec<ZiPatchChunk> = patch.chunks().map(Result::unwrap).collect();
+
+        assert_eq!(index.len(), chunks.len());
+        assert_eq!(index.get(0).unwrap().chunk_type, "FHDR");
+        assert_eq!(
+            index.get(1).unwrap().target_file.as_deref(),
+            Some("sqpack/ffxiv")
+        );
+
+        // Walk backwards, which the chunk iterator can't
+        for n in (0..index.len()).rev() {
+            let chunk = patch.chunk(&index, n).unwrap().unwrap();
+            assert_eq!(chunk.to_string(), chunks[n].to_string());
+        }
+        assert!(patch.chunk(&index, index.len()).unwrap().is_none());
+
+        let touching: Vec<usize> = index
+            .chunks_for_file("/boot\\ffxivboot.ver")
+            .map(|(n, _)| n)
+            .collect();
+        assert_eq!(touching, [2, 4]);
+
+        let offset = index.get(3).unwrap().offset;
+        assert!(matches!(
+            patch.read_chunk_at(offset).unwrap(),
+            ZiPatchChunk::Sqpk(SqpkCommand::File(file))
+                if file.target_file.relative_path == "game/ffxivgame.ver"
+        ));
+    }
+
+    #[test]
+    fn test_chunk_index_round_trip() {
+        let (mut patch, index) = indexed_patch();
+
+        let dir = TestDir::new("chunk-index");
+        let path = dir.root.join("patch.index");
+        index.save(&path).unwrap();
+        assert_eq!(ChunkIndex::load(&path).unwrap(), index);
+
+        let other = ChunkIndex {
+            patch_length: index.patch_length + 1,
+            ..index.clone()
+        };
+        assert!(matches!(
+            patch.chunk(&other, 0),
+            Err(ZiPatchError::InvalidChunkIndex(_))
+        ));
+
+        // Same length, different header
+        let (_, hist_index) = indexed_patch_of_type("HIST");
+        assert_eq!(hist_index.patch_length, index.patch_length);
+        assert!(matches!(
+            patch.verify_chunk_index(&hist_index),
+            Err(ZiPatchError::InvalidChunkIndex(_))
+        ));
+        patch.verify_chunk_index(&index).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_index_is_rejected() {
+        let (_, index) = indexed_patch();
+        let mut data = Vec::new();
+        index.write_to(&mut data).unwrap();
+
+        for len in [0, 10, data.len() - 1] {
+            assert!(matches!(
+                ChunkIndex::read_from(&mut Cursor::new(&data[..len])),
+                Err(ZiPatchError::InvalidChunkIndex(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_oversized_target_path_is_rejected() {
+        let (_, index) = indexed_patch();
+        let mut data = Vec::new();
+        index.write_to(&mut data).unwrap();
+
+        // Target path length of the ADIR entry, after the index header, the FHDR entry and
+        // the ADIR entry's offset, size, type and command
+        let offset = 4 + 4 + 8 + 4 + 8 + (8 + 8 + 4 + 1 + 4) + 8 + 8 + 4 + 1;
+        assert_eq!(&data[offset..offset + 4], &12u32.to_le_bytes());
+        data[offset..offset + 4].copy_from_slice(&0x1000_0000u32.to_le_bytes());
+        assert!(matches!(
+            ChunkIndex::read_from(&mut Cursor::new(data)),
+            Err(ZiPatchError::InvalidChunkIndex(_))
+        ));
+    }
+}
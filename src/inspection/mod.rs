@@ -1,5 +1,9 @@
 mod change_set;
+mod chunk_index;
 mod command_counts;
+mod scan;
 
 pub use change_set::ZiPatchChangeSet;
+pub use chunk_index::{ChunkIndex, ChunkIndexEntry};
 pub use command_counts::ZiPatchCommandCounts;
+pub use scan::ZiPatchScan;
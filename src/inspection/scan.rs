@@ -0,0 +1,147 @@
+/// Result of a structural scan of a patch file, from [`ZiPatchFile::scan_structure`]
+///
+/// [`ZiPatchFile::scan_structure`]: crate::ZiPatchFile::scan_structure
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ZiPatchScan {
+    /// Whether the scan reached a complete EOF_ chunk
+    pub complete: bool,
+    /// Number of complete chunks, including the FHDR and EOF_ chunks
+    pub chunk_count: u64,
+    /// Offset of the last complete chunk, if there is one
+    pub last_complete_chunk_offset: Option<u64>,
+    /// Offset just past the last complete chunk; everything before it is structurally sound
+    pub valid_length: u64,
+    /// Length of the patch file, if it was known before scanning
+    pub file_length: Option<u64>,
+    /// Number of bytes missing from the end of the file, if known
+    ///
+    /// Zero for a complete file. For a truncated file this is only known when the file length
+    /// is and the header of the cut-off chunk was read, and is then the number of bytes
+    /// needed to complete that chunk; chunks after it may be missing too.
+    pub missing_bytes: Option<u64>,
+    /// Offset of a chunk with an unknown type, which ended the scan
+    pub invalid_chunk_offset: Option<u64>,
+}
+
+impl ZiPatchScan {
+    /// Checks if the file stops short of its EOF_ chunk, with nothing but chunks before that
+    ///
+    /// A truncated file can be completed by resuming its download; one that is neither
+    /// complete nor truncated holds data that isn't a chunk, and has to be downloaded again.
+    /// Only chunk types are checked, so a corrupt chunk size may still pass for truncation.
+    pub fn is_truncated(&self) -> bool {
+        !self.complete && self.invalid_chunk_offset.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::PatchBuilder;
+    use crate::ZiPatchFile;
+    use std::io::Cursor;
+
+    fn patch_data() -> Vec<u8> {
+        let mut builder = PatchBuilder::new("DIFF");
+        builder.add_directory("sqpack/ffxiv");
+        builder
+            .add_file("boot/ffxivboot.ver", b"2024.01.01.0000.0000")
+            .unwrap();
+        builder.to_bytes().unwrap()
+    }
+
+    fn scan(data: &[u8]) -> ZiPatchScan {
+        ZiPatchFile::new(Cursor::new(data.to_vec()))
+            .unwrap()
+            .with_patch_length(data.len() as u64)
+            .scan_structure()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_scan_complete_patch() {
+        let data = patch_data();
+        let scan = scan(&data);
+
+        assert!(scan.complete);
+        assert!(!scan.is_truncated());
+        assert_eq!(scan.chunk_count, 4);
+        assert_eq!(scan.valid_length, data.len() as u64);
+        assert_eq!(
+            scan.last_complete_chunk_offset,
+            Some(data.len() as u64 - 12)
+        );
+        assert_eq!(scan.missing_bytes, Some(0));
+    }
+
+    #[test]
+    fn test_scan_truncated_patch() {
+        let data = patch_data();
+        let complete = scan(&data);
+
+        // Cut into the EOF_ chunk
+        let scan_cut = scan(&data[..data.len() - 2]);
+        assert!(!scan_cut.complete);
+        assert!(scan_cut.is_truncated());
+        assert_eq!(scan_cut.chunk_count, 3);
+        assert_eq!(scan_cut.valid_length, data.len() as u64 - 12);
+        assert_eq!(scan_cut.missing_bytes, Some(2));
+
+        // Cut into the EOF_ chunk's header
+        let scan_header = scan(&data[..data.len() - 10]);
+        assert!(scan_header.is_truncated());
+        assert_eq!(scan_header.missing_bytes, None);
+        assert_eq!(
+            scan_header.last_complete_chunk_offset,
+            scan_cut.last_complete_chunk_offset
+        );
+
+        // Ends cleanly between chunks, without the EOF_ chunk
+        let scan_boundary = scan(&data[..data.len() - 12]);
+        assert!(scan_boundary.is_truncated());
+        assert_eq!(Some(scan_boundary.valid_length), scan_boundary.file_length);
+        assert_ne!(scan_boundary, complete);
+    }
+
+    #[test]
+    fn test_scan_without_patch_length() {
+        let data = patch_data();
+        let scan_unknown = |data: &[u8]| {
+            ZiPatchFile::new(Cursor::new(data.to_vec()))
+                .unwrap()
+                .scan_structure()
+                .unwrap()
+        };
+
+        let complete = scan_unknown(&data);
+        assert_eq!(
+            complete,
+            ZiPatchScan {
+                file_length: None,
+                ..scan(&data)
+            }
+        );
+
+        // Stops at the readable data, without knowing how much is missing
+        let cut = &data[..data.len() - 2];
+        let scan_cut = scan_unknown(cut);
+        assert!(scan_cut.is_truncated());
+        assert_eq!(scan_cut.file_length, None);
+        assert_eq!(scan_cut.missing_bytes, None);
+        assert_eq!(scan_cut.valid_length, scan(cut).valid_length);
+        assert_eq!(scan_cut.chunk_count, 3);
+    }
+
+    #[test]
+    fn test_scan_corrupt_patch() {
+        let mut data = patch_data();
+        let eof_offset = data.len() - 12;
+        data[eof_offset + 4..eof_offset + 8].copy_from_slice(b"JUNK");
+
+        let scan = scan(&data);
+        assert!(!scan.complete);
+        assert!(!scan.is_truncated());
+        assert_eq!(scan.valid_length, eof_offset as u64);
+        assert_eq!(scan.invalid_chunk_offset, Some(eof_offset as u64));
+    }
+}
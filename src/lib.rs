@@ -4,9 +4,27 @@
 //!
 //! This library provides functionality to:
 //! - Read ZiPatch (.patch) files
+//! - Stream and apply patches from forward-only readers, like decompressors or HTTP bodies
 //! - Parse chunk-based patch file format
-//! - Apply patches to game installations
+//! - Apply patches to game installations, on disk or through a custom target
+//! - Roll back a failed or unwanted apply from an on-disk journal
+//! - Record an undo patch while applying, to downgrade again later
+//! - Apply a patch while it is still being downloaded
+//! - Resume an interrupted apply from a saved checkpoint
+//! - Report apply progress through observer callbacks
+//! - Plan the exact filesystem operations of a patch without applying it
+//! - Parse SqPack headers and rewrite SqPack index files
+//! - Verify the SHA-1 digests stored in SqPack headers
+//! - Extract game files from SqPack dat files
+//! - Resolve SqPack index hashes to game paths
 //! - Inspect patch contents and changes
+//! - Detect truncated or partially downloaded patch files
+//! - Index a patch's chunks for random access by position or target file
+//! - Write chunks back out as ZiPatch files
+//! - Generate patches from two versions of a loose-file tree or SqPack directory
+//! - Pack a whole game installation into a single patch
+//! - Merge a chain of patches into one equivalent patch
+//! - Rewrite patches, filtering or changing their chunks
 //!
 //! ## Example
 //!
@@ -52,16 +70,33 @@
     clippy::doc_markdown,
     missing_docs
 )]
+pub mod apply;
 pub mod chunk;
 pub mod config;
 pub mod error;
 pub mod file;
+pub mod generate;
 pub mod inspection;
+pub mod sqpack;
+pub mod stream;
+pub mod target;
+#[cfg(test)]
+mod test_support;
 pub mod util;
+pub mod writer;
 
 // Re-export commonly used types
+pub use apply::{
+    ApplyCheckpoint, ApplyJournal, ApplyObserver, ApplyOptions, ApplyPlan, CancellationToken,
+    ChunkInfo, FileOperation,
+};
 pub use chunk::{SqpkCommand, ZiPatchChunk};
 pub use config::{Platform, ZiPatchConfig, ZiPatchConfigBuilder};
 pub use error::{Result, ZiPatchError};
 pub use file::ZiPatchFile;
-pub use inspection::{ZiPatchChangeSet, ZiPatchCommandCounts};
+pub use inspection::{
+    ChunkIndex, ChunkIndexEntry, ZiPatchChangeSet, ZiPatchCommandCounts, ZiPatchScan,
+};
+pub use stream::ZiPatchStream;
+pub use target::{FileSystemTarget, MemoryTarget, PatchTarget};
+pub use writer::ZiPatchWriter;
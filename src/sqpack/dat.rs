@@ -0,0 +1,658 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::error::{Result, ZiPatchError};
+use crate::target::PatchTarget;
+use crate::util::{BinaryReaderExt, SqpkCompressedBlock};
+
+/// Size of the header preceding each data block of a file entry
+const BLOCK_HEADER_SIZE: u32 = 16;
+
+/// Size of the uncompressed header of a model file
+const MODEL_HEADER_SIZE: usize = 0x44;
+
+/// Number of model data regions: stack, runtime, then three LODs each of vertex, edge
+/// geometry and index buffers
+const MODEL_REGION_COUNT: usize = 11;
+
+/// Offset of the type-specific block tables in a file entry header
+const BLOCK_TABLE_OFFSET: u64 = 0x18;
+
+/// Offset of the block size table in a model entry header, after the version, the region
+/// tables, the vertex declaration and material counts, and the flags
+const MODEL_BLOCK_SIZES_OFFSET: u64 =
+    BLOCK_TABLE_OFFSET + MODEL_REGION_COUNT as u64 * (3 * 4 + 2 * 2) + 2 + 2 + 4;
+
+/// Type of a file entry in a SqPack dat file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatEntryType {
+    /// Free space, left behind by a deleted file
+    Empty,
+    /// Any file stored as a plain sequence of blocks
+    Standard,
+    /// A model (.mdl) file
+    Model,
+    /// A texture (.tex) file
+    Texture,
+    /// Unknown entry type
+    Unknown(u32),
+}
+
+impl DatEntryType {
+    /// Creates a DatEntryType from its on-disk value
+    pub fn from_u32(value: u32) -> Self {
+        match value {
+            1 => DatEntryType::Empty,
+            2 => DatEntryType::Standard,
+            3 => DatEntryType::Model,
+            4 => DatEntryType::Texture,
+            other => DatEntryType::Unknown(other),
+        }
+    }
+}
+
+/// Header shared by every file entry in a SqPack dat file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatEntryHeader {
+    /// Size of the entry header, including the type-specific block tables
+    pub header_size: u32,
+    /// Type of the entry
+    pub entry_type: DatEntryType,
+    /// Size of the file once decompressed
+    pub raw_file_size: u32,
+}
+
+impl DatEntryHeader {
+    /// Reads the header of the file entry at the given offset
+    pub fn read_from<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<Self> {
+        reader.seek(SeekFrom::Start(offset))?;
+
+        Ok(Self {
+            header_size: reader.read_u32_le()?,
+            entry_type: DatEntryType::from_u32(reader.read_u32_le()?),
+            raw_file_size: reader.read_u32_le()?,
+        })
+    }
+}
+
+/// Reads and decompresses the file entry at the given offset of a dat file
+///
+/// The offset is the one stored in the file's index entry (see [`IndexEntry::offset`]).
+/// Standard, model and texture entries are supported; model files are returned with their
+/// uncompressed header rebuilt, as the game expects them.
+///
+/// [`IndexEntry::offset`]: crate::sqpack::IndexEntry::offset
+pub fn read_dat_entry<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<Vec<u8>> {
+    let file_length = reader.seek(SeekFrom::End(0))?;
+    let header = DatEntryHeader::read_from(reader, offset)?;
+
+    // Every table read from the entry header is bounded by its size, so it must fit the file
+    if offset.saturating_add(header.header_size as u64) > file_length {
+        return Err(invalid(format!(
+            "Header of the file at offset {:#x} runs past the end of the dat file",
+            offset
+        )));
+    }
+
+    let entry = Entry {
+        offset,
+        header,
+        file_length,
+    };
+    match header.entry_type {
+        DatEntryType::Standard => read_standard_entry(reader, &entry),
+        DatEntryType::Texture => read_texture_entry(reader, &entry),
+        DatEntryType::Model => read_model_entry(reader, &entry),
+        DatEntryType::Empty => Err(invalid(format!("No file at offset {:#x}", offset))),
+        DatEntryType::Unknown(value) => Err(invalid(format!(
+            "Unknown entry type {} at offset {:#x}",
+            value, offset
+        ))),
+    }
+}
+
+/// Reads and decompresses the file entry at the given offset of a dat file in a patch target
+///
+/// See [`read_dat_entry`].
+pub fn read_dat_file(target: &dyn PatchTarget, path: &Path, offset: u64) -> Result<Vec<u8>> {
+    let mut reader = target.open(path)?;
+    read_dat_entry(&mut reader, offset)
+}
+
+/// A file entry being read, with the bounds its untrusted sizes are checked against
+struct Entry {
+    /// Offset of the entry in the dat file
+    offset: u64,
+    /// Header of the entry
+    header: DatEntryHeader,
+    /// Length of the dat file
+    file_length: u64,
+}
+
+impl Entry {
+    /// Offset of the entry's data, right after its header
+    fn data_offset(&self) -> u64 {
+        self.offset + self.header.header_size as u64
+    }
+
+    /// Checks that a table of `count` entries of `entry_size` bytes, starting
+    /// `table_offset` bytes into the entry, fits in the entry header
+    fn check_table(&self, table_offset: u64, count: u64, entry_size: u64) -> Result<()> {
+        if table_offset + count * entry_size > self.header.header_size as u64 {
+            return Err(invalid(format!(
+                "Block table of the file at offset {:#x} runs past the end of its header",
+                self.offset
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Checks that `length` bytes starting at `position` fit in the dat file
+    fn check_in_file(&self, position: u64, length: u64) -> Result<()> {
+        if position.saturating_add(length) > self.file_length {
+            return Err(invalid(format!(
+                "Data of the file at offset {:#x} runs past the end of the dat file",
+                self.offset
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Creates the buffer the file is decompressed into
+    ///
+    /// The raw size is only trusted once the blocks add up to it, so no more is reserved
+    /// than the bytes left in the dat file; compressed files grow past that as they're read.
+    fn output_buffer(&self, mut output: Vec<u8>) -> Vec<u8> {
+        let left = self.file_length.saturating_sub(self.data_offset());
+        output.reserve((self.header.raw_file_size as u64).min(left) as usize);
+        output
+    }
+}
+
+fn read_standard_entry<R: Read + Seek>(reader: &mut R, entry: &Entry) -> Result<Vec<u8>> {
+    // Two unknown fields precede the block count
+    reader.seek(SeekFrom::Start(entry.offset + 0x14))?;
+    let block_count = reader.read_u32_le()?;
+
+    entry.check_table(BLOCK_TABLE_OFFSET, block_count as u64, 8)?;
+    let mut block_offsets = Vec::with_capacity(block_count as usize);
+    for _ in 0..block_count {
+        block_offsets.push(reader.read_u32_le()?);
+        // Compressed and decompressed sizes, repeated in each block header
+        let _sizes = reader.read_u32_le()?;
+    }
+
+    let data_offset = entry.data_offset();
+    let mut output = entry.output_buffer(Vec::new());
+    for block_offset in block_offsets {
+        read_block_into(
+            reader,
+            entry,
+            data_offset + block_offset as u64,
+            &mut output,
+        )?;
+    }
+
+    check_raw_size(entry, output)
+}
+
+fn read_texture_entry<R: Read + Seek>(reader: &mut R, entry: &Entry) -> Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(entry.offset + 0x14))?;
+    let lod_count = reader.read_u32_le()?;
+
+    // Each LOD: compressed offset, compressed size, decompressed size, first block, block count
+    entry.check_table(BLOCK_TABLE_OFFSET, lod_count as u64, 20)?;
+    let mut lods = Vec::with_capacity(lod_count as usize);
+    for _ in 0..lod_count {
+        let compressed_offset = reader.read_u32_le()?;
+        let _compressed_size = reader.read_u32_le()?;
+        let _decompressed_size = reader.read_u32_le()?;
+        let _first_block = reader.read_u32_le()?;
+        let block_count = reader.read_u32_le()?;
+        lods.push((compressed_offset, block_count));
+    }
+
+    let total_blocks = lods
+        .iter()
+        .try_fold(0u32, |total, &(_, count)| total.checked_add(count))
+        .ok_or_else(|| {
+            invalid(format!(
+                "Block count of the file at offset {:#x} overflows",
+                entry.offset
+            ))
+        })?;
+    entry.check_table(
+        BLOCK_TABLE_OFFSET + lod_count as u64 * 20,
+        total_blocks as u64,
+        2,
+    )?;
+    let block_sizes = read_u16_table(reader, total_blocks as usize)?;
+
+    // The texture header is stored uncompressed ahead of the first LOD
+    let data_offset = entry.data_offset();
+    let texture_header_size = lods.first().map_or(0, |&(offset, _)| offset);
+    entry.check_in_file(data_offset, texture_header_size as u64)?;
+    reader.seek(SeekFrom::Start(data_offset))?;
+    let output = reader.read_bytes_required(texture_header_size as usize)?;
+    let mut output = entry.output_buffer(output);
+
+    let mut sizes = block_sizes.into_iter();
+    for (compressed_offset, block_count) in lods {
+        let mut block_offset = data_offset + compressed_offset as u64;
+        for _ in 0..block_count {
+            read_block_into(reader, entry, block_offset, &mut output)?;
+            block_offset += sizes
+                .next()
+                .ok_or_else(|| missing_block_size(entry.offset))?
+                as u64;
+        }
+    }
+
+    check_raw_size(entry, output)
+}
+
+fn read_model_entry<R: Read + Seek>(reader: &mut R, entry: &Entry) -> Result<Vec<u8>> {
+    let offset = entry.offset;
+
+    // Block count and used block count, then the model header proper
+    reader.seek(SeekFrom::Start(offset + 0x14))?;
+    let version = reader.read_u32_le()?;
+    let _sizes = read_u32_table(reader, MODEL_REGION_COUNT)?;
+    let _compressed_sizes = read_u32_table(reader, MODEL_REGION_COUNT)?;
+    let region_offsets = read_u32_table(reader, MODEL_REGION_COUNT)?;
+    let region_first_blocks = read_u16_table(reader, MODEL_REGION_COUNT)?;
+    let region_block_counts = read_u16_table(reader, MODEL_REGION_COUNT)?;
+    let vertex_declaration_count = reader.read_u16_le()?;
+    let material_count = reader.read_u16_le()?;
+    let flags = reader.read_bytes_required(4)?;
+
+    let total_blocks: usize = region_block_counts
+        .iter()
+        .map(|&count| count as usize)
+        .sum();
+    entry.check_table(MODEL_BLOCK_SIZES_OFFSET, total_blocks as u64, 2)?;
+    let block_sizes = read_u16_table(reader, total_blocks)?;
+
+    let data_offset = entry.data_offset();
+    let mut output = entry.output_buffer(vec![0u8; MODEL_HEADER_SIZE]);
+
+    let mut region_sizes = [0u32; MODEL_REGION_COUNT];
+    let mut region_starts = [0u32; MODEL_REGION_COUNT];
+    for region in model_region_order() {
+        let start = output.len();
+        let first_block = region_first_blocks[region] as usize;
+        let mut block_offset = data_offset + region_offsets[region] as u64;
+
+        for block in first_block..first_block + region_block_counts[region] as usize {
+            read_block_into(reader, entry, block_offset, &mut output)?;
+            block_offset += *block_sizes
+                .get(block)
+                .ok_or_else(|| missing_block_size(offset))? as u64;
+        }
+
+        region_starts[region] = start as u32;
+        region_sizes[region] = (output.len() - start) as u32;
+    }
+
+    // Rebuild the header the game reads from the uncompressed model file
+    let mut model_header = Vec::with_capacity(MODEL_HEADER_SIZE);
+    model_header.extend_from_slice(&version.to_le_bytes());
+    model_header.extend_from_slice(&region_sizes[0].to_le_bytes());
+    model_header.extend_from_slice(&region_sizes[1].to_le_bytes());
+    model_header.extend_from_slice(&vertex_declaration_count.to_le_bytes());
+    model_header.extend_from_slice(&material_count.to_le_bytes());
+    for lod in 0..3 {
+        model_header.extend_from_slice(&region_starts[2 + lod].to_le_bytes());
+    }
+    for lod in 0..3 {
+        model_header.extend_from_slice(&region_starts[8 + lod].to_le_bytes());
+    }
+    for lod in 0..3 {
+        model_header.extend_from_slice(&region_sizes[2 + lod].to_le_bytes());
+    }
+    for lod in 0..3 {
+        model_header.extend_from_slice(&region_sizes[8 + lod].to_le_bytes());
+    }
+    model_header.extend_from_slice(&flags);
+    output[..MODEL_HEADER_SIZE].copy_from_slice(&model_header);
+
+    Ok(output)
+}
+
+/// Order the model regions are laid out in the decompressed file: stack, runtime, then
+/// the vertex, edge geometry and index buffers of each LOD
+fn model_region_order() -> impl Iterator<Item = usize> {
+    [0, 1]
+        .into_iter()
+        .chain((0..3).flat_map(|lod| [2 + lod, 5 + lod, 8 + lod]))
+}
+
+/// Reads a data block at the given offset and appends its decompressed contents
+fn read_block_into<R: Read + Seek>(
+    reader: &mut R,
+    entry: &Entry,
+    offset: u64,
+    output: &mut Vec<u8>,
+) -> Result<()> {
+    reader.seek(SeekFrom::Start(offset))?;
+
+    let header_size = reader.read_i32_le()?;
+    let reserved = reader.read_u32_le()?;
+    let compressed_size = reader.read_i32_le()?;
+    let decompressed_size = reader.read_i32_le()?;
+
+    if header_size as u32 != BLOCK_HEADER_SIZE || compressed_size < 0 || decompressed_size < 0 {
+        return Err(invalid(format!(
+            "Invalid data block at offset {:#x}",
+            offset
+        )));
+    }
+
+    let mut block = SqpkCompressedBlock {
+        header_size,
+        reserved,
+        compressed_size,
+        decompressed_size,
+        compressed_block: Vec::new(),
+    };
+    let data_size = if block.is_compressed() {
+        compressed_size
+    } else {
+        decompressed_size
+    };
+    entry.check_in_file(offset + BLOCK_HEADER_SIZE as u64, data_size as u64)?;
+    block.compressed_block = reader.read_bytes_required(data_size as usize)?;
+
+    block.decompress_into(output).map_err(|e| match e {
+        ZiPatchError::DecompressionFailed(reason) => invalid(format!(
+            "Data block at offset {:#x} is corrupt: {}",
+            offset, reason
+        )),
+        e => e,
+    })
+}
+
+/// Checks that a decompressed file has the size its entry header gives
+fn check_raw_size(entry: &Entry, output: Vec<u8>) -> Result<Vec<u8>> {
+    if output.len() != entry.header.raw_file_size as usize {
+        return Err(invalid(format!(
+            "File at offset {:#x} decompressed to {} bytes, expected {}",
+            entry.offset,
+            output.len(),
+            entry.header.raw_file_size
+        )));
+    }
+
+    Ok(output)
+}
+
+fn missing_block_size(offset: u64) -> ZiPatchError {
+    invalid(format!(
+        "Block size table of the file at offset {:#x} is too short",
+        offset
+    ))
+}
+
+fn read_u32_table<R: Read>(reader: &mut R, count: usize) -> Result<Vec<u32>> {
+    (0..count).map(|_| reader.read_u32_le()).collect()
+}
+
+fn read_u16_table<R: Read>(reader: &mut R, count: usize) -> Result<Vec<u16>> {
+    (0..count).map(|_| reader.read_u16_le()).collect()
+}
+
+fn invalid(reason: String) -> ZiPatchError {
+    ZiPatchError::InvalidSqpackFile(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::target::MemoryTarget;
+    use crate::util::SqpackDatFile;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::{Cursor, Write};
+
+    fn block(data: &[u8], compress: bool) -> Vec<u8> {
+        let block = if compress {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data).unwrap();
+            let compressed = encoder.finish().unwrap();
+            SqpkCompressedBlock {
+                header_size: 16,
+                reserved: 0,
+                compressed_size: compressed.len() as i32,
+                decompressed_size: data.len() as i32,
+                compressed_block: compressed,
+            }
+        } else {
+            SqpkCompressedBlock {
+                header_size: 16,
+                reserved: 0,
+                compressed_size: 0x7d00,
+                decompressed_size: data.len() as i32,
+                compressed_block: data.to_vec(),
+            }
+        };
+
+        let mut written = Vec::new();
+        block.write_to(&mut written).unwrap();
+        written
+    }
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn u16s(values: &[u16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    /// Pads an entry header to a multiple of 128 bytes and prepends its size and type
+    fn entry(entry_type: u32, raw_size: u32, rest: &[u8], data: &[u8]) -> Vec<u8> {
+        let header_size = (12 + rest.len() as u32 + 127) & !127;
+        let mut entry = u32s(&[header_size, entry_type, raw_size]);
+        entry.extend_from_slice(rest);
+        entry.resize(header_size as usize, 0);
+        entry.extend_from_slice(data);
+        entry
+    }
+
+    #[test]
+    fn test_read_standard_entry() {
+        let first = vec![0xAB; 300];
+        let second = b"tail of the file".to_vec();
+        let first_block = block(&first, true);
+        let second_block = block(&second, false);
+
+        let mut rest = u32s(&[0, 0, 2]);
+        rest.extend(u32s(&[0, 0, first_block.len() as u32, 0]));
+        let data = [first_block, second_block].concat();
+        let raw_size = (first.len() + second.len()) as u32;
+
+        // Put the entry after some unrelated data to exercise the offset
+        let mut dat = vec![0u8; 0x800];
+        dat.extend(entry(2, raw_size, &rest, &data));
+
+        let target = MemoryTarget::new();
+        let path = Path::new("/game/sqpack/ffxiv/000000.win32.dat0");
+        target.insert_file(path, dat);
+
+        let file = read_dat_file(&target, path, 0x800).unwrap();
+        assert_eq!(file, [first, second].concat());
+    }
+
+    #[test]
+    fn test_read_texture_entry() {
+        let texture_header = vec![0x54; 0x50];
+        let mip0 = vec![0x01; 200];
+        let mip1 = vec![0x02; 50];
+        let mip2 = vec![0x03; 20];
+        let blocks = [block(&mip0, true), block(&mip1, false), block(&mip2, false)];
+
+        let mut rest = u32s(&[0, 0, 2]);
+        let lod0_size = (blocks[0].len() + blocks[1].len()) as u32;
+        rest.extend(u32s(&[0x50, lod0_size, 250, 0, 2]));
+        rest.extend(u32s(&[0x50 + lod0_size, blocks[2].len() as u32, 20, 2, 1]));
+        rest.extend(u16s(&[
+            blocks[0].len() as u16,
+            blocks[1].len() as u16,
+            blocks[2].len() as u16,
+        ]));
+
+        let mut data = texture_header.clone();
+        data.extend(blocks.concat());
+        let dat = entry(4, 0x50 + 270, &rest, &data);
+
+        let file = read_dat_entry(&mut Cursor::new(dat.clone()), 0).unwrap();
+        assert_eq!(file, [texture_header, mip0, mip1, mip2].concat());
+
+        // A raw size the blocks don't add up to
+        let mut wrong_size = dat;
+        wrong_size[8..12].copy_from_slice(&(0x50 + 271u32).to_le_bytes());
+        assert!(matches!(
+            read_dat_entry(&mut Cursor::new(wrong_size), 0),
+            Err(ZiPatchError::InvalidSqpackFile(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_model_entry() {
+        let stack = vec![0x10; 40];
+        let runtime = vec![0x20; 60];
+        let vertices = vec![0x30; 500];
+        let indices = vec![0x40; 30];
+        let blocks = [
+            block(&stack, false),
+            block(&runtime, false),
+            block(&vertices[..250], true),
+            block(&vertices[250..], true),
+            block(&indices, false),
+        ];
+
+        let mut offsets = [0u32; MODEL_REGION_COUNT];
+        let mut first_blocks = [0u16; MODEL_REGION_COUNT];
+        let mut block_counts = [0u16; MODEL_REGION_COUNT];
+        let mut position = 0;
+        for (region, range) in [(0, 0..1), (1, 1..2), (2, 2..4), (8, 4..5)] {
+            offsets[region] = position;
+            first_blocks[region] = range.start as u16;
+            block_counts[region] = range.len() as u16;
+            position += blocks[range].iter().map(|b| b.len() as u32).sum::<u32>();
+        }
+
+        let mut rest = u32s(&[5, 5, 6]);
+        rest.extend(u32s(&[0; MODEL_REGION_COUNT * 2]));
+        rest.extend(u32s(&offsets));
+        rest.extend(u16s(&first_blocks));
+        rest.extend(u16s(&block_counts));
+        rest.extend(u16s(&[3, 2]));
+        rest.extend([1, 0, 0, 0]);
+        rest.extend(u16s(
+            &blocks.iter().map(|b| b.len() as u16).collect::<Vec<_>>(),
+        ));
+
+        let dat = entry(3, 0, &rest, &blocks.concat());
+        let file = read_dat_entry(&mut Cursor::new(dat.clone()), 0).unwrap();
+
+        let header = &file[..MODEL_HEADER_SIZE];
+        let field =
+            |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+        assert_eq!(field(0x00), 6);
+        assert_eq!(field(0x04), 40);
+        assert_eq!(field(0x08), 60);
+        assert_eq!(&header[0x0C..0x10], &[3, 0, 2, 0]);
+        // Vertex and index buffer offsets of LOD 0
+        assert_eq!(field(0x10), 0x44 + 100);
+        assert_eq!(field(0x1C), 0x44 + 600);
+        // Vertex and index buffer sizes of LOD 0
+        assert_eq!(field(0x28), 500);
+        assert_eq!(field(0x34), 30);
+        assert_eq!(header[0x40], 1);
+
+        assert_eq!(
+            &file[MODEL_HEADER_SIZE..],
+            [stack, runtime, vertices, indices].concat()
+        );
+
+        // Point the index buffer of LOD 0 past the end of the block size table
+        let mut missing_size = dat;
+        let first_block_offset = 12 + 12 + MODEL_REGION_COUNT * 12 + 8 * 2;
+        missing_size[first_block_offset..first_block_offset + 2]
+            .copy_from_slice(&9u16.to_le_bytes());
+        assert!(matches!(
+            read_dat_entry(&mut Cursor::new(missing_size), 0),
+            Err(ZiPatchError::InvalidSqpackFile(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_corrupt_sizes_fails() {
+        let read = |dat: Vec<u8>| read_dat_entry(&mut Cursor::new(dat), 0);
+        let data = block(b"some data", false);
+
+        // A header running past the end of the file
+        let mut dat = entry(2, 9, &u32s(&[0, 0, 1, 0, 0]), &data);
+        dat[0..4].copy_from_slice(&0xFFFF_FF80u32.to_le_bytes());
+        assert!(matches!(read(dat), Err(ZiPatchError::InvalidSqpackFile(_))));
+
+        // A block count far larger than the header
+        let dat = entry(2, 9, &u32s(&[0, 0, u32::MAX, 0, 0]), &data);
+        assert!(matches!(read(dat), Err(ZiPatchError::InvalidSqpackFile(_))));
+
+        // A block whose data runs past the end of the file
+        let mut truncated = data.clone();
+        truncated[12..16].copy_from_slice(&i32::MAX.to_le_bytes());
+        let dat = entry(2, 9, &u32s(&[0, 0, 1, 0, 0]), &truncated);
+        assert!(matches!(read(dat), Err(ZiPatchError::InvalidSqpackFile(_))));
+
+        // A compressed block inflating past its decompressed size
+        let mut inflating = block(&[0; 0x10000], true);
+        inflating[12..16].copy_from_slice(&4i32.to_le_bytes());
+        let dat = entry(2, 4, &u32s(&[0, 0, 1, 0, 0]), &inflating);
+        assert!(matches!(read(dat), Err(ZiPatchError::InvalidSqpackFile(_))));
+
+        // LOD block counts adding up past u32::MAX
+        let mut rest = u32s(&[0, 0, 2]);
+        rest.extend(u32s(&[0, 0, 0, 0, 0x8000_0000]));
+        rest.extend(u32s(&[0, 0, 0, 0, 0x8000_0000]));
+        assert!(matches!(
+            read(entry(4, 9, &rest, &data)),
+            Err(ZiPatchError::InvalidSqpackFile(_))
+        ));
+
+        // A texture header larger than the file
+        let mut rest = u32s(&[0, 0, 1]);
+        rest.extend(u32s(&[u32::MAX, 0, 0, 0, 1]));
+        rest.extend(u16s(&[data.len() as u16]));
+        assert!(matches!(
+            read(entry(4, 9, &rest, &data)),
+            Err(ZiPatchError::InvalidSqpackFile(_))
+        ));
+
+        // Model block counts needing a larger header
+        let mut rest = u32s(&[1, 1, 6]);
+        rest.extend(u32s(&[0; MODEL_REGION_COUNT * 3]));
+        rest.extend(u16s(&[0; MODEL_REGION_COUNT]));
+        rest.extend(u16s(&[u16::MAX; MODEL_REGION_COUNT]));
+        assert!(matches!(
+            read(entry(3, 9, &rest, &data)),
+            Err(ZiPatchError::InvalidSqpackFile(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_empty_entry_fails() {
+        let mut dat = SqpackDatFile::empty_file_block_header(2).to_vec();
+        dat[4..8].copy_from_slice(&1u32.to_le_bytes());
+        dat.resize(0x100, 0);
+
+        assert!(matches!(
+            read_dat_entry(&mut Cursor::new(dat), 0),
+            Err(ZiPatchError::InvalidSqpackFile(_))
+        ));
+    }
+}
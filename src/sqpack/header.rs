@@ -0,0 +1,478 @@
+use crate::config::Platform;
+use crate::error::{Result, ZiPatchError};
+
+/// Size of every SqPack header
+pub const SQPACK_HEADER_SIZE: usize = 0x400;
+/// Offset of the SHA-1 digest of a header, which covers everything before it
+pub const HEADER_SHA1_OFFSET: usize = 0x3C0;
+/// Size of a SHA-1 digest
+pub const SHA1_SIZE: usize = 20;
+
+/// Magic at the start of every SqPack file
+pub const SQPACK_MAGIC: &[u8; 8] = b"SqPack\0\0";
+
+/// Kind of SqPack file, as stored in its version header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqpackFileType {
+    /// Database file
+    Database,
+    /// `.datN` file
+    Data,
+    /// `.index` or `.index2` file
+    Index,
+    /// Any other value
+    Unknown(u32),
+}
+
+impl SqpackFileType {
+    /// Creates a SqpackFileType from a u32 value
+    pub fn from_u32(value: u32) -> Self {
+        match value {
+            0 => SqpackFileType::Database,
+            1 => SqpackFileType::Data,
+            2 => SqpackFileType::Index,
+            other => SqpackFileType::Unknown(other),
+        }
+    }
+
+    /// Converts the file type to a u32 value
+    pub fn as_u32(self) -> u32 {
+        match self {
+            SqpackFileType::Database => 0,
+            SqpackFileType::Data => 1,
+            SqpackFileType::Index => 2,
+            SqpackFileType::Unknown(value) => value,
+        }
+    }
+}
+
+/// Header at the start of every SqPack file (written by SqpkHeader Version commands)
+#[derive(Debug, Clone, Copy)]
+pub struct SqpackVersionHeader {
+    /// Platform the file was built for
+    pub platform: Platform,
+    /// Size of the header (0x400)
+    pub size: u32,
+    /// Format version
+    pub version: u32,
+    /// Kind of SqPack file
+    pub file_type: SqpackFileType,
+    /// SHA-1 of the first 0x3C0 bytes of the header
+    pub sha1: [u8; SHA1_SIZE],
+    /// Raw header bytes, which the fields above are written over when serializing
+    pub raw: [u8; SQPACK_HEADER_SIZE],
+}
+
+impl PartialEq for SqpackVersionHeader {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_bytes() == other.to_bytes()
+    }
+}
+
+impl Eq for SqpackVersionHeader {}
+
+impl SqpackVersionHeader {
+    /// Parses a version header
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let data = header_bytes(data)?;
+        if &data[..8] != SQPACK_MAGIC {
+            return Err(ZiPatchError::InvalidSqpackFile(
+                "missing SqPack magic".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            platform: Platform::from_u16(data[0x08] as u16)?,
+            size: read_u32(data, 0x0C),
+            version: read_u32(data, 0x10),
+            file_type: SqpackFileType::from_u32(read_u32(data, 0x14)),
+            sha1: read_sha1(data, HEADER_SHA1_OFFSET),
+            raw: data.try_into().unwrap(),
+        })
+    }
+
+    /// Serializes the header, with the stored SHA-1
+    pub fn to_bytes(&self) -> [u8; SQPACK_HEADER_SIZE] {
+        let mut data = self.raw;
+        data[..8].copy_from_slice(SQPACK_MAGIC);
+        data[0x08] = self.platform.as_u16() as u8;
+        write_u32(&mut data, 0x0C, self.size);
+        write_u32(&mut data, 0x10, self.version);
+        write_u32(&mut data, 0x14, self.file_type.as_u32());
+        data[HEADER_SHA1_OFFSET..HEADER_SHA1_OFFSET + SHA1_SIZE].copy_from_slice(&self.sha1);
+        data
+    }
+
+    /// Computes the SHA-1 the header should carry for its current fields
+    pub fn computed_sha1(&self) -> [u8; SHA1_SIZE] {
+        header_sha1(&self.to_bytes())
+    }
+}
+
+/// Location, size and SHA-1 of a segment of an index file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndexSegment {
+    /// Offset of the segment in the index file
+    pub offset: u32,
+    /// Size of the segment
+    pub size: u32,
+    /// SHA-1 of the segment's contents (all zeros for an empty segment)
+    pub sha1: [u8; SHA1_SIZE],
+}
+
+impl IndexSegment {
+    fn read(data: &[u8], offset: usize) -> Self {
+        Self {
+            offset: read_u32(data, offset),
+            size: read_u32(data, offset + 4),
+            sha1: read_sha1(data, offset + 8),
+        }
+    }
+
+    fn write(&self, data: &mut [u8], offset: usize) {
+        write_u32(data, offset, self.offset);
+        write_u32(data, offset + 4, self.size);
+        data[offset + 8..offset + 8 + SHA1_SIZE].copy_from_slice(&self.sha1);
+    }
+
+    /// Gets the byte range of the segment in the index file
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.offset as usize..self.offset as usize + self.size as usize
+    }
+}
+
+/// Second header of an index file, describing its segments (written by SqpkHeader Index
+/// commands)
+#[derive(Debug, Clone, Copy)]
+pub struct SqpackIndexHeader {
+    /// Size of the header (0x400)
+    pub size: u32,
+    /// Format version
+    pub version: u32,
+    /// Hash table
+    pub index_data: IndexSegment,
+    /// Number of dat files the index points into
+    pub data_file_count: u32,
+    /// Synonym table
+    pub synonym_data: IndexSegment,
+    /// Empty block table
+    pub empty_block_data: IndexSegment,
+    /// Directory table
+    pub dir_index_data: IndexSegment,
+    /// Index type
+    pub index_type: u32,
+    /// SHA-1 of the first 0x3C0 bytes of the header
+    pub sha1: [u8; SHA1_SIZE],
+    /// Raw header bytes, which the fields above are written over when serializing
+    pub raw: [u8; SQPACK_HEADER_SIZE],
+}
+
+impl PartialEq for SqpackIndexHeader {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_bytes() == other.to_bytes()
+    }
+}
+
+impl Eq for SqpackIndexHeader {}
+
+impl SqpackIndexHeader {
+    /// Parses an index header
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let data = header_bytes(data)?;
+
+        Ok(Self {
+            size: read_u32(data, 0x00),
+            version: read_u32(data, 0x04),
+            index_data: IndexSegment::read(data, 0x08),
+            data_file_count: read_u32(data, 0x50),
+            synonym_data: IndexSegment::read(data, 0x54),
+            empty_block_data: IndexSegment::read(data, 0x9C),
+            dir_index_data: IndexSegment::read(data, 0xE4),
+            index_type: read_u32(data, 0x12C),
+            sha1: read_sha1(data, HEADER_SHA1_OFFSET),
+            raw: data.try_into().unwrap(),
+        })
+    }
+
+    /// Serializes the header, with the stored SHA-1
+    pub fn to_bytes(&self) -> [u8; SQPACK_HEADER_SIZE] {
+        let mut data = self.raw;
+        write_u32(&mut data, 0x00, self.size);
+        write_u32(&mut data, 0x04, self.version);
+        self.index_data.write(&mut data, 0x08);
+        write_u32(&mut data, 0x50, self.data_file_count);
+        self.synonym_data.write(&mut data, 0x54);
+        self.empty_block_data.write(&mut data, 0x9C);
+        self.dir_index_data.write(&mut data, 0xE4);
+        write_u32(&mut data, 0x12C, self.index_type);
+        data[HEADER_SHA1_OFFSET..HEADER_SHA1_OFFSET + SHA1_SIZE].copy_from_slice(&self.sha1);
+        data
+    }
+
+    /// Computes the SHA-1 the header should carry for its current fields
+    pub fn computed_sha1(&self) -> [u8; SHA1_SIZE] {
+        header_sha1(&self.to_bytes())
+    }
+
+    /// Gets the segments in file order: hash table, synonyms, empty blocks, directories
+    pub fn segments(&self) -> [&IndexSegment; 4] {
+        [
+            &self.index_data,
+            &self.synonym_data,
+            &self.empty_block_data,
+            &self.dir_index_data,
+        ]
+    }
+
+    /// Gets the segments mutably, in the same order as [`segments`](Self::segments)
+    pub fn segments_mut(&mut self) -> [&mut IndexSegment; 4] {
+        [
+            &mut self.index_data,
+            &mut self.synonym_data,
+            &mut self.empty_block_data,
+            &mut self.dir_index_data,
+        ]
+    }
+}
+
+/// Second header of a dat file, describing its data (written by SqpkHeader Data commands)
+#[derive(Debug, Clone, Copy)]
+pub struct SqpackDataHeader {
+    /// Size of the header (0x400)
+    pub size: u32,
+    /// Format version
+    pub version: u32,
+    /// Size of the data following the headers
+    pub data_size: u32,
+    /// Number of dat files the data spans
+    pub spanned_dat: u32,
+    /// Largest size the dat file may grow to
+    pub max_file_size: u64,
+    /// SHA-1 of the data following the headers
+    pub data_sha1: [u8; SHA1_SIZE],
+    /// SHA-1 of the first 0x3C0 bytes of the header
+    pub sha1: [u8; SHA1_SIZE],
+    /// Raw header bytes, which the fields above are written over when serializing
+    pub raw: [u8; SQPACK_HEADER_SIZE],
+}
+
+impl PartialEq for SqpackDataHeader {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_bytes() == other.to_bytes()
+    }
+}
+
+impl Eq for SqpackDataHeader {}
+
+impl SqpackDataHeader {
+    /// Parses a data header
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let data = header_bytes(data)?;
+
+        Ok(Self {
+            size: read_u32(data, 0x00),
+            version: read_u32(data, 0x08),
+            data_size: read_u32(data, 0x0C),
+            spanned_dat: read_u32(data, 0x10),
+            max_file_size: u64::from_le_bytes(data[0x18..0x20].try_into().unwrap()),
+            data_sha1: read_sha1(data, 0x20),
+            sha1: read_sha1(data, HEADER_SHA1_OFFSET),
+            raw: data.try_into().unwrap(),
+        })
+    }
+
+    /// Serializes the header, with the stored SHA-1
+    pub fn to_bytes(&self) -> [u8; SQPACK_HEADER_SIZE] {
+        let mut data = self.raw;
+        write_u32(&mut data, 0x00, self.size);
+        write_u32(&mut data, 0x08, self.version);
+        write_u32(&mut data, 0x0C, self.data_size);
+        write_u32(&mut data, 0x10, self.spanned_dat);
+        data[0x18..0x20].copy_from_slice(&self.max_file_size.to_le_bytes());
+        data[0x20..0x20 + SHA1_SIZE].copy_from_slice(&self.data_sha1);
+        data[HEADER_SHA1_OFFSET..HEADER_SHA1_OFFSET + SHA1_SIZE].copy_from_slice(&self.sha1);
+        data
+    }
+
+    /// Computes the SHA-1 the header should carry for its current fields
+    pub fn computed_sha1(&self) -> [u8; SHA1_SIZE] {
+        header_sha1(&self.to_bytes())
+    }
+}
+
+/// A SqPack header parsed according to its kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqpackHeader {
+    /// Version header at the start of a dat or index file
+    Version(SqpackVersionHeader),
+    /// Segment header of an index file
+    Index(SqpackIndexHeader),
+    /// Data header of a dat file
+    Data(SqpackDataHeader),
+}
+
+impl SqpackHeader {
+    /// Serializes the header, with the stored SHA-1
+    pub fn to_bytes(&self) -> [u8; SQPACK_HEADER_SIZE] {
+        match self {
+            SqpackHeader::Version(header) => header.to_bytes(),
+            SqpackHeader::Index(header) => header.to_bytes(),
+            SqpackHeader::Data(header) => header.to_bytes(),
+        }
+    }
+
+    /// Gets the SHA-1 stored in the header
+    pub fn sha1(&self) -> [u8; SHA1_SIZE] {
+        match self {
+            SqpackHeader::Version(header) => header.sha1,
+            SqpackHeader::Index(header) => header.sha1,
+            SqpackHeader::Data(header) => header.sha1,
+        }
+    }
+}
+
+/// Computes the SHA-1 of the part of a serialized header its digest covers
+pub fn header_sha1(data: &[u8]) -> [u8; SHA1_SIZE] {
+    sha1_smol::Sha1::from(&data[..HEADER_SHA1_OFFSET.min(data.len())])
+        .digest()
+        .bytes()
+}
+
+fn header_bytes(data: &[u8]) -> Result<&[u8]> {
+    data.get(..SQPACK_HEADER_SIZE).ok_or_else(|| {
+        ZiPatchError::InvalidSqpackFile(format!(
+            "header is {} bytes, expected {}",
+            data.len(),
+            SQPACK_HEADER_SIZE
+        ))
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_sha1(data: &[u8], offset: usize) -> [u8; SHA1_SIZE] {
+    data[offset..offset + SHA1_SIZE].try_into().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_header_round_trip() {
+        let mut header = SqpackVersionHeader {
+            platform: Platform::Ps4,
+            size: 0x400,
+            version: 1,
+            file_type: SqpackFileType::Data,
+            sha1: [0; SHA1_SIZE],
+            raw: [0; SQPACK_HEADER_SIZE],
+        };
+        header.sha1 = header.computed_sha1();
+
+        let data = header.to_bytes();
+        assert_eq!(&data[..8], SQPACK_MAGIC);
+        assert_eq!(data[0x08], 2);
+        assert_eq!(SqpackVersionHeader::from_bytes(&data).unwrap(), header);
+        assert_eq!(header_sha1(&data), header.sha1);
+    }
+
+    #[test]
+    fn test_index_header_round_trip() {
+        let header = SqpackIndexHeader {
+            size: 0x400,
+            version: 1,
+            index_data: IndexSegment {
+                offset: 0x800,
+                size: 0x30,
+                sha1: [1; SHA1_SIZE],
+            },
+            data_file_count: 2,
+            synonym_data: IndexSegment {
+                offset: 0x830,
+                size: 0x100,
+                sha1: [2; SHA1_SIZE],
+            },
+            empty_block_data: IndexSegment::default(),
+            dir_index_data: IndexSegment {
+                offset: 0x930,
+                size: 0x10,
+                sha1: [3; SHA1_SIZE],
+            },
+            index_type: 0,
+            sha1: [4; SHA1_SIZE],
+            raw: [0; SQPACK_HEADER_SIZE],
+        };
+
+        let data = header.to_bytes();
+        assert_eq!(read_u32(&data, 0x54), 0x830);
+        assert_eq!(read_u32(&data, 0xE8), 0x10);
+
+        let parsed = SqpackIndexHeader::from_bytes(&data).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(parsed.segments()[3].range(), 0x930..0x940);
+    }
+
+    #[test]
+    fn test_data_header_round_trip() {
+        let header = SqpackDataHeader {
+            size: 0x400,
+            version: 0x10,
+            data_size: 0x1234_5680,
+            spanned_dat: 1,
+            max_file_size: 2_000_000_000,
+            data_sha1: [5; SHA1_SIZE],
+            sha1: [6; SHA1_SIZE],
+            raw: [0; SQPACK_HEADER_SIZE],
+        };
+
+        assert_eq!(
+            SqpackDataHeader::from_bytes(&header.to_bytes()).unwrap(),
+            header
+        );
+    }
+
+    #[test]
+    fn test_round_trip_keeps_unknown_bytes() {
+        let mut data = [0u8; SQPACK_HEADER_SIZE];
+        data[..8].copy_from_slice(SQPACK_MAGIC);
+        write_u32(&mut data, 0x0C, 0x400);
+        data[0x200] = 0xAB;
+        data[SQPACK_HEADER_SIZE - 1] = 0xCD;
+        let sha1 = header_sha1(&data);
+        data[HEADER_SHA1_OFFSET..HEADER_SHA1_OFFSET + SHA1_SIZE].copy_from_slice(&sha1);
+
+        let version = SqpackVersionHeader::from_bytes(&data).unwrap();
+        assert_eq!(version.to_bytes(), data);
+        assert_eq!(version.computed_sha1(), version.sha1);
+        let index = SqpackIndexHeader::from_bytes(&data).unwrap();
+        assert_eq!(index.to_bytes(), data);
+        assert_eq!(index.computed_sha1(), index.sha1);
+        let header = SqpackDataHeader::from_bytes(&data).unwrap();
+        assert_eq!(header.to_bytes(), data);
+        assert_eq!(header.computed_sha1(), header.sha1);
+
+        let mut changed = header;
+        changed.spanned_dat = 2;
+        assert_eq!(changed.to_bytes()[0x200], 0xAB);
+        assert_ne!(changed, header);
+    }
+
+    #[test]
+    fn test_short_or_unmarked_headers_are_rejected() {
+        assert!(matches!(
+            SqpackDataHeader::from_bytes(&[0; 0x100]),
+            Err(ZiPatchError::InvalidSqpackFile(_))
+        ));
+        assert!(matches!(
+            SqpackVersionHeader::from_bytes(&[0; SQPACK_HEADER_SIZE]),
+            Err(ZiPatchError::InvalidSqpackFile(_))
+        ));
+    }
+}
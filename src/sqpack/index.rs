@@ -0,0 +1,606 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use super::header::{SqpackIndexHeader, SHA1_SIZE, SQPACK_HEADER_SIZE};
+use crate::error::{Result, ZiPatchError};
+
+/// Size of a synonym table entry
+const SYNONYM_ENTRY_SIZE: usize = 0x100;
+/// Size of the path field in a synonym table entry
+const SYNONYM_PATH_SIZE: usize = 0xF0;
+/// Size of a directory table entry
+const DIRECTORY_ENTRY_SIZE: usize = 16;
+
+/// Offset of the SqPack header size field
+const SQPACK_HEADER_SIZE_OFFSET: usize = 0x0C;
+
+/// Layout of a SqPack index file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexKind {
+    /// `.index` files, keyed by folder and file name hashes
+    Index1,
+    /// `.index2` files, keyed by full path hashes
+    Index2,
+}
+
+impl IndexKind {
+    /// Gets the kind of index with the given SqPack file ID (2 for `.index2`)
+    pub fn from_file_id(file_id: u32) -> Self {
+        if file_id == 2 {
+            IndexKind::Index2
+        } else {
+            IndexKind::Index1
+        }
+    }
+
+    /// Size of a hash table entry
+    pub fn entry_size(self) -> usize {
+        match self {
+            IndexKind::Index1 => 16,
+            IndexKind::Index2 => 8,
+        }
+    }
+}
+
+/// Entry of an index hash table, locating a file in the dat files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IndexEntry {
+    /// Path hash
+    ///
+    /// For `.index` files the folder hash is in the high 32 bits and the file name hash in the
+    /// low 32 bits. For `.index2` files only the low 32 bits are used.
+    pub hash: u64,
+    /// Packed location: the synonym flag in bit 0, the dat file number in bits 1-3 and the
+    /// offset divided by 8 in the remaining bits
+    pub data: u32,
+}
+
+impl IndexEntry {
+    /// Creates an entry pointing at a file in a dat file
+    ///
+    /// # Arguments
+    /// * `hash` - Path hash
+    /// * `data_file_id` - Number of the dat file (the N in `.datN`)
+    /// * `offset` - Offset of the file in the dat file, a multiple of 128
+    /// * `is_synonym` - True if the hash collides with another path
+    pub fn new(hash: u64, data_file_id: u8, offset: u64, is_synonym: bool) -> Self {
+        let data = ((offset >> 3) as u32 & !0xF) | ((data_file_id as u32 & 0x7) << 1);
+        Self {
+            hash,
+            data: data | is_synonym as u32,
+        }
+    }
+
+    /// Checks if the hash collides with another path, in which case the synonym table must
+    /// be used to find the file
+    pub fn is_synonym(&self) -> bool {
+        self.data & 1 != 0
+    }
+
+    /// Gets the number of the dat file holding the file
+    pub fn data_file_id(&self) -> u8 {
+        ((self.data >> 1) & 0x7) as u8
+    }
+
+    /// Gets the offset of the file in its dat file
+    pub fn offset(&self) -> u64 {
+        ((self.data & !0xF) as u64) << 3
+    }
+
+    /// Gets the folder hash of an `.index` entry
+    pub fn folder_hash(&self) -> u32 {
+        (self.hash >> 32) as u32
+    }
+}
+
+/// Entry of an index synonym table, for paths whose hashes collide
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SynonymEntry {
+    /// Path hash, laid out as in [`IndexEntry::hash`]
+    pub hash: u64,
+    /// Packed location, as in [`IndexEntry::data`]
+    pub data: u32,
+    /// Index of the entry among the entries sharing its hash
+    pub conflict_index: u32,
+    /// Full path of the file
+    pub path: String,
+}
+
+/// Parsed SqPack `.index` or `.index2` file
+///
+/// Holds the index header, the hash table and the synonym table parsed, and everything else
+/// (the SqPack header, the empty block table and, for `.index2`, the directory table) as raw
+/// bytes. [`write_to`](Self::write_to) lays the segments out one after another after
+/// the headers, rebuilding the `.index` directory table from the hash table and updating the
+/// segment offsets, sizes and SHA-1 digests in the index header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqpackIndex {
+    kind: IndexKind,
+    sqpack_header: Vec<u8>,
+    index_header: SqpackIndexHeader,
+    entries: Vec<IndexEntry>,
+    synonyms: Vec<SynonymEntry>,
+    empty_blocks: Vec<u8>,
+    directories: Vec<u8>,
+}
+
+impl SqpackIndex {
+    /// Reads an index file from a reader
+    pub fn read_from<R: Read>(reader: &mut R, kind: IndexKind) -> Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Self::from_bytes(&data, kind)
+    }
+
+    /// Parses an index file
+    pub fn from_bytes(data: &[u8], kind: IndexKind) -> Result<Self> {
+        if data.len() < 8 || &data[..6] != b"SqPack" {
+            return Err(invalid("missing SqPack magic"));
+        }
+
+        let sqpack_header_size = read_u32(data, SQPACK_HEADER_SIZE_OFFSET)? as usize;
+        let sqpack_header = slice(data, 0, sqpack_header_size)?;
+
+        let index_header = SqpackIndexHeader::from_bytes(
+            data.get(sqpack_header_size..)
+                .ok_or_else(|| invalid("file ends before the index header"))?,
+        )?;
+
+        let segments = index_header.segments();
+        let segment = |number: usize| -> Result<&[u8]> {
+            let segment = segments[number];
+            slice(data, segment.offset as usize, segment.size as usize)
+        };
+
+        let entry_size = kind.entry_size();
+        let hash_table = segment(0)?;
+        if hash_table.len() % entry_size != 0 {
+            return Err(invalid(
+                "hash table size is not a multiple of the entry size",
+            ));
+        }
+
+        let mut entries: Vec<IndexEntry> = hash_table
+            .chunks_exact(entry_size)
+            .map(|entry| match kind {
+                IndexKind::Index1 => IndexEntry {
+                    hash: u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+                    data: u32::from_le_bytes(entry[8..12].try_into().unwrap()),
+                },
+                IndexKind::Index2 => IndexEntry {
+                    hash: u32::from_le_bytes(entry[0..4].try_into().unwrap()) as u64,
+                    data: u32::from_le_bytes(entry[4..8].try_into().unwrap()),
+                },
+            })
+            .collect();
+        entries.sort();
+
+        let synonym_table = segment(1)?;
+        if synonym_table.len() % SYNONYM_ENTRY_SIZE != 0 {
+            return Err(invalid(
+                "synonym table size is not a multiple of the entry size",
+            ));
+        }
+
+        let synonyms = synonym_table
+            .chunks_exact(SYNONYM_ENTRY_SIZE)
+            .map(|entry| {
+                let hash = match kind {
+                    IndexKind::Index1 => u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+                    IndexKind::Index2 => u32::from_le_bytes(entry[0..4].try_into().unwrap()) as u64,
+                };
+                let path = &entry[16..];
+                let path_len = path.iter().position(|&b| b == 0).unwrap_or(path.len());
+
+                SynonymEntry {
+                    hash,
+                    data: u32::from_le_bytes(entry[8..12].try_into().unwrap()),
+                    conflict_index: u32::from_le_bytes(entry[12..16].try_into().unwrap()),
+                    path: String::from_utf8_lossy(&path[..path_len]).into_owned(),
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            kind,
+            sqpack_header: sqpack_header.to_vec(),
+            index_header,
+            entries,
+            synonyms,
+            empty_blocks: segment(2)?.to_vec(),
+            directories: segment(3)?.to_vec(),
+        })
+    }
+
+    /// Gets the layout of the index
+    pub fn kind(&self) -> IndexKind {
+        self.kind
+    }
+
+    /// Gets the hash table entries, sorted by hash
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// Gets the synonym table entries
+    pub fn synonyms(&self) -> &[SynonymEntry] {
+        &self.synonyms
+    }
+
+    /// Gets the synonym table entries mutably
+    pub fn synonyms_mut(&mut self) -> &mut Vec<SynonymEntry> {
+        &mut self.synonyms
+    }
+
+    /// Looks up the hash table entry for a hash
+    pub fn get(&self, hash: u64) -> Option<&IndexEntry> {
+        self.entries
+            .binary_search_by_key(&hash, |entry| entry.hash)
+            .ok()
+            .map(|i| &self.entries[i])
+    }
+
+    /// Adds an entry to the hash table, returning the entry it replaced
+    pub fn insert(&mut self, entry: IndexEntry) -> Option<IndexEntry> {
+        match self
+            .entries
+            .binary_search_by_key(&entry.hash, |existing| existing.hash)
+        {
+            Ok(i) => Some(std::mem::replace(&mut self.entries[i], entry)),
+            Err(i) => {
+                self.entries.insert(i, entry);
+                None
+            }
+        }
+    }
+
+    /// Removes the hash table entry for a hash, returning it
+    pub fn remove(&mut self, hash: u64) -> Option<IndexEntry> {
+        self.entries
+            .binary_search_by_key(&hash, |entry| entry.hash)
+            .ok()
+            .map(|i| self.entries.remove(i))
+    }
+
+    /// Adds a synonym table entry for a hash table entry whose hash collides with another path
+    ///
+    /// Does nothing if the table already has an entry with the same hash and location.
+    /// Otherwise the new entry gets the next conflict index for its hash, and the table is
+    /// kept sorted by hash.
+    pub fn insert_synonym(&mut self, entry: IndexEntry, path: String) {
+        let same_hash = self
+            .synonyms
+            .iter()
+            .filter(|synonym| synonym.hash == entry.hash);
+        if same_hash.clone().any(|synonym| synonym.data == entry.data) {
+            return;
+        }
+
+        let conflict_index = same_hash.count() as u32;
+        let position = self
+            .synonyms
+            .partition_point(|synonym| synonym.hash <= entry.hash);
+        self.synonyms.insert(
+            position,
+            SynonymEntry {
+                hash: entry.hash,
+                data: entry.data,
+                conflict_index,
+                path,
+            },
+        );
+    }
+
+    /// Removes the synonym table entry for a hash at a location, returning it
+    ///
+    /// Locations are compared without the synonym flag. Later entries for the same hash move
+    /// down a conflict index.
+    pub fn remove_synonym(&mut self, hash: u64, data: u32) -> Option<SynonymEntry> {
+        let position = self
+            .synonyms
+            .iter()
+            .position(|synonym| synonym.hash == hash && synonym.data & !1 == data & !1)?;
+        let removed = self.synonyms.remove(position);
+
+        for synonym in &mut self.synonyms {
+            if synonym.hash == hash && synonym.conflict_index > removed.conflict_index {
+                synonym.conflict_index -= 1;
+            }
+        }
+        Some(removed)
+    }
+
+    /// Gets the length of the file [`to_bytes`](Self::to_bytes) produces, without building it
+    pub fn serialized_len(&self) -> usize {
+        let directories = match self.kind {
+            IndexKind::Index1 => {
+                self.entries
+                    .chunk_by(|a, b| a.folder_hash() == b.folder_hash())
+                    .count()
+                    * DIRECTORY_ENTRY_SIZE
+            }
+            IndexKind::Index2 => self.directories.len(),
+        };
+
+        self.sqpack_header.len()
+            + SQPACK_HEADER_SIZE
+            + self.entries.len() * self.kind.entry_size()
+            + self.synonyms.len() * SYNONYM_ENTRY_SIZE
+            + self.empty_blocks.len()
+            + directories
+    }
+
+    /// Writes the index file to a writer
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Serializes the index file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut hash_table = Vec::with_capacity(self.entries.len() * self.kind.entry_size());
+        for entry in &self.entries {
+            match self.kind {
+                IndexKind::Index1 => {
+                    hash_table.extend_from_slice(&entry.hash.to_le_bytes());
+                    hash_table.extend_from_slice(&entry.data.to_le_bytes());
+                    hash_table.extend_from_slice(&[0; 4]);
+                }
+                IndexKind::Index2 => {
+                    hash_table.extend_from_slice(&(entry.hash as u32).to_le_bytes());
+                    hash_table.extend_from_slice(&entry.data.to_le_bytes());
+                }
+            }
+        }
+
+        let mut synonym_table = Vec::with_capacity(self.synonyms.len() * SYNONYM_ENTRY_SIZE);
+        for synonym in &self.synonyms {
+            match self.kind {
+                IndexKind::Index1 => synonym_table.extend_from_slice(&synonym.hash.to_le_bytes()),
+                IndexKind::Index2 => {
+                    synonym_table.extend_from_slice(&(synonym.hash as u32).to_le_bytes());
+                    synonym_table.extend_from_slice(&[0; 4]);
+                }
+            }
+            synonym_table.extend_from_slice(&synonym.data.to_le_bytes());
+            synonym_table.extend_from_slice(&synonym.conflict_index.to_le_bytes());
+
+            let mut path = [0u8; SYNONYM_PATH_SIZE];
+            let len = synonym.path.len().min(SYNONYM_PATH_SIZE - 1);
+            path[..len].copy_from_slice(&synonym.path.as_bytes()[..len]);
+            synonym_table.extend_from_slice(&path);
+        }
+
+        let hash_table_offset = self.sqpack_header.len() + SQPACK_HEADER_SIZE;
+        let directories = match self.kind {
+            IndexKind::Index1 => self.directory_table(hash_table_offset),
+            IndexKind::Index2 => self.directories.clone(),
+        };
+
+        let segments = [
+            hash_table,
+            synonym_table,
+            self.empty_blocks.clone(),
+            directories,
+        ];
+
+        let mut index_header = self.index_header;
+        let mut offset = hash_table_offset;
+        for (segment, descriptor) in segments.iter().zip(index_header.segments_mut()) {
+            descriptor.offset = offset as u32;
+            descriptor.size = segment.len() as u32;
+            descriptor.sha1 = if segment.is_empty() {
+                [0; SHA1_SIZE]
+            } else {
+                sha1_smol::Sha1::from(segment).digest().bytes()
+            };
+
+            offset += segment.len();
+        }
+        index_header.sha1 = index_header.computed_sha1();
+
+        let mut data = Vec::with_capacity(offset);
+        data.extend_from_slice(&self.sqpack_header);
+        data.extend_from_slice(&index_header.to_bytes());
+        for segment in &segments {
+            data.extend_from_slice(segment);
+        }
+        data
+    }
+
+    /// Builds the `.index` directory table, which lists the range of hash table entries
+    /// belonging to each folder
+    fn directory_table(&self, hash_table_offset: usize) -> Vec<u8> {
+        let entry_size = self.kind.entry_size();
+        let mut folders: BTreeMap<u32, (usize, usize)> = BTreeMap::new();
+
+        for (i, entry) in self.entries.iter().enumerate() {
+            folders
+                .entry(entry.folder_hash())
+                .and_modify(|(_, count)| *count += 1)
+                .or_insert((i, 1));
+        }
+
+        let mut table = Vec::with_capacity(folders.len() * DIRECTORY_ENTRY_SIZE);
+        for (folder_hash, (first, count)) in folders {
+            table.extend_from_slice(&folder_hash.to_le_bytes());
+            table.extend_from_slice(
+                &((hash_table_offset + first * entry_size) as u32).to_le_bytes(),
+            );
+            table.extend_from_slice(&((count * entry_size) as u32).to_le_bytes());
+            table.extend_from_slice(&[0; 4]);
+        }
+        table
+    }
+}
+
+fn invalid(reason: &str) -> ZiPatchError {
+    ZiPatchError::InvalidSqpackFile(reason.to_string())
+}
+
+fn slice(data: &[u8], offset: usize, size: usize) -> Result<&[u8]> {
+    offset
+        .checked_add(size)
+        .and_then(|end| data.get(offset..end))
+        .ok_or_else(|| invalid("segment extends past the end of the file"))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = slice(data, offset, 4)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::Platform;
+    use crate::sqpack::header::{header_sha1, HEADER_SHA1_OFFSET};
+    use crate::sqpack::{SqpackFileType, SqpackVersionHeader};
+
+    fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Builds an empty index file with 0x400-byte SqPack and index headers
+    pub(crate) fn empty_index(kind: IndexKind) -> Vec<u8> {
+        let mut version = SqpackVersionHeader {
+            platform: Platform::Win32,
+            size: 0x400,
+            version: 1,
+            file_type: SqpackFileType::Index,
+            sha1: [0; SHA1_SIZE],
+            raw: [0; SQPACK_HEADER_SIZE],
+        };
+        version.sha1 = version.computed_sha1();
+
+        let mut data = vec![0u8; 0x800];
+        data[..0x400].copy_from_slice(&version.to_bytes());
+        write_u32(&mut data, 0x400, 0x400);
+        for descriptor in [0x08, 0x54, 0x9C, 0xE4] {
+            write_u32(&mut data, 0x400 + descriptor, 0x800);
+        }
+
+        let index = SqpackIndex::from_bytes(&data, kind).unwrap();
+        index.to_bytes()
+    }
+
+    #[test]
+    fn test_entry_packing() {
+        let entry = IndexEntry::new(0x1234_5678_9ABC_DEF0, 3, 0x1_2345_6780, true);
+        assert!(entry.is_synonym());
+        assert_eq!(entry.data_file_id(), 3);
+        assert_eq!(entry.offset(), 0x1_2345_6780);
+        assert_eq!(entry.folder_hash(), 0x1234_5678);
+    }
+
+    #[test]
+    fn test_index1_round_trip() {
+        let mut index =
+            SqpackIndex::from_bytes(&empty_index(IndexKind::Index1), IndexKind::Index1).unwrap();
+        index.insert(IndexEntry::new(0x0000_0002_0000_0001, 0, 0x80, false));
+        index.insert(IndexEntry::new(0x0000_0001_0000_0005, 1, 0x100, false));
+        index.insert(IndexEntry::new(0x0000_0001_0000_0003, 0, 0x180, false));
+        index.synonyms_mut().push(SynonymEntry {
+            hash: 0x0000_0001_0000_0003,
+            data: 0,
+            conflict_index: 0,
+            path: "common/font/font1.tex".to_string(),
+        });
+
+        let data = index.to_bytes();
+        assert_eq!(data.len(), 0x800 + 3 * 16 + SYNONYM_ENTRY_SIZE + 2 * 16);
+        assert_eq!(index.serialized_len(), data.len());
+
+        let read = SqpackIndex::from_bytes(&data, IndexKind::Index1).unwrap();
+        assert_eq!(read.entries(), index.entries());
+        assert_eq!(read.synonyms(), index.synonyms());
+        assert_eq!(read.to_bytes(), data);
+        assert_eq!(read.get(0x0000_0001_0000_0005).unwrap().offset(), 0x100);
+        assert_eq!(read.synonyms()[0].path, "common/font/font1.tex");
+
+        // Folder 1 covers the first two entries, folder 2 the last
+        let dirs = &data[0x800 + 3 * 16 + SYNONYM_ENTRY_SIZE..];
+        assert_eq!(read_u32(dirs, 0).unwrap(), 1);
+        assert_eq!(read_u32(dirs, 4).unwrap(), 0x800);
+        assert_eq!(read_u32(dirs, 8).unwrap(), 32);
+        assert_eq!(read_u32(dirs, 16).unwrap(), 2);
+        assert_eq!(read_u32(dirs, 20).unwrap(), 0x820);
+
+        // Hash table digest and header self hash are updated
+        let header = &data[0x400..0x800];
+        let hash_table = &data[0x800..0x830];
+        assert_eq!(
+            &header[0x10..0x10 + SHA1_SIZE],
+            &sha1_smol::Sha1::from(hash_table).digest().bytes()
+        );
+        assert_eq!(
+            &header[HEADER_SHA1_OFFSET..HEADER_SHA1_OFFSET + SHA1_SIZE],
+            &header_sha1(header)
+        );
+    }
+
+    #[test]
+    fn test_index2_insert_and_remove() {
+        let mut index =
+            SqpackIndex::from_bytes(&empty_index(IndexKind::Index2), IndexKind::Index2).unwrap();
+        assert_eq!(index.insert(IndexEntry::new(7, 0, 0x80, false)), None);
+        assert!(index.insert(IndexEntry::new(7, 1, 0x80, false)).is_some());
+        index.insert(IndexEntry::new(3, 0, 0x100, false));
+
+        let read = SqpackIndex::from_bytes(&index.to_bytes(), IndexKind::Index2).unwrap();
+        assert_eq!(read.entries().len(), 2);
+        assert_eq!(read.entries()[0].hash, 3);
+        assert_eq!(read.get(7).unwrap().data_file_id(), 1);
+
+        index.remove(3);
+        assert!(index.get(3).is_none());
+        assert_eq!(index.to_bytes().len(), 0x800 + 8);
+    }
+
+    #[test]
+    fn test_synonyms_and_unknown_header_bytes() {
+        let mut data = empty_index(IndexKind::Index1);
+        data[0x400 + 0x200] = 0xAB;
+        let mut index = SqpackIndex::from_bytes(&data, IndexKind::Index1).unwrap();
+
+        let first = IndexEntry::new(0x0000_0001_0000_0003, 0, 0x80, true);
+        let second = IndexEntry::new(0x0000_0001_0000_0003, 0, 0x100, true);
+        index.insert_synonym(second, "b".to_string());
+        index.insert_synonym(IndexEntry::new(1, 0, 0x80, true), String::new());
+        index.insert_synonym(first, "a".to_string());
+        index.insert_synonym(first, "a".to_string());
+
+        let conflicts: Vec<(u64, u32)> = index
+            .synonyms()
+            .iter()
+            .map(|synonym| (synonym.hash, synonym.conflict_index))
+            .collect();
+        assert_eq!(conflicts, [(1, 0), (first.hash, 0), (first.hash, 1)]);
+
+        // Only the entry at the location goes, and the other takes its conflict index
+        assert_eq!(
+            index.remove_synonym(first.hash, second.data).unwrap().path,
+            "b"
+        );
+        assert!(index.remove_synonym(first.hash, second.data).is_none());
+        assert_eq!(index.synonyms()[1].path, "a");
+        assert_eq!(index.synonyms()[1].conflict_index, 0);
+
+        let written = index.to_bytes();
+        assert_eq!(written.len(), index.serialized_len());
+        assert_eq!(written[0x400 + 0x200], 0xAB);
+        assert_eq!(
+            &written[0x400 + HEADER_SHA1_OFFSET..0x400 + HEADER_SHA1_OFFSET + SHA1_SIZE],
+            &header_sha1(&written[0x400..0x800])
+        );
+    }
+
+    #[test]
+    fn test_truncated_file_is_rejected() {
+        let data = empty_index(IndexKind::Index1);
+        assert!(matches!(
+            SqpackIndex::from_bytes(&data[..0x500], IndexKind::Index1),
+            Err(ZiPatchError::InvalidSqpackFile(_))
+        ));
+    }
+}
@@ -0,0 +1,22 @@
+mod dat;
+mod header;
+mod index;
+mod path_hash;
+mod verify;
+
+pub use dat::{read_dat_entry, read_dat_file, DatEntryHeader, DatEntryType};
+pub use header::{
+    header_sha1, IndexSegment, SqpackDataHeader, SqpackFileType, SqpackHeader, SqpackIndexHeader,
+    SqpackVersionHeader, HEADER_SHA1_OFFSET, SQPACK_HEADER_SIZE, SQPACK_MAGIC,
+};
+pub use index::{IndexEntry, IndexKind, SqpackIndex, SynonymEntry};
+pub use path_hash::{
+    file_name_hash, folder_hash, index2_hash, index_hash, path_hash, PathResolver,
+};
+pub use verify::{
+    verify_sqpack_directory, verify_sqpack_file, DigestMismatch, InvalidFile, VerifiedSegment,
+    VerifyReport,
+};
+
+#[cfg(test)]
+pub(crate) use index::tests::empty_index;
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+
+use super::IndexKind;
+
+/// Hashes a string the way SqPack index files do: CRC-32 of the lowercased bytes, without the
+/// final inversion
+fn sqpack_crc(value: &str) -> u32 {
+    !crc32fast::hash(value.to_ascii_lowercase().as_bytes())
+}
+
+/// Splits a game path into its folder and file name at the last `/`
+fn split_path(path: &str) -> (&str, &str) {
+    match path.rfind('/') {
+        Some(separator) => (&path[..separator], &path[separator + 1..]),
+        None => ("", path),
+    }
+}
+
+/// Calculates the hash of a folder, as stored in the high 32 bits of an `.index` hash
+///
+/// # Arguments
+/// * `folder` - Folder path without a trailing `/` (e.g. `chara/monster/m0001/obj/body/b0001`)
+pub fn folder_hash(folder: &str) -> u32 {
+    sqpack_crc(folder)
+}
+
+/// Calculates the hash of a file name, as stored in the low 32 bits of an `.index` hash
+pub fn file_name_hash(file_name: &str) -> u32 {
+    sqpack_crc(file_name)
+}
+
+/// Calculates the `.index` hash of a game path: the folder hash in the high 32 bits and the
+/// file name hash in the low 32 bits
+pub fn index_hash(path: &str) -> u64 {
+    let (folder, file_name) = split_path(path);
+    ((folder_hash(folder) as u64) << 32) | file_name_hash(file_name) as u64
+}
+
+/// Calculates the `.index2` hash of a game path, computed over the full path
+pub fn index2_hash(path: &str) -> u32 {
+    sqpack_crc(path)
+}
+
+/// Calculates the hash of a game path for the given kind of index
+pub fn path_hash(path: &str, kind: IndexKind) -> u64 {
+    match kind {
+        IndexKind::Index1 => index_hash(path),
+        IndexKind::Index2 => index2_hash(path) as u64,
+    }
+}
+
+/// Maps index hashes back to game paths
+///
+/// Hashes can't be reversed, so the resolver only knows the paths it has been given, usually
+/// from a community-maintained path list.
+#[derive(Debug, Clone, Default)]
+pub struct PathResolver {
+    paths: Vec<String>,
+    index1: HashMap<u64, usize>,
+    index2: HashMap<u32, usize>,
+}
+
+impl PathResolver {
+    /// Creates an empty resolver
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a resolver knowing the given game paths
+    pub fn from_paths<I, S>(paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut resolver = Self::new();
+        for path in paths {
+            resolver.add_path(path);
+        }
+        resolver
+    }
+
+    /// Creates a resolver from a path list with one game path per line
+    ///
+    /// Blank lines are skipped, and surrounding whitespace is trimmed.
+    pub fn from_path_list(list: &str) -> Self {
+        Self::from_paths(list.lines().map(str::trim).filter(|line| !line.is_empty()))
+    }
+
+    /// Adds a game path to the resolver
+    ///
+    /// Paths are compared ignoring case, like their hashes, so adding a known path again does
+    /// nothing. A new path whose hash collides with a known one takes its place for that
+    /// hash.
+    pub fn add_path<S: Into<String>>(&mut self, path: S) {
+        let path = path.into();
+        let hash = index_hash(&path);
+        let known = self
+            .index1
+            .get(&hash)
+            .is_some_and(|&i| self.paths[i].eq_ignore_ascii_case(&path));
+        if known {
+            return;
+        }
+
+        let i = self.paths.len();
+        self.index1.insert(hash, i);
+        self.index2.insert(index2_hash(&path), i);
+        self.paths.push(path);
+    }
+
+    /// Gets the number of distinct paths known to the resolver
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// Checks if the resolver knows no paths
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Resolves a hash from an index of the given kind to a game path
+    pub fn resolve(&self, hash: u64, kind: IndexKind) -> Option<&str> {
+        match kind {
+            IndexKind::Index1 => self.index1.get(&hash),
+            IndexKind::Index2 => u32::try_from(hash)
+                .ok()
+                .and_then(|hash| self.index2.get(&hash)),
+        }
+        .map(|&i| self.paths[i].as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashes() {
+        // CRC-32/JAMCRC check value
+        assert_eq!(sqpack_crc("123456789"), 0x340B_C6D9);
+
+        // exd/root.exl, the excel sheet list in 0a0000.win32.index and .index2
+        assert_eq!(folder_hash("exd"), 0xE39B_7999);
+        assert_eq!(file_name_hash("root.exl"), 0x51B5_7EBC);
+        assert_eq!(index_hash("exd/root.exl"), 0xE39B_7999_51B5_7EBC);
+        assert_eq!(index2_hash("exd/root.exl"), 0x3E16_266C);
+
+        let path = "chara/monster/m0001/obj/body/b0001/model/m0001b0001.mdl";
+        assert_eq!(
+            index_hash(path),
+            ((folder_hash("chara/monster/m0001/obj/body/b0001/model") as u64) << 32)
+                | file_name_hash("m0001b0001.mdl") as u64
+        );
+        assert_eq!(index2_hash(path), sqpack_crc(path));
+        assert_eq!(index_hash(path), index_hash(&path.to_uppercase()));
+        assert_eq!(path_hash(path, IndexKind::Index2), index2_hash(path) as u64);
+    }
+
+    #[test]
+    fn test_resolver() {
+        let resolver = PathResolver::from_path_list(
+            "exd/root.exl\n\n  chara/monster/m0001/obj/body/b0001/model/m0001b0001.mdl \n\
+             EXD/Root.exl\nexd/root.exl\n",
+        );
+        assert_eq!(resolver.len(), 2);
+
+        let path = "chara/monster/m0001/obj/body/b0001/model/m0001b0001.mdl";
+        assert_eq!(
+            resolver.resolve(index_hash(path), IndexKind::Index1),
+            Some(path)
+        );
+        assert_eq!(
+            resolver.resolve(index2_hash("exd/root.exl") as u64, IndexKind::Index2),
+            Some("exd/root.exl")
+        );
+        assert_eq!(
+            resolver.resolve(index_hash("exd/missing.exh"), IndexKind::Index1),
+            None
+        );
+    }
+}
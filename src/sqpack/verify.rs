@@ -0,0 +1,421 @@
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use super::header::{
+    header_sha1, SqpackDataHeader, SqpackFileType, SqpackIndexHeader, SqpackVersionHeader,
+    SHA1_SIZE, SQPACK_HEADER_SIZE,
+};
+use crate::error::{Result, ZiPatchError};
+use crate::target::PatchTarget;
+
+/// Part of a SqPack file covered by a SHA-1 digest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifiedSegment {
+    /// The version header at the start of the file
+    VersionHeader,
+    /// The segment header of an index file
+    IndexHeader,
+    /// The index hash table
+    HashTable,
+    /// The index synonym table
+    SynonymTable,
+    /// The index empty block table
+    EmptyBlockTable,
+    /// The index directory table
+    DirectoryTable,
+    /// The data header of a dat file
+    DataHeader,
+    /// The data following the headers of a dat file
+    Data,
+}
+
+impl VerifiedSegment {
+    /// Index segments, in the order of [`SqpackIndexHeader::segments`]
+    const INDEX_SEGMENTS: [VerifiedSegment; 4] = [
+        VerifiedSegment::HashTable,
+        VerifiedSegment::SynonymTable,
+        VerifiedSegment::EmptyBlockTable,
+        VerifiedSegment::DirectoryTable,
+    ];
+}
+
+impl fmt::Display for VerifiedSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            VerifiedSegment::VersionHeader => "version header",
+            VerifiedSegment::IndexHeader => "index header",
+            VerifiedSegment::HashTable => "hash table",
+            VerifiedSegment::SynonymTable => "synonym table",
+            VerifiedSegment::EmptyBlockTable => "empty block table",
+            VerifiedSegment::DirectoryTable => "directory table",
+            VerifiedSegment::DataHeader => "data header",
+            VerifiedSegment::Data => "data",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A digest stored in a SqPack file that doesn't match the data it covers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestMismatch {
+    /// File the digest is stored in
+    pub path: PathBuf,
+    /// Part of the file the digest covers
+    pub segment: VerifiedSegment,
+    /// Digest stored in the file
+    pub expected: [u8; SHA1_SIZE],
+    /// Digest of the data as it is
+    pub actual: [u8; SHA1_SIZE],
+}
+
+impl fmt::Display for DigestMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} SHA-1 mismatch (expected {}, got {})",
+            self.path.display(),
+            self.segment,
+            hex(&self.expected),
+            hex(&self.actual)
+        )
+    }
+}
+
+/// A file in a verified directory that couldn't be read as a SqPack file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidFile {
+    /// Path of the file
+    pub path: PathBuf,
+    /// Why the file couldn't be read
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.reason)
+    }
+}
+
+/// Result of verifying the digests of one or more SqPack files
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of files checked
+    pub files_checked: usize,
+    /// Number of digests checked
+    pub digests_checked: usize,
+    /// Digests that didn't match
+    pub mismatches: Vec<DigestMismatch>,
+    /// Files that couldn't be read as SqPack files
+    pub invalid_files: Vec<InvalidFile>,
+}
+
+impl VerifyReport {
+    /// Checks if every file was valid and every digest matched
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty() && self.invalid_files.is_empty()
+    }
+}
+
+/// Verifies the SHA-1 digests of a SqPack `.datN`, `.index` or `.index2` file
+///
+/// Checks the version header, then either the index header and the segments it describes
+/// or the data header and the data following it, depending on the file type in the version
+/// header. Digests that are all zeros are treated as absent and skipped.
+pub fn verify_sqpack_file(target: &dyn PatchTarget, path: &Path) -> Result<VerifyReport> {
+    let mut verifier = Verifier {
+        path,
+        report: VerifyReport {
+            files_checked: 1,
+            ..VerifyReport::default()
+        },
+    };
+
+    let mut reader = target.open(path)?;
+    let version_bytes = read_header(&mut reader, 0)?;
+    let version = SqpackVersionHeader::from_bytes(&version_bytes)?;
+    verifier.check(
+        VerifiedSegment::VersionHeader,
+        version.sha1,
+        header_sha1(&version_bytes),
+    );
+
+    match version.file_type {
+        SqpackFileType::Index => {
+            let header_bytes = read_header(&mut reader, version.size as u64)?;
+            let header = SqpackIndexHeader::from_bytes(&header_bytes)?;
+            verifier.check(
+                VerifiedSegment::IndexHeader,
+                header.sha1,
+                header_sha1(&header_bytes),
+            );
+
+            for (segment, kind) in header
+                .segments()
+                .iter()
+                .zip(VerifiedSegment::INDEX_SEGMENTS)
+            {
+                if segment.size > 0 && !is_zero(&segment.sha1) {
+                    let actual =
+                        range_sha1(&mut reader, segment.offset as u64, segment.size as u64)?;
+                    verifier.check(kind, segment.sha1, actual);
+                }
+            }
+        }
+        SqpackFileType::Data => {
+            let header_bytes = read_header(&mut reader, version.size as u64)?;
+            let header = SqpackDataHeader::from_bytes(&header_bytes)?;
+            verifier.check(
+                VerifiedSegment::DataHeader,
+                header.sha1,
+                header_sha1(&header_bytes),
+            );
+
+            if header.data_size > 0 && !is_zero(&header.data_sha1) {
+                let data_offset = version.size as u64 + header.size as u64;
+                let actual = range_sha1(&mut reader, data_offset, header.data_size as u64)?;
+                verifier.check(VerifiedSegment::Data, header.data_sha1, actual);
+            }
+        }
+        SqpackFileType::Database | SqpackFileType::Unknown(_) => {}
+    }
+
+    Ok(verifier.report)
+}
+
+/// Verifies every `.datN`, `.index` and `.index2` file directly inside a directory, such as
+/// `sqpack/ffxiv`
+///
+/// Files are checked in name order; see [`verify_sqpack_file`] for what is checked. A file
+/// with invalid or truncated headers is recorded in the report's `invalid_files`, and the
+/// other files are still checked.
+pub fn verify_sqpack_directory(target: &dyn PatchTarget, dir: &Path) -> Result<VerifyReport> {
+    let mut files: Vec<PathBuf> = target
+        .list_files(dir)?
+        .into_iter()
+        .filter(|path| is_sqpack_file(path))
+        .collect();
+    files.sort();
+
+    let mut report = VerifyReport::default();
+    for path in files {
+        let file_report = match verify_sqpack_file(target, &path) {
+            Ok(file_report) => file_report,
+            Err(ZiPatchError::InvalidSqpackFile(reason)) => {
+                report.files_checked += 1;
+                report.invalid_files.push(InvalidFile { path, reason });
+                continue;
+            }
+            Err(ZiPatchError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                report.files_checked += 1;
+                report.invalid_files.push(InvalidFile {
+                    path,
+                    reason: "file is shorter than its headers say".to_string(),
+                });
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        report.files_checked += file_report.files_checked;
+        report.digests_checked += file_report.digests_checked;
+        report.mismatches.extend(file_report.mismatches);
+    }
+
+    Ok(report)
+}
+
+struct Verifier<'a> {
+    path: &'a Path,
+    report: VerifyReport,
+}
+
+impl Verifier<'_> {
+    fn check(
+        &mut self,
+        segment: VerifiedSegment,
+        expected: [u8; SHA1_SIZE],
+        actual: [u8; SHA1_SIZE],
+    ) {
+        if is_zero(&expected) {
+            return;
+        }
+
+        self.report.digests_checked += 1;
+        if expected != actual {
+            self.report.mismatches.push(DigestMismatch {
+                path: self.path.to_path_buf(),
+                segment,
+                expected,
+                actual,
+            });
+        }
+    }
+}
+
+/// Checks if a path has a `.datN`, `.index` or `.index2` extension
+fn is_sqpack_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.starts_with("dat") || extension.starts_with("index"))
+}
+
+fn read_header<R: Read + Seek + ?Sized>(reader: &mut R, offset: u64) -> Result<Vec<u8>> {
+    let mut data = vec![0u8; SQPACK_HEADER_SIZE];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn range_sha1<R: Read + Seek + ?Sized>(
+    reader: &mut R,
+    offset: u64,
+    length: u64,
+) -> Result<[u8; SHA1_SIZE]> {
+    let mut sha1 = sha1_smol::Sha1::new();
+    let mut buf = vec![0u8; 1 << 16];
+    let mut remaining = length;
+
+    reader.seek(SeekFrom::Start(offset))?;
+    while remaining > 0 {
+        let to_read = remaining.min(buf.len() as u64) as usize;
+        reader.read_exact(&mut buf[..to_read])?;
+        sha1.update(&buf[..to_read]);
+        remaining -= to_read as u64;
+    }
+
+    Ok(sha1.digest().bytes())
+}
+
+fn is_zero(digest: &[u8; SHA1_SIZE]) -> bool {
+    digest.iter().all(|&b| b == 0)
+}
+
+fn hex(digest: &[u8; SHA1_SIZE]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Platform;
+    use crate::sqpack::{empty_index, IndexEntry, IndexKind, SqpackIndex};
+    use crate::target::MemoryTarget;
+
+    fn dat_file(data: &[u8]) -> Vec<u8> {
+        let mut version = SqpackVersionHeader {
+            platform: Platform::Win32,
+            size: 0x400,
+            version: 1,
+            file_type: SqpackFileType::Data,
+            sha1: [0; SHA1_SIZE],
+            raw: [0; SQPACK_HEADER_SIZE],
+        };
+        version.sha1 = version.computed_sha1();
+
+        let mut header = SqpackDataHeader {
+            size: 0x400,
+            version: 0x10,
+            data_size: data.len() as u32,
+            spanned_dat: 1,
+            max_file_size: 2_000_000_000,
+            data_sha1: sha1_smol::Sha1::from(data).digest().bytes(),
+            sha1: [0; SHA1_SIZE],
+            raw: [0; SQPACK_HEADER_SIZE],
+        };
+        header.sha1 = header.computed_sha1();
+
+        let mut file = version.to_bytes().to_vec();
+        file.extend_from_slice(&header.to_bytes());
+        file.extend_from_slice(data);
+        file
+    }
+
+    fn index_file() -> Vec<u8> {
+        let mut index =
+            SqpackIndex::from_bytes(&empty_index(IndexKind::Index1), IndexKind::Index1).unwrap();
+        index.insert(IndexEntry::new(0x1_0000_0002, 0, 0x80, false));
+        index.to_bytes()
+    }
+
+    #[test]
+    fn test_intact_files_verify() {
+        let target = MemoryTarget::new();
+        let dir = Path::new("/game/sqpack/ffxiv");
+        target.insert_file(dir.join("000000.win32.dat0"), dat_file(&[0x11; 0x100]));
+        target.insert_file(dir.join("000000.win32.index"), index_file());
+        target.insert_file(
+            dir.join("000000.win32.index2"),
+            empty_index(IndexKind::Index2),
+        );
+        target.insert_file(dir.join("notes.txt"), b"not sqpack".to_vec());
+
+        let report = verify_sqpack_directory(&target, dir).unwrap();
+        assert!(report.is_ok(), "{:?}", report.mismatches);
+        assert_eq!(report.files_checked, 3);
+        // dat: 3 digests, index: version + header + hash table + directories, index2: 2
+        assert_eq!(report.digests_checked, 3 + 4 + 2);
+    }
+
+    #[test]
+    fn test_mismatches_are_reported_per_segment() {
+        let target = MemoryTarget::new();
+        let dat_path = Path::new("/game/sqpack/ffxiv/000000.win32.dat0");
+        let index_path = Path::new("/game/sqpack/ffxiv/000000.win32.index");
+
+        let mut dat = dat_file(&[0x11; 0x100]);
+        dat[0x810] ^= 0xFF;
+        target.insert_file(dat_path, dat);
+
+        let mut index = index_file();
+        index[0x800] ^= 0xFF;
+        index[0x500] ^= 0xFF;
+        target.insert_file(index_path, index);
+
+        let report = verify_sqpack_file(&target, dat_path).unwrap();
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(report.mismatches[0].segment, VerifiedSegment::Data);
+        assert_eq!(report.mismatches[0].path, dat_path);
+
+        let report = verify_sqpack_directory(&target, Path::new("/game/sqpack/ffxiv")).unwrap();
+        let segments: Vec<VerifiedSegment> = report.mismatches.iter().map(|m| m.segment).collect();
+        assert_eq!(
+            segments,
+            [
+                VerifiedSegment::Data,
+                VerifiedSegment::IndexHeader,
+                VerifiedSegment::HashTable
+            ]
+        );
+        assert!(report.mismatches[2]
+            .to_string()
+            .contains("hash table SHA-1 mismatch"));
+    }
+
+    #[test]
+    fn test_invalid_files_are_reported_and_skipped() {
+        let target = MemoryTarget::new();
+        let dir = Path::new("/game/sqpack/ffxiv");
+        target.insert_file(dir.join("000000.win32.dat0"), vec![0; 0x10]);
+        target.insert_file(dir.join("000000.win32.dat1"), dat_file(&[0x11; 0x100]));
+        target.insert_file(dir.join("000000.win32.index"), vec![0xFF; 0x800]);
+
+        let report = verify_sqpack_directory(&target, dir).unwrap();
+        assert!(!report.is_ok());
+        assert!(report.mismatches.is_empty());
+        assert_eq!(report.files_checked, 3);
+        assert_eq!(report.digests_checked, 3);
+
+        let invalid: Vec<&Path> = report
+            .invalid_files
+            .iter()
+            .map(|file| file.path.as_path())
+            .collect();
+        assert_eq!(
+            invalid,
+            [
+                dir.join("000000.win32.dat0"),
+                dir.join("000000.win32.index")
+            ]
+        );
+    }
+}
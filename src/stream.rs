@@ -0,0 +1,274 @@
+use std::io::Read;
+
+use crate::apply::ChunkInfo;
+use crate::chunk::{FileHeaderChunk, ZiPatchChunk};
+use crate::config::ZiPatchConfig;
+use crate::error::{Result, ZiPatchError};
+use crate::file::ZIPATCH_MAGIC;
+use crate::util::{BinaryReaderExt, ChecksumReader, ForwardReader};
+
+/// Forward-only ZiPatch reader
+///
+/// Parses chunks straight from any [`Read`], such as a decompressor, a pipe or an HTTP body,
+/// without seeking and without buffering more than the chunk being read. Unlike
+/// [`ZiPatchFile`](crate::ZiPatchFile), the stream can only be read once, from start to end:
+/// the file header is only known once its FHDR chunk has been read, and applies can't be
+/// checkpointed, resumed or journaled.
+///
+/// # Example
+///
+/// ```no_run
+/// use flate2::read::GzDecoder;
+/// use zipatch::{ZiPatchConfig, ZiPatchStream};
+///
+/// let file = std::fs::File::open("D2024.01.01.0000.0000.patch.gz")?;
+/// let mut config = ZiPatchConfig::new("/path/to/game");
+///
+/// ZiPatchStream::new(GzDecoder::new(file))?.apply(&mut config)?;
+/// # Ok::<(), zipatch::ZiPatchError>(())
+/// ```
+pub struct ZiPatchStream<R: Read> {
+    reader: ChecksumReader<ForwardReader<R>>,
+    header: Option<FileHeaderChunk>,
+    index: u64,
+    total_bytes: Option<u64>,
+    done: bool,
+}
+
+impl<R: Read> ZiPatchStream<R> {
+    /// Creates a new ZiPatchStream from a reader, reading and verifying the magic number
+    pub fn new(reader: R) -> Result<Self> {
+        let mut reader = ChecksumReader::new(ForwardReader::new(reader));
+
+        let mut magic = [0u32; 3];
+        for m in &mut magic {
+            *m = reader.read_u32_le()?;
+        }
+
+        if magic != ZIPATCH_MAGIC {
+            return Err(ZiPatchError::InvalidMagic(magic));
+        }
+
+        Ok(Self {
+            reader,
+            header: None,
+            index: 0,
+            total_bytes: None,
+            done: false,
+        })
+    }
+
+    /// Sets the patch length reported to observers in [`ChunkInfo::total_bytes`]
+    ///
+    /// The length of a stream isn't known up front, so it's reported as `None` unless set
+    /// here, for example from the Content-Length of an HTTP response.
+    pub fn total_bytes(mut self, total_bytes: u64) -> Self {
+        self.total_bytes = Some(total_bytes);
+        self
+    }
+
+    /// Gets a reference to the file header, once its FHDR chunk has been read
+    pub fn header(&self) -> Option<&FileHeaderChunk> {
+        self.header.as_ref()
+    }
+
+    /// Gets the number of bytes read from the stream so far, including the magic number
+    pub fn bytes_read(&self) -> u64 {
+        self.reader.get_ref().position()
+    }
+
+    /// Consumes the ZiPatchStream and returns the inner reader
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner().into_inner()
+    }
+
+    /// Applies every remaining chunk in the stream
+    ///
+    /// The configuration's [`ApplyObserver`](crate::apply::ApplyObserver), if any, is notified
+    /// before and after each chunk, and its cancellation token is checked before each one.
+    /// Returns once the EOF_ chunk has been applied; a stream ending before that fails with
+    /// an I/O error.
+    pub fn apply(&mut self, config: &mut ZiPatchConfig) -> Result<()> {
+        for result in self {
+            let (info, mut chunk) = result?;
+            chunk.apply_observed(config, &info)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for ZiPatchStream<R> {
+    type Item = Result<(ChunkInfo, ZiPatchChunk)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let offset = self.bytes_read();
+        let chunk = match ZiPatchChunk::read(&mut self.reader) {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        };
+
+        let info = ChunkInfo::new(
+            &chunk,
+            self.index,
+            offset,
+            self.bytes_read() - offset,
+            self.total_bytes,
+        );
+        self.index += 1;
+
+        match chunk {
+            ZiPatchChunk::FileHeader(ref fhdr) if self.header.is_none() => {
+                self.header = Some(fhdr.clone());
+            }
+            ZiPatchChunk::EndOfFile(_) => self.done = true,
+            _ => {}
+        }
+
+        Some(Ok((info, chunk)))
+    }
+}
+
+impl<R: Read> std::fmt::Debug for ZiPatchStream<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ZiPatchStream")
+            .field("header", &self.header)
+            .field("bytes_read", &self.bytes_read())
+            .field("index", &self.index)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::ApplyOptions;
+    use crate::generate::PatchBuilder;
+    use crate::{MemoryTarget, ZiPatchFile};
+    use flate2::read::DeflateDecoder;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::{Cursor, Write};
+
+    fn memory_config(target: &MemoryTarget) -> ZiPatchConfig {
+        ZiPatchConfig::builder("/game")
+            .target(target.clone())
+            .build()
+    }
+
+    #[test]
+    fn test_stream_applies_like_file() {
+        let mut builder = PatchBuilder::new("DIFF");
+        builder
+            .add_file("boot/ffxivboot.ver", b"2024.01.01.0000.0000")
+            .unwrap();
+        let dat: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        builder
+            .add_file("game/sqpack/ffxiv/000000.win32.dat0", &dat)
+            .unwrap();
+        builder.delete_file("game/movie/ffxiv/old.bk2");
+        let data = builder.to_bytes().unwrap();
+
+        let expected = MemoryTarget::new();
+        ZiPatchFile::new(Cursor::new(data.clone()))
+            .unwrap()
+            .apply(&mut memory_config(&expected), &ApplyOptions::default())
+            .unwrap();
+
+        // A decompressor can only be read forwards
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let actual = MemoryTarget::new();
+        let mut stream = ZiPatchStream::new(DeflateDecoder::new(compressed.as_slice())).unwrap();
+        assert!(stream.header().is_none());
+        stream.apply(&mut memory_config(&actual)).unwrap();
+
+        assert_eq!(stream.header().unwrap().patch_type, "DIFF");
+        assert_eq!(stream.bytes_read(), data.len() as u64);
+        assert!(stream.next().is_none());
+
+        assert_eq!(actual.files(), expected.files());
+        for file in expected.files() {
+            assert_eq!(actual.file(&file), expected.file(&file));
+        }
+    }
+
+    #[test]
+    fn test_stream_reports_truncation() {
+        let mut builder = PatchBuilder::new("DIFF");
+        builder
+            .add_file("boot/ffxivboot.ver", b"2024.01.01.0000.0000")
+            .unwrap();
+        let data = builder.to_bytes().unwrap();
+
+        let mut stream = ZiPatchStream::new(&data[..data.len() - 8]).unwrap();
+        let results: Vec<_> = stream.by_ref().collect();
+
+        assert!(results[..results.len() - 1].iter().all(Result::is_ok));
+        assert!(matches!(results.last(), Some(Err(ZiPatchError::Io(_)))));
+        assert!(stream.next().is_none());
+    }
+
+    /// Gets the offset and total size of every chunk in a written patch
+    fn chunk_ranges(data: &[u8]) -> Vec<(usize, usize)> {
+        let mut ranges = Vec::new();
+        let mut offset = 12;
+        while offset < data.len() {
+            let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+            ranges.push((offset, size + 12));
+            offset += size + 12;
+        }
+        ranges
+    }
+
+    fn boot_patch() -> Vec<u8> {
+        let mut builder = PatchBuilder::new("DIFF");
+        builder
+            .add_file("boot/ffxivboot.ver", b"2024.01.01.0000.0000")
+            .unwrap();
+        builder.to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_stream_reports_truncation_mid_chunk() {
+        let data = boot_patch();
+        let (offset, size) = chunk_ranges(&data)[1];
+
+        let target = MemoryTarget::new();
+        let mut stream = ZiPatchStream::new(&data[..offset + size / 2]).unwrap();
+        let result = stream.apply(&mut memory_config(&target));
+
+        assert!(matches!(result, Err(ZiPatchError::Io(_))));
+        assert!(stream.header().is_some());
+        assert!(stream.next().is_none());
+        assert!(target.files().is_empty());
+    }
+
+    #[test]
+    fn test_stream_rejects_bad_checksum() {
+        let mut data = boot_patch();
+        let (offset, size) = chunk_ranges(&data)[1];
+        data[offset + size - 1] ^= 0xFF;
+
+        let target = MemoryTarget::new();
+        let mut stream = ZiPatchStream::new(data.as_slice()).unwrap();
+        let result = stream.apply(&mut memory_config(&target));
+
+        assert!(matches!(
+            result,
+            Err(ZiPatchError::ChecksumMismatch { expected, actual, .. }) if expected != actual
+        ));
+        assert!(stream.next().is_none());
+        assert!(target.files().is_empty());
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{PatchTarget, TargetMetadata, TargetReader};
+use crate::error::{Result, ZiPatchError};
+use crate::util::{SqexFileStream, SqexFileStreamStore};
+
+/// Number of attempts when opening a file for writing
+const OPEN_TRIES: u32 = 5;
+/// Seconds to wait between attempts
+const OPEN_SLEEP_TIME: u64 = 1;
+
+/// Target that applies patches to the local filesystem
+///
+/// Files are opened on their first write and kept open in a [`SqexFileStreamStore`], so
+/// the streams are reused across chunks. The store closes the least recently used stream
+/// once too many are open, and a closed file is simply reopened on its next write. Every
+/// file written is remembered until the next [`sync`](PatchTarget::sync), which flushes each
+/// of them to disk.
+#[derive(Debug, Default)]
+pub struct FileSystemTarget {
+    store: SqexFileStreamStore,
+    /// Files written since the last sync
+    unsynced: HashSet<PathBuf>,
+}
+
+impl FileSystemTarget {
+    /// Creates a target with an empty stream store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a target that keeps file streams open in the given store
+    pub fn with_store(store: SqexFileStreamStore) -> Self {
+        Self {
+            store,
+            unsynced: HashSet::new(),
+        }
+    }
+
+    /// Gets the stream store
+    pub fn store(&self) -> &SqexFileStreamStore {
+        &self.store
+    }
+
+    /// Runs an operation on a writable stream for the file
+    fn with_stream<T>(
+        &mut self,
+        path: &Path,
+        operation: impl FnOnce(&mut SqexFileStream) -> Result<T>,
+    ) -> Result<T> {
+        if !self.unsynced.contains(path) {
+            self.unsynced.insert(path.to_path_buf());
+        }
+
+        operation(
+            self.store
+                .get_stream(path, true, OPEN_TRIES, OPEN_SLEEP_TIME)?,
+        )
+    }
+}
+
+impl PatchTarget for FileSystemTarget {
+    fn metadata(&self, path: &Path) -> Result<Option<TargetMetadata>> {
+        match fs::metadata(path) {
+            Ok(metadata) if metadata.is_dir() => Ok(Some(TargetMetadata::directory())),
+            Ok(metadata) => Ok(Some(TargetMetadata::file(metadata.len()))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn is_symlink(&self, path: &Path) -> Result<Option<bool>> {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) => Ok(Some(metadata.file_type().is_symlink())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        Ok(fs::canonicalize(path)?)
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn TargetReader + '_>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn write_at(&mut self, path: &Path, offset: u64, data: &[u8]) -> Result<()> {
+        self.with_stream(path, |stream| stream.write_from_offset(data, offset as i64))
+    }
+
+    fn wipe_at(&mut self, path: &Path, offset: u64, length: u64) -> Result<()> {
+        self.with_stream(path, |stream| {
+            stream.wipe_from_offset(length, offset as i64)
+        })
+    }
+
+    fn set_len(&mut self, path: &Path, len: u64) -> Result<()> {
+        self.with_stream(path, |stream| Ok(stream.get_mut().set_len(len)?))
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> Result<()> {
+        fs::create_dir_all(path).map_err(|e| ZiPatchError::DirectoryCreationFailed {
+            path: path.to_path_buf(),
+            source: e,
+        })
+    }
+
+    fn remove_file(&mut self, path: &Path) -> Result<()> {
+        // Close any cached stream first so it isn't reused for a recreated file
+        self.store.remove(path);
+        self.unsynced.remove(path);
+
+        fs::remove_file(path).map_err(|e| ZiPatchError::FileOperationFailed {
+            path: path.to_path_buf(),
+            source: e,
+        })
+    }
+
+    fn remove_dir(&mut self, path: &Path) -> Result<()> {
+        fs::remove_dir(path).map_err(|e| ZiPatchError::FileOperationFailed {
+            path: path.to_path_buf(),
+            source: e,
+        })
+    }
+
+    fn list_files(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut files = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        for path in &self.unsynced {
+            self.store
+                .get_stream(path, true, OPEN_TRIES, OPEN_SLEEP_TIME)?
+                .get_ref()
+                .sync_all()?;
+        }
+
+        self.unsynced.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::PatchBuilder;
+    use crate::test_support::{open_patch, TestDir};
+    use crate::{ApplyOptions, ZiPatchConfig};
+    use std::io::Read;
+
+    #[test]
+    fn test_write_wipe_and_remove() {
+        let dir = TestDir::new("target-fs");
+
+        let mut target = FileSystemTarget::with_store(SqexFileStreamStore::new());
+        let path = dir.path("sqpack/ffxiv/000000.win32.dat0");
+        target.create_dir_all(path.parent().unwrap()).unwrap();
+        assert!(
+            target
+                .metadata(&dir.path("sqpack"))
+                .unwrap()
+                .unwrap()
+                .is_dir
+        );
+
+        target.write_at(&path, 4, b"data").unwrap();
+        target.wipe_at(&path, 5, 2).unwrap();
+        target.sync().unwrap();
+        assert_eq!(
+            target.metadata(&path).unwrap(),
+            Some(TargetMetadata::file(8))
+        );
+
+        let mut contents = Vec::new();
+        target
+            .open(&path)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, b"\0\0\0\0d\0\0a");
+
+        assert_eq!(
+            target.list_files(path.parent().unwrap()).unwrap(),
+            vec![path.clone()]
+        );
+        assert!(target.list_files(&dir.path("missing")).unwrap().is_empty());
+
+        target.remove_file(&path).unwrap();
+        assert_eq!(target.metadata(&path).unwrap(), None);
+    }
+
+    #[test]
+    fn test_sync_flushes_files_written_since_last_sync() {
+        let dir = TestDir::new("target-fs-sync");
+
+        let mut target = FileSystemTarget::new();
+        let first = dir.path("first.dat");
+        let second = dir.path("second.dat");
+        target.write_at(&first, 0, b"first").unwrap();
+        target.set_len(&second, 4).unwrap();
+        target.write_at(&first, 5, b"!").unwrap();
+        assert_eq!(target.unsynced.len(), 2);
+        assert_eq!(target.store().len(), 2);
+
+        target.sync().unwrap();
+        assert!(target.unsynced.is_empty());
+
+        // A removed file has nothing left to sync
+        target.write_at(&second, 0, b"data").unwrap();
+        target.remove_file(&second).unwrap();
+        target.sync().unwrap();
+        assert_eq!(dir.read("first.dat"), b"first!");
+    }
+
+    #[test]
+    fn test_apply_more_files_than_open_streams() {
+        let dir = TestDir::new("target-fs-streams");
+        let mut builder = PatchBuilder::new("DIFF");
+        for i in 0..10 {
+            builder
+                .add_file(&format!("boot/file{}.dat", i), &[i as u8; 0x10])
+                .unwrap();
+        }
+
+        let mut config = ZiPatchConfig::builder(&dir.game)
+            .store(SqexFileStreamStore::new().with_max_streams(4))
+            .build();
+        open_patch(&builder)
+            .apply(&mut config, &ApplyOptions::new())
+            .unwrap();
+
+        for i in 0..10 {
+            assert_eq!(dir.read(&format!("boot/file{}.dat", i)), [i as u8; 0x10]);
+        }
+
+        // Evicted streams are reopened to write and sync again
+        let mut target =
+            FileSystemTarget::with_store(SqexFileStreamStore::new().with_max_streams(4));
+        for i in 0..10 {
+            target
+                .write_at(&dir.path(&format!("boot/file{}.dat", i)), 0x10, b"more")
+                .unwrap();
+        }
+        assert_eq!(target.store().len(), 4);
+        target.sync().unwrap();
+        assert_eq!(dir.read("boot/file0.dat")[0x10..], *b"more");
+    }
+}
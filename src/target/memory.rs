@@ -0,0 +1,466 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::{PatchTarget, TargetMetadata, TargetReader};
+use crate::error::{Result, ZiPatchError};
+
+/// Contents of a [`MemoryTarget`]
+#[derive(Debug, Default)]
+struct MemoryState {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+    directories: BTreeSet<PathBuf>,
+    /// Contents of every file touched since the last snapshot, as they were before the first
+    /// change (None if the file didn't exist)
+    originals: BTreeMap<PathBuf, Option<Vec<u8>>>,
+}
+
+impl MemoryState {
+    /// Saves the current contents of a file the first time it's about to change
+    fn touch(&mut self, path: &Path) {
+        if !self.originals.contains_key(path) {
+            let original = self.files.get(path).cloned();
+            self.originals.insert(path.to_path_buf(), original);
+        }
+    }
+
+    /// Gets a file for writing, creating it and its parent directories if needed
+    fn file_mut(&mut self, path: &Path) -> Result<&mut Vec<u8>> {
+        if self.directories.contains(path) {
+            return Err(not_a_file(path));
+        }
+
+        self.touch(path);
+        if let Some(parent) = path.parent() {
+            self.add_directories(parent);
+        }
+        Ok(self.files.entry(path.to_path_buf()).or_default())
+    }
+
+    fn add_directories(&mut self, path: &Path) {
+        for dir in path.ancestors() {
+            if dir.as_os_str().is_empty() || !self.directories.insert(dir.to_path_buf()) {
+                break;
+            }
+        }
+    }
+}
+
+/// Target that applies patches to files held in memory
+///
+/// Files are byte buffers keyed by their resolved paths, the same paths a [`FileSystemTarget`]
+/// would write to, so the game path given to the configuration decides where they live. A
+/// snapshot of an existing install can be loaded with [`load_from_dir`](Self::load_from_dir);
+/// nothing is ever written back to disk.
+///
+/// Clones share the same contents, so a clone can be given to
+/// [`ZiPatchConfigBuilder::target`](crate::ZiPatchConfigBuilder::target) and the original
+/// used to inspect the result after applying.
+///
+/// Writes that would grow a file past the [maximum file size](Self::with_max_file_size) fail
+/// instead of allocating, so a corrupt or hostile patch can't exhaust memory.
+///
+/// [`FileSystemTarget`]: super::FileSystemTarget
+#[derive(Debug, Clone)]
+pub struct MemoryTarget {
+    state: Arc<Mutex<MemoryState>>,
+    max_file_size: u64,
+}
+
+impl Default for MemoryTarget {
+    fn default() -> Self {
+        Self {
+            state: Arc::default(),
+            max_file_size: Self::DEFAULT_MAX_FILE_SIZE,
+        }
+    }
+}
+
+impl MemoryTarget {
+    /// Largest size a file may grow to unless set otherwise (4 GiB)
+    pub const DEFAULT_MAX_FILE_SIZE: u64 = 4 << 30;
+
+    /// Creates an empty target
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the largest size a file may grow to
+    ///
+    /// Writes and length changes past it fail with [`ZiPatchError::FileOperationFailed`].
+    /// Files inserted or loaded into the snapshot aren't checked.
+    pub fn with_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// Creates a target holding a copy of every file and directory under `dir`
+    ///
+    /// Files are keyed by `dir` joined with their path inside it, so `dir` should also be the
+    /// game path of the configuration the target is used with.
+    pub fn load_from_dir<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        let target = Self::new();
+
+        {
+            let mut state = target.state();
+            state.add_directories(dir);
+
+            let mut pending = vec![dir.to_path_buf()];
+            while let Some(current) = pending.pop() {
+                for entry in fs::read_dir(&current)? {
+                    let entry = entry?;
+                    let path = entry.path();
+
+                    if entry.file_type()?.is_dir() {
+                        state.directories.insert(path.clone());
+                        pending.push(path);
+                    } else {
+                        let data = fs::read(&path)?;
+                        state.files.insert(path, data);
+                    }
+                }
+            }
+        }
+
+        Ok(target)
+    }
+
+    /// Adds a file to the snapshot without counting it as a change
+    ///
+    /// Parent directories are created as needed.
+    pub fn insert_file<P: Into<PathBuf>>(&self, path: P, data: Vec<u8>) {
+        let path = path.into();
+        let mut state = self.state();
+
+        if let Some(parent) = path.parent() {
+            state.add_directories(parent);
+        }
+        state.originals.remove(&path);
+        state.files.insert(path, data);
+    }
+
+    /// Gets a copy of a file's contents
+    pub fn file<P: AsRef<Path>>(&self, path: P) -> Option<Vec<u8>> {
+        self.state().files.get(path.as_ref()).cloned()
+    }
+
+    /// Gets a copy of a range of a file, cut short at the end of the file
+    ///
+    /// Returns `None` if the file doesn't exist.
+    pub fn read_at<P: AsRef<Path>>(&self, path: P, offset: u64, length: u64) -> Option<Vec<u8>> {
+        let state = self.state();
+        let data = state.files.get(path.as_ref())?;
+
+        let start = offset.min(data.len() as u64) as usize;
+        let end = offset.saturating_add(length).min(data.len() as u64) as usize;
+        Some(data[start..end].to_vec())
+    }
+
+    /// Gets the paths of all files, in sorted order
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.state().files.keys().cloned().collect()
+    }
+
+    /// Gets the files whose contents differ from the snapshot, in sorted order
+    ///
+    /// Includes files that were created or deleted. A file that was changed and then
+    /// restored to its original contents isn't listed.
+    pub fn changed_files(&self) -> Vec<PathBuf> {
+        let state = self.state();
+
+        state
+            .originals
+            .iter()
+            .filter(|(path, original)| state.files.get(*path) != original.as_ref())
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Makes the current contents the snapshot that changes are compared against
+    pub fn reset_changes(&self) {
+        self.state().originals.clear();
+    }
+
+    fn state(&self) -> MutexGuard<'_, MemoryState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Converts a file length to a buffer length, if it's within the maximum file size
+    fn checked_len(&self, path: &Path, len: Option<u64>) -> Result<usize> {
+        len.filter(|len| *len <= self.max_file_size)
+            .and_then(|len| usize::try_from(len).ok())
+            .ok_or_else(|| ZiPatchError::FileOperationFailed {
+                path: path.to_path_buf(),
+                source: io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "file would grow past the maximum of {} bytes",
+                        self.max_file_size
+                    ),
+                ),
+            })
+    }
+}
+
+impl PatchTarget for MemoryTarget {
+    fn metadata(&self, path: &Path) -> Result<Option<TargetMetadata>> {
+        let state = self.state();
+
+        if let Some(data) = state.files.get(path) {
+            Ok(Some(TargetMetadata::file(data.len() as u64)))
+        } else if state.directories.contains(path) {
+            Ok(Some(TargetMetadata::directory()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Opens a copy of the file
+    fn open(&self, path: &Path) -> Result<Box<dyn TargetReader + '_>> {
+        match self.state().files.get(path) {
+            Some(data) => Ok(Box::new(Cursor::new(data.clone()))),
+            None => Err(not_found(path).into()),
+        }
+    }
+
+    fn write_at(&mut self, path: &Path, offset: u64, data: &[u8]) -> Result<()> {
+        let end = self.checked_len(path, offset.checked_add(data.len() as u64))?;
+        let offset = end - data.len();
+
+        let mut state = self.state();
+        let file = state.file_mut(path)?;
+        if file.len() < end {
+            file.resize(end, 0);
+        }
+        file[offset..end].copy_from_slice(data);
+
+        Ok(())
+    }
+
+    fn set_len(&mut self, path: &Path, len: u64) -> Result<()> {
+        let len = self.checked_len(path, Some(len))?;
+        let mut state = self.state();
+        state.file_mut(path)?.resize(len, 0);
+        Ok(())
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> Result<()> {
+        let mut state = self.state();
+
+        if let Some(dir) = path.ancestors().find(|dir| state.files.contains_key(*dir)) {
+            return Err(ZiPatchError::DirectoryCreationFailed {
+                path: path.to_path_buf(),
+                source: io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is a file", dir.display()),
+                ),
+            });
+        }
+
+        state.add_directories(path);
+        Ok(())
+    }
+
+    fn remove_file(&mut self, path: &Path) -> Result<()> {
+        let mut state = self.state();
+
+        if !state.files.contains_key(path) {
+            return Err(ZiPatchError::FileOperationFailed {
+                path: path.to_path_buf(),
+                source: not_found(path),
+            });
+        }
+
+        state.touch(path);
+        state.files.remove(path);
+        Ok(())
+    }
+
+    fn remove_dir(&mut self, path: &Path) -> Result<()> {
+        let mut state = self.state();
+        let failed = |source| ZiPatchError::FileOperationFailed {
+            path: path.to_path_buf(),
+            source,
+        };
+
+        if !state.directories.contains(path) {
+            return Err(failed(not_found(path)));
+        }
+
+        let has_children = state.files.keys().any(|file| file.parent() == Some(path))
+            || state
+                .directories
+                .iter()
+                .any(|dir| dir.parent() == Some(path));
+        if has_children {
+            return Err(failed(io::Error::other("directory is not empty")));
+        }
+
+        state.directories.remove(path);
+        Ok(())
+    }
+
+    fn list_files(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        Ok(self
+            .state()
+            .files
+            .keys()
+            .filter(|file| file.parent() == Some(path))
+            .cloned()
+            .collect())
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} does not exist", path.display()),
+    )
+}
+
+fn not_a_file(path: &Path) -> ZiPatchError {
+    ZiPatchError::FileOperationFailed {
+        path: path.to_path_buf(),
+        source: io::Error::other("path is a directory"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Platform;
+    use crate::generate::PatchBuilder;
+    use crate::test_support::{add_data, dat_file, open_patch, TestDir};
+    use crate::{ApplyOptions, ZiPatchConfig};
+
+    fn patch() -> PatchBuilder {
+        let mut builder = PatchBuilder::new("DIFF");
+        builder.push(add_data(dat_file(0, 0, 0), 0x80, vec![0x22; 0x80], 0x80));
+        builder.add_file("boot/new.exe", b"new").unwrap();
+        builder.delete_file("boot/old.dll");
+        // Rewritten with the same contents, so not a change
+        builder.add_file("game.ver", b"2024.01.01").unwrap();
+        builder
+    }
+
+    #[test]
+    fn test_apply_to_loaded_snapshot() {
+        let dir = TestDir::new("target-mem");
+        dir.write("sqpack/ffxiv/000000.win32.dat0", &[0x11; 0x200]);
+        dir.write("boot/old.dll", b"old");
+        dir.write("game.ver", b"2024.01.01");
+
+        let game = &dir.game;
+        let target = MemoryTarget::load_from_dir(game).unwrap();
+        assert_eq!(target.files().len(), 3);
+
+        let mut config = ZiPatchConfig::builder(game)
+            .platform(Platform::Win32)
+            .target(target.clone())
+            .build();
+        open_patch(&patch())
+            .apply(&mut config, &ApplyOptions::new())
+            .unwrap();
+
+        let dat = game.join("sqpack/ffxiv/000000.win32.dat0");
+        assert_eq!(
+            target.changed_files(),
+            vec![
+                game.join("boot/new.exe"),
+                game.join("boot/old.dll"),
+                dat.clone(),
+            ]
+        );
+        assert_eq!(
+            target.read_at(&dat, 0x7e, 4).unwrap(),
+            [0x11, 0x11, 0x22, 0x22]
+        );
+        assert_eq!(target.read_at(&dat, 0x100, 0x80).unwrap(), vec![0; 0x80]);
+        assert_eq!(target.read_at(&dat, 0x1f0, 0x100).unwrap().len(), 0x10);
+        assert_eq!(target.file(game.join("boot/new.exe")).unwrap(), b"new");
+        assert!(target.file(game.join("boot/old.dll")).is_none());
+
+        // The install on disk is untouched
+        assert_eq!(fs::read(&dat).unwrap(), vec![0x11u8; 0x200]);
+        assert!(!game.join("boot/new.exe").exists());
+        assert!(game.join("boot/old.dll").exists());
+
+        target.reset_changes();
+        assert!(target.changed_files().is_empty());
+    }
+
+    #[test]
+    fn test_directory_chunks_stay_in_memory() {
+        let dir = TestDir::new("target-mem-dirs");
+        dir.create_dir("movie/ex1");
+
+        let game = &dir.game;
+        let target = MemoryTarget::load_from_dir(game).unwrap();
+        let mut config = ZiPatchConfig::builder(game)
+            .platform(Platform::Win32)
+            .target(target.clone())
+            .build();
+
+        let mut builder = PatchBuilder::new("DIFF");
+        builder.add_directory("mods/textures");
+        builder.delete_directory("movie/ex1");
+        open_patch(&builder)
+            .apply(&mut config, &ApplyOptions::new())
+            .unwrap();
+
+        let metadata = |path: &str| config.target.metadata(&game.join(path)).unwrap();
+        assert_eq!(metadata("mods/textures"), Some(TargetMetadata::directory()));
+        assert_eq!(metadata("movie/ex1"), None);
+
+        // The install on disk is untouched
+        assert!(!game.join("mods").exists());
+        assert!(game.join("movie/ex1").is_dir());
+    }
+
+    #[test]
+    fn test_writes_past_max_file_size_fail() {
+        let mut target = MemoryTarget::new().with_max_file_size(0x100);
+        let path = Path::new("/game/boot/big.dat");
+
+        target.write_at(path, 0xF0, &[1; 0x10]).unwrap();
+        assert!(matches!(
+            target.write_at(path, 0xF1, &[1; 0x10]),
+            Err(ZiPatchError::FileOperationFailed { .. })
+        ));
+        assert!(target.write_at(path, u64::MAX, &[1]).is_err());
+        assert!(target.set_len(path, 0x101).is_err());
+        assert_eq!(target.file(path).unwrap().len(), 0x100);
+    }
+
+    #[test]
+    fn test_directories() {
+        let mut target = MemoryTarget::new();
+        let root = Path::new("/game");
+
+        target.create_dir_all(&root.join("movie/ex1")).unwrap();
+        assert_eq!(
+            target.metadata(&root.join("movie")).unwrap(),
+            Some(TargetMetadata::directory())
+        );
+
+        target
+            .write_at(&root.join("movie/ex1/00000.bk2"), 0, b"bk2")
+            .unwrap();
+        assert!(target.remove_dir(&root.join("movie/ex1")).is_err());
+        assert!(target
+            .create_dir_all(&root.join("movie/ex1/00000.bk2/sub"))
+            .is_err());
+
+        target
+            .remove_file(&root.join("movie/ex1/00000.bk2"))
+            .unwrap();
+        target.remove_dir(&root.join("movie/ex1")).unwrap();
+        assert_eq!(target.metadata(&root.join("movie/ex1")).unwrap(), None);
+        assert!(target
+            .remove_file(&root.join("movie/ex1/00000.bk2"))
+            .is_err());
+    }
+}
@@ -0,0 +1,119 @@
+mod filesystem;
+mod memory;
+
+pub use filesystem::FileSystemTarget;
+pub use memory::MemoryTarget;
+
+use std::fmt;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+
+/// Kind and size of an entry in a [`PatchTarget`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetMetadata {
+    /// True for directories, false for files
+    pub is_dir: bool,
+    /// Length of the file in bytes (0 for directories)
+    pub len: u64,
+}
+
+impl TargetMetadata {
+    /// Creates the metadata for a file of the given length
+    pub fn file(len: u64) -> Self {
+        Self { is_dir: false, len }
+    }
+
+    /// Creates the metadata for a directory
+    pub fn directory() -> Self {
+        Self {
+            is_dir: true,
+            len: 0,
+        }
+    }
+
+    /// Checks if the entry is a file
+    pub fn is_file(&self) -> bool {
+        !self.is_dir
+    }
+}
+
+/// A readable, seekable file opened from a [`PatchTarget`]
+pub trait TargetReader: Read + Seek {}
+
+impl<T: Read + Seek> TargetReader for T {}
+
+/// Filesystem that patches are applied to
+///
+/// Every apply operation goes through the configuration's target, so installs can live
+/// somewhere other than the local disk. Paths are always full paths, already resolved
+/// against the game directory with [`ZiPatchConfig::resolve_path`](crate::ZiPatchConfig::resolve_path).
+///
+/// [`FileSystemTarget`] is the default and applies to the real filesystem. [`MemoryTarget`]
+/// keeps everything in memory, for tests and previews.
+pub trait PatchTarget: fmt::Debug {
+    /// Gets the kind and size of an entry, or `None` if it doesn't exist
+    fn metadata(&self, path: &Path) -> Result<Option<TargetMetadata>>;
+
+    /// Opens a file for reading
+    fn open(&self, path: &Path) -> Result<Box<dyn TargetReader + '_>>;
+
+    /// Writes data at an offset, creating the file if it doesn't exist
+    ///
+    /// Writing past the end of the file extends it, filling any gap with zeros.
+    fn write_at(&mut self, path: &Path, offset: u64, data: &[u8]) -> Result<()>;
+
+    /// Sets the length of a file, creating it if it doesn't exist
+    fn set_len(&mut self, path: &Path, len: u64) -> Result<()>;
+
+    /// Creates a directory and any missing parents
+    fn create_dir_all(&mut self, path: &Path) -> Result<()>;
+
+    /// Removes a file
+    fn remove_file(&mut self, path: &Path) -> Result<()>;
+
+    /// Removes an empty directory
+    fn remove_dir(&mut self, path: &Path) -> Result<()>;
+
+    /// Lists the files directly inside a directory, or nothing if it doesn't exist
+    fn list_files(&self, path: &Path) -> Result<Vec<PathBuf>>;
+
+    /// Checks if an entry is a symlink, without following it
+    ///
+    /// Returns `None` if the entry doesn't exist. By default every existing entry is reported
+    /// as not being a symlink, for targets that don't have them.
+    fn is_symlink(&self, path: &Path) -> Result<Option<bool>> {
+        Ok(self.metadata(path)?.map(|_| false))
+    }
+
+    /// Gets the canonical form of a path, with every symlink along it followed
+    ///
+    /// Only called for paths leading through a symlink. By default the path is returned as is.
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        Ok(path.to_path_buf())
+    }
+
+    /// Overwrites a range of a file with zeros
+    fn wipe_at(&mut self, path: &Path, offset: u64, length: u64) -> Result<()> {
+        static ZEROS: [u8; 1 << 16] = [0u8; 1 << 16];
+
+        let mut written = 0;
+        while written < length {
+            let to_write = (length - written).min(ZEROS.len() as u64);
+            self.write_at(path, offset + written, &ZEROS[..to_write as usize])?;
+            written += to_write;
+        }
+
+        Ok(())
+    }
+
+    /// Makes sure everything written so far is durable
+    ///
+    /// Apply checkpoints are saved right after this returns, so a target on storage that
+    /// survives a crash must flush every write made since the last sync. The default does
+    /// nothing, for targets without durable storage.
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
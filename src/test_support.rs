@@ -0,0 +1,116 @@
+//! Fixtures shared by the unit tests
+
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use crate::chunk::sqpk::{OperationKind, SqpkAddData, SqpkFile};
+use crate::chunk::{SqpkCommand, ZiPatchChunk};
+use crate::config::{Platform, ZiPatchConfig};
+use crate::file::ZiPatchFile;
+use crate::generate::PatchBuilder;
+use crate::util::{SqexFile, SqpackDatFile, SqpackFile};
+
+/// Scratch directory holding a game directory, removed again when dropped
+pub(crate) struct TestDir {
+    pub(crate) root: PathBuf,
+    pub(crate) game: PathBuf,
+}
+
+impl TestDir {
+    /// Creates an empty game directory in a fresh `zipatch-<name>-<pid>` temp directory
+    ///
+    /// Tests run in parallel, so every test needs its own name.
+    pub(crate) fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("zipatch-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let game = root.join("game");
+        fs::create_dir_all(&game).unwrap();
+        Self { root, game }
+    }
+
+    /// Gets the path of a file in the game directory
+    pub(crate) fn path(&self, path: &str) -> PathBuf {
+        self.game.join(path)
+    }
+
+    /// Writes a file in the game directory, creating its parent directories
+    pub(crate) fn write(&self, path: &str, data: &[u8]) {
+        let path = self.path(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+
+    /// Reads a file from the game directory
+    pub(crate) fn read(&self, path: &str) -> Vec<u8> {
+        fs::read(self.path(path)).unwrap()
+    }
+
+    /// Creates a directory and its parents in the game directory
+    pub(crate) fn create_dir(&self, path: &str) {
+        fs::create_dir_all(self.path(path)).unwrap();
+    }
+
+    /// Creates a Win32 configuration for the game directory
+    pub(crate) fn config(&self) -> ZiPatchConfig {
+        ZiPatchConfig::builder(&self.game)
+            .platform(Platform::Win32)
+            .build()
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Gets a SqPack dat file by its IDs
+pub(crate) fn dat_file(main_id: u16, sub_id: u16, file_id: u32) -> SqpackDatFile {
+    SqpackDatFile {
+        sqpack: SqpackFile {
+            main_id,
+            sub_id,
+            file_id,
+            sqex_file: SqexFile::default(),
+        },
+    }
+}
+
+/// Builds an SqpkAddData command writing `data` at `offset` and wiping `delete` bytes after it
+pub(crate) fn add_data(
+    dat: SqpackDatFile,
+    offset: i64,
+    data: Vec<u8>,
+    delete: i64,
+) -> ZiPatchChunk {
+    ZiPatchChunk::Sqpk(SqpkCommand::AddData(SqpkAddData {
+        alignment: [0; 3],
+        target_file: dat,
+        block_offset: offset,
+        block_number: data.len() as i64,
+        block_delete_number: delete,
+        block_data: data,
+    }))
+}
+
+/// Builds an SqpkFile RemoveAll command for an expansion
+pub(crate) fn remove_all(expansion_id: u16) -> ZiPatchChunk {
+    ZiPatchChunk::Sqpk(SqpkCommand::File(SqpkFile {
+        operation: OperationKind::RemoveAll,
+        alignment: [0; 2],
+        file_offset: 0,
+        file_size: 0,
+        expansion_id,
+        padding: [0; 2],
+        target_file: SqexFile::default(),
+        path_padding: vec![0],
+        compressed_data: Vec::new(),
+        unknown_data: Vec::new(),
+    }))
+}
+
+/// Writes the builder's patch and opens it
+pub(crate) fn open_patch(builder: &PatchBuilder) -> ZiPatchFile<Cursor<Vec<u8>>> {
+    ZiPatchFile::new(Cursor::new(builder.to_bytes().unwrap())).unwrap()
+}
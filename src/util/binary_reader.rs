@@ -80,6 +80,20 @@ pub trait BinaryReaderExt: Read {
         Ok(String::from_utf8_lossy(trimmed).into_owned())
     }
 
+    /// Reads a fixed-length ASCII string, keeping the bytes after it
+    ///
+    /// Returns the string up to the first null byte, and every byte from that null byte to
+    /// the end of the field, so the field can be written back unchanged.
+    fn read_padded_string(&mut self, length: usize) -> Result<(String, Vec<u8>)> {
+        let mut buffer = vec![0u8; length];
+        self.read_exact(&mut buffer)?;
+
+        let end = buffer.iter().position(|&b| b == 0).unwrap_or(length);
+        let padding = buffer.split_off(end);
+
+        Ok((String::from_utf8_lossy(&buffer).into_owned(), padding))
+    }
+
     /// Reads exactly the specified number of bytes or returns an error
     fn read_bytes_required(&mut self, length: usize) -> Result<Vec<u8>> {
         let mut buffer = vec![0u8; length];
@@ -131,6 +145,20 @@ mod tests {
         assert_eq!(s, "SQPK");
     }
 
+    #[test]
+    fn test_read_padded_string() {
+        let data = b"game\0\x01\0";
+        let mut cursor = Cursor::new(data);
+        let (s, padding) = cursor.read_padded_string(7).unwrap();
+        assert_eq!(s, "game");
+        assert_eq!(padding, [0, 1, 0]);
+
+        let mut cursor = Cursor::new(b"game");
+        let (s, padding) = cursor.read_padded_string(4).unwrap();
+        assert_eq!(s, "game");
+        assert!(padding.is_empty());
+    }
+
     #[test]
     fn test_read_chunk_type() {
         let data = b"EOF_";
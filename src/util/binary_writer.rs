@@ -0,0 +1,143 @@
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+use std::io::Write;
+
+use crate::error::Result;
+
+/// Extension trait for writing binary data
+///
+/// The counterpart of [`BinaryReaderExt`](super::BinaryReaderExt).
+pub trait BinaryWriterExt: Write {
+    /// Writes a u16 in big-endian byte order
+    fn write_u16_be(&mut self, value: u16) -> Result<()> {
+        Ok(self.write_u16::<BigEndian>(value)?)
+    }
+
+    /// Writes an i16 in big-endian byte order
+    fn write_i16_be(&mut self, value: i16) -> Result<()> {
+        Ok(self.write_i16::<BigEndian>(value)?)
+    }
+
+    /// Writes a u32 in big-endian byte order
+    fn write_u32_be(&mut self, value: u32) -> Result<()> {
+        Ok(self.write_u32::<BigEndian>(value)?)
+    }
+
+    /// Writes an i32 in big-endian byte order
+    fn write_i32_be(&mut self, value: i32) -> Result<()> {
+        Ok(self.write_i32::<BigEndian>(value)?)
+    }
+
+    /// Writes a u64 in big-endian byte order
+    fn write_u64_be(&mut self, value: u64) -> Result<()> {
+        Ok(self.write_u64::<BigEndian>(value)?)
+    }
+
+    /// Writes an i64 in big-endian byte order
+    fn write_i64_be(&mut self, value: i64) -> Result<()> {
+        Ok(self.write_i64::<BigEndian>(value)?)
+    }
+
+    /// Writes a u16 in little-endian byte order
+    fn write_u16_le(&mut self, value: u16) -> Result<()> {
+        Ok(self.write_u16::<LittleEndian>(value)?)
+    }
+
+    /// Writes an i16 in little-endian byte order
+    fn write_i16_le(&mut self, value: i16) -> Result<()> {
+        Ok(self.write_i16::<LittleEndian>(value)?)
+    }
+
+    /// Writes a u32 in little-endian byte order
+    fn write_u32_le(&mut self, value: u32) -> Result<()> {
+        Ok(self.write_u32::<LittleEndian>(value)?)
+    }
+
+    /// Writes an i32 in little-endian byte order
+    fn write_i32_le(&mut self, value: i32) -> Result<()> {
+        Ok(self.write_i32::<LittleEndian>(value)?)
+    }
+
+    /// Writes a u64 in little-endian byte order
+    fn write_u64_le(&mut self, value: u64) -> Result<()> {
+        Ok(self.write_u64::<LittleEndian>(value)?)
+    }
+
+    /// Writes an i64 in little-endian byte order
+    fn write_i64_le(&mut self, value: i64) -> Result<()> {
+        Ok(self.write_i64::<LittleEndian>(value)?)
+    }
+
+    /// Writes a string into a fixed-length field, padding with null bytes
+    ///
+    /// Strings longer than `length` are truncated.
+    fn write_fixed_string(&mut self, value: &str, length: usize) -> Result<()> {
+        let bytes = value.as_bytes();
+        let used = bytes.len().min(length);
+        self.write_all(&bytes[..used])?;
+        self.write_zeros(length - used)
+    }
+
+    /// Writes the given number of zero bytes
+    fn write_zeros(&mut self, length: usize) -> Result<()> {
+        const ZEROS: [u8; 256] = [0u8; 256];
+        let mut left = length;
+
+        while left > 0 {
+            let to_write = left.min(ZEROS.len());
+            self.write_all(&ZEROS[..to_write])?;
+            left -= to_write;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a 4-character type identifier (e.g., "FHDR", "SQPK")
+    fn write_chunk_type(&mut self, chunk_type: &str) -> Result<()> {
+        self.write_fixed_string(chunk_type, 4)
+    }
+}
+
+// Implement for all types that implement Write
+impl<W: Write> BinaryWriterExt for W {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::BinaryReaderExt;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_write_u32_be() {
+        let mut buf = Vec::new();
+        buf.write_u32_be(42).unwrap();
+        assert_eq!(buf, vec![0x00, 0x00, 0x00, 0x2A]);
+    }
+
+    #[test]
+    fn test_write_fixed_string_pads() {
+        let mut buf = Vec::new();
+        buf.write_fixed_string("FHDR", 8).unwrap();
+        assert_eq!(&buf, b"FHDR\0\0\0\0");
+    }
+
+    #[test]
+    fn test_write_zeros() {
+        let mut buf = Vec::new();
+        buf.write_zeros(300).unwrap();
+        assert_eq!(buf.len(), 300);
+        assert!(buf.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_round_trip_with_reader() {
+        let mut buf = Vec::new();
+        buf.write_i64_be(-5).unwrap();
+        buf.write_u16_le(0xBEEF).unwrap();
+        buf.write_chunk_type("SQPK").unwrap();
+
+        let mut cursor = Cursor::new(buf);
+        assert_eq!(cursor.read_i64_be().unwrap(), -5);
+        assert_eq!(cursor.read_u16_le().unwrap(), 0xBEEF);
+        assert_eq!(cursor.read_chunk_type().unwrap(), "SQPK");
+    }
+}
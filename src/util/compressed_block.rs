@@ -4,6 +4,7 @@ use flate2::read::DeflateDecoder;
 
 use crate::error::{Result, ZiPatchError};
 use crate::util::binary_reader::BinaryReaderExt;
+use crate::util::binary_writer::BinaryWriterExt;
 
 /// Represents a compressed data block from SQPK files
 ///
@@ -12,11 +13,17 @@ use crate::util::binary_reader::BinaryReaderExt;
 pub struct SqpkCompressedBlock {
     /// Size of the block header
     pub header_size: i32,
+    /// Header field after the header size, zero as far as observed, kept for
+    /// re-serialization
+    pub reserved: u32,
     /// Size of compressed data (0x7d00 if uncompressed)
     pub compressed_size: i32,
     /// Size of decompressed data
     pub decompressed_size: i32,
     /// The compressed or uncompressed block data
+    ///
+    /// Blocks read from a patch include the padding up to the full block length, which is
+    /// kept for re-serialization.
     pub compressed_block: Vec<u8>,
 }
 
@@ -25,40 +32,43 @@ impl SqpkCompressedBlock {
     pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let header_size = reader.read_i32_le()?;
 
-        // Read and skip padding (4 bytes)
-        let _pad = reader.read_u32_le()?;
+        let reserved = reader.read_u32_le()?;
 
         let compressed_size = reader.read_i32_le()?;
         let decompressed_size = reader.read_i32_le()?;
 
-        let is_compressed = compressed_size != 0x7d00;
+        // Read the data along with its padding
         let compressed_block_length =
             Self::calculate_compressed_block_length(compressed_size, decompressed_size);
-
-        let compressed_block = if is_compressed {
-            // Read compressed data
-            reader.read_bytes_required((compressed_block_length - header_size) as usize)?
-        } else {
-            // Read uncompressed data
-            let block = reader.read_bytes_required(decompressed_size as usize)?;
-
-            // Read and discard padding
-            let padding_size = compressed_block_length - header_size - decompressed_size;
-            if padding_size > 0 {
-                let _ = reader.read_bytes_required(padding_size as usize)?;
-            }
-
-            block
-        };
+        let compressed_block =
+            reader.read_bytes_required((compressed_block_length - header_size) as usize)?;
 
         Ok(Self {
             header_size,
+            reserved,
             compressed_size,
             decompressed_size,
             compressed_block,
         })
     }
 
+    /// Writes the block, including its header and trailing padding, to a binary writer
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_i32_le(self.header_size)?;
+        writer.write_u32_le(self.reserved)?;
+        writer.write_i32_le(self.compressed_size)?;
+        writer.write_i32_le(self.decompressed_size)?;
+
+        // Blocks read from a patch keep their padding in `compressed_block`, but blocks built
+        // in memory may not, so always pad up to the full block length
+        let data_length = (self.compressed_block_length() - self.header_size) as usize;
+        let used = self.compressed_block.len().min(data_length);
+        writer.write_all(&self.compressed_block[..used])?;
+        writer.write_zeros(data_length - used)?;
+
+        Ok(())
+    }
+
     /// Checks if this block is compressed
     pub fn is_compressed(&self) -> bool {
         self.compressed_size != 0x7d00
@@ -83,18 +93,32 @@ impl SqpkCompressedBlock {
 
     /// Decompresses the block into the output stream
     ///
+    /// A compressed block that inflates to more than its decompressed size fails with
+    /// [`ZiPatchError::DecompressionFailed`] once one byte past that size has been written.
+    ///
     /// # Arguments
     /// * `out_stream` - The stream to write decompressed data to
     pub fn decompress_into<W: Write>(&self, out_stream: &mut W) -> Result<()> {
         if self.is_compressed() {
-            // Decompress using deflate
-            let mut decoder = DeflateDecoder::new(&self.compressed_block[..]);
-            std::io::copy(&mut decoder, out_stream).map_err(|e| {
+            // Decompress using deflate, stopping just past the expected size
+            let limit = self.decompressed_size.max(0) as u64;
+            let mut decoder = DeflateDecoder::new(&self.compressed_block[..]).take(limit + 1);
+            let written = std::io::copy(&mut decoder, out_stream).map_err(|e| {
                 ZiPatchError::DecompressionFailed(format!("Failed to decompress block: {}", e))
             })?;
+            if written > limit {
+                return Err(ZiPatchError::DecompressionFailed(format!(
+                    "Block decompresses to more than its {} bytes",
+                    limit
+                )));
+            }
         } else {
-            // Write uncompressed data directly
-            out_stream.write_all(&self.compressed_block)?;
+            // Write uncompressed data directly, without any padding
+            let len = self
+                .compressed_block
+                .len()
+                .min(self.decompressed_size.max(0) as usize);
+            out_stream.write_all(&self.compressed_block[..len])?;
         }
 
         Ok(())
@@ -116,6 +140,7 @@ mod tests {
     fn test_is_compressed() {
         let block = SqpkCompressedBlock {
             header_size: 16,
+            reserved: 0,
             compressed_size: 100,
             decompressed_size: 200,
             compressed_block: vec![],
@@ -124,6 +149,7 @@ mod tests {
 
         let block2 = SqpkCompressedBlock {
             header_size: 16,
+            reserved: 0,
             compressed_size: 0x7d00,
             decompressed_size: 200,
             compressed_block: vec![],
@@ -135,6 +161,7 @@ mod tests {
     fn test_compressed_block_length() {
         let block = SqpkCompressedBlock {
             header_size: 16,
+            reserved: 0,
             compressed_size: 100,
             decompressed_size: 200,
             compressed_block: vec![],
@@ -143,11 +170,31 @@ mod tests {
         assert_eq!(block.compressed_block_length(), 128);
     }
 
+    #[test]
+    fn test_write_round_trip() {
+        let data = b"Hello, World!";
+        let block = SqpkCompressedBlock {
+            header_size: 16,
+            reserved: 0,
+            compressed_size: 0x7d00,
+            decompressed_size: data.len() as i32,
+            compressed_block: data.to_vec(),
+        };
+
+        let mut written = Vec::new();
+        block.write_to(&mut written).unwrap();
+        assert_eq!(written.len(), block.compressed_block_length() as usize);
+
+        let read_back = SqpkCompressedBlock::read_from(&mut &written[..]).unwrap();
+        assert_eq!(read_back.decompress().unwrap(), data);
+    }
+
     #[test]
     fn test_uncompressed_block() {
         let data = b"Hello, World!";
         let block = SqpkCompressedBlock {
             header_size: 16,
+            reserved: 0,
             compressed_size: 0x7d00,
             decompressed_size: data.len() as i32,
             compressed_block: data.to_vec(),
@@ -156,4 +203,26 @@ mod tests {
         let decompressed = block.decompress().unwrap();
         assert_eq!(decompressed, data);
     }
+
+    #[test]
+    fn test_decompression_stops_past_decompressed_size() {
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&[0; 0x10000]).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let block = SqpkCompressedBlock {
+            header_size: 16,
+            reserved: 0,
+            compressed_size: compressed.len() as i32,
+            decompressed_size: 4,
+            compressed_block: compressed,
+        };
+
+        let mut output = Vec::new();
+        assert!(matches!(
+            block.decompress_into(&mut output),
+            Err(ZiPatchError::DecompressionFailed(_))
+        ));
+        assert_eq!(output.len(), 5);
+    }
 }
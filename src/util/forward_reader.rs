@@ -0,0 +1,122 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// A reader wrapper that lets a forward-only reader stand in where `Seek` is required
+///
+/// The wrapper counts the bytes read, which is reported as the stream position. Seeking
+/// forwards reads and discards the bytes in between; seeking backwards, or relative to the
+/// end, fails with [`io::ErrorKind::Unsupported`].
+#[derive(Debug)]
+pub struct ForwardReader<R: Read> {
+    inner: R,
+    position: u64,
+}
+
+impl<R: Read> ForwardReader<R> {
+    /// Creates a new ForwardReader wrapping the given reader, starting at position 0
+    pub fn new(inner: R) -> Self {
+        Self { inner, position: 0 }
+    }
+
+    /// Gets the number of bytes read or skipped so far
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Consumes the ForwardReader and returns the inner reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Gets a reference to the inner reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the inner reader
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    fn skip(&mut self, count: u64) -> io::Result<()> {
+        let skipped = io::copy(&mut (&mut self.inner).take(count), &mut io::sink())?;
+        self.position += skipped;
+
+        if skipped < count {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "stream ended while skipping forward",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ForwardReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read> Seek for ForwardReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(_) => None,
+        };
+
+        match target {
+            Some(target) if target >= self.position => {
+                self.skip(target - self.position)?;
+                Ok(self.position)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("can't seek to {:?} in a forward-only stream", pos),
+            )),
+        }
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forward_reader_tracks_position() {
+        let data: &[u8] = &[1, 2, 3, 4, 5, 6, 7, 8];
+        let mut reader = ForwardReader::new(data);
+
+        let mut buf = [0u8; 2];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(reader.stream_position().unwrap(), 2);
+
+        assert_eq!(reader.seek(SeekFrom::Current(1)).unwrap(), 3);
+        assert_eq!(reader.seek(SeekFrom::Start(5)).unwrap(), 5);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [6, 7]);
+    }
+
+    #[test]
+    fn test_forward_reader_rejects_backward_seeks() {
+        let data: &[u8] = &[1, 2, 3, 4];
+        let mut reader = ForwardReader::new(data);
+        reader.seek(SeekFrom::Start(2)).unwrap();
+
+        for pos in [SeekFrom::Start(1), SeekFrom::Current(-1), SeekFrom::End(0)] {
+            let err = reader.seek(pos).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        }
+        assert_eq!(reader.position(), 2);
+
+        let err = reader.seek(SeekFrom::Start(8)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(reader.position(), 4);
+    }
+}
@@ -0,0 +1,322 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+
+/// Signal telling a [`GrowingReader`] whether more of a file is on its way
+pub trait DownloadSignal {
+    /// Blocks until more than `len` bytes of the file are available
+    ///
+    /// Returns `false` once the download has finished and no more than `len` bytes will ever
+    /// be available.
+    fn wait_for_data(&mut self, len: u64) -> io::Result<bool>;
+
+    /// Gets the length the file will have once downloaded, if known
+    fn total_length(&self) -> Option<u64> {
+        None
+    }
+}
+
+#[derive(Debug, Default)]
+struct DownloadState {
+    written: u64,
+    total_length: Option<u64>,
+    finished: bool,
+}
+
+/// [`DownloadSignal`] updated by the downloader as it writes the file
+///
+/// Clones share the same state, so one clone can be given to the [`GrowingReader`] and
+/// another kept by the downloader, which calls [`set_written`](Self::set_written) after
+/// each write and [`finish`](Self::finish) once it's done, whether it succeeded or not.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadTracker {
+    state: Arc<(Mutex<DownloadState>, Condvar)>,
+}
+
+impl DownloadTracker {
+    /// Creates a tracker for a download that hasn't written anything yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the length the file will have once downloaded, e.g. from its Content-Length
+    pub fn set_total_length(&self, total_length: u64) {
+        self.update(|state| state.total_length = Some(total_length));
+    }
+
+    /// Sets the number of bytes written to the file so far, waking up waiting readers
+    pub fn set_written(&self, written: u64) {
+        self.update(|state| state.written = state.written.max(written));
+    }
+
+    /// Marks the download as finished, waking up waiting readers
+    pub fn finish(&self) {
+        self.update(|state| state.finished = true);
+    }
+
+    fn update<F: FnOnce(&mut DownloadState)>(&self, f: F) {
+        let (lock, condvar) = &*self.state;
+        f(&mut lock.lock().unwrap_or_else(PoisonError::into_inner));
+        condvar.notify_all();
+    }
+}
+
+impl DownloadSignal for DownloadTracker {
+    fn wait_for_data(&mut self, len: u64) -> io::Result<bool> {
+        let (lock, condvar) = &*self.state;
+        let state = condvar
+            .wait_while(
+                lock.lock().unwrap_or_else(PoisonError::into_inner),
+                |state| !state.finished && state.written <= len,
+            )
+            .unwrap_or_else(PoisonError::into_inner);
+
+        Ok(state.written > len)
+    }
+
+    fn total_length(&self) -> Option<u64> {
+        let (lock, _) = &*self.state;
+        lock.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .total_length
+    }
+}
+
+/// A reader wrapper for files that are still being written, such as a patch being downloaded
+///
+/// Reads at the current end of the file block on the [`DownloadSignal`] until more data
+/// arrives, instead of returning end-of-file; only once the download has finished does the
+/// end of the file become visible. Seeking relative to the end uses the signal's total
+/// length if it's known, and otherwise waits for the download to finish.
+///
+/// # Example
+///
+/// ```no_run
+/// use zipatch::util::{DownloadTracker, GrowingReader};
+/// use zipatch::{ApplyOptions, ZiPatchConfig, ZiPatchFile};
+///
+/// let tracker = DownloadTracker::new();
+/// // ... hand a clone of the tracker to the downloader ...
+///
+/// let file = std::fs::File::open("D2024.01.01.0000.0000.patch")?;
+/// let mut patch = ZiPatchFile::new(GrowingReader::new(file, tracker))?;
+/// let mut config = ZiPatchConfig::new("/path/to/game");
+/// patch.apply(&mut config, &ApplyOptions::default())?;
+/// # Ok::<(), zipatch::ZiPatchError>(())
+/// ```
+#[derive(Debug)]
+pub struct GrowingReader<R: Read + Seek, S: DownloadSignal> {
+    inner: R,
+    signal: S,
+    finished: bool,
+}
+
+impl<R: Read + Seek, S: DownloadSignal> GrowingReader<R, S> {
+    /// Creates a new GrowingReader wrapping the given reader and download signal
+    pub fn new(inner: R, signal: S) -> Self {
+        Self {
+            inner,
+            signal,
+            finished: false,
+        }
+    }
+
+    /// Consumes the GrowingReader and returns the inner reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Gets a reference to the inner reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the inner reader
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+}
+
+impl<R: Read + Seek, S: DownloadSignal> Read for GrowingReader<R, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.inner.read(buf)?;
+            if n > 0 || buf.is_empty() || self.finished {
+                return Ok(n);
+            }
+
+            let position = self.inner.stream_position()?;
+            self.finished = !self.signal.wait_for_data(position)?;
+        }
+    }
+}
+
+impl<R: Read + Seek, S: DownloadSignal> Seek for GrowingReader<R, S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let SeekFrom::End(offset) = pos else {
+            return self.inner.seek(pos);
+        };
+
+        if !self.finished {
+            if let Some(total_length) = self.signal.total_length() {
+                let target = total_length.checked_add_signed(offset).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "seek before start of file")
+                })?;
+                return self.inner.seek(SeekFrom::Start(target));
+            }
+
+            while self.signal.wait_for_data(u64::MAX)? {}
+            self.finished = true;
+        }
+
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::{ApplyObserver, ApplyOptions, ChunkInfo};
+    use crate::generate::PatchBuilder;
+    use crate::test_support::TestDir;
+    use crate::{MemoryTarget, ZiPatchConfig, ZiPatchFile};
+    use std::fs::{File, OpenOptions};
+    use std::io::Write;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Sends the total length reported for each applied chunk
+    struct TotalBytesSender(mpsc::Sender<Option<u64>>);
+
+    impl ApplyObserver for TotalBytesSender {
+        fn on_chunk_end(&mut self, info: &ChunkInfo) {
+            let _ = self.0.send(info.total_bytes);
+        }
+    }
+
+    #[test]
+    fn test_apply_while_downloading() {
+        let dir = TestDir::new("growing");
+        let path = dir.root.join("download.patch");
+
+        let mut builder = PatchBuilder::new("DIFF");
+        let data: Vec<u8> = (0..50_000).map(|i| (i % 251) as u8).collect();
+        builder
+            .add_file("game/sqpack/ffxiv/000000.win32.dat0", &data)
+            .unwrap();
+        builder
+            .add_file("boot/ffxivboot.ver", b"2024.01.01.0000.0000")
+            .unwrap();
+        let patch_data = builder.to_bytes().unwrap();
+
+        File::create(&path).unwrap();
+        let tracker = DownloadTracker::new();
+        tracker.set_total_length(patch_data.len() as u64);
+
+        let downloader = {
+            let tracker = tracker.clone();
+            let path = path.clone();
+            let patch_data = patch_data.clone();
+            thread::spawn(move || {
+                let mut file = OpenOptions::new().append(true).open(path).unwrap();
+                let mut written = 0;
+                for piece in patch_data.chunks(1000) {
+                    file.write_all(piece).unwrap();
+                    written += piece.len() as u64;
+                    tracker.set_written(written);
+                    thread::sleep(Duration::from_millis(1));
+                }
+                tracker.finish();
+            })
+        };
+
+        let reader = GrowingReader::new(File::open(&path).unwrap(), tracker);
+        let mut patch = ZiPatchFile::new(reader).unwrap();
+        let target = MemoryTarget::new();
+        let mut config = ZiPatchConfig::builder("/game")
+            .target(target.clone())
+            .build();
+        patch.apply(&mut config, &ApplyOptions::default()).unwrap();
+        downloader.join().unwrap();
+
+        assert_eq!(
+            target.file("/game/game/sqpack/ffxiv/000000.win32.dat0"),
+            Some(data)
+        );
+    }
+
+    #[test]
+    fn test_apply_without_total_length() {
+        let dir = TestDir::new("growing-unknown-length");
+        let path = dir.root.join("download.patch");
+
+        let mut builder = PatchBuilder::new("DIFF");
+        builder
+            .add_file("boot/ffxivboot.ver", b"2024.01.01.0000.0000")
+            .unwrap();
+        builder
+            .add_file("game/ffxivgame.ver", b"2024.01.01.0000.0000")
+            .unwrap();
+        let patch_data = builder.to_bytes().unwrap();
+        let (head, tail) = patch_data.split_at(patch_data.len() / 2);
+
+        File::create(&path).unwrap();
+        let tracker = DownloadTracker::new();
+        let (sender, receiver) = mpsc::channel();
+
+        // Holds back the second half until a chunk has been applied, which an apply seeking
+        // to the end of the file first would never do
+        let downloader = {
+            let tracker = tracker.clone();
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            let (head, tail) = (head.to_vec(), tail.to_vec());
+            thread::spawn(move || {
+                file.write_all(&head).unwrap();
+                tracker.set_written(head.len() as u64);
+                let applied = receiver.recv_timeout(Duration::from_secs(10));
+
+                file.write_all(&tail).unwrap();
+                tracker.set_written((head.len() + tail.len()) as u64);
+                tracker.finish();
+                applied
+            })
+        };
+
+        let reader = GrowingReader::new(File::open(&path).unwrap(), tracker);
+        let mut patch = ZiPatchFile::new(reader).unwrap();
+        let target = MemoryTarget::new();
+        let mut config = ZiPatchConfig::builder("/game")
+            .target(target.clone())
+            .observer(TotalBytesSender(sender))
+            .build();
+        patch.apply(&mut config, &ApplyOptions::default()).unwrap();
+
+        assert_eq!(downloader.join().unwrap(), Ok(None));
+        assert_eq!(
+            target.file("/game/game/ffxivgame.ver").unwrap(),
+            b"2024.01.01.0000.0000"
+        );
+    }
+
+    #[test]
+    fn test_unfinished_download_ends_at_finish() {
+        let tracker = DownloadTracker::new();
+        tracker.set_written(4);
+
+        let waiter = {
+            let tracker = tracker.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                tracker.finish();
+            })
+        };
+
+        let mut reader = GrowingReader::new(io::Cursor::new(vec![1, 2, 3, 4]), tracker);
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        waiter.join().unwrap();
+
+        assert_eq!(buf, [1, 2, 3, 4]);
+        assert_eq!(reader.seek(SeekFrom::End(-1)).unwrap(), 3);
+    }
+}
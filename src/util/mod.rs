@@ -1,8 +1,12 @@
 mod advance_guard;
 mod binary_reader;
+mod binary_writer;
 mod checksum_reader;
 mod compressed_block;
 mod crc32;
+mod forward_reader;
+mod growing_reader;
+mod path_resolver;
 mod sqex_file;
 mod sqex_file_stream;
 mod sqex_stream_store;
@@ -10,9 +14,14 @@ mod sqpack_file;
 
 pub use advance_guard::AdvanceGuard;
 pub use binary_reader::BinaryReaderExt;
+pub use binary_writer::BinaryWriterExt;
 pub use checksum_reader::ChecksumReader;
 pub use compressed_block::SqpkCompressedBlock;
 pub use crc32::Crc32;
+pub use forward_reader::ForwardReader;
+pub use growing_reader::{DownloadSignal, DownloadTracker, GrowingReader};
+pub(crate) use path_resolver::normalize_path;
+pub use path_resolver::{resolve_game_path, resolve_game_path_in};
 pub use sqex_file::SqexFile;
 pub use sqex_file_stream::SqexFileStream;
 pub use sqex_stream_store::SqexFileStreamStore;
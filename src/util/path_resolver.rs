@@ -0,0 +1,160 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, ZiPatchError};
+use crate::target::{FileSystemTarget, PatchTarget};
+
+/// Normalizes a patch path the way it is resolved against the game directory
+///
+/// Separators become `/`, and leading, repeated and `.` components are dropped, so
+/// `/sqpack/ffxiv/000000.win32.dat0` and `sqpack\ffxiv\000000.win32.dat0` compare equal.
+pub(crate) fn normalize_path(path: &str) -> String {
+    path.split(['/', '\\'])
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Resolves a patch-supplied relative path against the game directory
+///
+/// Patch paths use either separator and SqPack paths carry a leading one
+/// (e.g. "/sqpack/ffxiv/000000.win32.dat0"), so leading, repeated and `.` components are
+/// dropped rather than being allowed to replace the game directory. Paths that could leave
+/// the game directory are rejected with [`ZiPatchError::UnsafePath`]:
+/// - `..` components
+/// - components carrying a drive or stream prefix (containing `:`)
+/// - existing symlinks along the path that point outside the game directory
+///
+/// Symlinks are looked up on the local filesystem; use [`resolve_game_path_in`] for a path
+/// in a [`PatchTarget`].
+///
+/// # Arguments
+/// * `game_path` - The base game directory path
+/// * `relative_path` - The path as stored in the patch
+pub fn resolve_game_path<P: AsRef<Path>>(game_path: P, relative_path: &str) -> Result<PathBuf> {
+    resolve_game_path_in(&FileSystemTarget::new(), game_path, relative_path)
+}
+
+/// Resolves a patch-supplied relative path against the game directory in a target
+///
+/// Same as [`resolve_game_path`], but symlinks are looked up through the target's
+/// [`is_symlink`](PatchTarget::is_symlink) and [`canonicalize`](PatchTarget::canonicalize),
+/// so targets without symlinks never touch the local filesystem.
+pub fn resolve_game_path_in<P: AsRef<Path>>(
+    target: &dyn PatchTarget,
+    game_path: P,
+    relative_path: &str,
+) -> Result<PathBuf> {
+    let game_path = game_path.as_ref();
+    let unsafe_path = |reason: &str| ZiPatchError::UnsafePath {
+        path: relative_path.to_string(),
+        reason: reason.to_string(),
+    };
+
+    let mut components = Vec::new();
+    for component in relative_path.split(['/', '\\']) {
+        match component {
+            "" | "." => continue,
+            ".." => return Err(unsafe_path("parent directory components are not allowed")),
+            c if c.contains(':') => {
+                return Err(unsafe_path("drive or stream prefixes are not allowed"))
+            }
+            c => components.push(c),
+        }
+    }
+
+    // Walk the path as it exists on disk, making sure no symlink leads out of the game directory
+    let mut resolved = game_path.to_path_buf();
+    let mut canonical_root = None;
+    let mut exists = true;
+
+    for component in components {
+        resolved.push(component);
+
+        if !exists {
+            continue;
+        }
+
+        match target.is_symlink(&resolved)? {
+            Some(true) => {
+                let root = match &canonical_root {
+                    Some(root) => root,
+                    None => canonical_root.insert(target.canonicalize(game_path)?),
+                };
+
+                match target.canonicalize(&resolved) {
+                    Ok(canonical) if canonical.starts_with(root) => {}
+                    Ok(_) => return Err(unsafe_path("symlink points outside the game directory")),
+                    Err(_) => return Err(unsafe_path("symlink target does not exist")),
+                }
+            }
+            Some(false) => {}
+            None => exists = false,
+        }
+    }
+
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestDir;
+    use crate::MemoryTarget;
+    use std::fs;
+
+    #[test]
+    fn test_leading_separator_stays_in_game_path() {
+        let path = resolve_game_path("/game", "/sqpack/ffxiv/000000.win32.dat0").unwrap();
+        assert_eq!(path, PathBuf::from("/game/sqpack/ffxiv/000000.win32.dat0"));
+    }
+
+    #[test]
+    fn test_backslashes_and_dots_are_normalized() {
+        let path = resolve_game_path("/game", "\\\\boot\\.\\ffxivboot.exe").unwrap();
+        assert_eq!(path, PathBuf::from("/game/boot/ffxivboot.exe"));
+    }
+
+    #[test]
+    fn test_parent_components_are_rejected() {
+        assert!(matches!(
+            resolve_game_path("/game", "sqpack/../../etc/passwd"),
+            Err(ZiPatchError::UnsafePath { .. })
+        ));
+    }
+
+    #[test]
+    fn test_drive_prefixes_are_rejected() {
+        assert!(matches!(
+            resolve_game_path("/game", "C:\\Windows\\system32"),
+            Err(ZiPatchError::UnsafePath { .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_escape_is_rejected() {
+        let dir = TestDir::new("resolver");
+        let game = &dir.game;
+        let outside = dir.root.join("outside");
+        dir.create_dir("sqpack");
+        fs::create_dir_all(&outside).unwrap();
+
+        std::os::unix::fs::symlink(&outside, game.join("movie")).unwrap();
+        std::os::unix::fs::symlink(game.join("sqpack"), game.join("data")).unwrap();
+
+        assert!(matches!(
+            resolve_game_path(game, "movie/ffxiv/00000.bk2"),
+            Err(ZiPatchError::UnsafePath { .. })
+        ));
+        assert_eq!(
+            resolve_game_path(game, "data/ffxiv").unwrap(),
+            game.join("data").join("ffxiv")
+        );
+
+        // A memory target has no symlinks, whatever is on disk at the same path
+        assert_eq!(
+            resolve_game_path_in(&MemoryTarget::new(), game, "movie/ffxiv/00000.bk2").unwrap(),
+            game.join("movie/ffxiv/00000.bk2")
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use super::{SqexFileStream, SqexFileStreamStore};
+use super::{resolve_game_path, SqexFileStream, SqexFileStreamStore};
 use crate::error::{Result, ZiPatchError};
 
 /// Represents a Square Enix game file with a relative path
@@ -33,7 +33,7 @@ impl SqexFile {
         tries: u32,
         sleeptime: u64,
     ) -> Result<SqexFileStream> {
-        let full_path = self.resolve_full_path(base_path);
+        let full_path = self.resolve_full_path(base_path)?;
         SqexFileStream::wait_for_stream(&full_path, write, tries, sleeptime)
     }
 
@@ -53,7 +53,7 @@ impl SqexFile {
         tries: u32,
         sleeptime: u64,
     ) -> Result<&'a mut SqexFileStream> {
-        let full_path = self.resolve_full_path(base_path);
+        let full_path = self.resolve_full_path(base_path)?;
         store.get_stream(&full_path, write, tries, sleeptime)
     }
 
@@ -62,9 +62,9 @@ impl SqexFile {
     /// # Arguments
     /// * `base_path` - The base game directory path
     pub fn create_directory_tree<P: AsRef<Path>>(&self, base_path: P) -> Result<()> {
-        let full_path = self.resolve_full_path(base_path);
+        let full_path = self.resolve_full_path(base_path)?;
 
-        if let Some(parent) = Path::new(&full_path).parent() {
+        if let Some(parent) = full_path.parent() {
             fs::create_dir_all(parent).map_err(|e| ZiPatchError::DirectoryCreationFailed {
                 path: parent.to_path_buf(),
                 source: e,
@@ -75,9 +75,10 @@ impl SqexFile {
     }
 
     /// Resolves the full path by combining base path and relative path
-    fn resolve_full_path<P: AsRef<Path>>(&self, base_path: P) -> PathBuf {
-        let base = base_path.as_ref();
-        base.join(&self.relative_path)
+    ///
+    /// The relative path is sandboxed to the base path; see [`resolve_game_path`].
+    pub fn resolve_full_path<P: AsRef<Path>>(&self, base_path: P) -> Result<PathBuf> {
+        resolve_game_path(base_path, &self.relative_path)
     }
 
     /// Gets the expansion folder name for a given expansion ID
@@ -111,8 +112,8 @@ impl SqexFile {
         let full_path = full_path.as_ref();
         let xpac_folder = Self::get_expansion_folder(expansion_id as u8);
 
-        let sqpack_path = full_path.join("sqpack").join(&xpac_folder);
-        let movie_path = full_path.join("movie").join(&xpac_folder);
+        let sqpack_path = resolve_game_path(full_path, &format!("sqpack/{}", xpac_folder))?;
+        let movie_path = resolve_game_path(full_path, &format!("movie/{}", xpac_folder))?;
 
         let mut files = Vec::new();
 
@@ -163,7 +164,18 @@ mod tests {
     #[test]
     fn test_resolve_full_path() {
         let file = SqexFile::new("sqpack/ffxiv/000000.win32.dat0");
-        let full_path = file.resolve_full_path("/game");
+        let full_path = file.resolve_full_path("/game").unwrap();
+
+        assert_eq!(
+            full_path,
+            PathBuf::from("/game/sqpack/ffxiv/000000.win32.dat0")
+        );
+    }
+
+    #[test]
+    fn test_resolve_full_path_sqpack_leading_slash() {
+        let file = SqexFile::new("/sqpack/ffxiv/000000.win32.dat0");
+        let full_path = file.resolve_full_path("/game").unwrap();
 
         assert_eq!(
             full_path,
@@ -29,6 +29,7 @@ impl SqexFileStream {
                 .read(true)
                 .write(true)
                 .create(true)
+                .truncate(false)
                 .open(path)?
         } else {
             OpenOptions::new().read(true).open(path)?
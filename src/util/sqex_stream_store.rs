@@ -7,20 +7,35 @@ use crate::error::Result;
 /// A cache/store for Square Enix file streams
 ///
 /// Keeps file streams open and returns them from cache on subsequent requests,
-/// avoiding repeated file open operations.
+/// avoiding repeated file open operations. At most [`max_streams`](Self::with_max_streams)
+/// streams are kept open; opening another closes the least recently used one.
 #[derive(Debug)]
 pub struct SqexFileStreamStore {
-    streams: HashMap<PathBuf, SqexFileStream>,
+    /// Cached streams and the request count at which each was last used
+    streams: HashMap<PathBuf, (SqexFileStream, u64)>,
+    max_streams: usize,
+    requests: u64,
 }
 
 impl SqexFileStreamStore {
+    /// Number of streams kept open unless set otherwise
+    pub const DEFAULT_MAX_STREAMS: usize = 64;
+
     /// Creates a new empty stream store
     pub fn new() -> Self {
         Self {
             streams: HashMap::new(),
+            max_streams: Self::DEFAULT_MAX_STREAMS,
+            requests: 0,
         }
     }
 
+    /// Sets the number of streams kept open (at least 1)
+    pub fn with_max_streams(mut self, max_streams: usize) -> Self {
+        self.max_streams = max_streams.max(1);
+        self
+    }
+
     /// Gets a stream for the given path, opening it if not already cached
     ///
     /// # Arguments
@@ -44,11 +59,31 @@ impl SqexFileStreamStore {
             // Open new stream with retry logic
             let stream =
                 SqexFileStream::wait_for_stream(&normalized_path, write, tries, sleeptime)?;
-            self.streams.insert(normalized_path.clone(), stream);
+
+            if self.streams.len() >= self.max_streams {
+                self.evict_least_recently_used();
+            }
+            self.streams.insert(normalized_path.clone(), (stream, 0));
         }
 
         // Return mutable reference to the stream
-        Ok(self.streams.get_mut(&normalized_path).unwrap())
+        self.requests += 1;
+        let (stream, last_used) = self.streams.get_mut(&normalized_path).unwrap();
+        *last_used = self.requests;
+        Ok(stream)
+    }
+
+    /// Closes the stream that has gone unused the longest
+    fn evict_least_recently_used(&mut self) {
+        let oldest = self
+            .streams
+            .iter()
+            .min_by_key(|(_, (_, last_used))| *last_used)
+            .map(|(path, _)| path.clone());
+
+        if let Some(path) = oldest {
+            self.streams.remove(&path);
+        }
     }
 
     /// Checks if a stream for the given path is already cached
@@ -72,7 +107,9 @@ impl SqexFileStreamStore {
     pub fn remove<P: AsRef<Path>>(&mut self, path: P) -> Option<SqexFileStream> {
         let path = path.as_ref();
         let normalized_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
-        self.streams.remove(&normalized_path)
+        self.streams
+            .remove(&normalized_path)
+            .map(|(stream, _)| stream)
     }
 
     /// Closes all cached streams
@@ -97,6 +134,7 @@ impl Drop for SqexFileStreamStore {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::TestDir;
 
     #[test]
     fn test_stream_store_new() {
@@ -110,4 +148,20 @@ mod tests {
         let store = SqexFileStreamStore::default();
         assert_eq!(store.len(), 0);
     }
+
+    #[test]
+    fn test_stream_store_closes_least_recently_used() {
+        let dir = TestDir::new("stream-store");
+        let mut store = SqexFileStreamStore::new().with_max_streams(2);
+
+        store.get_stream(dir.path("a.dat"), true, 1, 0).unwrap();
+        store.get_stream(dir.path("b.dat"), true, 1, 0).unwrap();
+        store.get_stream(dir.path("a.dat"), true, 1, 0).unwrap();
+        store.get_stream(dir.path("c.dat"), true, 1, 0).unwrap();
+
+        assert_eq!(store.len(), 2);
+        assert!(store.contains(dir.path("a.dat")));
+        assert!(!store.contains(dir.path("b.dat")));
+        assert!(store.contains(dir.path("c.dat")));
+    }
 }
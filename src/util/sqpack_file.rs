@@ -1,9 +1,12 @@
 use std::io::{Read, Write};
+use std::path::Path;
 
 use super::{SqexFile, SqexFileStream};
 use crate::config::Platform;
 use crate::error::Result;
+use crate::target::PatchTarget;
 use crate::util::binary_reader::BinaryReaderExt;
+use crate::util::binary_writer::BinaryWriterExt;
 
 /// Base structure for Sqpack files (index and data files)
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -36,6 +39,14 @@ impl SqpackFile {
         })
     }
 
+    /// Writes the file identifiers to a binary writer
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u16_be(self.main_id)?;
+        writer.write_u16_be(self.sub_id)?;
+        writer.write_u32_be(self.file_id)?;
+        Ok(())
+    }
+
     /// Gets the expansion ID from the sub_id
     pub fn expansion_id(&self) -> u8 {
         (self.sub_id >> 8) as u8
@@ -73,6 +84,11 @@ impl SqpackDatFile {
         Ok(Self { sqpack })
     }
 
+    /// Writes the file identifiers to a binary writer
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.sqpack.write_to(writer)
+    }
+
     /// Gets the full filename including .dat{N} extension
     pub fn get_file_name(&self, platform: Platform) -> String {
         format!(
@@ -103,6 +119,9 @@ impl SqpackDatFile {
         &mut self.sqpack.sqex_file
     }
 
+    /// Size of the header written by [`write_empty_file_block_at`](Self::write_empty_file_block_at)
+    pub const EMPTY_BLOCK_HEADER_SIZE: u64 = 20;
+
     /// Writes an empty file block at the specified offset
     ///
     /// This creates a file block header with zeroed data
@@ -114,20 +133,40 @@ impl SqpackDatFile {
         // Wipe the block area
         stream.wipe_from_offset((block_number << 7) as u64, offset)?;
         stream.seek_to(offset as u64)?;
+        stream.write_all(&Self::empty_file_block_header(block_number))?;
+
+        Ok(())
+    }
+
+    /// Writes an empty file block at the specified offset of a file in a patch target
+    pub fn write_empty_file_block_to(
+        target: &mut dyn PatchTarget,
+        path: &Path,
+        offset: i64,
+        block_number: i64,
+    ) -> Result<()> {
+        target.wipe_at(path, offset as u64, (block_number << 7) as u64)?;
+        target.write_at(
+            path,
+            offset as u64,
+            &Self::empty_file_block_header(block_number),
+        )
+    }
+
+    /// Builds the header of an empty file block spanning `block_number` 128-byte blocks
+    pub fn empty_file_block_header(
+        block_number: i64,
+    ) -> [u8; Self::EMPTY_BLOCK_HEADER_SIZE as usize] {
+        let mut header = [0u8; Self::EMPTY_BLOCK_HEADER_SIZE as usize];
 
-        // Write file block header
         // Block size
-        stream.write_all(&(1i32 << 7).to_le_bytes())?;
-        // Unknown field (0)
-        stream.write_all(&0i32.to_le_bytes())?;
-        // File size (0)
-        stream.write_all(&0i32.to_le_bytes())?;
+        header[0..4].copy_from_slice(&(1i32 << 7).to_le_bytes());
+        // Unknown field (0) and file size (0) are left zeroed
         // Total number of blocks
-        stream.write_all(&((block_number - 1) as i32).to_le_bytes())?;
+        header[12..16].copy_from_slice(&((block_number - 1) as i32).to_le_bytes());
         // Used number of blocks (0)
-        stream.write_all(&0i32.to_le_bytes())?;
 
-        Ok(())
+        header
     }
 }
 
@@ -152,6 +191,11 @@ impl SqpackIndexFile {
         Ok(Self { sqpack })
     }
 
+    /// Writes the file identifiers to a binary writer
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.sqpack.write_to(writer)
+    }
+
     /// Gets the full filename including .index or .index{N} extension
     pub fn get_file_name(&self, platform: Platform) -> String {
         let index_suffix = if self.sqpack.file_id == 0 {
@@ -247,6 +291,18 @@ mod tests {
         assert_eq!(filename, "/sqpack/ffxiv/0a0000.win32.index2");
     }
 
+    #[test]
+    fn test_sqpack_file_write_round_trip() {
+        let data = vec![0x00, 0x0A, 0x01, 0x00, 0x00, 0x00, 0x00, 0x02];
+        let mut cursor = Cursor::new(data.clone());
+
+        let sqpack = SqpackFile::read_from(&mut cursor).unwrap();
+        let mut written = Vec::new();
+        sqpack.write_to(&mut written).unwrap();
+
+        assert_eq!(written, data);
+    }
+
     #[test]
     fn test_expansion_id_extraction() {
         let data = vec![0x00, 0x0A, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00];
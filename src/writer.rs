@@ -0,0 +1,589 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use crate::chunk::{EndOfFileChunk, ZiPatchChunk};
+use crate::error::{Result, ZiPatchError};
+use crate::file::ZIPATCH_MAGIC;
+use crate::util::BinaryWriterExt;
+
+/// Main ZiPatch file writer
+///
+/// Serializes chunks into a patch stream that [`ZiPatchFile`](crate::ZiPatchFile) can read back.
+/// The magic is written on creation; chunks are written in the order given, and
+/// [`finish`](Self::finish) terminates the stream with an EOF_ chunk if one wasn't written yet.
+pub struct ZiPatchWriter<W: Write> {
+    writer: W,
+    finished: bool,
+}
+
+impl ZiPatchWriter<File> {
+    /// Creates a new ZiPatch file at the given path
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::create(path)?;
+        Self::new(file)
+    }
+}
+
+impl<W: Write> ZiPatchWriter<W> {
+    /// Creates a new ZiPatchWriter, writing the magic number to the writer
+    pub fn new(mut writer: W) -> Result<Self> {
+        for m in ZIPATCH_MAGIC {
+            writer.write_u32_le(m)?;
+        }
+
+        Ok(Self {
+            writer,
+            finished: false,
+        })
+    }
+
+    /// Writes a single chunk
+    ///
+    /// Writing an EOF_ chunk ends the stream; no further chunks may be written after it.
+    pub fn write_chunk(&mut self, chunk: &ZiPatchChunk) -> Result<()> {
+        if self.finished {
+            return Err(ZiPatchError::Custom(
+                "Cannot write chunks after EOF_".to_string(),
+            ));
+        }
+
+        chunk.write(&mut self.writer)?;
+
+        if chunk.is_eof() {
+            self.finished = true;
+        }
+
+        Ok(())
+    }
+
+    /// Writes every chunk from an iterator
+    pub fn write_chunks<'a, I>(&mut self, chunks: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a ZiPatchChunk>,
+    {
+        for chunk in chunks {
+            self.write_chunk(chunk)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks if the EOF_ chunk has been written
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Finishes the patch, writing the EOF_ chunk if needed, and returns the inner writer
+    pub fn finish(mut self) -> Result<W> {
+        if !self.finished {
+            self.write_chunk(&ZiPatchChunk::EndOfFile(EndOfFileChunk::default()))?;
+        }
+
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Gets a reference to the inner writer
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Gets a mutable reference to the inner writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::sqpk::{
+        IndexCommandKind, OperationKind, RegionId, SqpkAddData, SqpkDeleteData, SqpkExpandData,
+        SqpkFile, SqpkHeader, SqpkIndex, SqpkPatchInfo, SqpkTargetInfo, TargetFile, TargetFileKind,
+        TargetHeaderKind,
+    };
+    use crate::chunk::{
+        AddDirectoryChunk, ApplyFreeSpaceChunk, ApplyOptionChunk, ApplyOptionKind,
+        DeleteDirectoryChunk, FileHeaderChunk, SqpkCommand,
+    };
+    use crate::config::{Platform, ZiPatchConfig};
+    use crate::file::ZIPATCH_MAGIC;
+    use crate::inspection::ZiPatchCommandCounts;
+    use crate::util::Crc32;
+    use crate::util::{SqexFile, SqpackDatFile, SqpackFile, SqpackIndexFile, SqpkCompressedBlock};
+    use crate::ZiPatchFile;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Cursor;
+
+    fn sqpack_file(main_id: u16, sub_id: u16, file_id: u32) -> SqpackFile {
+        SqpackFile {
+            main_id,
+            sub_id,
+            file_id,
+            sqex_file: SqexFile::default(),
+        }
+    }
+
+    fn compressed_block(data: &[u8]) -> SqpkCompressedBlock {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        SqpkCompressedBlock {
+            header_size: 16,
+            reserved: 0,
+            compressed_size: compressed.len() as i32,
+            decompressed_size: data.len() as i32,
+            compressed_block: compressed,
+        }
+    }
+
+    fn sample_chunks() -> Vec<ZiPatchChunk> {
+        let dat = SqpackDatFile {
+            sqpack: sqpack_file(0x0A, 0x0100, 1),
+        };
+        let index = SqpackIndexFile {
+            sqpack: sqpack_file(0x04, 0x0000, 0),
+        };
+
+        vec![
+            ZiPatchChunk::FileHeader(FileHeaderChunk {
+                version: 3,
+                version_reserved: 0,
+                patch_type: "DIFF".to_string(),
+                entry_files: 2,
+                command_counts: Some(ZiPatchCommandCounts::with_counts(1, 1, 12, 1, 1, 1, 1, 2)),
+                add_directories: 1,
+                delete_directories: 1,
+                delete_data_size: 0x1_0000_0080,
+                minor_version: 7,
+                repository_name: 0x1234_5678,
+                reserved: vec![0xAB; 0xB8],
+            }),
+            ZiPatchChunk::ApplyOption(ApplyOptionChunk::new(ApplyOptionKind::IgnoreMissing, true)),
+            ZiPatchChunk::ApplyOption(ApplyOptionChunk::new(
+                ApplyOptionKind::IgnoreOldMismatch,
+                false,
+            )),
+            ZiPatchChunk::ApplyFreeSpace(ApplyFreeSpaceChunk {
+                unknown_field_a: -1,
+                unknown_field_b: 42,
+            }),
+            ZiPatchChunk::Sqpk(SqpkCommand::TargetInfo(SqpkTargetInfo {
+                alignment: [0; 3],
+                platform: Platform::Win32,
+                region: RegionId::Global,
+                debug: 0,
+                version: 1,
+                deleted_data_size: 12345,
+                seek_count: 6789,
+                reserved: Vec::new(),
+            })),
+            ZiPatchChunk::Sqpk(SqpkCommand::PatchInfo(SqpkPatchInfo {
+                status: 1,
+                version: 2,
+                alignment: 0,
+                install_size: 0xDEAD_BEEF,
+            })),
+            ZiPatchChunk::Sqpk(SqpkCommand::Header(SqpkHeader {
+                file_kind: TargetFileKind::Index,
+                header_kind: TargetHeaderKind::Index,
+                alignment: 0,
+                target_file: TargetFile::Index(index.clone()),
+                header_data: (0..SqpkHeader::HEADER_SIZE).map(|i| i as u8).collect(),
+            })),
+            ZiPatchChunk::Sqpk(SqpkCommand::AddData(SqpkAddData {
+                alignment: [0; 3],
+                target_file: dat.clone(),
+                block_offset: 0x800,
+                block_number: 0x100,
+                block_delete_number: 0x80,
+                block_data: vec![0x5A; 0x100],
+            })),
+            ZiPatchChunk::Sqpk(SqpkCommand::DeleteData(SqpkDeleteData {
+                alignment: [0; 3],
+                reserved: 0,
+                target_file: dat.clone(),
+                block_offset: 0x1000,
+                block_number: 4,
+            })),
+            ZiPatchChunk::Sqpk(SqpkCommand::ExpandData(SqpkExpandData {
+                alignment: [0; 3],
+                reserved: 0,
+                target_file: dat,
+                block_offset: 0x2000,
+                block_number: 8,
+            })),
+            ZiPatchChunk::Sqpk(SqpkCommand::File(SqpkFile {
+                operation: OperationKind::AddFile,
+                alignment: [0; 2],
+                file_offset: 0,
+                file_size: 40013,
+                expansion_id: 0,
+                padding: [0; 2],
+                target_file: SqexFile::new("boot/ffxivboot.exe"),
+                path_padding: vec![0],
+                compressed_data: vec![
+                    compressed_block(&[7u8; 40000]),
+                    SqpkCompressedBlock {
+                        header_size: 16,
+                        reserved: 0,
+                        compressed_size: 0x7d00,
+                        decompressed_size: 13,
+                        compressed_block: b"Hello, World!".to_vec(),
+                    },
+                ],
+                unknown_data: Vec::new(),
+            })),
+            ZiPatchChunk::Sqpk(SqpkCommand::File(SqpkFile {
+                operation: OperationKind::RemoveAll,
+                alignment: [0; 2],
+                file_offset: 0,
+                file_size: 0,
+                expansion_id: 1,
+                padding: [0; 2],
+                target_file: SqexFile::new(""),
+                path_padding: vec![0],
+                compressed_data: vec![],
+                unknown_data: Vec::new(),
+            })),
+            ZiPatchChunk::Sqpk(SqpkCommand::Index(SqpkIndex {
+                index_command: IndexCommandKind::Delete,
+                synonym: 1,
+                alignment: 0,
+                target_file: index,
+                file_hash: 0x0123_4567_89AB_CDEF,
+                block_offset: 99,
+                block_number: 3,
+            })),
+            ZiPatchChunk::AddDirectory(AddDirectoryChunk::new("movie/ex1")),
+            ZiPatchChunk::DeleteDirectory(DeleteDirectoryChunk::new("movie/old")),
+            ZiPatchChunk::EndOfFile(EndOfFileChunk::default()),
+        ]
+    }
+
+    fn write_patch(chunks: &[ZiPatchChunk]) -> Vec<u8> {
+        let mut writer = ZiPatchWriter::new(Vec::new()).unwrap();
+        writer.write_chunks(chunks).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn read_patch(data: Vec<u8>) -> Vec<ZiPatchChunk> {
+        let mut patch = ZiPatchFile::new(Cursor::new(data)).unwrap();
+        patch.chunks().collect::<Result<Vec<_>>>().unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_is_byte_identical() {
+        let written = write_patch(&sample_chunks());
+        let parsed = read_patch(written.clone());
+
+        assert_eq!(parsed.len(), sample_chunks().len());
+        assert_eq!(write_patch(&parsed), written);
+    }
+
+    /// Frames a chunk body by hand, independently of [`ZiPatchChunk::write`]
+    fn frame_chunk(data: &mut Vec<u8>, chunk_type: &str, body: &[u8]) {
+        let mut crc32 = Crc32::new();
+        crc32.update(chunk_type.as_bytes());
+        crc32.update(body);
+
+        data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        data.extend_from_slice(chunk_type.as_bytes());
+        data.extend_from_slice(body);
+        data.extend_from_slice(&crc32.finalize().to_be_bytes());
+    }
+
+    /// Frames an SQPK command body, adding the inner size and command character
+    fn frame_sqpk(data: &mut Vec<u8>, command: u8, body: &[u8]) {
+        let mut sqpk = (body.len() as i32 + 5).to_be_bytes().to_vec();
+        sqpk.push(command);
+        sqpk.extend_from_slice(body);
+        frame_chunk(data, "SQPK", &sqpk);
+    }
+
+    #[test]
+    fn test_round_trip_keeps_unknown_and_reserved_values() {
+        let mut data = Vec::new();
+        for m in ZIPATCH_MAGIC {
+            data.extend_from_slice(&m.to_le_bytes());
+        }
+
+        // V3 FHDR with the unknown version bytes set and non-zero reserved data
+        let mut fhdr = vec![0xA5, 0x5A, 3, 0x7E];
+        fhdr.extend_from_slice(b"DIFF");
+        fhdr.extend((0..13u32).flat_map(|i| (i + 1).to_be_bytes()));
+        fhdr.extend((0..0xB8).map(|i| i as u8));
+        frame_chunk(&mut data, "FHDR", &fhdr);
+
+        // APLY with an unknown option kind and unusual padding
+        let mut aply = Vec::new();
+        aply.extend_from_slice(&7u32.to_be_bytes());
+        aply.extend_from_slice(&0x1234u32.to_be_bytes());
+        aply.extend_from_slice(&1u32.to_be_bytes());
+        frame_chunk(&mut data, "APLY", &aply);
+
+        // SqpkTargetInfo with an unknown region and a non-zero tail
+        let mut target_info = vec![0; 3];
+        target_info.extend_from_slice(&0u16.to_be_bytes());
+        target_info.extend_from_slice(&7i16.to_be_bytes());
+        target_info.extend_from_slice(&0i16.to_be_bytes());
+        target_info.extend_from_slice(&1u16.to_be_bytes());
+        target_info.extend_from_slice(&12345u64.to_le_bytes());
+        target_info.extend_from_slice(&6789u64.to_le_bytes());
+        target_info.extend((0..96).map(|i| 0xFF - i as u8));
+        frame_sqpk(&mut data, b'T', &target_info);
+
+        frame_chunk(&mut data, "XXXX", b"xxxx");
+        frame_chunk(&mut data, "EOF_", b"trailing");
+
+        let parsed = read_patch(data.clone());
+        assert!(matches!(
+            &parsed[0],
+            ZiPatchChunk::FileHeader(fhdr) if fhdr.version == 3
+        ));
+        assert!(matches!(
+            &parsed[1],
+            ZiPatchChunk::ApplyOption(aply) if aply.option_kind == ApplyOptionKind::Unknown(7)
+        ));
+        assert!(matches!(
+            &parsed[2],
+            ZiPatchChunk::Sqpk(SqpkCommand::TargetInfo(info)) if info.region == RegionId::Unknown(7)
+        ));
+        assert_eq!(write_patch(&parsed), data);
+    }
+
+    #[test]
+    fn test_round_trip_keeps_nonzero_padding() {
+        let mut data = Vec::new();
+        for m in ZIPATCH_MAGIC {
+            data.extend_from_slice(&m.to_le_bytes());
+        }
+        let mut fhdr = vec![0, 0, 2, 0];
+        fhdr.extend_from_slice(b"DIFF");
+        fhdr.extend_from_slice(&[0; 12]);
+        frame_chunk(&mut data, "FHDR", &fhdr);
+
+        // Alignment, target file, block offset, block count and a 128 byte block
+        let mut add_data = vec![1, 2, 3];
+        add_data.extend_from_slice(&[0; 8]);
+        add_data.extend([1u32, 1, 0].iter().flat_map(|value| value.to_be_bytes()));
+        add_data.extend_from_slice(&[0x42; 128]);
+        frame_sqpk(&mut data, b'A', &add_data);
+
+        // Alignment, target file, block offset, block count and reserved field
+        for (command, alignment) in [(b'D', [4, 5, 6]), (b'E', [7, 8, 9])] {
+            let mut body = alignment.to_vec();
+            body.extend_from_slice(&[0; 8]);
+            body.extend(
+                [1u32, 1, 0xDEAD_BEEF]
+                    .iter()
+                    .flat_map(|value| value.to_be_bytes()),
+            );
+            frame_sqpk(&mut data, command, &body);
+        }
+
+        // AddFile with an uncompressed and a compressed block, both padded with non-zero bytes
+        let path = b"boot/ffxivboot.ver\0";
+        let mut file = vec![b'A', 0x0A, 0x0B];
+        file.extend_from_slice(&0i64.to_be_bytes());
+        file.extend_from_slice(&10i64.to_be_bytes());
+        file.extend_from_slice(&(path.len() as u32).to_be_bytes());
+        file.extend_from_slice(&0u16.to_be_bytes());
+        file.extend_from_slice(&[0x0C, 0x0D]);
+        file.extend_from_slice(path);
+
+        let compressed = compressed_block(b"world").compressed_block;
+        for (compressed_size, block) in [(0x7d00, &b"hello"[..]), (compressed.len(), &compressed)] {
+            file.extend_from_slice(&16i32.to_le_bytes());
+            file.extend_from_slice(&0x55u32.to_le_bytes());
+            file.extend_from_slice(&(compressed_size as i32).to_le_bytes());
+            file.extend_from_slice(&5i32.to_le_bytes());
+            file.extend_from_slice(block);
+            file.resize(
+                file.len() + (block.len() + 143) / 128 * 128 - 16 - block.len(),
+                0xEE,
+            );
+        }
+        frame_sqpk(&mut data, b'F', &file);
+        frame_chunk(&mut data, "EOF_", &[]);
+
+        let parsed = read_patch(data.clone());
+        match &parsed[4] {
+            ZiPatchChunk::Sqpk(SqpkCommand::File(file)) => {
+                let mut contents = Vec::new();
+                for block in &file.compressed_data {
+                    block.decompress_into(&mut contents).unwrap();
+                }
+                assert_eq!(contents, b"helloworld");
+            }
+            other => panic!("unexpected chunk {}", other),
+        }
+        assert_eq!(write_patch(&parsed), data);
+    }
+
+    #[test]
+    fn test_round_trip_keeps_names_flags_and_unknown_kinds() {
+        let mut data = Vec::new();
+        for m in ZIPATCH_MAGIC {
+            data.extend_from_slice(&m.to_le_bytes());
+        }
+        let mut fhdr = vec![0, 0, 2, 0];
+        fhdr.extend_from_slice(b"DIFF");
+        fhdr.extend_from_slice(&[0; 12]);
+        frame_chunk(&mut data, "FHDR", &fhdr);
+
+        // Alignment, platform, region, a debug flag other than 1, version and sizes
+        let mut target_info = vec![1, 2, 3];
+        target_info.extend_from_slice(&0u16.to_be_bytes());
+        target_info.extend_from_slice(&(-1i16).to_be_bytes());
+        target_info.extend_from_slice(&2i16.to_be_bytes());
+        target_info.extend_from_slice(&1u16.to_be_bytes());
+        target_info.extend_from_slice(&[0; 16 + 96]);
+        frame_sqpk(&mut data, b'T', &target_info);
+
+        // Status, version, alignment and install size
+        let mut patch_info = vec![1, 2, 7];
+        patch_info.extend_from_slice(&0xDEAD_BEEFu64.to_be_bytes());
+        frame_sqpk(&mut data, b'X', &patch_info);
+
+        // Unknown file and header kinds with a non-zero alignment byte
+        let mut header = vec![b'Z', b'Q', 9];
+        header.extend_from_slice(&[0; 8]);
+        header.extend((0..SqpkHeader::HEADER_SIZE).map(|i| i as u8));
+        frame_sqpk(&mut data, b'H', &header);
+
+        // Delete with a synonym flag other than 1 and a non-zero alignment byte
+        let mut index = vec![b'D', 2, 5];
+        index.extend_from_slice(&[0; 8]);
+        index.extend_from_slice(&0x0123_4567_89AB_CDEFu64.to_be_bytes());
+        index.extend_from_slice(&[0, 0, 0, 99, 0, 0, 0, 3]);
+        frame_sqpk(&mut data, b'I', &index);
+
+        // An unknown index command kind
+        let mut unknown_index = index.clone();
+        unknown_index[0] = b'Q';
+        frame_sqpk(&mut data, b'I', &unknown_index);
+
+        // DeleteFile with bytes after the path's null terminator
+        let path = b"game/old.exe\0\x07\0";
+        let mut file = vec![b'D', 0, 0];
+        file.extend_from_slice(&[0; 16]);
+        file.extend_from_slice(&(path.len() as u32).to_be_bytes());
+        file.extend_from_slice(&[0; 4]);
+        file.extend_from_slice(path);
+        frame_sqpk(&mut data, b'F', &file);
+
+        // An unknown file operation with data after the path
+        let mut unknown_file = file.clone();
+        unknown_file[0] = b'Z';
+        unknown_file.extend_from_slice(b"opaque payload");
+        frame_sqpk(&mut data, b'F', &unknown_file);
+
+        // A directory name without a null terminator, and one with extra padding
+        for (chunk_type, name) in [("ADIR", &b"movie/ex1"[..]), ("DELD", b"movie/old\0\0\0")] {
+            let mut body = (name.len() as u32).to_be_bytes().to_vec();
+            body.extend_from_slice(name);
+            frame_chunk(&mut data, chunk_type, &body);
+        }
+        frame_chunk(&mut data, "EOF_", &[]);
+
+        let parsed = read_patch(data.clone());
+        assert!(matches!(
+            &parsed[1],
+            ZiPatchChunk::Sqpk(SqpkCommand::TargetInfo(info)) if info.is_debug()
+        ));
+        assert!(matches!(
+            &parsed[3],
+            ZiPatchChunk::Sqpk(SqpkCommand::Header(header))
+                if header.file_kind == TargetFileKind::Unknown(b'Z')
+                    && header.header_kind == TargetHeaderKind::Unknown(b'Q')
+        ));
+        assert!(matches!(
+            &parsed[4],
+            ZiPatchChunk::Sqpk(SqpkCommand::Index(index)) if index.is_synonym()
+        ));
+        assert!(matches!(
+            &parsed[5],
+            ZiPatchChunk::Sqpk(SqpkCommand::Index(index))
+                if index.index_command == IndexCommandKind::Unknown(b'Q')
+        ));
+        assert!(matches!(
+            &parsed[6],
+            ZiPatchChunk::Sqpk(SqpkCommand::File(file))
+                if file.target_file.relative_path == "game/old.exe"
+        ));
+        match &parsed[7] {
+            ZiPatchChunk::Sqpk(SqpkCommand::File(file)) => {
+                assert_eq!(file.operation, OperationKind::Unknown(b'Z'));
+                assert_eq!(file.unknown_data, b"opaque payload");
+                assert!(file.compressed_data.is_empty());
+
+                // Never applied
+                let mut config = ZiPatchConfig::new("game");
+                assert!(file.planned_operations(&config).unwrap().is_empty());
+                file.clone().apply(&mut config).unwrap();
+            }
+            other => panic!("unexpected chunk {}", other),
+        }
+        assert!(matches!(
+            &parsed[8],
+            ZiPatchChunk::AddDirectory(adir) if adir.dir_name == "movie/ex1"
+        ));
+        assert!(matches!(
+            &parsed[9],
+            ZiPatchChunk::DeleteDirectory(deld) if deld.dir_name == "movie/old"
+        ));
+        assert_eq!(write_patch(&parsed), data);
+    }
+
+    #[test]
+    fn test_round_trip_preserves_header_and_data() {
+        let parsed = read_patch(write_patch(&sample_chunks()));
+
+        match (&parsed[0], &sample_chunks()[0]) {
+            (ZiPatchChunk::FileHeader(actual), ZiPatchChunk::FileHeader(expected)) => {
+                assert_eq!(actual, expected)
+            }
+            _ => panic!("expected FHDR first"),
+        }
+
+        match &parsed[10] {
+            ZiPatchChunk::Sqpk(SqpkCommand::File(file)) => {
+                assert_eq!(file.target_file.relative_path, "boot/ffxivboot.exe");
+                let mut data = Vec::new();
+                for block in &file.compressed_data {
+                    block.decompress_into(&mut data).unwrap();
+                }
+                assert_eq!(data.len(), 40013);
+                assert!(data.ends_with(b"Hello, World!"));
+            }
+            other => panic!("unexpected chunk {}", other),
+        }
+    }
+
+    #[test]
+    fn test_finish_appends_eof_once() {
+        let mut chunks = sample_chunks();
+        chunks.pop();
+
+        let written = write_patch(&chunks);
+        let parsed = read_patch(written);
+        assert!(parsed.last().unwrap().is_eof());
+        assert_eq!(parsed.iter().filter(|c| c.is_eof()).count(), 1);
+    }
+
+    #[test]
+    fn test_write_after_eof_fails() {
+        let mut writer = ZiPatchWriter::new(Vec::new()).unwrap();
+        writer
+            .write_chunk(&ZiPatchChunk::EndOfFile(EndOfFileChunk::default()))
+            .unwrap();
+        assert!(writer.is_finished());
+        assert!(writer
+            .write_chunk(&ZiPatchChunk::EndOfFile(EndOfFileChunk::default()))
+            .is_err());
+    }
+}